@@ -0,0 +1,568 @@
+use std::time::Duration;
+
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    blob::{self, BlobId},
+    chat::Chat,
+    chunk,
+    error::ApiError,
+    globals::{CHATS, GROUPS, MEETINGS, STAGED_ATTACHMENTS, UPLOAD_BUFFERS},
+    impl_candid_storable, media,
+    runtime::{IcRuntime, Runtime},
+    user,
+};
+
+/// Without a subscription users can only share images up to this size.
+pub const FREE_MAX_ATTACHMENT_SIZE: u64 = 5 * 1024 * 1024;
+pub const SUBSCRIBED_MAX_ATTACHMENT_SIZE: u64 = 100 * 1024 * 1024;
+pub const MAX_ATTACHMENTS_PER_CHAT: usize = 10;
+
+/// Uploads that no chat picked up for this long are dropped together with their buffers.
+pub const STAGED_ATTACHMENT_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+
+/// Images are checked against their magic bytes, so only types we can recognize are allowed.
+const IMAGE_MIME_TYPES: [&str; 4] = ["image/png", "image/jpeg", "image/gif", "image/webp"];
+const FILE_MIME_TYPES: [&str; 7] = [
+    "application/pdf",
+    "application/zip",
+    "text/plain",
+    "text/csv",
+    "audio/mpeg",
+    "video/mp4",
+    "application/octet-stream",
+];
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize, PartialEq, Eq)]
+pub struct AttachedFile {
+    pub name: String,
+    pub mime_type: String,
+    pub size: u64,
+    pub blob: BlobId,
+}
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize, PartialEq, Eq)]
+pub enum ChatAttachment {
+    Image(AttachedFile),
+    File(AttachedFile),
+    VideoFrame { meeting_id: u128, frame_index: u128 },
+}
+
+impl ChatAttachment {
+    fn file(&self) -> Option<&AttachedFile> {
+        match self {
+            Self::Image(file) | Self::File(file) => Some(file),
+            Self::VideoFrame { .. } => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct AttachmentUpload {
+    pub name: String,
+    pub mime_type: String,
+}
+
+/// Uploads waiting for the chat with the same `uuid`, which takes them over once it is sent.
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct StagedAttachments {
+    pub group_id: u128,
+    pub attachments: Vec<ChatAttachment>,
+    pub created_time_unix: u128,
+}
+
+impl_candid_storable!(StagedAttachments);
+
+fn staged_key(principal: Principal, chat_uuid: &str) -> String {
+    format!("{}/{}", principal, chat_uuid)
+}
+
+fn buffer_key_prefix(staged_key: &str) -> String {
+    format!("chat_attachment/{}/", staged_key)
+}
+
+fn validate_upload(
+    subscribed: bool,
+    upload: &AttachmentUpload,
+    total_data_length: u128,
+) -> Result<(), ApiError> {
+    if upload.name.trim().is_empty() || upload.name.len() > 255 || upload.name.contains('/') {
+        return Err(ApiError::invalid_input(
+            "name",
+            "must be between 1 and 255 characters without slashes",
+        ));
+    }
+
+    let is_image = IMAGE_MIME_TYPES.contains(&upload.mime_type.as_str());
+    if !is_image && !FILE_MIME_TYPES.contains(&upload.mime_type.as_str()) {
+        return Err(ApiError::invalid_input(
+            "mime_type",
+            "is not an allowed attachment type",
+        ));
+    }
+
+    if total_data_length == 0 || total_data_length > SUBSCRIBED_MAX_ATTACHMENT_SIZE as u128 {
+        return Err(ApiError::invalid_input(
+            "total_data_length",
+            &format!(
+                "must be between 1 and {} bytes",
+                SUBSCRIBED_MAX_ATTACHMENT_SIZE
+            ),
+        ));
+    }
+
+    if !subscribed && (!is_image || total_data_length > FREE_MAX_ATTACHMENT_SIZE as u128) {
+        return Err(ApiError::SubscriptionRequired);
+    }
+
+    Ok(())
+}
+
+/// Buffers a chunk of an attachment for the chat the client is about to send with `chat_uuid`.
+pub fn upload_chat_attachment(
+    rt: &impl Runtime,
+    group_id: u128,
+    chat_uuid: String,
+    upload: AttachmentUpload,
+    chunk_data: Vec<u8>,
+    chunk_index: u128,
+    total_data_length: u128,
+) -> Result<(), ApiError> {
+    user::assert_user_logged_in(rt)?;
+
+    let selfuser = user::get_selfuser(rt)?.ok_or(ApiError::NoUsername)?;
+    let group = GROUPS
+        .with_borrow(|groups| groups.get(&group_id))
+        .ok_or(ApiError::not_found("group", group_id))?;
+    if !group.is_member(&selfuser.username) {
+        return Err(ApiError::forbidden("This user is not in this group!"));
+    }
+
+    // buffers are found by prefix, so a slash would let one chat reach into another
+    if chat_uuid.is_empty() || chat_uuid.len() > 64 || chat_uuid.contains('/') {
+        return Err(ApiError::invalid_input(
+            "chat_uuid",
+            "must be between 1 and 64 characters without slashes",
+        ));
+    }
+    validate_upload(selfuser.subscription.is_some(), &upload, total_data_length)?;
+
+    let principal = rt.caller();
+    let key = staged_key(principal, &chat_uuid);
+    let mut staged = STAGED_ATTACHMENTS
+        .with_borrow(|staged_attachments| staged_attachments.get(&key))
+        .unwrap_or(StagedAttachments {
+            group_id,
+            attachments: Vec::new(),
+            created_time_unix: rt.time(),
+        });
+    if staged.group_id != group_id {
+        return Err(ApiError::conflict(
+            "Attachments of this chat are uploaded to another group!",
+        ));
+    }
+    if staged.attachments.len() >= MAX_ATTACHMENTS_PER_CHAT {
+        return Err(ApiError::invalid_input(
+            "chat_uuid",
+            &format!(
+                "cannot have more than {} attachments",
+                MAX_ATTACHMENTS_PER_CHAT
+            ),
+        ));
+    }
+
+    let buffer_key = format!("{}{}", buffer_key_prefix(&key), upload.name);
+    let Some(data) = chunk::buffer_upload_chunk(
+        rt,
+        buffer_key,
+        chunk_data,
+        chunk_index,
+        total_data_length,
+        SUBSCRIBED_MAX_ATTACHMENT_SIZE,
+    )?
+    else {
+        // keeps track of the buffer, so it expires even if the upload is never finished
+        STAGED_ATTACHMENTS
+            .with_borrow_mut(|staged_attachments| staged_attachments.insert(key, staged));
+        return Ok(());
+    };
+
+    let id = blob::store(&data);
+    let file = AttachedFile {
+        name: upload.name,
+        mime_type: upload.mime_type,
+        size: data.len() as u64,
+        blob: id.clone(),
+    };
+    let attachment = if IMAGE_MIME_TYPES.contains(&file.mime_type.as_str()) {
+        if media::sniff_image_content_type(&id) != file.mime_type {
+            blob::release(&id);
+            return Err(ApiError::invalid_input(
+                "mime_type",
+                "does not match the content of the image",
+            ));
+        }
+
+        ChatAttachment::Image(file)
+    } else {
+        ChatAttachment::File(file)
+    };
+
+    staged.attachments.push(attachment);
+    STAGED_ATTACHMENTS.with_borrow_mut(|staged_attachments| staged_attachments.insert(key, staged));
+
+    Ok(())
+}
+
+fn remove_staged(key: &str) -> Option<StagedAttachments> {
+    let prefix = buffer_key_prefix(key);
+    let buffer_keys = UPLOAD_BUFFERS.with_borrow(|upload_buffers| {
+        upload_buffers
+            .range(prefix.clone()..)
+            .map(|(buffer_key, _)| buffer_key)
+            .take_while(|buffer_key| buffer_key.starts_with(&prefix))
+            .collect::<Vec<_>>()
+    });
+    for buffer_key in buffer_keys {
+        chunk::remove_buffer(&buffer_key);
+    }
+
+    STAGED_ATTACHMENTS
+        .with_borrow_mut(|staged_attachments| staged_attachments.remove(&key.to_string()))
+}
+
+/// Attachments of a chat that is being sent: the finished uploads staged under its `uuid`,
+/// plus the video frame links the client put on it. Client supplied files are ignored.
+pub fn take_staged(principal: Principal, chat: &Chat) -> Result<Vec<ChatAttachment>, ApiError> {
+    let mut attachments = Vec::new();
+    for attachment in chat.attachments.iter().flatten() {
+        if let ChatAttachment::VideoFrame {
+            meeting_id,
+            frame_index,
+        } = attachment
+        {
+            let meeting = MEETINGS
+                .with_borrow(|meetings| meetings.get(&(chat.group_id, *meeting_id)))
+                .ok_or(ApiError::not_found("meeting", meeting_id))?;
+            if meeting.frames.len() <= *frame_index as usize {
+                return Err(ApiError::not_found("frame", frame_index));
+            }
+
+            attachments.push(attachment.clone());
+        }
+    }
+
+    let key = staged_key(principal, &chat.uuid);
+    let staged = STAGED_ATTACHMENTS.with_borrow(|staged_attachments| staged_attachments.get(&key));
+    if let Some(staged) = staged.filter(|staged| staged.group_id == chat.group_id) {
+        if attachments.len() + staged.attachments.len() > MAX_ATTACHMENTS_PER_CHAT {
+            return Err(ApiError::invalid_input(
+                "attachments",
+                &format!("cannot have more than {}", MAX_ATTACHMENTS_PER_CHAT),
+            ));
+        }
+
+        remove_staged(&key);
+        attachments.extend(staged.attachments);
+    }
+
+    Ok(attachments)
+}
+
+/// Drops the references the attachments hold in the blob store.
+pub fn release(attachments: &[ChatAttachment]) {
+    for file in attachments.iter().filter_map(ChatAttachment::file) {
+        blob::release(&file.blob);
+    }
+}
+
+/// Drops the staged uploads that are older than [`STAGED_ATTACHMENT_TIMEOUT`].
+pub fn remove_expired_staged(rt: &impl Runtime) {
+    let now = rt.time();
+    let expired_keys = STAGED_ATTACHMENTS.with_borrow(|staged_attachments| {
+        staged_attachments
+            .iter()
+            .filter(|(_, staged)| {
+                now.saturating_sub(staged.created_time_unix) > STAGED_ATTACHMENT_TIMEOUT.as_nanos()
+            })
+            .map(|(key, _)| key)
+            .collect::<Vec<_>>()
+    });
+
+    for key in expired_keys {
+        if let Some(staged) = remove_staged(&key) {
+            release(&staged.attachments);
+        }
+    }
+}
+
+pub fn poll_expired_staged_attachments() {
+    ic_cdk::println!("Starting poll expired staged attachments");
+    ic_cdk_timers::set_timer_interval(Duration::from_secs(60), || {
+        remove_expired_staged(&IcRuntime)
+    });
+}
+
+/// Returns the `chunk_index`-th chunk of `chunk::MB` bytes of an image or file on the chat.
+pub fn get_chat_attachment_chunk(
+    rt: &impl Runtime,
+    group_id: u128,
+    chat_id: u128,
+    attachment_index: u32,
+    chunk_index: u128,
+) -> Result<Vec<u8>, ApiError> {
+    user::assert_user_logged_in(rt)?;
+
+    let selfname = user::get_selfname_force(rt)?;
+    let group = GROUPS
+        .with_borrow(|groups| groups.get(&group_id))
+        .ok_or(ApiError::not_found("group", group_id))?;
+    if !group.is_member(&selfname) {
+        return Err(ApiError::forbidden("This user is not in this group!"));
+    }
+
+    let chat = CHATS
+        .with_borrow(|chats| chats.get(&(group_id, chat_id)))
+        .ok_or(ApiError::not_found("chat", chat_id))?;
+    let file = chat
+        .attachments
+        .unwrap_or_default()
+        .get(attachment_index as usize)
+        .and_then(ChatAttachment::file)
+        .cloned()
+        .ok_or(ApiError::not_found("attachment", attachment_index))?;
+
+    Ok(blob::get_chunk(&file.blob, chunk_index))
+}
+
+mod endpoints {
+    use super::*;
+    use crate::runtime::IcRuntime;
+
+    #[ic_cdk::update]
+    fn upload_chat_attachment(
+        group_id: u128,
+        chat_uuid: String,
+        upload: AttachmentUpload,
+        chunk_data: Vec<u8>,
+        chunk_index: u128,
+        total_data_length: u128,
+    ) -> Result<(), ApiError> {
+        super::upload_chat_attachment(
+            &IcRuntime,
+            group_id,
+            chat_uuid,
+            upload,
+            chunk_data,
+            chunk_index,
+            total_data_length,
+        )
+    }
+
+    #[ic_cdk::query]
+    fn get_chat_attachment_chunk(
+        group_id: u128,
+        chat_id: u128,
+        attachment_index: u32,
+        chunk_index: u128,
+    ) -> Result<Vec<u8>, ApiError> {
+        super::get_chat_attachment_chunk(
+            &IcRuntime,
+            group_id,
+            chat_id,
+            attachment_index,
+            chunk_index,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        globals::{BLOBS, UPLOAD_BUFFER_CHUNKS},
+        group,
+        runtime::TestRuntime,
+        user::tests::sign_in,
+        websocket::{tests::send_from, WebsocketEventMessage},
+    };
+
+    const PNG: [u8; 16] = [
+        0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A, 0, 0, 0, 0x0D, b'I', b'H', b'D', b'R',
+    ];
+
+    fn upload_image(rt: &TestRuntime, group_id: u128, mime_type: &str) -> Result<(), ApiError> {
+        upload_chat_attachment(
+            rt,
+            group_id,
+            String::from("uuid"),
+            upload("cat.png", mime_type),
+            PNG.to_vec(),
+            0,
+            PNG.len() as u128,
+        )
+    }
+
+    fn send_chat(rt: &TestRuntime, principal: Principal, group_id: u128) -> Chat {
+        let chat = Chat {
+            uuid: String::from("uuid"),
+            content: String::from("Look!"),
+            group_id,
+            ..Default::default()
+        };
+        send_from(rt, principal, WebsocketEventMessage::AddChat(chat));
+
+        CHATS.with_borrow(|chats| {
+            chats
+                .range((group_id, u128::MIN)..=(group_id, u128::MAX))
+                .last()
+                .map(|(_, chat)| chat)
+                .unwrap()
+        })
+    }
+
+    fn upload(name: &str, mime_type: &str) -> AttachmentUpload {
+        AttachmentUpload {
+            name: name.to_string(),
+            mime_type: mime_type.to_string(),
+        }
+    }
+
+    #[test]
+    fn limits_depend_on_the_subscription() {
+        let image = upload("cat.png", "image/png");
+        let file = upload("notes.pdf", "application/pdf");
+        let free_size = FREE_MAX_ATTACHMENT_SIZE as u128;
+
+        assert!(validate_upload(false, &image, free_size).is_ok());
+        assert!(matches!(
+            validate_upload(false, &image, free_size + 1),
+            Err(ApiError::SubscriptionRequired)
+        ));
+        assert!(matches!(
+            validate_upload(false, &file, 1),
+            Err(ApiError::SubscriptionRequired)
+        ));
+
+        assert!(validate_upload(true, &file, free_size + 1).is_ok());
+        assert!(matches!(
+            validate_upload(true, &file, SUBSCRIBED_MAX_ATTACHMENT_SIZE as u128 + 1),
+            Err(ApiError::InvalidInput { .. })
+        ));
+        assert!(matches!(
+            validate_upload(true, &upload("run.exe", "application/x-msdownload"), 1),
+            Err(ApiError::InvalidInput { .. })
+        ));
+        assert!(matches!(
+            validate_upload(true, &upload("a/b.pdf", "application/pdf"), 1),
+            Err(ApiError::InvalidInput { .. })
+        ));
+    }
+
+    #[test]
+    fn unfinished_uploads_expire() {
+        let rt = TestRuntime::new();
+        sign_in(&rt, 1, "alice");
+        let group_id = group::create_group(&rt, String::from("Team")).unwrap();
+
+        let image = upload("cat.png", "image/png");
+        upload_chat_attachment(
+            &rt,
+            group_id,
+            String::from("uuid"),
+            image,
+            vec![0; chunk::MB],
+            0,
+            chunk::MB as u128 + 16,
+        )
+        .unwrap();
+        assert_eq!(
+            UPLOAD_BUFFERS.with_borrow(|upload_buffers| upload_buffers.len()),
+            1
+        );
+
+        remove_expired_staged(&rt);
+        assert_eq!(
+            STAGED_ATTACHMENTS.with_borrow(|staged_attachments| staged_attachments.len()),
+            1
+        );
+
+        rt.advance_time(STAGED_ATTACHMENT_TIMEOUT + Duration::from_secs(1));
+        remove_expired_staged(&rt);
+        assert!(STAGED_ATTACHMENTS.with_borrow(|staged_attachments| staged_attachments.is_empty()));
+        assert!(UPLOAD_BUFFERS.with_borrow(|upload_buffers| upload_buffers.is_empty()));
+        assert!(UPLOAD_BUFFER_CHUNKS
+            .with_borrow(|upload_buffer_chunks| upload_buffer_chunks.is_empty()));
+    }
+
+    #[test]
+    fn sent_chats_take_over_their_uploads() {
+        let rt = TestRuntime::new();
+        let alice = sign_in(&rt, 1, "alice");
+        let group_id = group::create_group(&rt, String::from("Team")).unwrap();
+
+        upload_image(&rt, group_id, "image/png").unwrap();
+        let chat = send_chat(&rt, alice, group_id);
+
+        let attachments = chat.attachments.clone().unwrap();
+        assert!(matches!(
+            attachments.as_slice(),
+            [ChatAttachment::Image(file)] if file.name == "cat.png" && file.size == 16
+        ));
+        assert!(STAGED_ATTACHMENTS.with_borrow(|staged_attachments| staged_attachments.is_empty()));
+        assert_eq!(
+            get_chat_attachment_chunk(&rt, group_id, chat.id, 0, 0).unwrap(),
+            PNG.to_vec()
+        );
+        assert!(matches!(
+            get_chat_attachment_chunk(&rt, group_id, chat.id, 1, 0),
+            Err(ApiError::NotFound { .. })
+        ));
+
+        rt.set_caller(sign_in(&rt, 2, "bob"));
+        assert!(matches!(
+            get_chat_attachment_chunk(&rt, group_id, chat.id, 0, 0),
+            Err(ApiError::Forbidden { .. })
+        ));
+    }
+
+    #[test]
+    fn rejects_images_that_are_not_what_they_claim() {
+        let rt = TestRuntime::new();
+        sign_in(&rt, 1, "alice");
+        let group_id = group::create_group(&rt, String::from("Team")).unwrap();
+
+        assert!(matches!(
+            upload_image(&rt, group_id, "image/jpeg"),
+            Err(ApiError::InvalidInput { .. })
+        ));
+        assert!(STAGED_ATTACHMENTS.with_borrow(|staged_attachments| staged_attachments.is_empty()));
+        assert!(BLOBS.with_borrow(|blobs| blobs.is_empty()));
+    }
+
+    #[test]
+    fn uploads_stay_in_their_group() {
+        let rt = TestRuntime::new();
+        let alice = sign_in(&rt, 1, "alice");
+        let group_id = group::create_group(&rt, String::from("Team")).unwrap();
+        let other_group_id = group::create_group(&rt, String::from("Other")).unwrap();
+
+        upload_image(&rt, group_id, "image/png").unwrap();
+        assert!(matches!(
+            upload_image(&rt, other_group_id, "image/png"),
+            Err(ApiError::Conflict { .. })
+        ));
+
+        // a chat with the same uuid in another group leaves the upload alone
+        let chat = send_chat(&rt, alice, other_group_id);
+        assert!(chat.attachments.is_none());
+        assert_eq!(
+            STAGED_ATTACHMENTS.with_borrow(|staged_attachments| staged_attachments.len()),
+            1
+        );
+
+        let chat = send_chat(&rt, alice, group_id);
+        assert_eq!(chat.attachments.unwrap().len(), 1);
+    }
+}
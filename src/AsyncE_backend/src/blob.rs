@@ -0,0 +1,196 @@
+use candid::CandidType;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    chunk,
+    globals::{BLOBS, BLOB_CHUNKS},
+    impl_candid_storable,
+};
+
+/// Hex encoded SHA-256 of the blob content
+pub type BlobId = String;
+
+pub type BlobKey = [u8; 32];
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct BlobMetadata {
+    pub size: u64,
+    pub chunk_count: u32,
+    pub ref_count: u64,
+}
+
+impl_candid_storable!(BlobMetadata);
+
+pub fn blob_key(id: &BlobId) -> Option<BlobKey> {
+    hex::decode(id).ok()?.try_into().ok()
+}
+
+pub fn hash(data: &[u8]) -> BlobKey {
+    Sha256::digest(data).into()
+}
+
+/// Stores `data` and returns its ID, holding one reference to it.
+///
+/// Storing bytes that already exist only bumps the reference count of the existing blob.
+pub fn store(data: &[u8]) -> BlobId {
+    let chunks = data.chunks(chunk::MB).collect::<Vec<_>>();
+
+    store_chunks(
+        hash(data),
+        data.len() as u64,
+        chunks.len() as u32,
+        |index| chunks[index as usize].to_vec(),
+    )
+}
+
+/// Like [`store`], but takes the content one `chunk::MB` sized chunk at a time from
+/// `take_chunk`, so big uploads never have to be on the heap as a whole.
+/// `key` has to be the SHA-256 of the whole content.
+pub fn store_chunks(
+    key: BlobKey,
+    size: u64,
+    chunk_count: u32,
+    mut take_chunk: impl FnMut(u32) -> Vec<u8>,
+) -> BlobId {
+    BLOBS.with_borrow_mut(|blobs| {
+        if let Some(mut metadata) = blobs.get(&key) {
+            metadata.ref_count += 1;
+            blobs.insert(key, metadata);
+            return;
+        }
+
+        BLOB_CHUNKS.with_borrow_mut(|blob_chunks| {
+            for index in 0..chunk_count {
+                blob_chunks.insert((key, index), take_chunk(index));
+            }
+        });
+
+        blobs.insert(
+            key,
+            BlobMetadata {
+                size,
+                chunk_count,
+                ref_count: 1,
+            },
+        );
+    });
+
+    hex::encode(key)
+}
+
+/// Takes another reference to an existing blob, e.g. when it is assigned to a second field.
+pub fn retain(id: &BlobId) {
+    let Some(key) = blob_key(id) else {
+        return;
+    };
+
+    BLOBS.with_borrow_mut(|blobs| {
+        if let Some(mut metadata) = blobs.get(&key) {
+            metadata.ref_count += 1;
+            blobs.insert(key, metadata);
+        }
+    });
+}
+
+/// Drops a reference to the blob, the bytes are deleted once nothing references them anymore.
+pub fn release(id: &BlobId) {
+    let Some(key) = blob_key(id) else {
+        return;
+    };
+
+    BLOBS.with_borrow_mut(|blobs| {
+        let Some(mut metadata) = blobs.get(&key) else {
+            return;
+        };
+
+        metadata.ref_count = metadata.ref_count.saturating_sub(1);
+        if metadata.ref_count > 0 {
+            blobs.insert(key, metadata);
+            return;
+        }
+
+        blobs.remove(&key);
+        BLOB_CHUNKS.with_borrow_mut(|blob_chunks| {
+            for index in 0..metadata.chunk_count {
+                blob_chunks.remove(&(key, index));
+            }
+        });
+    });
+}
+
+/// Replaces the blob referenced by `slot` with `id`, releasing the previous one.
+pub fn replace(slot: &mut Option<BlobId>, id: BlobId) {
+    if let Some(previous) = slot.replace(id) {
+        release(&previous);
+    }
+}
+
+pub fn get_metadata(id: &BlobId) -> Option<BlobMetadata> {
+    BLOBS.with_borrow(|blobs| blobs.get(&blob_key(id)?))
+}
+
+pub fn get_size(id: &BlobId) -> u128 {
+    get_metadata(id).map(|x| x.size as u128).unwrap_or_default()
+}
+
+/// Returns the `index`-th chunk of `chunk::MB` bytes, or an empty chunk past the end.
+pub fn get_chunk(id: &BlobId, index: u128) -> Vec<u8> {
+    let Some(key) = blob_key(id) else {
+        return Vec::new();
+    };
+
+    let Ok(index) = u32::try_from(index) else {
+        return Vec::new();
+    };
+
+    BLOB_CHUNKS
+        .with_borrow(|blob_chunks| blob_chunks.get(&(key, index)))
+        .unwrap_or_default()
+}
+
+pub fn read(id: &BlobId) -> Vec<u8> {
+    let Some(key) = blob_key(id) else {
+        return Vec::new();
+    };
+
+    BLOB_CHUNKS.with_borrow(|blob_chunks| {
+        blob_chunks
+            .range((key, u32::MIN)..=(key, u32::MAX))
+            .flat_map(|(_, chunk)| chunk)
+            .collect()
+    })
+}
+
+/// Reads `length` bytes starting at `offset`, touching only the chunks that overlap the range.
+pub fn read_range(id: &BlobId, offset: u64, length: u64) -> Vec<u8> {
+    let Some(key) = blob_key(id) else {
+        return Vec::new();
+    };
+
+    if length == 0 {
+        return Vec::new();
+    }
+
+    let chunk_size = chunk::MB as u64;
+    let end = offset + length;
+    let first_chunk = (offset / chunk_size) as u32;
+    let last_chunk = (end.saturating_sub(1) / chunk_size) as u32;
+
+    BLOB_CHUNKS.with_borrow(|blob_chunks| {
+        let mut data = Vec::with_capacity(length as usize);
+        for (index, chunk) in blob_chunks
+            .range((key, first_chunk)..=(key, last_chunk))
+            .map(|((_, index), chunk)| (index as u64, chunk))
+        {
+            let chunk_start = index * chunk_size;
+            let from = offset.saturating_sub(chunk_start) as usize;
+            let to = ((end - chunk_start) as usize).min(chunk.len());
+            if from < to {
+                data.extend_from_slice(&chunk[from..to]);
+            }
+        }
+
+        data
+    })
+}
@@ -0,0 +1,164 @@
+//! Response certification for media served by `http_request`, using the `http_assets` layout
+//! of the certified asset canister so boundary nodes can verify the bodies they pass along.
+
+use std::cell::RefCell;
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use ic_certification::{labeled, labeled_hash, AsHashTree, Hash, RbTree};
+use serde::Serialize;
+
+use crate::{
+    blob,
+    globals::MEETINGS,
+    media::{HeaderField, MediaRoute},
+    meeting::{Meeting, MeetingProcessType},
+    runtime::Runtime,
+};
+
+const ASSETS_LABEL: &[u8] = b"http_assets";
+
+/// Maps the URL path of every certified file to the SHA-256 of its content.
+pub type AssetHashes = RbTree<String, Hash>;
+
+thread_local! {
+    // only the root hash is kept by the system, the tree is rebuilt from the meetings after an upgrade
+    static ASSET_HASHES: RefCell<AssetHashes> = RefCell::default();
+}
+
+fn meeting_prefix(group_id: u128, meeting_id: u128) -> String {
+    format!("/media/groups/{}/meetings/{}/", group_id, meeting_id)
+}
+
+/// Files of a meeting that are final enough to certify, the meeting video is left out while it
+/// is still being processed.
+fn meeting_assets(group_id: u128, meeting: &Meeting) -> Vec<(MediaRoute, Hash)> {
+    let meeting_id = meeting.id;
+    let mut assets = Vec::new();
+
+    if let Some(thumbnail) = meeting.thumbnail.as_ref().and_then(blob::blob_key) {
+        assets.push((
+            MediaRoute::MeetingThumbnail {
+                group_id,
+                meeting_id,
+            },
+            thumbnail,
+        ));
+    }
+
+    if meeting.process_type == MeetingProcessType::None {
+        if let Some(full_video) = meeting.full_video.as_ref().and_then(blob::blob_key) {
+            assets.push((
+                MediaRoute::MeetingVideo {
+                    group_id,
+                    meeting_id,
+                },
+                full_video,
+            ));
+        }
+    }
+
+    for (frame_index, frame) in meeting.frames.iter().enumerate() {
+        if let Some(video) = blob::blob_key(&frame.video) {
+            assets.push((
+                MediaRoute::FrameVideo {
+                    group_id,
+                    meeting_id,
+                    frame_index,
+                },
+                video,
+            ));
+        }
+        if let Some(thumbnail) = frame.thumbnail.as_ref().and_then(blob::blob_key) {
+            assets.push((
+                MediaRoute::FrameThumbnail {
+                    group_id,
+                    meeting_id,
+                    frame_index,
+                },
+                thumbnail,
+            ));
+        }
+    }
+
+    assets
+}
+
+fn remove_meeting_assets(asset_hashes: &mut AssetHashes, group_id: u128, meeting_id: u128) {
+    let prefix = meeting_prefix(group_id, meeting_id);
+    let paths = asset_hashes
+        .iter()
+        .filter(|(path, _)| path.starts_with(&prefix))
+        .map(|(path, _)| path.clone())
+        .collect::<Vec<_>>();
+
+    for path in paths {
+        asset_hashes.delete(path.as_bytes());
+    }
+}
+
+fn update_certified_data(rt: &impl Runtime, asset_hashes: &AssetHashes) {
+    let root_hash = labeled_hash(ASSETS_LABEL, &asset_hashes.root_hash());
+    rt.set_certified_data(&root_hash);
+}
+
+/// Re-certifies every file of the meeting, has to be called whenever its blobs are replaced.
+pub fn certify_meeting(rt: &impl Runtime, group_id: u128, meeting: &Meeting) {
+    ASSET_HASHES.with_borrow_mut(|asset_hashes| {
+        remove_meeting_assets(asset_hashes, group_id, meeting.id);
+        for (route, hash) in meeting_assets(group_id, meeting) {
+            asset_hashes.insert(route.path(), hash);
+        }
+
+        update_certified_data(rt, asset_hashes);
+    });
+}
+
+pub fn uncertify_meeting(rt: &impl Runtime, group_id: u128, meeting_id: u128) {
+    ASSET_HASHES.with_borrow_mut(|asset_hashes| {
+        remove_meeting_assets(asset_hashes, group_id, meeting_id);
+        update_certified_data(rt, asset_hashes);
+    });
+}
+
+/// Rebuilds the tree from the stored meetings, the heap is empty after install and upgrade.
+pub fn init_certified_media(rt: &impl Runtime) {
+    let mut asset_hashes = AssetHashes::new();
+    MEETINGS.with_borrow(|meetings| {
+        for ((group_id, _), meeting) in meetings.iter() {
+            for (route, hash) in meeting_assets(group_id, &meeting) {
+                asset_hashes.insert(route.path(), hash);
+            }
+        }
+    });
+
+    update_certified_data(rt, &asset_hashes);
+    ASSET_HASHES.set(asset_hashes);
+}
+
+/// Whether full responses of `path` can be verified, everything else is only served on the raw
+/// domain.
+pub fn is_certified(path: &str) -> bool {
+    ASSET_HASHES.with_borrow(|asset_hashes| asset_hashes.get(path.as_bytes()).is_some())
+}
+
+/// The `IC-Certificate` header for a full response of `path`, the certificate is only
+/// available in query calls so nothing is returned otherwise.
+pub fn certificate_header(rt: &impl Runtime, path: &str) -> Option<HeaderField> {
+    let certificate = rt.data_certificate()?;
+
+    let witness = ASSET_HASHES.with_borrow(|asset_hashes| asset_hashes.witness(path.as_bytes()));
+    let tree = labeled(ASSETS_LABEL, witness);
+
+    let mut serializer = serde_cbor::ser::Serializer::new(Vec::new());
+    serializer.self_describe().ok()?;
+    tree.serialize(&mut serializer).ok()?;
+
+    Some((
+        String::from("IC-Certificate"),
+        format!(
+            "certificate=:{}:, tree=:{}:",
+            BASE64.encode(certificate),
+            BASE64.encode(serializer.into_inner())
+        ),
+    ))
+}
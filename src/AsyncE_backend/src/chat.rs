@@ -1,12 +1,22 @@
+use std::ops::Bound;
+
 use candid::CandidType;
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    globals::{CHATS, GROUPS},
+    attachment::{self, ChatAttachment},
+    error::ApiError,
+    globals::{ChatStore, CHATS, CHAT_HISTORY, CHAT_REPLIES, GROUPS},
+    group::Group,
+    impl_candid_storable,
+    notification::{self, NotificationKind},
+    reaction::Reaction,
+    runtime::Runtime,
+    search::{self, SearchDoc},
     user, websocket,
 };
 
-#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+#[derive(Clone, Debug, Default, CandidType, Serialize, Deserialize)]
 pub struct Chat {
     pub id: u128,
     pub uuid: String,
@@ -14,101 +24,711 @@ pub struct Chat {
     pub group_id: u128,
     pub username: String,
     pub created_time_unix: u128,
+    /// The chat this one replies to, threads are only one level deep
+    pub reply_to: Option<u128>,
+    /// Replies in the thread started by this chat, absent until the first reply
+    pub reply_count: Option<u64>,
+    /// Absent until the first reaction
+    pub reactions: Option<Vec<Reaction>>,
+    /// Members addressed with `@username`, absent when there are none
+    pub mentions: Option<Vec<String>>,
+    /// Last time the content changed, absent for chats that were never edited
+    pub edited_time_unix: Option<u128>,
+    /// Deleted chats stay in place as tombstones without content, until an admin purges them
+    pub deleted_time_unix: Option<u128>,
+    /// Absent for chats without attachments
+    pub attachments: Option<Vec<ChatAttachment>>,
+}
+
+/// The content a chat had before an edit replaced it.
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct ChatRevision {
+    pub content: String,
+    pub edited_by: String,
+    pub edited_time_unix: u128,
+}
+
+impl_candid_storable!(Chat, ChatRevision);
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct ChatThread {
+    pub parent: Chat,
+    /// Oldest first
+    pub replies: Vec<Chat>,
 }
 
-#[ic_cdk::query]
-pub fn get_chats(group_id: u128) -> Result<Vec<Chat>, String> {
-    user::assert_user_logged_in()?;
+/// Pages never hold more chats than this, whatever limit the client asks for.
+pub const MAX_CHAT_PAGE_SIZE: u32 = 100;
 
-    let selfname = user::get_selfname_force()?;
+/// Chat IDs only grow, so they double as positions in the history of a group.
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub enum ChatCursor {
+    /// Chats older than the chat with this ID
+    Before(u128),
+    /// Chats newer than the chat with this ID
+    After(u128),
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct ChatPage {
+    /// Oldest first
+    pub chats: Vec<Chat>,
+    /// Whether there are more chats past this page, in the direction of the cursor
+    pub has_more: bool,
+}
 
-    GROUPS.with_borrow(|groups| {
-        let group = groups
-            .get(&group_id)
-            .ok_or(String::from("Cannot find group with this ID!"))?;
+impl ChatPage {
+    /// Takes up to `limit` chats, peeking at one more to know whether the history goes on.
+    fn take(chats: impl Iterator<Item = Chat>, limit: u32) -> Self {
+        let mut chats = chats.take(limit as usize + 1).collect::<Vec<_>>();
+        let has_more = chats.len() > limit as usize;
+        chats.truncate(limit as usize);
 
-        if !group.is_member(&selfname) {
-            return Err(String::from("This user is not in this group!"));
+        Self { chats, has_more }
+    }
+
+    /// For pages that were read newest first.
+    fn reversed(mut self) -> Self {
+        self.chats.reverse();
+        self
+    }
+}
+
+/// Members of the group the content addresses with `@username`, in the order they appear.
+/// An `@` right after a letter or digit is part of something else, like an email address.
+pub fn find_mentions(group: &Group, content: &str) -> Vec<String> {
+    let mut mentions = Vec::<String>::new();
+    let mut previous = None;
+
+    for (i, c) in content.char_indices() {
+        if c == '@' && !previous.is_some_and(char::is_alphanumeric) {
+            let name = content[i + 1..]
+                .chars()
+                .take_while(|x| x.is_alphanumeric())
+                .collect::<String>();
+            let username = group
+                .members
+                .iter()
+                .map(|member| member.username.as_str())
+                .chain([group.owner.as_str()])
+                .find(|username| username.eq_ignore_ascii_case(&name));
+
+            if let Some(username) = username {
+                if !mentions.iter().any(|x| x == username) {
+                    mentions.push(username.to_string());
+                }
+            }
         }
 
-        CHATS.with_borrow(|chats| {
-            Ok(chats
-                .get(&group_id)
-                .cloned()
-                .unwrap_or_default()
-                .values()
-                .cloned()
-                .collect::<Vec<_>>())
-        })
-    })
+        previous = Some(c);
+    }
+
+    mentions
 }
 
-#[ic_cdk::update]
-pub fn edit_chat(group_id: u128, chat_id: u128, new_content: String) -> Result<(), String> {
-    user::assert_user_logged_in()?;
+/// Sets the mentions of the chat and notifies the members that were not mentioned before.
+pub fn update_mentions(rt: &impl Runtime, group: &Group, chat: &mut Chat, mentioned_by: &str) {
+    let previous = chat.mentions.take().unwrap_or_default();
+    let mentions = find_mentions(group, &chat.content);
 
-    let selfuser =
-        user::get_selfuser()?.ok_or(String::from("This user does not have a username!"))?;
-    if selfuser.subscription.is_none() {
-        return Err(String::from("User must be subscribed to use this feature!"));
+    for username in mentions.iter() {
+        if previous.contains(username) || username.eq_ignore_ascii_case(mentioned_by) {
+            continue;
+        }
+
+        notification::notify_user(
+            rt,
+            username,
+            NotificationKind::Mention {
+                group_id: group.id,
+                chat_id: chat.id,
+                mentioned_by: mentioned_by.to_string(),
+            },
+        );
+    }
+
+    chat.mentions = (!mentions.is_empty()).then_some(mentions);
+}
+
+fn assert_group_member(rt: &impl Runtime, group_id: u128) -> Result<(), ApiError> {
+    user::assert_user_logged_in(rt)?;
+
+    let selfname = user::get_selfname_force(rt)?;
+
+    let group = GROUPS
+        .with_borrow(|groups| groups.get(&group_id))
+        .ok_or(ApiError::not_found("group", group_id))?;
+    if !group.is_member(&selfname) {
+        return Err(ApiError::forbidden("This user is not in this group!"));
     }
 
-    GROUPS.with_borrow(|groups| {
-        let group = groups
-            .get(&group_id)
-            .ok_or(String::from("Cannot find group with this ID!"))?;
+    Ok(())
+}
+
+fn validate_page_limit(limit: u32) -> Result<u32, ApiError> {
+    if limit == 0 {
+        return Err(ApiError::invalid_input("limit", "must be at least 1"));
+    }
 
-        if !group.is_member(&selfuser.username) {
-            return Err(String::from("This user is not in this group!"));
+    Ok(limit.min(MAX_CHAT_PAGE_SIZE))
+}
+
+/// The whole history of the group, prefer [`get_latest_chats`] and [`get_chat_page`].
+pub fn get_chats(rt: &impl Runtime, group_id: u128) -> Result<Vec<Chat>, ApiError> {
+    assert_group_member(rt, group_id)?;
+
+    CHATS.with_borrow(|chats| {
+        Ok(chats
+            .range((group_id, u128::MIN)..=(group_id, u128::MAX))
+            .map(|(_, chat)| chat)
+            .collect::<Vec<_>>())
+    })
+}
+
+/// The newest `limit` chats of the group, clients page further back with [`get_chat_page`].
+pub fn get_latest_chats(
+    rt: &impl Runtime,
+    group_id: u128,
+    limit: u32,
+) -> Result<ChatPage, ApiError> {
+    assert_group_member(rt, group_id)?;
+    let limit = validate_page_limit(limit)?;
+
+    Ok(CHATS.with_borrow(|chats| {
+        let newest_first = chats
+            .range((group_id, u128::MIN)..=(group_id, u128::MAX))
+            .rev()
+            .map(|(_, chat)| chat);
+
+        ChatPage::take(newest_first, limit).reversed()
+    }))
+}
+
+pub fn get_chat_page(
+    rt: &impl Runtime,
+    group_id: u128,
+    cursor: ChatCursor,
+    limit: u32,
+) -> Result<ChatPage, ApiError> {
+    assert_group_member(rt, group_id)?;
+    let limit = validate_page_limit(limit)?;
+
+    Ok(CHATS.with_borrow(|chats| match cursor {
+        ChatCursor::Before(chat_id) => {
+            let newest_first = chats
+                .range((group_id, u128::MIN)..(group_id, chat_id))
+                .rev()
+                .map(|(_, chat)| chat);
+
+            ChatPage::take(newest_first, limit).reversed()
         }
+        ChatCursor::After(chat_id) => {
+            let oldest_first = chats
+                .range((
+                    Bound::Excluded((group_id, chat_id)),
+                    Bound::Included((group_id, u128::MAX)),
+                ))
+                .map(|(_, chat)| chat);
 
-        CHATS.with_borrow_mut(|chats| {
-            let chats = chats
-                .get_mut(&group_id)
-                .ok_or(String::from("Cannot find any chat from this group!"))?;
+            ChatPage::take(oldest_first, limit)
+        }
+    }))
+}
+
+pub fn get_chat_count(rt: &impl Runtime, group_id: u128) -> Result<u64, ApiError> {
+    assert_group_member(rt, group_id)?;
+
+    Ok(CHATS.with_borrow(|chats| {
+        chats
+            .keys_range((group_id, u128::MIN)..=(group_id, u128::MAX))
+            .count() as u64
+    }))
+}
+
+pub fn get_thread(
+    rt: &impl Runtime,
+    group_id: u128,
+    chat_id: u128,
+) -> Result<ChatThread, ApiError> {
+    assert_group_member(rt, group_id)?;
+
+    CHATS.with_borrow(|chats| {
+        let parent = chats
+            .get(&(group_id, chat_id))
+            .ok_or(ApiError::not_found("chat", chat_id))?;
+        let replies = CHAT_REPLIES.with_borrow(|chat_replies| {
+            chat_replies
+                .keys_range((chat_id, u128::MIN)..=(chat_id, u128::MAX))
+                .filter_map(|(_, reply_id)| chats.get(&(group_id, reply_id)))
+                .collect()
+        });
+
+        Ok(ChatThread { parent, replies })
+    })
+}
+
+/// Replies have to stay in the group of their parent, and cannot start threads of their own.
+pub fn validate_reply(group_id: u128, parent_id: u128) -> Result<(), ApiError> {
+    let parent = CHATS
+        .with_borrow(|chats| chats.get(&(group_id, parent_id)))
+        .ok_or(ApiError::not_found("chat", parent_id))?;
+    if parent.deleted_time_unix.is_some() {
+        return Err(ApiError::conflict("Cannot reply to a deleted chat!"));
+    }
+    if parent.reply_to.is_some() {
+        return Err(ApiError::invalid_input(
+            "reply_to",
+            "cannot reply to a reply, reply to the start of the thread instead",
+        ));
+    }
+
+    Ok(())
+}
+
+/// Adds a stored reply to the thread of its parent and returns the new reply count.
+pub fn link_reply(reply: &Chat, parent_id: u128) -> u64 {
+    CHAT_REPLIES.with_borrow_mut(|chat_replies| chat_replies.insert((parent_id, reply.id), ()));
+
+    CHATS.with_borrow_mut(|chats| {
+        update_reply_count(chats, reply.group_id, parent_id, |count| count + 1)
+    })
+}
+
+fn update_reply_count(
+    chats: &mut ChatStore,
+    group_id: u128,
+    parent_id: u128,
+    update: impl FnOnce(u64) -> u64,
+) -> u64 {
+    let Some(mut parent) = chats.get(&(group_id, parent_id)) else {
+        return 0;
+    };
 
-            let chat = chats
-                .get_mut(&chat_id)
-                .ok_or(String::from("Cannot get chat with this ID!"))?;
-            chat.content = new_content;
+    let reply_count = update(parent.reply_count.unwrap_or_default());
+    parent.reply_count = Some(reply_count);
+    chats.insert((group_id, parent_id), parent);
 
-            websocket::broadcast_edit_chat(group_id, chat_id, chat.content.clone());
+    reply_count
+}
+
+/// Keeps the threads consistent once `chat` is gone, returns the IDs of its replies.
+fn unlink_chat(chats: &mut ChatStore, chat: &Chat) -> Vec<u128> {
+    if let Some(parent_id) = chat.reply_to {
+        CHAT_REPLIES.with_borrow_mut(|chat_replies| chat_replies.remove(&(parent_id, chat.id)));
+        update_reply_count(chats, chat.group_id, parent_id, |count| {
+            count.saturating_sub(1)
+        });
+    }
 
-            Ok(())
-        })
+    CHAT_REPLIES.with_borrow_mut(|chat_replies| {
+        let reply_keys = chat_replies
+            .keys_range((chat.id, u128::MIN)..=(chat.id, u128::MAX))
+            .collect::<Vec<_>>();
+        for key in &reply_keys {
+            chat_replies.remove(key);
+        }
+
+        reply_keys
+            .into_iter()
+            .map(|(_, reply_id)| reply_id)
+            .collect()
     })
 }
 
-#[ic_cdk::update]
-pub fn delete_chat(group_id: u128, chat_id: u128) -> Result<(), String> {
-    user::assert_user_logged_in()?;
+/// Appends the content the chat had until now to its edit history.
+fn push_revision(chat_id: u128, revision: ChatRevision) {
+    CHAT_HISTORY.with_borrow_mut(|chat_history| {
+        let index = chat_history
+            .keys_range((chat_id, u32::MIN)..=(chat_id, u32::MAX))
+            .count() as u32;
+        chat_history.insert((chat_id, index), revision);
+    });
+}
+
+/// Returns the username of the caller, the group and the chat, as long as the caller
+/// wrote the chat or is an admin of the group, and the chat was not deleted.
+fn get_changeable_chat(
+    rt: &impl Runtime,
+    group_id: u128,
+    chat_id: u128,
+) -> Result<(String, Group, Chat), ApiError> {
+    user::assert_user_logged_in(rt)?;
 
-    let selfuser =
-        user::get_selfuser()?.ok_or(String::from("This user does not have a username!"))?;
+    let selfuser = user::get_selfuser(rt)?.ok_or(ApiError::NoUsername)?;
     if selfuser.subscription.is_none() {
-        return Err(String::from("User must be subscribed to use this feature!"));
+        return Err(ApiError::SubscriptionRequired);
     }
 
-    GROUPS.with_borrow(|groups| {
-        let group = groups
-            .get(&group_id)
-            .ok_or(String::from("Cannot find group with this ID!"))?;
+    let group = GROUPS
+        .with_borrow(|groups| groups.get(&group_id))
+        .ok_or(ApiError::not_found("group", group_id))?;
+    if !group.is_member(&selfuser.username) {
+        return Err(ApiError::forbidden("This user is not in this group!"));
+    }
+
+    let chat = CHATS
+        .with_borrow(|chats| chats.get(&(group_id, chat_id)))
+        .ok_or(ApiError::not_found("chat", chat_id))?;
+    if !chat.username.eq_ignore_ascii_case(&selfuser.username)
+        && !group.is_admin(&selfuser.username)
+    {
+        return Err(ApiError::forbidden(
+            "Only an admin or the author can change a chat!",
+        ));
+    }
+    if chat.deleted_time_unix.is_some() {
+        return Err(ApiError::conflict("This chat was deleted!"));
+    }
+
+    Ok((selfuser.username, group, chat))
+}
+
+pub fn edit_chat(
+    rt: &impl Runtime,
+    group_id: u128,
+    chat_id: u128,
+    new_content: String,
+) -> Result<(), ApiError> {
+    let (selfname, group, mut chat) = get_changeable_chat(rt, group_id, chat_id)?;
+
+    push_revision(
+        chat_id,
+        ChatRevision {
+            content: chat.content.clone(),
+            edited_by: selfname.clone(),
+            edited_time_unix: rt.time(),
+        },
+    );
+
+    let doc = SearchDoc::Chat { chat_id };
+    search::unindex(group_id, doc, &chat.content);
+    search::index(group_id, doc, &new_content);
+    chat.content = new_content;
+    chat.edited_time_unix = Some(rt.time());
+    update_mentions(rt, &group, &mut chat, &selfname);
+
+    websocket::broadcast_edit_chat(rt, &group, chat_id, chat.content.clone());
+    CHATS.with_borrow_mut(|chats| chats.insert((group_id, chat_id), chat));
+
+    Ok(())
+}
+
+/// Leaves a tombstone behind, so threads and the history of the group keep their shape.
+/// The last content stays in the edit history for admins, under the name of the author.
+pub fn delete_chat(rt: &impl Runtime, group_id: u128, chat_id: u128) -> Result<(), ApiError> {
+    let (_, group, mut chat) = get_changeable_chat(rt, group_id, chat_id)?;
+
+    push_revision(
+        chat_id,
+        ChatRevision {
+            content: chat.content.clone(),
+            edited_by: chat.username.clone(),
+            edited_time_unix: rt.time(),
+        },
+    );
+    search::unindex(group_id, SearchDoc::Chat { chat_id }, &chat.content);
+    chat.content = String::new();
+    notification::remove_mentions(group_id, chat_id, &chat.mentions.take().unwrap_or_default());
+    chat.reactions = None;
+    attachment::release(&chat.attachments.take().unwrap_or_default());
+    chat.deleted_time_unix = Some(rt.time());
+
+    CHATS.with_borrow_mut(|chats| chats.insert((group_id, chat_id), chat));
+    websocket::broadcast_delete_chat(rt, &group, chat_id);
+
+    Ok(())
+}
+
+/// Removes the chat and its edit history for good, deleted or not, together with its replies.
+pub fn purge_chat(rt: &impl Runtime, group_id: u128, chat_id: u128) -> Result<(), ApiError> {
+    user::assert_user_logged_in(rt)?;
+
+    let selfname = user::get_selfname_force(rt)?;
 
-        if !group.is_member(&selfuser.username) {
-            return Err(String::from("This user is not in this group!"));
+    let group = GROUPS
+        .with_borrow(|groups| groups.get(&group_id))
+        .ok_or(ApiError::not_found("group", group_id))?;
+    if !group.is_admin(&selfname) {
+        return Err(ApiError::forbidden("Only an admin can purge a chat!"));
+    }
+
+    let purged = CHATS.with_borrow_mut(|chats| {
+        let chat = chats
+            .remove(&(group_id, chat_id))
+            .ok_or(ApiError::not_found("chat", chat_id))?;
+        // threads are one level deep, so replies have no replies of their own
+        let replies = unlink_chat(chats, &chat)
+            .into_iter()
+            .filter_map(|reply_id| chats.remove(&(group_id, reply_id)))
+            .collect::<Vec<_>>();
+
+        Ok::<_, ApiError>([vec![chat], replies].concat())
+    })?;
+
+    for chat in purged {
+        search::unindex(
+            group_id,
+            SearchDoc::Chat { chat_id: chat.id },
+            &chat.content,
+        );
+        attachment::release(&chat.attachments.unwrap_or_default());
+        notification::remove_mentions(group_id, chat.id, &chat.mentions.unwrap_or_default());
+        CHAT_HISTORY.with_borrow_mut(|chat_history| {
+            let revision_keys = chat_history
+                .keys_range((chat.id, u32::MIN)..=(chat.id, u32::MAX))
+                .collect::<Vec<_>>();
+            for key in revision_keys {
+                chat_history.remove(&key);
+            }
+        });
+        websocket::broadcast_purge_chat(rt, &group, chat.id);
+    }
+
+    Ok(())
+}
+
+/// Earlier contents of the chat, oldest first. Once a chat is deleted only admins see them.
+pub fn get_chat_history(
+    rt: &impl Runtime,
+    group_id: u128,
+    chat_id: u128,
+) -> Result<Vec<ChatRevision>, ApiError> {
+    assert_group_member(rt, group_id)?;
+
+    let chat = CHATS
+        .with_borrow(|chats| chats.get(&(group_id, chat_id)))
+        .ok_or(ApiError::not_found("chat", chat_id))?;
+    if chat.deleted_time_unix.is_some() {
+        let selfname = user::get_selfname_force(rt)?;
+        let is_admin = GROUPS.with_borrow(|groups| {
+            groups
+                .get(&group_id)
+                .is_some_and(|group| group.is_admin(&selfname))
+        });
+        if !is_admin {
+            return Err(ApiError::forbidden(
+                "Only an admin can see the history of a deleted chat!",
+            ));
         }
+    }
+
+    Ok(CHAT_HISTORY.with_borrow(|chat_history| {
+        chat_history
+            .range((chat_id, u32::MIN)..=(chat_id, u32::MAX))
+            .map(|(_, revision)| revision)
+            .collect()
+    }))
+}
+
+mod endpoints {
+    use super::*;
+    use crate::runtime::IcRuntime;
+
+    #[ic_cdk::query]
+    fn get_chats(group_id: u128) -> Result<Vec<Chat>, ApiError> {
+        super::get_chats(&IcRuntime, group_id)
+    }
+
+    #[ic_cdk::query]
+    fn get_latest_chats(group_id: u128, limit: u32) -> Result<ChatPage, ApiError> {
+        super::get_latest_chats(&IcRuntime, group_id, limit)
+    }
 
+    #[ic_cdk::query]
+    fn get_chat_page(group_id: u128, cursor: ChatCursor, limit: u32) -> Result<ChatPage, ApiError> {
+        super::get_chat_page(&IcRuntime, group_id, cursor, limit)
+    }
+
+    #[ic_cdk::query]
+    fn get_chat_count(group_id: u128) -> Result<u64, ApiError> {
+        super::get_chat_count(&IcRuntime, group_id)
+    }
+
+    #[ic_cdk::query]
+    fn get_thread(group_id: u128, chat_id: u128) -> Result<ChatThread, ApiError> {
+        super::get_thread(&IcRuntime, group_id, chat_id)
+    }
+
+    #[ic_cdk::update]
+    fn edit_chat(group_id: u128, chat_id: u128, new_content: String) -> Result<(), ApiError> {
+        super::edit_chat(&IcRuntime, group_id, chat_id, new_content)
+    }
+
+    #[ic_cdk::update]
+    fn delete_chat(group_id: u128, chat_id: u128) -> Result<(), ApiError> {
+        super::delete_chat(&IcRuntime, group_id, chat_id)
+    }
+
+    #[ic_cdk::update]
+    fn purge_chat(group_id: u128, chat_id: u128) -> Result<(), ApiError> {
+        super::purge_chat(&IcRuntime, group_id, chat_id)
+    }
+
+    #[ic_cdk::query]
+    fn get_chat_history(group_id: u128, chat_id: u128) -> Result<Vec<ChatRevision>, ApiError> {
+        super::get_chat_history(&IcRuntime, group_id, chat_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        group::{self, GroupMember, GroupMemberRole},
+        runtime::TestRuntime,
+        user::{self, tests::sign_in},
+    };
+
+    fn add_chats(group_id: u128, ids: impl IntoIterator<Item = u128>) {
+        CHATS.with_borrow_mut(|chats| {
+            for id in ids {
+                let chat = Chat {
+                    id,
+                    uuid: id.to_string(),
+                    content: format!("chat {}", id),
+                    group_id,
+                    username: String::from("alice"),
+                    ..Default::default()
+                };
+                chats.insert((group_id, id), chat);
+            }
+        });
+    }
+
+    fn ids(page: &ChatPage) -> Vec<u128> {
+        page.chats.iter().map(|chat| chat.id).collect()
+    }
+
+    #[test]
+    fn pages_through_the_history() {
+        let rt = TestRuntime::new();
+        sign_in(&rt, 1, "alice");
+        let group_id = group::create_group(&rt, String::from("Team")).unwrap();
+        let other_group_id = group::create_group(&rt, String::from("Other")).unwrap();
+        add_chats(group_id, 1..=5);
+        add_chats(other_group_id, [6]);
+        add_chats(group_id, [7]);
+
+        let latest = get_latest_chats(&rt, group_id, 3).unwrap();
+        assert_eq!(ids(&latest), vec![4, 5, 7]);
+        assert!(latest.has_more);
+
+        let older = get_chat_page(&rt, group_id, ChatCursor::Before(4), 3).unwrap();
+        assert_eq!(ids(&older), vec![1, 2, 3]);
+        assert!(!older.has_more);
+
+        let newer = get_chat_page(&rt, group_id, ChatCursor::After(2), 2).unwrap();
+        assert_eq!(ids(&newer), vec![3, 4]);
+        assert!(newer.has_more);
+        let newest = get_chat_page(&rt, group_id, ChatCursor::After(5), 2).unwrap();
+        assert_eq!(ids(&newest), vec![7]);
+        assert!(!newest.has_more);
+
+        assert_eq!(get_chat_count(&rt, group_id), Ok(6));
+        assert!(matches!(
+            get_latest_chats(&rt, group_id, 0),
+            Err(ApiError::InvalidInput { .. })
+        ));
+
+        sign_in(&rt, 2, "bob");
+        assert!(matches!(
+            get_chat_count(&rt, group_id),
+            Err(ApiError::Forbidden { .. })
+        ));
+    }
+
+    #[test]
+    fn only_authors_and_admins_change_chats() {
+        let rt = TestRuntime::new();
+        let bob = sign_in(&rt, 2, "bob");
+        user::buy_subscription(&rt).unwrap();
+        let alice = sign_in(&rt, 1, "alice");
+        user::buy_subscription(&rt).unwrap();
+        let group_id = group::create_group(&rt, String::from("Team")).unwrap();
+        GROUPS.with_borrow_mut(|groups| {
+            let mut group = groups.get(&group_id).unwrap();
+            group
+                .members
+                .push(GroupMember::new("bob", GroupMemberRole::Member));
+            groups.insert(group_id, group);
+        });
+        add_chats(group_id, 1..=2);
         CHATS.with_borrow_mut(|chats| {
-            let chats = chats
-                .get_mut(&group_id)
-                .ok_or(String::from("Cannot find any chat from this group!"))?;
+            let mut chat = chats.get(&(group_id, 2)).unwrap();
+            chat.username = String::from("bob");
+            chats.insert((group_id, 2), chat);
+        });
 
-            chats
-                .remove(&chat_id)
-                .ok_or(String::from("Cannot get chat with this ID!"))?;
-            websocket::broadcast_delete_chat(group_id, chat_id);
+        rt.set_caller(bob);
+        assert!(matches!(
+            edit_chat(&rt, group_id, 1, String::from("mine now")),
+            Err(ApiError::Forbidden { .. })
+        ));
+        edit_chat(&rt, group_id, 2, String::from("second")).unwrap();
+        edit_chat(&rt, group_id, 2, String::from("third")).unwrap();
+        let history = get_chat_history(&rt, group_id, 2)
+            .unwrap()
+            .into_iter()
+            .map(|revision| revision.content)
+            .collect::<Vec<_>>();
+        assert_eq!(history, vec!["chat 2", "second"]);
 
-            Ok(())
-        })
-    })
+        // admins moderate every chat, deleted ones stay as tombstones
+        rt.set_caller(alice);
+        delete_chat(&rt, group_id, 2).unwrap();
+        let tombstone = CHATS.with_borrow(|chats| chats.get(&(group_id, 2)).unwrap());
+        assert!(tombstone.content.is_empty());
+        assert!(tombstone.edited_time_unix.is_some());
+        assert!(tombstone.deleted_time_unix.is_some());
+        assert!(matches!(
+            edit_chat(&rt, group_id, 2, String::from("back")),
+            Err(ApiError::Conflict { .. })
+        ));
+        let last_revision = get_chat_history(&rt, group_id, 2).unwrap().pop().unwrap();
+        assert_eq!(last_revision.content, "third");
+        assert_eq!(last_revision.edited_by, "bob");
+
+        rt.set_caller(bob);
+        assert!(matches!(
+            get_chat_history(&rt, group_id, 2),
+            Err(ApiError::Forbidden { .. })
+        ));
+        assert!(matches!(
+            purge_chat(&rt, group_id, 2),
+            Err(ApiError::Forbidden { .. })
+        ));
+
+        rt.set_caller(alice);
+        purge_chat(&rt, group_id, 2).unwrap();
+        assert_eq!(get_chat_count(&rt, group_id).unwrap(), 1);
+        assert!(CHAT_HISTORY.with_borrow(|chat_history| chat_history.is_empty()));
+    }
+
+    #[test]
+    fn purges_a_thread_with_its_replies() {
+        let rt = TestRuntime::new();
+        sign_in(&rt, 1, "alice");
+        user::buy_subscription(&rt).unwrap();
+        let group_id = group::create_group(&rt, String::from("Team")).unwrap();
+        add_chats(group_id, 1..=4);
+        for reply_id in [2, 3] {
+            let reply = CHATS.with_borrow_mut(|chats| {
+                let mut reply = chats.get(&(group_id, reply_id)).unwrap();
+                reply.reply_to = Some(1);
+                chats.insert((group_id, reply_id), reply.clone());
+                reply
+            });
+            link_reply(&reply, 1);
+        }
+        edit_chat(&rt, group_id, 3, String::from("edited reply")).unwrap();
+        delete_chat(&rt, group_id, 1).unwrap();
+
+        purge_chat(&rt, group_id, 1).unwrap();
+        assert_eq!(
+            CHATS.with_borrow(|chats| chats.keys().collect::<Vec<_>>()),
+            vec![(group_id, 4)]
+        );
+        assert!(CHAT_REPLIES.with_borrow(|chat_replies| chat_replies.is_empty()));
+        assert!(CHAT_HISTORY.with_borrow(|chat_history| chat_history.is_empty()));
+    }
 }
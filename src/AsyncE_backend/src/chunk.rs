@@ -1 +1,293 @@
+use std::{collections::BTreeSet, time::Duration};
+
+use candid::CandidType;
+use serde::Deserialize;
+
+use crate::{
+    blob,
+    error::ApiError,
+    globals::{UPLOAD_BUFFERS, UPLOAD_BUFFER_CHUNKS},
+    impl_candid_storable,
+    runtime::{IcRuntime, Runtime},
+};
+
 pub const MB: usize = (1024.0 * 1024.0 * 1.8) as usize;
+
+/// Uploads that received no chunk for this long are dropped.
+pub const UPLOAD_BUFFER_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+
+/// An upload that is still being sent, its chunks are kept in `UPLOAD_BUFFER_CHUNKS`
+/// until the last one arrives.
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct UploadBuffer {
+    pub total_data_length: u64,
+    pub chunk_count: u32,
+    pub received_chunks: BTreeSet<u32>,
+    /// Absent for buffers from before they expired, those are dropped by the next cleanup
+    pub last_activity_time_unix: Option<u128>,
+}
+
+impl_candid_storable!(UploadBuffer);
+
+/// Every chunk is exactly `MB` bytes, except for the last one which holds the rest.
+pub fn expected_chunk_size(total_size: u64, chunk_count: u32, chunk_index: u32) -> u64 {
+    if chunk_index + 1 == chunk_count {
+        total_size - (chunk_count as u64 - 1) * MB as u64
+    } else {
+        MB as u64
+    }
+}
+
+/// Chunks are keyed by the hash of the buffer key, since stable keys have to be bounded.
+fn chunk_key(key: &str, chunk_index: u32) -> ([u8; 32], u32) {
+    (blob::hash(key.as_bytes()), chunk_index)
+}
+
+/// Buffers an upload of at most `max_total_data_length` bytes that is sent in `MB` sized chunks
+/// under `key`, returns the whole data once every chunk of the `total_data_length` bytes has
+/// arrived.
+///
+/// The first chunk always starts the upload over, so an abandoned upload never blocks a new one.
+pub fn buffer_upload_chunk(
+    rt: &impl Runtime,
+    key: String,
+    chunk_data: Vec<u8>,
+    chunk_index: u128,
+    total_data_length: u128,
+    max_total_data_length: u64,
+) -> Result<Option<Vec<u8>>, ApiError> {
+    if total_data_length == 0 || total_data_length > max_total_data_length as u128 {
+        return Err(ApiError::invalid_input(
+            "total_data_length",
+            &format!("must be between 1 and {} bytes", max_total_data_length),
+        ));
+    }
+    let chunk_count = total_data_length.div_ceil(MB as u128) as u32;
+
+    if chunk_index == 0 {
+        remove_buffer(&key);
+    }
+    let mut buffer = UPLOAD_BUFFERS
+        .with_borrow(|upload_buffers| upload_buffers.get(&key))
+        .unwrap_or(UploadBuffer {
+            total_data_length: total_data_length as u64,
+            chunk_count,
+            received_chunks: BTreeSet::new(),
+            last_activity_time_unix: None,
+        });
+    buffer.last_activity_time_unix = Some(rt.time());
+
+    if buffer.total_data_length as u128 != total_data_length {
+        return Err(ApiError::invalid_input(
+            "total_data_length",
+            &format!(
+                "must stay {} bytes for every chunk of the upload",
+                buffer.total_data_length
+            ),
+        ));
+    }
+
+    if chunk_index >= buffer.chunk_count as u128 {
+        return Err(ApiError::invalid_input(
+            "chunk_index",
+            &format!("must be less than {}", buffer.chunk_count),
+        ));
+    }
+    let chunk_index = chunk_index as u32;
+
+    if buffer.received_chunks.contains(&chunk_index) {
+        return Err(ApiError::conflict("This chunk has already been received!"));
+    }
+
+    let expected_size =
+        expected_chunk_size(buffer.total_data_length, buffer.chunk_count, chunk_index);
+    if chunk_data.len() as u64 != expected_size {
+        return Err(ApiError::invalid_input(
+            "chunk_data",
+            &format!(
+                "chunk {} must be exactly {} bytes, got {}",
+                chunk_index,
+                expected_size,
+                chunk_data.len()
+            ),
+        ));
+    }
+
+    UPLOAD_BUFFER_CHUNKS.with_borrow_mut(|upload_buffer_chunks| {
+        upload_buffer_chunks.insert(chunk_key(&key, chunk_index), chunk_data)
+    });
+    buffer.received_chunks.insert(chunk_index);
+
+    if buffer.received_chunks.len() < buffer.chunk_count as usize {
+        UPLOAD_BUFFERS.with_borrow_mut(|upload_buffers| upload_buffers.insert(key, buffer));
+        return Ok(None);
+    }
+
+    let mut data = Vec::with_capacity(buffer.total_data_length as usize);
+    UPLOAD_BUFFER_CHUNKS.with_borrow_mut(|upload_buffer_chunks| {
+        for index in 0..buffer.chunk_count {
+            if let Some(chunk) = upload_buffer_chunks.remove(&chunk_key(&key, index)) {
+                data.extend(chunk);
+            }
+        }
+    });
+    UPLOAD_BUFFERS.with_borrow_mut(|upload_buffers| upload_buffers.remove(&key));
+
+    Ok(Some(data))
+}
+
+/// Drops the uploads that received no chunk for [`UPLOAD_BUFFER_TIMEOUT`].
+pub fn remove_expired_buffers(rt: &impl Runtime) {
+    let now = rt.time();
+    let expired_keys = UPLOAD_BUFFERS.with_borrow(|upload_buffers| {
+        upload_buffers
+            .iter()
+            .filter(|(_, buffer)| {
+                buffer.last_activity_time_unix.is_none_or(|last_activity| {
+                    now.saturating_sub(last_activity) > UPLOAD_BUFFER_TIMEOUT.as_nanos()
+                })
+            })
+            .map(|(key, _)| key)
+            .collect::<Vec<_>>()
+    });
+
+    for key in expired_keys {
+        remove_buffer(&key);
+    }
+}
+
+pub fn poll_expired_upload_buffers() {
+    ic_cdk::println!("Starting poll expired upload buffers");
+    ic_cdk_timers::set_timer_interval(Duration::from_secs(60), || {
+        remove_expired_buffers(&IcRuntime)
+    });
+}
+
+/// Drops an unfinished upload together with the chunks it has received so far.
+pub fn remove_buffer(key: &str) {
+    let Some(buffer) =
+        UPLOAD_BUFFERS.with_borrow_mut(|upload_buffers| upload_buffers.remove(&key.to_string()))
+    else {
+        return;
+    };
+
+    UPLOAD_BUFFER_CHUNKS.with_borrow_mut(|upload_buffer_chunks| {
+        for index in buffer.received_chunks {
+            upload_buffer_chunks.remove(&chunk_key(key, index));
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::TestRuntime;
+
+    const MAX_SIZE: u64 = 4 * MB as u64;
+
+    fn buffer_sized(
+        rt: &TestRuntime,
+        chunk_data: Vec<u8>,
+        chunk_index: u128,
+        total_data_length: u128,
+    ) -> Result<Option<Vec<u8>>, ApiError> {
+        buffer_upload_chunk(
+            rt,
+            String::from("key"),
+            chunk_data,
+            chunk_index,
+            total_data_length,
+            MAX_SIZE,
+        )
+    }
+
+    fn buffer(
+        rt: &TestRuntime,
+        chunk_data: Vec<u8>,
+        chunk_index: u128,
+    ) -> Result<Option<Vec<u8>>, ApiError> {
+        buffer_sized(rt, chunk_data, chunk_index, MB as u128 + 2)
+    }
+
+    #[test]
+    fn assembles_chunks_after_the_first_in_any_order() {
+        let rt = TestRuntime::new();
+        let total = 2 * MB as u128 + 2;
+        assert_eq!(buffer_sized(&rt, vec![1; MB], 0, total).unwrap(), None);
+        assert_eq!(buffer_sized(&rt, vec![3, 3], 2, total).unwrap(), None);
+        assert_eq!(
+            buffer_sized(&rt, vec![2; MB], 1, total)
+                .unwrap()
+                .unwrap()
+                .len(),
+            2 * MB + 2
+        );
+
+        assert!(UPLOAD_BUFFERS.with_borrow(|upload_buffers| upload_buffers.is_empty()));
+        assert!(UPLOAD_BUFFER_CHUNKS
+            .with_borrow(|upload_buffer_chunks| upload_buffer_chunks.is_empty()));
+    }
+
+    #[test]
+    fn rejects_invalid_chunks() {
+        let rt = TestRuntime::new();
+        assert!(matches!(
+            buffer(&rt, vec![2, 3], 2),
+            Err(ApiError::InvalidInput { .. })
+        ));
+        assert!(matches!(
+            buffer(&rt, vec![2, 3, 4], 1),
+            Err(ApiError::InvalidInput { .. })
+        ));
+        assert!(matches!(
+            buffer(&rt, vec![1; MB - 1], 0),
+            Err(ApiError::InvalidInput { .. })
+        ));
+        assert!(matches!(
+            buffer_sized(&rt, vec![1; MB], 0, MAX_SIZE as u128 + 1),
+            Err(ApiError::InvalidInput { .. })
+        ));
+
+        assert_eq!(buffer(&rt, vec![2, 3], 1).unwrap(), None);
+        assert!(matches!(
+            buffer(&rt, vec![2, 3], 1),
+            Err(ApiError::Conflict { .. })
+        ));
+        assert!(matches!(
+            buffer_sized(&rt, vec![2, 3, 4], 1, MB as u128 + 3),
+            Err(ApiError::InvalidInput { .. })
+        ));
+
+        remove_buffer("key");
+        assert!(UPLOAD_BUFFERS.with_borrow(|upload_buffers| upload_buffers.is_empty()));
+        assert!(UPLOAD_BUFFER_CHUNKS
+            .with_borrow(|upload_buffer_chunks| upload_buffer_chunks.is_empty()));
+    }
+
+    #[test]
+    fn abandoned_uploads_are_started_over_or_expire() {
+        let rt = TestRuntime::new();
+        assert_eq!(buffer(&rt, vec![1; MB], 0).unwrap(), None);
+
+        // a new first chunk replaces the upload, whatever its size
+        assert_eq!(
+            buffer_sized(&rt, vec![1, 2], 0, 2).unwrap(),
+            Some(vec![1, 2])
+        );
+        assert!(UPLOAD_BUFFER_CHUNKS
+            .with_borrow(|upload_buffer_chunks| upload_buffer_chunks.is_empty()));
+
+        assert_eq!(buffer(&rt, vec![1; MB], 0).unwrap(), None);
+        remove_expired_buffers(&rt);
+        assert_eq!(
+            UPLOAD_BUFFERS.with_borrow(|upload_buffers| upload_buffers.len()),
+            1
+        );
+
+        rt.advance_time(UPLOAD_BUFFER_TIMEOUT + Duration::from_secs(1));
+        remove_expired_buffers(&rt);
+        assert!(UPLOAD_BUFFERS.with_borrow(|upload_buffers| upload_buffers.is_empty()));
+        assert!(UPLOAD_BUFFER_CHUNKS
+            .with_borrow(|upload_buffer_chunks| upload_buffer_chunks.is_empty()));
+    }
+}
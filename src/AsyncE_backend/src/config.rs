@@ -0,0 +1,215 @@
+use candid::CandidType;
+use serde::Deserialize;
+
+use crate::{
+    error::ApiError, globals::CONFIG, impl_candid_storable, processor::ProcessorKind,
+    runtime::Runtime,
+};
+
+/// Replicas refuse HTTP outcalls with a bigger response than this
+pub const MAX_RESPONSE_BYTES_LIMIT: u64 = 2_000_000;
+
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq, Eq)]
+pub struct Config {
+    /// Base URL of the video editor service, without a trailing slash
+    pub processor_base_url: String,
+    /// Cycles attached to every HTTP outcall to the video editor service
+    pub processor_cycles_per_request: u128,
+    pub processor_max_response_bytes: Option<u64>,
+    /// Optional since configs saved before the processor was selectable do not have it
+    pub processor: Option<ProcessorKind>,
+}
+
+impl_candid_storable!(Config);
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            processor_base_url: String::from("http://localhost:17191"),
+            processor_cycles_per_request: 1_000_000_000_000,
+            processor_max_response_bytes: None,
+            processor: None,
+        }
+    }
+}
+
+/// Every field is optional so deployments only have to pass the settings they change.
+#[derive(Clone, Debug, Default, CandidType, Deserialize)]
+pub struct ConfigUpdate {
+    pub processor_base_url: Option<String>,
+    pub processor_cycles_per_request: Option<u128>,
+    /// `Some(None)` goes back to the limit of the replica
+    pub processor_max_response_bytes: Option<Option<u64>>,
+    pub processor: Option<ProcessorKind>,
+}
+
+/// Passed to `init` and `post_upgrade`, upgrading without arguments keeps the stored config.
+#[derive(Clone, Debug, Default, CandidType, Deserialize)]
+pub struct InitArgs {
+    pub config: Option<ConfigUpdate>,
+}
+
+impl Config {
+    pub fn processor(&self) -> ProcessorKind {
+        self.processor.unwrap_or_default()
+    }
+
+    pub fn apply(&mut self, update: ConfigUpdate) -> Result<(), ApiError> {
+        if let Some(processor_base_url) = update.processor_base_url {
+            let processor_base_url = processor_base_url.trim().trim_end_matches('/');
+            if !processor_base_url.starts_with("http://")
+                && !processor_base_url.starts_with("https://")
+            {
+                return Err(ApiError::invalid_input(
+                    "processor_base_url",
+                    "must start with http:// or https://",
+                ));
+            }
+
+            self.processor_base_url = processor_base_url.to_string();
+        }
+
+        if let Some(processor_cycles_per_request) = update.processor_cycles_per_request {
+            self.processor_cycles_per_request = processor_cycles_per_request;
+        }
+
+        if let Some(processor_max_response_bytes) = update.processor_max_response_bytes {
+            if processor_max_response_bytes.is_some_and(|x| x > MAX_RESPONSE_BYTES_LIMIT) {
+                return Err(ApiError::invalid_input(
+                    "processor_max_response_bytes",
+                    &format!("cannot be more than {}", MAX_RESPONSE_BYTES_LIMIT),
+                ));
+            }
+
+            self.processor_max_response_bytes = processor_max_response_bytes;
+        }
+
+        if let Some(processor) = update.processor {
+            self.processor = Some(processor);
+        }
+
+        Ok(())
+    }
+}
+
+pub fn get_config() -> Config {
+    CONFIG.with_borrow(|config| config.get().clone())
+}
+
+fn update_config_internal(update: ConfigUpdate) -> Result<(), ApiError> {
+    let mut config = get_config();
+    config.apply(update)?;
+
+    CONFIG
+        .with_borrow_mut(|store| store.set(config))
+        .expect("FAILED TO SAVE CONFIG!");
+
+    Ok(())
+}
+
+/// Applies the config passed to `init` or `post_upgrade`, an invalid config fails the deployment.
+pub fn init_config(args: Option<InitArgs>) {
+    if let Some(update) = args.and_then(|args| args.config) {
+        update_config_internal(update).unwrap_or_else(|err| ic_cdk::trap(&err.to_string()));
+    }
+}
+
+fn assert_controller(rt: &impl Runtime) -> Result<(), ApiError> {
+    if !rt.is_controller(&rt.caller()) {
+        return Err(ApiError::forbidden(
+            "Only controllers can manage the canister config!",
+        ));
+    }
+
+    Ok(())
+}
+
+pub fn get_config_settings(rt: &impl Runtime) -> Result<Config, ApiError> {
+    assert_controller(rt)?;
+
+    Ok(get_config())
+}
+
+pub fn update_config(rt: &impl Runtime, update: ConfigUpdate) -> Result<(), ApiError> {
+    assert_controller(rt)?;
+
+    update_config_internal(update)
+}
+
+mod endpoints {
+    use super::*;
+    use crate::runtime::IcRuntime;
+
+    #[ic_cdk::query]
+    fn get_config_settings() -> Result<Config, ApiError> {
+        super::get_config_settings(&IcRuntime)
+    }
+
+    #[ic_cdk::update]
+    fn update_config(update: ConfigUpdate) -> Result<(), ApiError> {
+        super::update_config(&IcRuntime, update)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn applies_partial_updates() {
+        let mut config = Config::default();
+        config
+            .apply(ConfigUpdate {
+                processor_base_url: Some(String::from("https://editor.example.com/")),
+                ..Default::default()
+            })
+            .unwrap();
+
+        assert_eq!(config.processor_base_url, "https://editor.example.com");
+        assert_eq!(
+            config.processor_cycles_per_request,
+            Config::default().processor_cycles_per_request
+        );
+    }
+
+    #[test]
+    fn resets_the_response_limit() {
+        let mut config = Config::default();
+        let limit = |processor_max_response_bytes| ConfigUpdate {
+            processor_max_response_bytes,
+            ..Default::default()
+        };
+
+        config.apply(limit(Some(Some(1_000)))).unwrap();
+        config.apply(limit(None)).unwrap();
+        assert_eq!(config.processor_max_response_bytes, Some(1_000));
+
+        config.apply(limit(Some(None))).unwrap();
+        assert_eq!(config, Config::default());
+
+        // an absent field and an explicit reset are told apart on the wire
+        let update =
+            candid::decode_one::<ConfigUpdate>(&candid::encode_one(limit(Some(None))).unwrap())
+                .unwrap();
+        assert_eq!(update.processor_max_response_bytes, Some(None));
+    }
+
+    #[test]
+    fn rejects_invalid_updates() {
+        let mut config = Config::default();
+
+        assert!(config
+            .apply(ConfigUpdate {
+                processor_base_url: Some(String::from("editor.example.com")),
+                ..Default::default()
+            })
+            .is_err());
+        assert!(config
+            .apply(ConfigUpdate {
+                processor_max_response_bytes: Some(Some(MAX_RESPONSE_BYTES_LIMIT + 1)),
+                ..Default::default()
+            })
+            .is_err());
+        assert_eq!(config, Config::default());
+    }
+}
@@ -0,0 +1,90 @@
+use std::fmt::{self, Display};
+
+use candid::CandidType;
+use serde::{Deserialize, Serialize};
+
+/// Errors returned through the Candid API, so clients can match on the variant instead of
+/// on the message.
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize, PartialEq, Eq)]
+pub enum ApiError {
+    NotLoggedIn,
+    NoUsername,
+    NotFound {
+        entity: String,
+        id: String,
+    },
+    /// The caller is known but is not allowed to do this, e.g. is not a member or an admin
+    Forbidden {
+        reason: String,
+    },
+    /// The request clashes with existing state, e.g. the user is already a member
+    Conflict {
+        reason: String,
+    },
+    SubscriptionRequired,
+    InsufficientBalance,
+    /// The meeting is still being processed
+    Busy,
+    /// The uploaded chunks are still being hashed, the same call has to be retried
+    UploadIncomplete {
+        hashed_chunks: u32,
+        chunk_count: u32,
+    },
+    InvalidInput {
+        field: String,
+        reason: String,
+    },
+}
+
+impl ApiError {
+    pub fn not_found(entity: &str, id: impl ToString) -> Self {
+        Self::NotFound {
+            entity: entity.to_string(),
+            id: id.to_string(),
+        }
+    }
+
+    pub fn forbidden(reason: &str) -> Self {
+        Self::Forbidden {
+            reason: reason.to_string(),
+        }
+    }
+
+    pub fn conflict(reason: &str) -> Self {
+        Self::Conflict {
+            reason: reason.to_string(),
+        }
+    }
+
+    pub fn invalid_input(field: &str, reason: &str) -> Self {
+        Self::InvalidInput {
+            field: field.to_string(),
+            reason: reason.to_string(),
+        }
+    }
+}
+
+impl Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotLoggedIn => write!(f, "User needs to sign in to proceed!"),
+            Self::NoUsername => write!(f, "User needs to have a username to proceed!"),
+            Self::NotFound { entity, id } => write!(f, "Cannot find {} with ID {}!", entity, id),
+            Self::Forbidden { reason } | Self::Conflict { reason } => write!(f, "{}", reason),
+            Self::SubscriptionRequired => {
+                write!(f, "User must be subscribed to use this feature!")
+            }
+            Self::InsufficientBalance => write!(f, "Balance is not sufficient!"),
+            Self::Busy => write!(f, "Video is still on procesing... Please try again later.."),
+            Self::UploadIncomplete {
+                hashed_chunks,
+                chunk_count,
+            } => write!(
+                f,
+                "Upload is still being verified ({}/{} chunks), please try again!",
+                hashed_chunks, chunk_count
+            ),
+            Self::InvalidInput { field, reason } => write!(f, "Invalid {}: {}", field, reason),
+        }
+    }
+}
@@ -0,0 +1,392 @@
+use std::{cell::RefCell, collections::BTreeMap, ops::Bound, time::Duration};
+
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::ApiError,
+    globals::{EVENT_CURSORS, USER_EVENTS},
+    impl_candid_storable,
+    runtime::{IcRuntime, Runtime},
+    user,
+    websocket::WebsocketEventMessage,
+};
+
+/// Events older than this are dropped even when the client never acknowledged them.
+pub const EVENT_RETENTION: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Events and principals one compaction message looks at, the rest is left to the next one.
+const MAX_COMPACTED_EVENTS_PER_RUN: usize = 1000;
+
+/// Pages never hold more events than this, whatever limit the client asks for.
+pub const MAX_EVENT_PAGE_SIZE: u32 = 100;
+
+/// Events replayed at once to a reconnected client, the next batch follows its ack.
+pub const MAX_REPLAYED_EVENTS: usize = 50;
+
+thread_local! {
+    // principal the last compaction run stopped at, after an upgrade it starts over
+    static COMPACTION_CURSOR: RefCell<Option<Principal>> = RefCell::default();
+    // replays that did not fit in one batch, the websocket connections are gone after an upgrade
+    static REPLAYS: RefCell<BTreeMap<Principal, Replay>> = RefCell::default();
+}
+
+/// Where the replay of a reconnected client stands.
+struct Replay {
+    /// Last event that was replayed
+    sent_seq: u64,
+    /// First event that was sent live instead of replayed
+    end_seq: u64,
+}
+
+/// A websocket event kept in the log of one user, sequence numbers start at 1 and never repeat.
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+pub struct UserEvent {
+    pub seq: u64,
+    pub created_time_unix: u128,
+    pub message: WebsocketEventMessage,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct EventCursor {
+    /// Sequence number of the next event appended to the log
+    pub next_seq: u64,
+    /// Every event up to and including this one was received by the client
+    pub acked_seq: u64,
+}
+
+impl_candid_storable!(UserEvent, EventCursor);
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct EventPage {
+    /// Oldest first
+    pub events: Vec<UserEvent>,
+    /// Pass it back as `seq` to get the next page, absent on the last one
+    pub next_seq: Option<u64>,
+}
+
+impl From<UserEvent> for WebsocketEventMessage {
+    fn from(value: UserEvent) -> Self {
+        Self::Event {
+            seq: value.seq,
+            message: Box::new(value.message),
+        }
+    }
+}
+
+fn get_cursor(principal: Principal) -> EventCursor {
+    EVENT_CURSORS
+        .with_borrow(|event_cursors| event_cursors.get(&principal))
+        .unwrap_or(EventCursor {
+            next_seq: 1,
+            acked_seq: 0,
+        })
+}
+
+/// Appends the message to the log of the user and returns it with its sequence number.
+pub fn record(
+    rt: &impl Runtime,
+    principal: Principal,
+    message: WebsocketEventMessage,
+) -> UserEvent {
+    let mut cursor = get_cursor(principal);
+    let event = UserEvent {
+        seq: cursor.next_seq,
+        created_time_unix: rt.time(),
+        message,
+    };
+
+    cursor.next_seq += 1;
+    EVENT_CURSORS.with_borrow_mut(|event_cursors| event_cursors.insert(principal, cursor));
+    USER_EVENTS
+        .with_borrow_mut(|user_events| user_events.insert((principal, event.seq), event.clone()));
+
+    event
+}
+
+/// At most `limit` events of the user that come after `seq` and before `end_seq`, oldest first.
+fn events_between(principal: Principal, seq: u64, end_seq: u64, limit: usize) -> Vec<UserEvent> {
+    USER_EVENTS.with_borrow(|user_events| {
+        user_events
+            .range((principal, seq.saturating_add(1))..(principal, end_seq))
+            .take(limit)
+            .map(|(_, event)| event)
+            .collect()
+    })
+}
+
+/// At most `limit` events of the user that come after `seq`, oldest first.
+pub fn events_since(principal: Principal, seq: u64, limit: usize) -> Vec<UserEvent> {
+    events_between(principal, seq, u64::MAX, limit)
+}
+
+/// Replays the next batch of events between `seq` and the end of the replay, remembers where it
+/// stopped when there may be more.
+fn replay_batch(principal: Principal, seq: u64, end_seq: u64) -> Vec<UserEvent> {
+    let events = events_between(principal, seq, end_seq, MAX_REPLAYED_EVENTS);
+
+    REPLAYS.with_borrow_mut(|replays| match events.last() {
+        Some(last) if events.len() == MAX_REPLAYED_EVENTS && last.seq + 1 < end_seq => {
+            replays.insert(
+                principal,
+                Replay {
+                    sent_seq: last.seq,
+                    end_seq,
+                },
+            );
+        }
+        _ => {
+            replays.remove(&principal);
+        }
+    });
+
+    events
+}
+
+/// The first batch of events the client has not acknowledged yet, they are replayed when it
+/// reconnects. Every event recorded from now on is sent live.
+pub fn start_replay(principal: Principal) -> Vec<UserEvent> {
+    let cursor = get_cursor(principal);
+    replay_batch(principal, cursor.acked_seq, cursor.next_seq)
+}
+
+/// The next batch of the replay, once the client acknowledged the last one.
+pub fn continue_replay(principal: Principal, acked_seq: u64) -> Vec<UserEvent> {
+    let Some((sent_seq, end_seq)) = REPLAYS.with_borrow(|replays| {
+        replays
+            .get(&principal)
+            .map(|replay| (replay.sent_seq, replay.end_seq))
+    }) else {
+        return Vec::new();
+    };
+    if acked_seq < sent_seq {
+        return Vec::new();
+    }
+
+    replay_batch(principal, sent_seq, end_seq)
+}
+
+pub fn stop_replay(principal: Principal) {
+    REPLAYS.with_borrow_mut(|replays| replays.remove(&principal));
+}
+
+pub fn ack(principal: Principal, seq: u64) -> Result<(), ApiError> {
+    let mut cursor = get_cursor(principal);
+    if seq >= cursor.next_seq {
+        return Err(ApiError::invalid_input(
+            "seq",
+            "cannot acknowledge an event that was not sent yet",
+        ));
+    }
+
+    // acks may arrive out of order, the cursor never moves back
+    if seq > cursor.acked_seq {
+        cursor.acked_seq = seq;
+        EVENT_CURSORS.with_borrow_mut(|event_cursors| event_cursors.insert(principal, cursor));
+    }
+
+    Ok(())
+}
+
+fn next_principal(after: Option<Principal>) -> Option<Principal> {
+    let start = after.map_or(Bound::Unbounded, Bound::Excluded);
+    EVENT_CURSORS.with_borrow(|event_cursors| {
+        event_cursors
+            .range((start, Bound::Unbounded))
+            .next()
+            .map(|(principal, _)| principal)
+    })
+}
+
+/// Drops the events that are older than [`EVENT_RETENTION`].
+///
+/// The log of every user is in `seq` order, so each walk stops at the first event that is
+/// still recent. After [`MAX_COMPACTED_EVENTS_PER_RUN`] steps the run remembers where it
+/// stopped and continues in a message of its own.
+pub fn compact_events(rt: &impl Runtime) {
+    let cutoff = rt.time().saturating_sub(EVENT_RETENTION.as_nanos());
+    let mut budget = MAX_COMPACTED_EVENTS_PER_RUN;
+    let mut principal = COMPACTION_CURSOR.take().or_else(|| next_principal(None));
+
+    while let Some(current) = principal {
+        if budget == 0 {
+            COMPACTION_CURSOR.set(Some(current));
+            let worker = rt.clone();
+            rt.set_timer(Duration::ZERO, move || compact_events(&worker));
+            return;
+        }
+        budget -= 1;
+
+        let expired_events = USER_EVENTS.with_borrow(|user_events| {
+            user_events
+                .range((current, u64::MIN)..=(current, u64::MAX))
+                .take(budget)
+                .take_while(|(_, event)| event.created_time_unix < cutoff)
+                .map(|(key, _)| key)
+                .collect::<Vec<_>>()
+        });
+        budget -= expired_events.len();
+
+        USER_EVENTS.with_borrow_mut(|user_events| {
+            for key in expired_events.iter() {
+                user_events.remove(key);
+            }
+        });
+
+        // the log may still have expired events when the budget ran out, it is walked again
+        if budget > 0 {
+            principal = next_principal(Some(current));
+        }
+    }
+}
+
+pub fn poll_event_compaction() {
+    ic_cdk::println!("Starting poll event compaction");
+    ic_cdk_timers::set_timer_interval(Duration::from_secs(60 * 60), || compact_events(&IcRuntime));
+}
+
+/// Events that were compacted away are missing, clients notice the gap from the first `seq`.
+pub fn get_events_since(rt: &impl Runtime, seq: u64, limit: u32) -> Result<EventPage, ApiError> {
+    user::assert_user_logged_in(rt)?;
+
+    if limit == 0 {
+        return Err(ApiError::invalid_input("limit", "must be at least 1"));
+    }
+    let limit = limit.min(MAX_EVENT_PAGE_SIZE) as usize;
+
+    let mut events = events_since(rt.caller(), seq, limit + 1);
+    let next_seq = if events.len() > limit {
+        events.truncate(limit);
+        events.last().map(|event| event.seq)
+    } else {
+        None
+    };
+
+    Ok(EventPage { events, next_seq })
+}
+
+mod endpoints {
+    use super::*;
+    use crate::runtime::IcRuntime;
+
+    #[ic_cdk::query]
+    fn get_events_since(seq: u64, limit: u32) -> Result<EventPage, ApiError> {
+        super::get_events_since(&IcRuntime, seq, limit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{runtime::TestRuntime, user::tests::sign_in};
+
+    fn seqs(events: Vec<UserEvent>) -> Vec<u64> {
+        events.into_iter().map(|event| event.seq).collect()
+    }
+
+    fn all_events(principal: Principal) -> Vec<UserEvent> {
+        events_since(principal, 0, usize::MAX)
+    }
+
+    #[test]
+    fn old_events_are_compacted() {
+        let rt = TestRuntime::new();
+        let alice = sign_in(&rt, 1, "alice");
+        let bob = sign_in(&rt, 2, "bob");
+
+        record(&rt, alice, WebsocketEventMessage::Ping);
+        record(&rt, alice, WebsocketEventMessage::Ping);
+        rt.advance_time(Duration::from_secs(2 * 24 * 60 * 60));
+        record(&rt, alice, WebsocketEventMessage::Ping);
+        record(&rt, bob, WebsocketEventMessage::Ping);
+
+        rt.set_caller(alice);
+        assert_eq!(
+            seqs(get_events_since(&rt, 0, 10).unwrap().events),
+            vec![1, 2, 3]
+        );
+        assert_eq!(seqs(get_events_since(&rt, 2, 10).unwrap().events), vec![3]);
+
+        rt.advance_time(Duration::from_secs(6 * 24 * 60 * 60));
+        compact_events(&rt);
+        assert_eq!(seqs(get_events_since(&rt, 0, 10).unwrap().events), vec![3]);
+        assert_eq!(seqs(all_events(bob)), vec![1]);
+
+        // sequence numbers are not reused after compaction
+        assert_eq!(record(&rt, alice, WebsocketEventMessage::Ping).seq, 4);
+    }
+
+    #[test]
+    fn compaction_continues_where_it_stopped() {
+        let rt = TestRuntime::new();
+        let alice = sign_in(&rt, 1, "alice");
+        let bob = sign_in(&rt, 2, "bob");
+
+        for _ in 0..MAX_COMPACTED_EVENTS_PER_RUN {
+            record(&rt, alice, WebsocketEventMessage::Ping);
+        }
+        record(&rt, bob, WebsocketEventMessage::Ping);
+        rt.advance_time(EVENT_RETENTION + Duration::from_secs(1));
+        record(&rt, alice, WebsocketEventMessage::Ping);
+
+        compact_events(&rt);
+        assert_eq!(all_events(alice).len(), 2);
+        assert_eq!(seqs(all_events(bob)), vec![1]);
+
+        rt.run_timers();
+        assert_eq!(
+            seqs(all_events(alice)),
+            vec![MAX_COMPACTED_EVENTS_PER_RUN as u64 + 1]
+        );
+        assert!(all_events(bob).is_empty());
+    }
+
+    #[test]
+    fn pages_through_the_log() {
+        let rt = TestRuntime::new();
+        let alice = sign_in(&rt, 1, "alice");
+        for _ in 0..5 {
+            record(&rt, alice, WebsocketEventMessage::Ping);
+        }
+
+        let first = get_events_since(&rt, 0, 2).unwrap();
+        assert_eq!(seqs(first.events), vec![1, 2]);
+        let second = get_events_since(&rt, first.next_seq.unwrap(), 2).unwrap();
+        assert_eq!(seqs(second.events), vec![3, 4]);
+        let last = get_events_since(&rt, second.next_seq.unwrap(), 2).unwrap();
+        assert_eq!(seqs(last.events), vec![5]);
+        assert_eq!(last.next_seq, None);
+
+        assert_eq!(get_events_since(&rt, 0, u32::MAX).unwrap().events.len(), 5);
+        assert!(matches!(
+            get_events_since(&rt, 0, 0),
+            Err(ApiError::InvalidInput { .. })
+        ));
+    }
+
+    #[test]
+    fn replays_in_batches_until_the_reconnect() {
+        let rt = TestRuntime::new();
+        let alice = sign_in(&rt, 1, "alice");
+        for _ in 0..MAX_REPLAYED_EVENTS + 2 {
+            record(&rt, alice, WebsocketEventMessage::Ping);
+        }
+
+        let batch = seqs(start_replay(alice));
+        assert_eq!(batch.len(), MAX_REPLAYED_EVENTS);
+        let sent_seq = *batch.last().unwrap();
+
+        // events recorded after the reconnect are sent live and not replayed again
+        let live = record(&rt, alice, WebsocketEventMessage::Ping).seq;
+        assert!(continue_replay(alice, sent_seq - 1).is_empty());
+        assert_eq!(
+            seqs(continue_replay(alice, live)),
+            vec![sent_seq + 1, sent_seq + 2]
+        );
+        assert!(continue_replay(alice, live).is_empty());
+
+        start_replay(alice);
+        stop_replay(alice);
+        assert!(continue_replay(alice, live).is_empty());
+    }
+}
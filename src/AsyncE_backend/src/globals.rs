@@ -1,35 +1,158 @@
-use std::{
-    cell::RefCell,
-    collections::{BTreeMap, BTreeSet},
-    sync::Mutex,
-};
+use std::{cell::RefCell, collections::BTreeSet};
 
 use candid::Principal;
+use ic_stable_structures::{StableBTreeMap, StableCell};
 use ic_websocket_cdk::ClientPrincipal;
 
 use crate::{
-    chat::Chat, group::Group, meeting::Meeting, primary_key::PrimaryKeyContainer, user::User,
+    attachment::StagedAttachments,
+    blob::BlobMetadata,
+    chat::{Chat, ChatRevision},
+    chunk::UploadBuffer,
+    config::Config,
+    event::{EventCursor, UserEvent},
+    group::Group,
+    invite::GroupInviteSet,
+    job::Job,
+    meeting::Meeting,
+    memory::{self, Memory},
+    migration,
+    notification::Notification,
+    primary_key::PrimaryKeyType,
+    receipt::ReadReceipt,
+    search::{SearchDoc, SearchToken},
+    upload::VideoUploadSession,
+    user::User,
 };
 
-pub type UserStore = BTreeMap<Principal, User>;
-pub type GroupStore = BTreeMap<u128, Group>;
-pub type MeetingStore = BTreeMap<u128, BTreeMap<u128, Meeting>>;
-pub type GroupInviteStore = BTreeMap<String, BTreeSet<u128>>;
+pub type UserStore = StableBTreeMap<Principal, User, Memory>;
+pub type GroupStore = StableBTreeMap<u128, Group, Memory>;
+/// Keyed by `(group_id, meeting_id)`
+pub type MeetingStore = StableBTreeMap<(u128, u128), Meeting, Memory>;
+pub type GroupInviteStore = StableBTreeMap<String, GroupInviteSet, Memory>;
 pub type WebSocketClientStore = BTreeSet<ClientPrincipal>;
-pub type ChatStore = BTreeMap<u128, BTreeMap<u128, Chat>>;
-pub type PrimaryKeyContainerStore = PrimaryKeyContainer;
-pub type VideoUploadStore = BTreeMap<String, Vec<u8>>;
+/// Keyed by `(group_id, chat_id)`
+pub type ChatStore = StableBTreeMap<(u128, u128), Chat, Memory>;
+pub type PrimaryKeyContainerStore = StableBTreeMap<PrimaryKeyType, u128, Memory>;
+pub type UploadBufferStore = StableBTreeMap<String, UploadBuffer, Memory>;
+/// Keyed by `(SHA-256 of the buffer key, chunk_index)`
+pub type UploadBufferChunkStore = StableBTreeMap<([u8; 32], u32), Vec<u8>, Memory>;
+pub type SchemaVersionStore = StableCell<u32, Memory>;
+/// Keyed by the SHA-256 of the blob content
+pub type BlobStore = StableBTreeMap<[u8; 32], BlobMetadata, Memory>;
+/// Keyed by `(blob hash, chunk index)`
+pub type BlobChunkStore = StableBTreeMap<([u8; 32], u32), Vec<u8>, Memory>;
+pub type UploadSessionStore = StableBTreeMap<u128, VideoUploadSession, Memory>;
+/// Keyed by `(session_id, chunk_index)`
+pub type UploadChunkStore = StableBTreeMap<(u128, u32), Vec<u8>, Memory>;
+/// Empty until the first `raw_rand` call after install finishes
+pub type MediaTokenSecretStore = StableCell<Vec<u8>, Memory>;
+pub type ConfigStore = StableCell<Config, Memory>;
+pub type JobStore = StableBTreeMap<u128, Job, Memory>;
+/// Unfinished jobs keyed by `(next_attempt_time_unix, job_id)`
+pub type JobQueueStore = StableBTreeMap<(u128, u128), (), Memory>;
+/// Keyed by `(group_id, meeting_id, job_id)`
+pub type MeetingJobStore = StableBTreeMap<(u128, u128, u128), (), Memory>;
+/// Keyed by the lowercased username
+pub type UsernameStore = StableBTreeMap<String, Principal, Memory>;
+/// Keyed by `(principal, seq)`
+pub type UserEventStore = StableBTreeMap<(Principal, u64), UserEvent, Memory>;
+pub type EventCursorStore = StableBTreeMap<Principal, EventCursor, Memory>;
+/// Keyed by `(parent chat_id, reply chat_id)`
+pub type ChatReplyStore = StableBTreeMap<(u128, u128), (), Memory>;
+/// Keyed by `(chat_id, revision)`, revisions count up from 0 for every chat
+pub type ChatHistoryStore = StableBTreeMap<(u128, u32), ChatRevision, Memory>;
+/// Keyed by `{principal}/{chat uuid}`
+pub type StagedAttachmentStore = StableBTreeMap<String, StagedAttachments, Memory>;
+/// Keyed by `(group_id, token, document)`
+pub type SearchIndexStore = StableBTreeMap<(u128, SearchToken, SearchDoc), (), Memory>;
+/// Keyed by `(principal, notification_id)`
+pub type NotificationStore = StableBTreeMap<(Principal, u128), Notification, Memory>;
+/// Number of unread notifications in the inbox of each user, users without any are left out
+pub type UnreadNotificationStore = StableBTreeMap<Principal, u64, Memory>;
+/// Keyed by `(group_id, created_time_unix, (meeting_id, frame_index))`, so the video parts of a
+/// group are counted without decoding its meetings
+pub type FrameTimeStore = StableBTreeMap<(u128, u128, (u128, u128)), (), Memory>;
+/// Keyed by `(group_id, principal)`
+pub type ReadReceiptStore = StableBTreeMap<(u128, Principal), ReadReceipt, Memory>;
+/// Last time each user connected, disconnected or sent a websocket message
+pub type LastSeenStore = StableBTreeMap<Principal, u128, Memory>;
 
 thread_local! {
-    pub static USERS: RefCell<UserStore> = RefCell::default();
-    pub static GROUPS: RefCell<GroupStore> = RefCell::default();
-    pub static GROUP_INVITES: RefCell<GroupInviteStore> = RefCell::default();
-    pub static WEBSOCKET_CLIENTS: RefCell<WebSocketClientStore> = RefCell::default();
-    pub static CHATS: RefCell<ChatStore> = RefCell::default();
-    pub static PRIMARY_KEY_CONTAINERS: RefCell<PrimaryKeyContainer> = RefCell::default();
-    pub static VIDEO_UPLOADS: RefCell<VideoUploadStore> = RefCell::default();
-}
+    pub static USERS: RefCell<UserStore> =
+        RefCell::new(StableBTreeMap::init(memory::get_memory(memory::USERS_MEMORY_ID)));
+    pub static GROUPS: RefCell<GroupStore> =
+        RefCell::new(StableBTreeMap::init(memory::get_memory(memory::GROUPS_MEMORY_ID)));
+    pub static MEETINGS: RefCell<MeetingStore> =
+        RefCell::new(StableBTreeMap::init(memory::get_memory(memory::MEETINGS_MEMORY_ID)));
+    pub static GROUP_INVITES: RefCell<GroupInviteStore> =
+        RefCell::new(StableBTreeMap::init(memory::get_memory(memory::GROUP_INVITES_MEMORY_ID)));
+    pub static CHATS: RefCell<ChatStore> =
+        RefCell::new(StableBTreeMap::init(memory::get_memory(memory::CHATS_MEMORY_ID)));
+    pub static PRIMARY_KEY_CONTAINERS: RefCell<PrimaryKeyContainerStore> = RefCell::new(
+        StableBTreeMap::init(memory::get_memory(memory::PRIMARY_KEY_CONTAINERS_MEMORY_ID)),
+    );
+    pub static UPLOAD_BUFFERS: RefCell<UploadBufferStore> =
+        RefCell::new(StableBTreeMap::init(memory::get_memory(memory::UPLOAD_BUFFERS_MEMORY_ID)));
+    pub static UPLOAD_BUFFER_CHUNKS: RefCell<UploadBufferChunkStore> = RefCell::new(
+        StableBTreeMap::init(memory::get_memory(memory::UPLOAD_BUFFER_CHUNKS_MEMORY_ID)),
+    );
+    pub static SCHEMA_VERSION: RefCell<SchemaVersionStore> = RefCell::new(
+        StableCell::init(
+            memory::get_memory(memory::SCHEMA_VERSION_MEMORY_ID),
+            migration::STABLE_STRUCTURES_SCHEMA_VERSION,
+        )
+        .expect("FAILED TO INITIALIZE SCHEMA VERSION!"),
+    );
+    pub static BLOBS: RefCell<BlobStore> =
+        RefCell::new(StableBTreeMap::init(memory::get_memory(memory::BLOBS_MEMORY_ID)));
+    pub static BLOB_CHUNKS: RefCell<BlobChunkStore> =
+        RefCell::new(StableBTreeMap::init(memory::get_memory(memory::BLOB_CHUNKS_MEMORY_ID)));
+    pub static UPLOAD_SESSIONS: RefCell<UploadSessionStore> =
+        RefCell::new(StableBTreeMap::init(memory::get_memory(memory::UPLOAD_SESSIONS_MEMORY_ID)));
+    pub static UPLOAD_CHUNKS: RefCell<UploadChunkStore> =
+        RefCell::new(StableBTreeMap::init(memory::get_memory(memory::UPLOAD_CHUNKS_MEMORY_ID)));
+    pub static MEDIA_TOKEN_SECRET: RefCell<MediaTokenSecretStore> = RefCell::new(
+        StableCell::init(memory::get_memory(memory::MEDIA_TOKEN_SECRET_MEMORY_ID), Vec::new())
+            .expect("FAILED TO INITIALIZE MEDIA TOKEN SECRET!"),
+    );
+    pub static CONFIG: RefCell<ConfigStore> = RefCell::new(
+        StableCell::init(memory::get_memory(memory::CONFIG_MEMORY_ID), Config::default())
+            .expect("FAILED TO INITIALIZE CONFIG!"),
+    );
+    pub static JOBS: RefCell<JobStore> =
+        RefCell::new(StableBTreeMap::init(memory::get_memory(memory::JOBS_MEMORY_ID)));
+    pub static JOB_QUEUE: RefCell<JobQueueStore> =
+        RefCell::new(StableBTreeMap::init(memory::get_memory(memory::JOB_QUEUE_MEMORY_ID)));
+    pub static MEETING_JOBS: RefCell<MeetingJobStore> =
+        RefCell::new(StableBTreeMap::init(memory::get_memory(memory::MEETING_JOBS_MEMORY_ID)));
+    pub static USERNAMES: RefCell<UsernameStore> =
+        RefCell::new(StableBTreeMap::init(memory::get_memory(memory::USERNAMES_MEMORY_ID)));
+    pub static USER_EVENTS: RefCell<UserEventStore> =
+        RefCell::new(StableBTreeMap::init(memory::get_memory(memory::USER_EVENTS_MEMORY_ID)));
+    pub static EVENT_CURSORS: RefCell<EventCursorStore> =
+        RefCell::new(StableBTreeMap::init(memory::get_memory(memory::EVENT_CURSORS_MEMORY_ID)));
+    pub static CHAT_REPLIES: RefCell<ChatReplyStore> =
+        RefCell::new(StableBTreeMap::init(memory::get_memory(memory::CHAT_REPLIES_MEMORY_ID)));
+    pub static CHAT_HISTORY: RefCell<ChatHistoryStore> =
+        RefCell::new(StableBTreeMap::init(memory::get_memory(memory::CHAT_HISTORY_MEMORY_ID)));
+    pub static STAGED_ATTACHMENTS: RefCell<StagedAttachmentStore> =
+        RefCell::new(StableBTreeMap::init(memory::get_memory(memory::STAGED_ATTACHMENTS_MEMORY_ID)));
+    pub static SEARCH_INDEX: RefCell<SearchIndexStore> =
+        RefCell::new(StableBTreeMap::init(memory::get_memory(memory::SEARCH_INDEX_MEMORY_ID)));
+    pub static NOTIFICATIONS: RefCell<NotificationStore> =
+        RefCell::new(StableBTreeMap::init(memory::get_memory(memory::NOTIFICATIONS_MEMORY_ID)));
+    pub static UNREAD_NOTIFICATIONS: RefCell<UnreadNotificationStore> = RefCell::new(
+        StableBTreeMap::init(memory::get_memory(memory::UNREAD_NOTIFICATIONS_MEMORY_ID)),
+    );
+    pub static FRAME_TIMES: RefCell<FrameTimeStore> =
+        RefCell::new(StableBTreeMap::init(memory::get_memory(memory::FRAME_TIMES_MEMORY_ID)));
+    pub static READ_RECEIPTS: RefCell<ReadReceiptStore> =
+        RefCell::new(StableBTreeMap::init(memory::get_memory(memory::READ_RECEIPTS_MEMORY_ID)));
+    pub static LAST_SEEN: RefCell<LastSeenStore> =
+        RefCell::new(StableBTreeMap::init(memory::get_memory(memory::LAST_SEEN_MEMORY_ID)));
 
-lazy_static::lazy_static! {
-    pub static ref MEETINGS: Mutex<MeetingStore> = Mutex::default();
+    // connected clients only live as long as their websocket session,
+    // so there is no point in keeping them across upgrades
+    pub static WEBSOCKET_CLIENTS: RefCell<WebSocketClientStore> = RefCell::default();
 }
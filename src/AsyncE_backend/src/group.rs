@@ -1,10 +1,17 @@
-use candid::CandidType;
+use std::collections::BTreeSet;
+
+use candid::{CandidType, Principal};
 use serde::Deserialize;
 
 use crate::{
+    blob::{self, BlobId},
     chunk,
+    error::ApiError,
     globals::GROUPS,
+    impl_candid_storable,
     primary_key::{self, PrimaryKeyType},
+    receipt,
+    runtime::Runtime,
     user,
 };
 
@@ -15,7 +22,7 @@ pub struct Group {
     pub owner: String,
     pub members: Vec<GroupMember>,
     pub created_time_unix: u128,
-    pub profile_picture_blob: Vec<u8>,
+    pub profile_picture: Option<BlobId>,
 }
 
 #[derive(Clone, Debug, CandidType, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
@@ -46,19 +53,23 @@ pub struct GroupQueryResponse {
     pub owner: String,
     pub members: Vec<GroupMember>,
     pub created_time_unix: u128,
+    /// Chats of the group after the last one the caller read
+    pub unread_chat_count: u64,
+    /// Video parts of the group created after the last one the caller saw
+    pub unread_video_count: u64,
 }
 
 impl Group {
-    pub fn new(name: String) -> Result<Self, String> {
-        let owner = user::get_selfname_force()?;
+    pub fn new(rt: &impl Runtime, name: String) -> Result<Self, ApiError> {
+        let owner = user::get_selfname_force(rt)?;
 
         Ok(Self {
             id: primary_key::get_primary_key(PrimaryKeyType::Group),
             name: name.clone(),
             owner: owner.clone(),
             members: Vec::from([GroupMember::new(owner, GroupMemberRole::Admin)]),
-            created_time_unix: ic_cdk::api::time() as u128,
-            profile_picture_blob: Vec::new(),
+            created_time_unix: rt.time(),
+            profile_picture: None,
         })
     }
 
@@ -69,25 +80,47 @@ impl Group {
                 .iter()
                 .any(|x| x.username.eq_ignore_ascii_case(name))
     }
+
+    /// Principals of the owner and every member that still has an account.
+    pub fn member_principals(&self) -> BTreeSet<Principal> {
+        self.members
+            .iter()
+            .map(|member| member.username.as_str())
+            .chain([self.owner.as_str()])
+            .filter_map(user::get_principal)
+            .collect()
+    }
+
+    pub fn is_admin(&self, name: &str) -> bool {
+        self.members
+            .iter()
+            .any(|x| x.username.eq_ignore_ascii_case(name) && x.role == GroupMemberRole::Admin)
+    }
 }
 
-impl From<&Group> for GroupQueryResponse {
-    fn from(x: &Group) -> Self {
+impl_candid_storable!(Group);
+
+impl GroupQueryResponse {
+    /// The unread counts are the ones of the given member.
+    fn new(group: &Group, principal: Principal) -> Self {
+        let receipt = receipt::get_receipt(group.id, principal);
+
         Self {
-            id: x.id,
-            name: x.name.clone(),
-            owner: x.owner.clone(),
-            members: x.members.clone(),
-            created_time_unix: x.created_time_unix,
+            id: group.id,
+            name: group.name.clone(),
+            owner: group.owner.clone(),
+            members: group.members.clone(),
+            created_time_unix: group.created_time_unix,
+            unread_chat_count: receipt::unread_chat_count(group.id, &receipt),
+            unread_video_count: receipt::unread_video_count(group.id, &receipt),
         }
     }
 }
 
-#[ic_cdk::update]
-pub fn create_group(name: String) -> Result<u128, String> {
-    user::assert_user_logged_in()?;
+pub fn create_group(rt: &impl Runtime, name: String) -> Result<u128, ApiError> {
+    user::assert_user_logged_in(rt)?;
 
-    let group = Group::new(name)?;
+    let group = Group::new(rt, name)?;
     let group_id = group.id;
 
     GROUPS.with_borrow_mut(|groups| groups.insert(group.id, group));
@@ -95,200 +128,331 @@ pub fn create_group(name: String) -> Result<u128, String> {
     Ok(group_id)
 }
 
-#[ic_cdk::update]
-pub fn get_all_groups() -> Result<Vec<GroupQueryResponse>, String> {
-    user::assert_user_logged_in()?;
+pub fn get_all_groups(rt: &impl Runtime) -> Result<Vec<GroupQueryResponse>, ApiError> {
+    user::assert_user_logged_in(rt)?;
 
-    let owner = user::get_selfname_force()?;
+    let owner = user::get_selfname_force(rt)?;
 
     Ok(GROUPS.with_borrow(|groups| {
         groups
-            .values()
-            .filter(|x| x.is_member(&owner))
-            .map(GroupQueryResponse::from)
+            .iter()
+            .filter(|(_, x)| x.is_member(&owner))
+            .map(|(_, x)| GroupQueryResponse::new(&x, rt.caller()))
             .collect::<Vec<_>>()
     }))
 }
 
-#[ic_cdk::query]
-pub fn get_group(group_id: u128) -> Result<Option<GroupQueryResponse>, String> {
-    user::assert_user_logged_in()?;
+pub fn get_group(
+    rt: &impl Runtime,
+    group_id: u128,
+) -> Result<Option<GroupQueryResponse>, ApiError> {
+    user::assert_user_logged_in(rt)?;
 
-    let selfname = user::get_selfname_force()?;
+    let selfname = user::get_selfname_force(rt)?;
 
     GROUPS.with_borrow(|groups| {
         let group = groups.get(&group_id);
 
         if let Some(group) = group.as_ref() {
             if !group.is_member(&selfname) {
-                return Err(String::from("This user is not in this group!"));
+                return Err(ApiError::forbidden("This user is not in this group!"));
             }
         }
 
-        Ok(group.map(GroupQueryResponse::from))
+        Ok(group.map(|group| GroupQueryResponse::new(&group, rt.caller())))
     })
 }
 
-#[ic_cdk::update]
 pub fn upload_group_profile_picture(
+    rt: &impl Runtime,
     group_id: u128,
     chunk_data: Vec<u8>,
     chunk_index: u128,
     total_data_length: u128,
-) -> Result<(), String> {
-    user::assert_user_logged_in()?;
+) -> Result<(), ApiError> {
+    user::assert_user_logged_in(rt)?;
 
-    let selfname = user::get_selfname_force()?;
+    let selfname = user::get_selfname_force(rt)?;
 
     GROUPS.with_borrow_mut(|groups| {
-        let group = groups
-            .get_mut(&group_id)
-            .ok_or(String::from("Cannot find group with this ID!"))?;
+        let mut group = groups
+            .get(&group_id)
+            .ok_or(ApiError::not_found("group", group_id))?;
 
         if !group.is_member(&selfname) {
-            return Err(String::from("This user is not in this group!"));
+            return Err(ApiError::forbidden("This user is not in this group!"));
         }
 
-        if group.profile_picture_blob.capacity() != total_data_length as usize {
-            group
-                .profile_picture_blob
-                .reserve_exact(total_data_length as usize);
+        // every member uploads into their own buffer, the last finished upload wins
+        let key = format!("group_profile_picture/{}/{}", group_id, rt.caller());
+        if let Some(data) = chunk::buffer_upload_chunk(
+            rt,
+            key,
+            chunk_data,
+            chunk_index,
+            total_data_length,
+            user::MAX_PROFILE_PICTURE_SIZE,
+        )? {
+            blob::replace(&mut group.profile_picture, blob::store(&data));
+            groups.insert(group_id, group);
         }
 
-        let offset = chunk_index as usize * chunk::MB;
-        group
-            .profile_picture_blob
-            .splice(offset..offset, chunk_data);
-
         Ok(())
     })
 }
 
-#[ic_cdk::query]
-pub fn get_group_profile_picture_size(group_id: u128) -> Result<u128, String> {
-    user::assert_user_logged_in()?;
+pub fn get_group_profile_picture_size(rt: &impl Runtime, group_id: u128) -> Result<u128, ApiError> {
+    user::assert_user_logged_in(rt)?;
 
-    let selfname = user::get_selfname_force()?;
+    let selfname = user::get_selfname_force(rt)?;
 
     GROUPS.with_borrow(|groups| {
         let group = groups
             .get(&group_id)
-            .ok_or(String::from("Cannot find group with this ID!"))?;
+            .ok_or(ApiError::not_found("group", group_id))?;
 
         if !group.is_member(&selfname) {
-            return Err(String::from("This user is not in this group!"));
+            return Err(ApiError::forbidden("This user is not in this group!"));
         }
 
-        Ok(group.profile_picture_blob.len() as u128)
+        Ok(group
+            .profile_picture
+            .as_ref()
+            .map(blob::get_size)
+            .unwrap_or_default())
     })
 }
 
-#[ic_cdk::query]
 pub fn get_group_profile_picture_chunk_blob(
+    rt: &impl Runtime,
     group_id: u128,
     index: u128,
-) -> Result<Vec<u8>, String> {
-    user::assert_user_logged_in()?;
+) -> Result<Vec<u8>, ApiError> {
+    user::assert_user_logged_in(rt)?;
 
-    let selfname = user::get_selfname_force()?;
+    let selfname = user::get_selfname_force(rt)?;
 
     GROUPS.with_borrow(|groups| {
         let group = groups
             .get(&group_id)
-            .ok_or(String::from("Cannot find group with this ID!"))?;
+            .ok_or(ApiError::not_found("group", group_id))?;
 
         if !group.is_member(&selfname) {
-            return Err(String::from("This user is not in this group!"));
+            return Err(ApiError::forbidden("This user is not in this group!"));
         }
 
         Ok(group
-            .profile_picture_blob
-            .iter()
-            .skip(index as usize * chunk::MB)
-            .take(chunk::MB)
-            .cloned()
-            .collect())
+            .profile_picture
+            .map(|id| blob::get_chunk(&id, index))
+            .unwrap_or_default())
     })
 }
 
-#[ic_cdk::update]
-pub fn kick_member(group_id: u128, username: String) -> Result<(), String> {
-    user::assert_user_logged_in()?;
+pub fn kick_member(rt: &impl Runtime, group_id: u128, username: String) -> Result<(), ApiError> {
+    user::assert_user_logged_in(rt)?;
 
-    let selfname = user::get_selfname_force()?;
+    let selfname = user::get_selfname_force(rt)?;
 
     GROUPS.with_borrow_mut(|groups| {
-        let group = groups
-            .get_mut(&group_id)
-            .ok_or(String::from("Cannot find group with this ID!"))?;
+        let mut group = groups
+            .get(&group_id)
+            .ok_or(ApiError::not_found("group", group_id))?;
 
         if !group.is_member(&selfname) {
-            return Err(String::from("This user is not in this group!"));
+            return Err(ApiError::forbidden("This user is not in this group!"));
         }
 
         if !group.is_member(&username) {
-            return Err(String::from("Chosen user is not in this group!"));
+            return Err(ApiError::not_found("member", &username));
         }
 
         let member = group
             .members
             .iter()
             .find(|x| x.username.eq_ignore_ascii_case(&selfname))
-            .ok_or(String::from("This user is not in this group!"))?;
+            .ok_or(ApiError::forbidden("This user is not in this group!"))?;
         if member.role != GroupMemberRole::Admin {
-            return Err(String::from("Only an admin can kick a member!"));
+            return Err(ApiError::forbidden("Only an admin can kick a member!"));
         }
 
         let remove_idx = group
             .members
             .iter()
             .position(|x| x.username.eq_ignore_ascii_case(&username))
-            .ok_or(String::from("Chosen user is not in this group!"))?;
+            .ok_or(ApiError::not_found("member", &username))?;
         group.members.remove(remove_idx);
+        groups.insert(group_id, group);
+        if let Some(principal) = user::get_principal(&username) {
+            receipt::remove_receipt(group_id, principal);
+        }
 
         Ok(())
     })
 }
 
-#[ic_cdk::update]
 pub fn edit_member_role(
+    rt: &impl Runtime,
     group_id: u128,
     username: String,
     new_role: GroupMemberRole,
-) -> Result<(), String> {
-    user::assert_user_logged_in()?;
+) -> Result<(), ApiError> {
+    user::assert_user_logged_in(rt)?;
 
-    let selfname = user::get_selfname_force()?;
+    let selfname = user::get_selfname_force(rt)?;
 
     GROUPS.with_borrow_mut(|groups| {
-        let group = groups
-            .get_mut(&group_id)
-            .ok_or(String::from("Cannot find group with this ID!"))?;
+        let mut group = groups
+            .get(&group_id)
+            .ok_or(ApiError::not_found("group", group_id))?;
 
         if !group.is_member(&selfname) {
-            return Err(String::from("This user is not in this group!"));
+            return Err(ApiError::forbidden("This user is not in this group!"));
         }
 
         if !group.is_member(&username) {
-            return Err(String::from("Chosen user is not in this group!"));
+            return Err(ApiError::not_found("member", &username));
         }
 
         let member = group
             .members
             .iter()
             .find(|x| x.username.eq_ignore_ascii_case(&selfname))
-            .ok_or(String::from("This user is not in this group!"))?;
+            .ok_or(ApiError::forbidden("This user is not in this group!"))?;
         if member.role != GroupMemberRole::Admin {
-            return Err(String::from("Only an admin can kick a member!"));
+            return Err(ApiError::forbidden("Only an admin can edit member roles!"));
         }
 
         let member = group
             .members
             .iter_mut()
             .find(|x| x.username.eq_ignore_ascii_case(&username))
-            .ok_or(String::from("Chosen user is not in this group!"))?;
+            .ok_or(ApiError::not_found("member", &username))?;
         member.role = new_role;
+        groups.insert(group_id, group);
 
         Ok(())
     })
 }
+
+mod endpoints {
+    use super::*;
+    use crate::runtime::IcRuntime;
+
+    #[ic_cdk::update]
+    fn create_group(name: String) -> Result<u128, ApiError> {
+        super::create_group(&IcRuntime, name)
+    }
+
+    #[ic_cdk::update]
+    fn get_all_groups() -> Result<Vec<GroupQueryResponse>, ApiError> {
+        super::get_all_groups(&IcRuntime)
+    }
+
+    #[ic_cdk::query]
+    fn get_group(group_id: u128) -> Result<Option<GroupQueryResponse>, ApiError> {
+        super::get_group(&IcRuntime, group_id)
+    }
+
+    #[ic_cdk::update]
+    fn upload_group_profile_picture(
+        group_id: u128,
+        chunk_data: Vec<u8>,
+        chunk_index: u128,
+        total_data_length: u128,
+    ) -> Result<(), ApiError> {
+        super::upload_group_profile_picture(
+            &IcRuntime,
+            group_id,
+            chunk_data,
+            chunk_index,
+            total_data_length,
+        )
+    }
+
+    #[ic_cdk::query]
+    fn get_group_profile_picture_size(group_id: u128) -> Result<u128, ApiError> {
+        super::get_group_profile_picture_size(&IcRuntime, group_id)
+    }
+
+    #[ic_cdk::query]
+    fn get_group_profile_picture_chunk_blob(
+        group_id: u128,
+        index: u128,
+    ) -> Result<Vec<u8>, ApiError> {
+        super::get_group_profile_picture_chunk_blob(&IcRuntime, group_id, index)
+    }
+
+    #[ic_cdk::update]
+    fn kick_member(group_id: u128, username: String) -> Result<(), ApiError> {
+        super::kick_member(&IcRuntime, group_id, username)
+    }
+
+    #[ic_cdk::update]
+    fn edit_member_role(
+        group_id: u128,
+        username: String,
+        new_role: GroupMemberRole,
+    ) -> Result<(), ApiError> {
+        super::edit_member_role(&IcRuntime, group_id, username, new_role)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{runtime::TestRuntime, user::tests::sign_in};
+
+    #[test]
+    fn only_members_see_the_group() {
+        let rt = TestRuntime::new();
+        let bob = sign_in(&rt, 2, "bob");
+        sign_in(&rt, 1, "alice");
+        let group_id = create_group(&rt, String::from("Team")).unwrap();
+
+        let group = get_group(&rt, group_id).unwrap().unwrap();
+        assert_eq!(group.owner, "alice");
+        assert_eq!(group.members.len(), 1);
+        assert_eq!(group.members[0].role, GroupMemberRole::Admin);
+
+        rt.set_caller(bob);
+        assert!(matches!(
+            get_group(&rt, group_id),
+            Err(ApiError::Forbidden { .. })
+        ));
+        assert!(get_all_groups(&rt).unwrap().is_empty());
+    }
+
+    #[test]
+    fn only_admins_manage_members() {
+        let rt = TestRuntime::new();
+        let bob = sign_in(&rt, 2, "bob");
+        let carol = sign_in(&rt, 3, "carol");
+        let alice = sign_in(&rt, 1, "alice");
+        let group_id = create_group(&rt, String::from("Team")).unwrap();
+        GROUPS.with_borrow_mut(|groups| {
+            let mut group = groups.get(&group_id).unwrap();
+            group
+                .members
+                .push(GroupMember::new("bob", GroupMemberRole::Member));
+            group
+                .members
+                .push(GroupMember::new("carol", GroupMemberRole::Member));
+            groups.insert(group_id, group);
+        });
+
+        rt.set_caller(bob);
+        assert!(kick_member(&rt, group_id, String::from("carol")).is_err());
+        assert!(
+            edit_member_role(&rt, group_id, String::from("bob"), GroupMemberRole::Admin).is_err()
+        );
+
+        rt.set_caller(alice);
+        edit_member_role(&rt, group_id, String::from("bob"), GroupMemberRole::Admin).unwrap();
+
+        rt.set_caller(bob);
+        kick_member(&rt, group_id, String::from("carol")).unwrap();
+
+        rt.set_caller(carol);
+        assert!(get_group(&rt, group_id).is_err());
+    }
+}
@@ -1,176 +1,20 @@
-use std::{
-    sync::{Arc, Mutex},
-    time::Duration,
-};
-
 use ic_cdk::api::{
     call::RejectionCode,
-    management_canister::http_request::{
-        http_request, CanisterHttpRequestArgument, HttpMethod, HttpResponse,
-    },
+    management_canister::http_request::{CanisterHttpRequestArgument, HttpMethod, HttpResponse},
 };
 use serde::Deserialize;
 
-use crate::{chunk, globals::MEETINGS, meeting::MeetingProcessType};
-
-#[derive(Debug, Clone)]
-pub struct ProcessRequest {
-    group_id: u128,
-    meeting_id: u128,
-    uuid: String,
-    index: usize,
-}
+use crate::{config, job::JobKind, processor::VideoProcessor, runtime::Runtime};
 
 lazy_static::lazy_static! {
     pub static ref HTTP_OK: candid::Nat = candid::Nat::from(200u128);
 }
 
-thread_local! {
-    pub static CONCAT_REQUESTS: Arc<Mutex<Vec<ProcessRequest>>> = Arc::new(Mutex::new(Vec::new()));
-    pub static SUBTITLE_REQUESTS: Arc<Mutex<Vec<ProcessRequest>>> = Arc::new(Mutex::new(Vec::new()));
-}
-
-pub fn poll_concat_requests() {
-    ic_cdk::println!("Starting poll concat requests");
-    ic_cdk_timers::set_timer_interval(Duration::from_secs(10), || {
-        ic_cdk::spawn(async move {
-            let concat_requests =
-                CONCAT_REQUESTS.with(|concat_requests| concat_requests.lock().unwrap().clone());
-            if concat_requests.is_empty() {
-                return;
-            }
-
-            let mut indexes_to_remove = Vec::new();
-            for req in concat_requests {
-                let processed_video_data = match get_processed_video_concat(&req.uuid).await {
-                    Ok(processed_video_data) => processed_video_data,
-                    Err(err) => {
-                        ic_cdk::eprintln!("Error while getting processed video concat: {}", err);
-                        continue;
-                    }
-                };
-
-                let processed_video_data = match processed_video_data {
-                    Some(processed_video_data) => processed_video_data,
-                    None => continue,
-                };
-
-                let mut meetings = MEETINGS.lock().unwrap();
-                let meetings = meetings
-                    .get_mut(&req.group_id)
-                    .ok_or(String::from("No meetings found on this group!"))
-                    .unwrap();
-
-                let meeting = meetings
-                    .get_mut(&req.meeting_id)
-                    .ok_or(String::from("No meeting found on this meeting ID!"))
-                    .unwrap();
-
-                meeting.process_type = MeetingProcessType::None;
-                meeting.full_video_data = processed_video_data;
-                indexes_to_remove.push(req.uuid);
-            }
-
-            for uuid in indexes_to_remove {
-                let concat_requests =
-                    CONCAT_REQUESTS.with(|concat_requests| concat_requests.clone());
-                let mut concat_requests = concat_requests.lock().unwrap();
-                let index_to_remove = concat_requests.iter().position(|x| x.uuid == uuid).unwrap();
-                concat_requests.remove(index_to_remove);
-            }
-        });
-    });
-}
-
-pub fn poll_subtitle_requests() {
-    ic_cdk::println!("Starting poll subtitles requests");
-    ic_cdk_timers::set_timer_interval(Duration::from_secs(10), || {
-        ic_cdk::spawn(async move {
-            let subtitle_requests = SUBTITLE_REQUESTS
-                .with(|subtitle_requests| subtitle_requests.lock().unwrap().clone());
-            if subtitle_requests.is_empty() {
-                return;
-            }
-
-            let mut indexes_to_remove = Vec::new();
-
-            ic_cdk::println!("AH HA! {:?}", subtitle_requests);
-
-            for req in subtitle_requests {
-                let processed_video_data = match get_processed_video_subtitles(&req.uuid).await {
-                    Ok(processed_video_data) => processed_video_data,
-                    Err(err) => {
-                        ic_cdk::eprintln!("Error while getting processed video subtitles: {}", err);
-                        continue;
-                    }
-                };
-
-                let processed_video_data = match processed_video_data {
-                    Some(processed_video_data) => processed_video_data,
-                    None => continue,
-                };
-
-                let mut meetings = MEETINGS.lock().unwrap();
-                let meetings = meetings
-                    .get_mut(&req.group_id)
-                    .ok_or(String::from("No meetings found on this group!"))
-                    .unwrap();
-
-                let meeting = meetings
-                    .get_mut(&req.meeting_id)
-                    .ok_or(String::from("No meeting found on this meeting ID!"))
-                    .unwrap();
-
-                meeting.process_type = MeetingProcessType::None;
-                meeting.frames.get_mut(req.index).unwrap().data = processed_video_data.clone();
-                indexes_to_remove.push(req.uuid);
-
-                if meeting.full_video_data.is_empty() {
-                    meeting.full_video_data = processed_video_data.clone();
-                } else {
-                    meeting.process_type = MeetingProcessType::Concat;
-                    send_concat_video_request(
-                        req.group_id,
-                        req.meeting_id,
-                        meeting.full_video_data.clone(),
-                        processed_video_data,
-                    );
-                }
-            }
-
-            for uuid in indexes_to_remove {
-                let subtitle_requests =
-                    SUBTITLE_REQUESTS.with(|subtitle_requests| subtitle_requests.clone());
-                let mut subtitle_requests = subtitle_requests.lock().unwrap();
-                let index_to_remove = subtitle_requests
-                    .iter()
-                    .position(|x| x.uuid == uuid)
-                    .unwrap();
-                subtitle_requests.remove(index_to_remove);
-            }
-        });
-    });
-}
-
-pub fn send_concat_video_request(
-    group_id: u128,
-    meeting_id: u128,
-    video1: Vec<u8>,
-    video2: Vec<u8>,
-) {
-    ic_cdk::spawn(async move {
-        if let Err(err) =
-            send_concat_video_request_internal(group_id, meeting_id, video1, video2).await
-        {
-            ic_cdk::eprintln!("Error while sending video concat request: {}", err);
-        }
-    });
-}
-
+/// Returned by the processor once a job is finished, the result is then fetched chunk by chunk
 #[derive(Deserialize, Debug)]
-struct ChunkInfoResponse {
-    chunk_count: usize,
-    file_size: usize,
+pub struct ChunkInfoResponse {
+    pub chunk_count: usize,
+    pub file_size: usize,
 }
 
 pub fn map_response_body_to_err<T>(url: &str, response: HttpResponse) -> Result<T, String> {
@@ -181,256 +25,160 @@ pub fn map_response_body_to_err<T>(url: &str, response: HttpResponse) -> Result<
     Err(body_str)
 }
 
+fn processor_url(path: impl AsRef<str>) -> String {
+    format!(
+        "{}/{}",
+        config::get_config().processor_base_url,
+        path.as_ref()
+    )
+}
+
 pub async fn send_http_request(
+    rt: &impl Runtime,
     url: impl Into<String>,
     body: Vec<u8>,
     method: HttpMethod,
 ) -> Result<HttpResponse, (RejectionCode, String)> {
+    let config = config::get_config();
     let request = CanisterHttpRequestArgument {
         url: url.into(),
         body: Some(body),
         method,
         headers: Vec::new(),
         transform: None,
-        max_response_bytes: None,
+        max_response_bytes: config.processor_max_response_bytes,
     };
 
-    let response = http_request(request, 1_000_000_000_000).await?.0;
-    Ok(response)
+    rt.http_request(request, config.processor_cycles_per_request)
+        .await
 }
 
 pub async fn send_get_request(
+    rt: &impl Runtime,
     url: impl Into<String>,
 ) -> Result<HttpResponse, (RejectionCode, String)> {
-    send_http_request(url, Vec::new(), HttpMethod::GET).await
+    send_http_request(rt, url, Vec::new(), HttpMethod::GET).await
 }
 
 pub async fn send_post_request(
+    rt: &impl Runtime,
     url: impl Into<String>,
     body: Vec<u8>,
 ) -> Result<HttpResponse, (RejectionCode, String)> {
-    send_http_request(url, body, HttpMethod::POST).await
+    send_http_request(rt, url, body, HttpMethod::POST).await
 }
 
-pub async fn send_process_subtitles_request(
-    group_id: u128,
-    meeting_id: u128,
-    index: usize,
-    body: Vec<u8>,
-) -> Result<(), String> {
-    let uuid_response = send_post_request("http://localhost:17191/subtitles/start", Vec::new())
-        .await
-        .map_err(|(code, body)| {
-            format!(
-                "Failed to send HTTP request for processing subtitle.start ({:?}: {})",
-                code, body
-            )
-        })?;
+/// Talks to the video editor service at `Config::processor_base_url`.
+pub struct HttpProcessor<R> {
+    rt: R,
+}
 
-    if uuid_response.status != *HTTP_OK {
-        return map_response_body_to_err("subtitles.start", uuid_response);
+impl<R: Runtime> HttpProcessor<R> {
+    pub fn new(rt: R) -> Self {
+        Self { rt }
     }
 
-    let uuid = String::from_utf8(uuid_response.body).map_err(|_| {
-        String::from("Cannot convert bytes to uuid while processing subtitle.start")
-    })?;
-
-    let chunks = body.chunks(chunk::MB).collect::<Vec<_>>();
-    for chunk in chunks {
-        let url = format!("http://localhost:17191/subtitles/{}/add", uuid);
-        let response = send_post_request(&url, chunk.to_vec())
+    async fn post(&self, url: String, body: Vec<u8>, action: &str) -> Result<Vec<u8>, String> {
+        let response = send_post_request(&self.rt, &url, body)
             .await
-            .map_err(|_| String::from("Failed to send HTTP request for processing subtitle.add"))?;
+            .map_err(|(code, body)| {
+                format!(
+                    "Failed to send HTTP request for processing {} ({:?}: {})",
+                    action, code, body
+                )
+            })?;
         if response.status != *HTTP_OK {
             return map_response_body_to_err(&url, response);
         }
-    }
 
-    let url = format!("http://localhost:17191/subtitles/{}/end", uuid);
-    let response = send_post_request(&url, Vec::new())
-        .await
-        .map_err(|_| String::from("Failed to send HTTP request for processing subtitle.add"))?;
-    if response.status != *HTTP_OK {
-        return map_response_body_to_err(&url, response);
+        Ok(response.body)
     }
-
-    let uuid = String::from_utf8(response.body)
-        .map_err(|_| String::from("Cannot convert bytes to uuid"))?;
-
-    SUBTITLE_REQUESTS.with(|subtitle_requests| {
-        subtitle_requests.lock().unwrap().push(ProcessRequest {
-            group_id,
-            meeting_id,
-            uuid,
-            index,
-        });
-    });
-
-    Ok(())
 }
 
-pub async fn send_thumbnail_request(body: Vec<u8>) -> Result<Vec<u8>, String> {
-    let uuid_response = send_post_request("http://localhost:17191/thumbnail/start", Vec::new())
-        .await
-        .map_err(|err| {
-            format!(
-                "Failed to send HTTP request for processing thumbnail.start {:?} {}",
-                err.0, err.1
+impl<R: Runtime> VideoProcessor for HttpProcessor<R> {
+    async fn start(&self, kind: JobKind) -> Result<String, String> {
+        let path = kind.processor_path();
+        let body = self
+            .post(
+                processor_url(format!("{}/start", path)),
+                Vec::new(),
+                &format!("{}.start", path),
             )
-        })?;
-    if uuid_response.status != *HTTP_OK {
-        return map_response_body_to_err("thumbnail.start", uuid_response);
-    }
-
-    let uuid = String::from_utf8(uuid_response.body)
-        .map_err(|_| String::from("Cannot convert bytes to uuid"))?;
-
-    let chunks = body.chunks(chunk::MB).collect::<Vec<_>>();
-    for chunk in chunks.into_iter() {
-        let url = format!("http://localhost:17191/thumbnail/{}/add", uuid);
-
-        let response = send_post_request(url, chunk.to_vec()).await.map_err(|_| {
-            String::from("Failed to send HTTP request for processing thumbnail.add")
-        })?;
-        if response.status != *HTTP_OK {
-            return map_response_body_to_err("thumbnail.add", response);
-        }
-    }
+            .await?;
 
-    let url = format!("http://localhost:17191/thumbnail/{}/end", uuid);
-    let response = send_post_request(url, Vec::new())
-        .await
-        .map_err(|_| String::from("Failed to send HTTP request for processing thumbnail.end"))?;
-    if response.status != *HTTP_OK {
-        return map_response_body_to_err("thumbnail.end", response);
+        String::from_utf8(body).map_err(|_| String::from("Cannot convert bytes to uuid"))
     }
 
-    Ok(response.body)
-}
-
-async fn send_concat_video_request_internal(
-    group_id: u128,
-    meeting_id: u128,
-    video1: Vec<u8>,
-    video2: Vec<u8>,
-) -> Result<(), String> {
-    let uuid_response = send_post_request("http://localhost:17191/concat/start", Vec::new())
-        .await
-        .map_err(|_| String::from("Failed to send HTTP request for processing concat"))?;
-    if uuid_response.status != *HTTP_OK {
-        return map_response_body_to_err("concat.start", uuid_response);
-    }
-
-    let uuid = String::from_utf8(uuid_response.body)
-        .map_err(|_| String::from("Cannot convert bytes to uuid"))?;
-
-    let chunks = video1.chunks(chunk::MB);
-    for chunk in chunks.into_iter() {
-        let url = format!("http://localhost:17191/concat/{}/add", uuid);
-
-        let response = send_post_request(url, chunk.to_vec())
-            .await
-            .map_err(|_| String::from("Failed to send HTTP request for processing concat.add"))?;
-        if response.status != *HTTP_OK {
-            return map_response_body_to_err("concat.add", response);
-        }
-    }
-
-    let chunks = video2.chunks(chunk::MB);
-    for (i, chunk) in chunks.into_iter().enumerate() {
-        let url = if i == 0 {
-            format!("http://localhost:17191/concat/{}/new", uuid)
+    async fn add(
+        &self,
+        kind: JobKind,
+        id: &str,
+        input_index: usize,
+        chunk_index: usize,
+        chunk: &[u8],
+    ) -> Result<(), String> {
+        let path = kind.processor_path();
+        // every input after the first one is opened with `new`
+        let action = if input_index > 0 && chunk_index == 0 {
+            "new"
         } else {
-            format!("http://localhost:17191/concat/{}/add", uuid)
+            "add"
         };
 
-        let response = send_post_request(&url, chunk.to_vec())
-            .await
-            .map_err(|_| String::from("Failed to send HTTP request for processing concat.add"))?;
-        if response.status != *HTTP_OK {
-            return map_response_body_to_err(&url, response);
-        }
-    }
-
-    let url = format!("http://localhost:17191/concat/{}/end", uuid);
-    let response = send_post_request(&url, Vec::new())
+        self.post(
+            processor_url(format!("{}/{}/{}", path, id, action)),
+            chunk.to_vec(),
+            &format!("{}.{}", path, action),
+        )
         .await
-        .map_err(|_| String::from("Failed to send HTTP request for processing concat.end"))?;
-    if response.status != *HTTP_OK {
-        return map_response_body_to_err(&url, response);
+        .map(|_| ())
     }
 
-    let uuid = String::from_utf8(response.body)
-        .map_err(|_| String::from("Cannot convert bytes to uuid"))?;
-
-    CONCAT_REQUESTS.with(|concat_requests| {
-        concat_requests.lock().unwrap().push(ProcessRequest {
-            group_id,
-            meeting_id,
-            uuid,
-            index: 0,
-        });
-    });
-
-    Ok(())
-}
-
-pub async fn get_processed_video_subtitles(uuid: &str) -> Result<Option<Vec<u8>>, String> {
-    let url = format!("http://localhost:17191/subtitles/{}", uuid);
-    let uuid_response = send_get_request(url).await.map_err(|_| {
-        String::from("Failed to send HTTP request for getting processed video subtitles")
-    })?;
-    if uuid_response.status != *HTTP_OK {
-        return map_response_body_to_err("subtitles.get", uuid_response);
+    async fn end(&self, kind: JobKind, id: &str) -> Result<Vec<u8>, String> {
+        let path = kind.processor_path();
+        self.post(
+            processor_url(format!("{}/{}/end", path, id)),
+            Vec::new(),
+            &format!("{}.end", path),
+        )
+        .await
     }
 
-    let response = serde_json::from_slice::<ChunkInfoResponse>(&uuid_response.body)
-        .map_err(|err| format!("Deserialize to chunk info from json error: {}", err))?;
-
-    let mut data = Vec::with_capacity(response.file_size);
-    for i in 0..response.chunk_count {
-        let url = format!("http://localhost:17191/subtitles/{}/{}", uuid, i + 1);
-        let response = send_get_request(url).await.map_err(|_| {
-            String::from("Failed to send HTTP request for getting processed video subtitles chunk")
-        })?;
-
+    async fn poll(&self, kind: JobKind, id: &str) -> Result<Option<ChunkInfoResponse>, String> {
+        let path = kind.processor_path();
+        let response = send_get_request(&self.rt, processor_url(format!("{}/{}", path, id)))
+            .await
+            .map_err(|(code, body)| {
+                format!(
+                    "Failed to send HTTP request for getting processed video {} ({:?}: {})",
+                    path, code, body
+                )
+            })?;
+
+        // the processor answers with an error status until the result is ready
         if response.status != *HTTP_OK {
-            return map_response_body_to_err("subtitles.get.i", response);
+            return Ok(None);
         }
 
-        data.extend(response.body);
-    }
-
-    Ok(Some(data))
-}
-
-pub async fn get_processed_video_concat(uuid: &str) -> Result<Option<Vec<u8>>, String> {
-    let url = format!("http://localhost:17191/concat/{}", uuid);
-    let uuid_response = send_get_request(url).await.map_err(|_| {
-        String::from("Failed to send HTTP request for getting processed video concat")
-    })?;
-    if uuid_response.status != *HTTP_OK {
-        return map_response_body_to_err("concat.get", uuid_response);
+        serde_json::from_slice::<ChunkInfoResponse>(&response.body)
+            .map(Some)
+            .map_err(|err| format!("Deserialize to chunk info from json error: {}", err))
     }
 
-    let response = serde_json::from_slice::<ChunkInfoResponse>(&uuid_response.body)
-        .map_err(|err| format!("Deserialize to chunk info from json error: {}", err))?;
-
-    ic_cdk::println!("concat/id {:?} {:?}", uuid_response.body, response);
-
-    let mut data = Vec::with_capacity(response.file_size);
-    for i in 0..response.chunk_count {
-        let url = format!("http://localhost:17191/concat/{}/{}", uuid, i + 1);
-        let response = send_get_request(url).await.map_err(|_| {
-            String::from("Failed to send HTTP request for getting processed video concat chunk")
+    async fn fetch(&self, kind: JobKind, id: &str, chunk_index: usize) -> Result<Vec<u8>, String> {
+        let path = kind.processor_path();
+        let url = processor_url(format!("{}/{}/{}", path, id, chunk_index + 1));
+        let response = send_get_request(&self.rt, &url).await.map_err(|_| {
+            format!(
+                "Failed to send HTTP request for getting processed video {} chunk",
+                path
+            )
         })?;
 
         if response.status != *HTTP_OK {
-            return map_response_body_to_err("concat.get.i", response);
+            return map_response_body_to_err(&url, response);
         }
 
-        data.extend(response.body);
+        Ok(response.body)
     }
-
-    Ok(Some(data))
 }
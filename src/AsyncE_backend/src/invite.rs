@@ -1,12 +1,24 @@
+use std::collections::BTreeSet;
+
 use candid::CandidType;
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    globals::{GROUPS, GROUP_INVITES, USERS},
+    error::ApiError,
+    globals::{GROUPS, GROUP_INVITES},
     group::{GroupMember, GroupMemberRole},
+    impl_candid_storable,
+    notification::{self, NotificationKind},
+    runtime::Runtime,
     user, websocket,
 };
 
+/// IDs of the groups a user has been invited to
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+pub struct GroupInviteSet(pub BTreeSet<u128>);
+
+impl_candid_storable!(GroupInviteSet);
+
 #[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
 pub struct GroupInviteResponse {
     pub group_id: u128,
@@ -14,11 +26,11 @@ pub struct GroupInviteResponse {
 }
 
 impl GroupInviteResponse {
-    fn new(group_id: u128) -> Result<Self, String> {
+    fn new(group_id: u128) -> Result<Self, ApiError> {
         GROUPS.with_borrow(|groups| {
             let group_name = groups
                 .get(&group_id)
-                .ok_or(String::from("Cannot find this group with the provided ID!"))?
+                .ok_or(ApiError::not_found("group", group_id))?
                 .name
                 .clone();
 
@@ -30,102 +42,164 @@ impl GroupInviteResponse {
     }
 }
 
-#[ic_cdk::update]
-pub fn invite_user(group_id: u128, username: String) -> Result<(), String> {
-    user::assert_user_logged_in()?;
+pub fn invite_user(rt: &impl Runtime, group_id: u128, username: String) -> Result<(), ApiError> {
+    user::assert_user_logged_in(rt)?;
 
-    let selfuser =
-        user::get_selfuser()?.ok_or(String::from("This user does not have a username!"))?;
+    let selfuser = user::get_selfuser(rt)?.ok_or(ApiError::NoUsername)?;
 
     GROUPS.with_borrow(|groups| {
         let group = groups
             .get(&group_id)
-            .ok_or(String::from("Cannot find group with this ID!"))?;
+            .ok_or(ApiError::not_found("group", group_id))?;
 
         if !group.is_member(&selfuser.username) {
-            return Err(String::from("This user is not in this group!"));
+            return Err(ApiError::forbidden("This user is not in this group!"));
         }
 
         if group.is_member(&username) {
-            return Err(String::from("Chosen user is already in this group!"));
+            return Err(ApiError::conflict("Chosen user is already in this group!"));
         }
 
         if selfuser.subscription.is_none() && group.members.len() >= 10 {
-            return Err(String::from(
-                "User must be subscribed to invite more users!",
-            ));
+            return Err(ApiError::SubscriptionRequired);
         }
 
         GROUP_INVITES.with_borrow_mut(|group_invites| {
-            let group_invites = group_invites.entry(username.clone()).or_default();
-            if group_invites.contains(&group_id) {
-                return Err(String::from(
+            let mut user_group_invites = group_invites.get(&username).unwrap_or_default();
+            if user_group_invites.0.contains(&group_id) {
+                return Err(ApiError::conflict(
                     "Chosen user is already invited to this group!",
                 ));
             }
 
-            USERS.with_borrow(|users| {
-                let principal = users
-                    .iter()
-                    .find(|user| user.1.username.eq_ignore_ascii_case(&username))
-                    .map(|x| *x.0)
-                    .ok_or(String::from("Cannot find user with this username!"))?;
+            let principal =
+                user::get_principal(&username).ok_or(ApiError::not_found("user", &username))?;
+
+            user_group_invites.0.insert(group_id);
+            group_invites.insert(username, user_group_invites);
+            websocket::send_group_invited_notif(rt, principal, group.id, &group.name);
+            notification::notify(
+                rt,
+                principal,
+                NotificationKind::GroupInvited {
+                    group_id: group.id,
+                    group_name: group.name.clone(),
+                },
+            );
 
-                group_invites.insert(group_id);
-                websocket::send_group_invited_notif(principal, group.id, &group.name);
-
-                Ok(())
-            })
+            Ok(())
         })
     })
 }
 
-#[ic_cdk::query]
-pub fn get_self_group_invites() -> Result<Vec<GroupInviteResponse>, String> {
-    user::assert_user_logged_in()?;
+pub fn get_self_group_invites(rt: &impl Runtime) -> Result<Vec<GroupInviteResponse>, ApiError> {
+    user::assert_user_logged_in(rt)?;
 
-    let selfname = user::get_selfname_force()?;
+    let selfname = user::get_selfname_force(rt)?;
 
     GROUP_INVITES.with_borrow(|group_invites| {
         group_invites
             .get(&selfname)
-            .cloned()
             .unwrap_or_default()
+            .0
             .into_iter()
             .map(GroupInviteResponse::new)
             .collect::<_>()
     })
 }
 
-#[ic_cdk::update]
-pub fn update_group_invite(group_id: u128, approved: bool) -> Result<(), String> {
-    user::assert_user_logged_in()?;
+pub fn update_group_invite(
+    rt: &impl Runtime,
+    group_id: u128,
+    approved: bool,
+) -> Result<(), ApiError> {
+    user::assert_user_logged_in(rt)?;
 
-    let selfname = user::get_selfname_force()?;
+    let selfname = user::get_selfname_force(rt)?;
 
     GROUP_INVITES.with_borrow_mut(|group_invites| {
-        let group_invites = group_invites
-            .get_mut(&selfname)
-            .ok_or(String::from("Cannot find invite data on this group"))?;
+        let mut user_group_invites = group_invites
+            .get(&selfname)
+            .ok_or(ApiError::not_found("invite", group_id))?;
 
-        if !group_invites.contains(&group_id) {
-            return Err(String::from("Cannot find invite data on this group"));
+        if !user_group_invites.0.contains(&group_id) {
+            return Err(ApiError::not_found("invite", group_id));
         }
 
         GROUPS.with_borrow_mut(|groups| {
-            group_invites.remove(&group_id);
+            user_group_invites.0.remove(&group_id);
+            group_invites.insert(selfname.clone(), user_group_invites);
 
             if approved {
-                let group = groups
-                    .get_mut(&group_id)
-                    .ok_or(String::from("Cannot find group with this ID!"))?;
+                let mut group = groups
+                    .get(&group_id)
+                    .ok_or(ApiError::not_found("group", group_id))?;
 
                 group
                     .members
                     .push(GroupMember::new(selfname, GroupMemberRole::Member));
+                groups.insert(group_id, group);
             }
 
             Ok(())
         })
     })
 }
+
+mod endpoints {
+    use super::*;
+    use crate::runtime::IcRuntime;
+
+    #[ic_cdk::update]
+    fn invite_user(group_id: u128, username: String) -> Result<(), ApiError> {
+        super::invite_user(&IcRuntime, group_id, username)
+    }
+
+    #[ic_cdk::query]
+    fn get_self_group_invites() -> Result<Vec<GroupInviteResponse>, ApiError> {
+        super::get_self_group_invites(&IcRuntime)
+    }
+
+    #[ic_cdk::update]
+    fn update_group_invite(group_id: u128, approved: bool) -> Result<(), ApiError> {
+        super::update_group_invite(&IcRuntime, group_id, approved)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{group, runtime::TestRuntime, user::tests::sign_in};
+
+    #[test]
+    fn invited_users_join_after_accepting() {
+        let rt = TestRuntime::new();
+        let bob = sign_in(&rt, 2, "bob");
+        let carol = sign_in(&rt, 3, "carol");
+        let alice = sign_in(&rt, 1, "alice");
+        let group_id = group::create_group(&rt, String::from("Team")).unwrap();
+
+        invite_user(&rt, group_id, String::from("bob")).unwrap();
+        invite_user(&rt, group_id, String::from("carol")).unwrap();
+        assert!(invite_user(&rt, group_id, String::from("bob")).is_err());
+        assert!(invite_user(&rt, group_id, String::from("dave")).is_err());
+
+        rt.set_caller(bob);
+        let invites = get_self_group_invites(&rt).unwrap();
+        assert_eq!(invites.len(), 1);
+        assert_eq!(invites[0].group_name, "Team");
+        assert!(group::get_group(&rt, group_id).is_err());
+
+        update_group_invite(&rt, group_id, true).unwrap();
+        assert!(get_self_group_invites(&rt).unwrap().is_empty());
+        assert!(group::get_group(&rt, group_id).unwrap().is_some());
+        assert!(update_group_invite(&rt, group_id, true).is_err());
+
+        rt.set_caller(carol);
+        update_group_invite(&rt, group_id, false).unwrap();
+        assert!(group::get_group(&rt, group_id).is_err());
+
+        rt.set_caller(alice);
+        assert!(invite_user(&rt, group_id, String::from("bob")).is_err());
+    }
+}
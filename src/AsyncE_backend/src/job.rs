@@ -0,0 +1,996 @@
+use std::{cell::RefCell, collections::BTreeMap, time::Duration};
+
+use candid::CandidType;
+use serde::Deserialize;
+
+use crate::{
+    blob::{self, BlobId},
+    certification,
+    error::ApiError,
+    globals::{GROUPS, JOBS, JOB_QUEUE, MEETINGS, MEETING_JOBS},
+    impl_candid_storable,
+    meeting::{self, MeetingProcessType},
+    notification::{self, NotificationKind},
+    primary_key::{self, PrimaryKeyType},
+    processor::{self, VideoProcessor},
+    runtime::{IcRuntime, Runtime},
+    user, websocket,
+};
+
+/// A job is marked as failed once this many attempts went wrong.
+pub const MAX_JOB_ATTEMPTS: u32 = 5;
+
+const JOB_POLL_INTERVAL: Duration = Duration::from_secs(10);
+const RETRY_BASE_DELAY: Duration = Duration::from_secs(30);
+const RETRY_MAX_DELAY: Duration = Duration::from_secs(60 * 60);
+
+/// How long the processor may take on a job before it is uploaded again.
+const PROCESSING_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+
+/// Steps that trapped never release their lease, so leases expire after a while.
+const LEASE_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// Finished jobs stay around this long for admins to look into, then they are deleted.
+pub const JOB_RETENTION: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+const MAX_PRUNED_JOBS_PER_RUN: usize = 100;
+
+#[derive(Copy, Clone, Debug, CandidType, Deserialize, PartialEq, Eq)]
+pub enum JobKind {
+    Concat,
+    Subtitle,
+    Thumbnail,
+}
+
+impl JobKind {
+    pub fn processor_path(&self) -> &'static str {
+        match self {
+            Self::Concat => "concat",
+            Self::Subtitle => "subtitles",
+            Self::Thumbnail => "thumbnail",
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, CandidType, Deserialize, PartialEq, Eq)]
+pub enum JobState {
+    Queued,
+    Uploading,
+    Processing,
+    Fetching,
+    Done,
+    Failed,
+    Cancelled,
+}
+
+impl JobState {
+    pub fn is_finished(&self) -> bool {
+        matches!(self, Self::Done | Self::Failed | Self::Cancelled)
+    }
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct Job {
+    pub id: u128,
+    pub kind: JobKind,
+    pub group_id: u128,
+    pub meeting_id: u128,
+    pub frame_index: u128,
+    pub requested_by: String,
+
+    /// Videos sent to the processor, referenced in the blob store until the job is finished
+    pub inputs: Vec<BlobId>,
+
+    pub state: JobState,
+    /// ID the processor gave this job, set once the upload went through
+    pub processor_id: Option<String>,
+    pub attempts: u32,
+    pub last_error: Option<String>,
+    pub next_attempt_time_unix: u128,
+
+    pub created_time_unix: u128,
+    /// Last time the job changed its state
+    pub updated_time_unix: u128,
+}
+
+impl_candid_storable!(Job);
+
+thread_local! {
+    // jobs that have a step running right now, keyed to the time the step started
+    static JOB_LEASES: RefCell<BTreeMap<u128, u128>> = RefCell::default();
+}
+
+pub fn get_job(job_id: u128) -> Option<Job> {
+    JOBS.with_borrow(|jobs| jobs.get(&job_id))
+}
+
+/// Saves the job and keeps its place in the queue up to date.
+fn save_job(job: Job) {
+    let queue_key = (!job.state.is_finished()).then_some((job.next_attempt_time_unix, job.id));
+    let previous = JOBS.with_borrow_mut(|jobs| jobs.insert(job.id, job));
+
+    JOB_QUEUE.with_borrow_mut(|job_queue| {
+        if let Some(previous) = previous.filter(|job| !job.state.is_finished()) {
+            job_queue.remove(&(previous.next_attempt_time_unix, previous.id));
+        }
+        if let Some(queue_key) = queue_key {
+            job_queue.insert(queue_key, ());
+        }
+    });
+}
+
+/// Exponential backoff, doubling after every failed attempt.
+pub fn retry_delay(attempts: u32) -> Duration {
+    RETRY_BASE_DELAY
+        .saturating_mul(2u32.saturating_pow(attempts.saturating_sub(1)))
+        .min(RETRY_MAX_DELAY)
+}
+
+/// Queues a new job, the inputs are retained until the job is finished.
+pub fn enqueue(
+    rt: &impl Runtime,
+    kind: JobKind,
+    group_id: u128,
+    meeting_id: u128,
+    frame_index: usize,
+    requested_by: String,
+    inputs: Vec<BlobId>,
+) -> u128 {
+    for input in inputs.iter() {
+        blob::retain(input);
+    }
+
+    let now = rt.time();
+    let job = Job {
+        id: primary_key::get_primary_key(PrimaryKeyType::Job),
+        kind,
+        group_id,
+        meeting_id,
+        frame_index: frame_index as u128,
+        requested_by,
+        inputs,
+        state: JobState::Queued,
+        processor_id: None,
+        attempts: 0,
+        last_error: None,
+        next_attempt_time_unix: now,
+        created_time_unix: now,
+        updated_time_unix: now,
+    };
+    let job_id = job.id;
+    MEETING_JOBS
+        .with_borrow_mut(|meeting_jobs| meeting_jobs.insert((group_id, meeting_id, job_id), ()));
+    save_job(job);
+
+    // no need to wait for the next poll
+    let worker = rt.clone();
+    rt.set_timer(Duration::ZERO, move || run_due_jobs(&worker));
+
+    job_id
+}
+
+pub fn poll_jobs() {
+    ic_cdk::println!("Starting poll processing jobs");
+    ic_cdk_timers::set_timer_interval(JOB_POLL_INTERVAL, || {
+        run_due_jobs(&IcRuntime);
+        prune_finished_jobs(&IcRuntime);
+    });
+}
+
+fn run_due_jobs(rt: &impl Runtime) {
+    let now = rt.time();
+    let due_jobs = JOB_QUEUE.with_borrow(|job_queue| {
+        job_queue
+            .range(..(now + 1, u128::MIN))
+            .map(|((_, job_id), _)| job_id)
+            .collect::<Vec<_>>()
+    });
+
+    for job_id in due_jobs {
+        let leased = JOB_LEASES.with_borrow_mut(|job_leases| {
+            if job_leases
+                .get(&job_id)
+                .is_some_and(|started| now.saturating_sub(*started) < LEASE_TIMEOUT.as_nanos())
+            {
+                return false;
+            }
+
+            job_leases.insert(job_id, now);
+            true
+        });
+
+        if leased {
+            let worker = rt.clone();
+            rt.spawn(async move {
+                run_job(&worker, job_id).await;
+                JOB_LEASES.with_borrow_mut(|job_leases| job_leases.remove(&job_id));
+            });
+        }
+    }
+}
+
+async fn run_job(rt: &impl Runtime, job_id: u128) {
+    let Some(job) = get_job(job_id) else {
+        return;
+    };
+
+    match job.state {
+        JobState::Queued | JobState::Uploading => upload(rt, job).await,
+        JobState::Processing => poll(rt, job).await,
+        JobState::Fetching => fetch(rt, job).await,
+        JobState::Done | JobState::Failed | JobState::Cancelled => {}
+    }
+}
+
+/// Applies `update` only if the job is still in `expected`, which it may have left while we were awaiting.
+fn transition(
+    rt: &impl Runtime,
+    job_id: u128,
+    expected: JobState,
+    update: impl FnOnce(&mut Job),
+) -> Option<Job> {
+    let mut job = get_job(job_id).filter(|job| job.state == expected)?;
+    let state = job.state;
+    update(&mut job);
+    if job.state != state {
+        job.updated_time_unix = rt.time();
+    }
+
+    save_job(job.clone());
+    Some(job)
+}
+
+fn set_state(rt: &impl Runtime, job_id: u128, expected: JobState, state: JobState) -> Option<Job> {
+    transition(rt, job_id, expected, |job| job.state = state)
+}
+
+async fn upload(rt: &impl Runtime, job: Job) {
+    let Some(job) = set_state(rt, job.id, job.state, JobState::Uploading) else {
+        return;
+    };
+
+    let inputs = job.inputs.iter().map(blob::read).collect::<Vec<_>>();
+    if inputs.iter().any(|x| x.is_empty()) {
+        return fail(rt, job.id, JobState::Uploading, "Input video is missing!");
+    }
+
+    let response = match processor::upload(&processor::get_processor(rt), job.kind, &inputs).await {
+        Ok(response) => response,
+        Err(err) => return retry(rt, job.id, JobState::Uploading, JobState::Queued, err),
+    };
+
+    // thumbnails are returned right away, there is nothing to poll
+    if job.kind == JobKind::Thumbnail {
+        return finish(rt, job.id, JobState::Uploading, response);
+    }
+
+    match String::from_utf8(response) {
+        Ok(processor_id) => {
+            transition(rt, job.id, JobState::Uploading, |job| {
+                job.state = JobState::Processing;
+                job.processor_id = Some(processor_id);
+            });
+        }
+        Err(_) => retry(
+            rt,
+            job.id,
+            JobState::Uploading,
+            JobState::Queued,
+            String::from("Cannot convert bytes to uuid"),
+        ),
+    }
+}
+
+async fn poll(rt: &impl Runtime, job: Job) {
+    let processor_id = job.processor_id.clone().unwrap_or_default();
+    match processor::get_processor(rt)
+        .poll(job.kind, &processor_id)
+        .await
+    {
+        Ok(Some(_)) => {
+            if let Some(job) = set_state(rt, job.id, JobState::Processing, JobState::Fetching) {
+                fetch(rt, job).await;
+            }
+        }
+        Ok(None) => {
+            if rt.time().saturating_sub(job.updated_time_unix) > PROCESSING_TIMEOUT.as_nanos() {
+                retry(
+                    rt,
+                    job.id,
+                    JobState::Processing,
+                    JobState::Queued,
+                    String::from("Processor did not finish the job in time"),
+                );
+                return;
+            }
+
+            transition(rt, job.id, JobState::Processing, |job| {
+                job.next_attempt_time_unix = rt.time() + JOB_POLL_INTERVAL.as_nanos();
+            });
+        }
+        Err(err) => retry(rt, job.id, JobState::Processing, JobState::Processing, err),
+    }
+}
+
+async fn fetch(rt: &impl Runtime, job: Job) {
+    let processor = processor::get_processor(rt);
+    let processor_id = job.processor_id.clone().unwrap_or_default();
+
+    let data = match processor.poll(job.kind, &processor_id).await {
+        Ok(Some(info)) => processor::download(&processor, job.kind, &processor_id, &info).await,
+        Ok(None) => Err(String::from("Processed video is not available anymore")),
+        Err(err) => Err(err),
+    };
+
+    match data {
+        Ok(data) => finish(rt, job.id, JobState::Fetching, data),
+        Err(err) => retry(rt, job.id, JobState::Fetching, JobState::Queued, err),
+    }
+}
+
+/// Counts a failed attempt, the job continues from `restart` after a backoff until it runs out of attempts.
+fn retry(rt: &impl Runtime, job_id: u128, expected: JobState, restart: JobState, err: String) {
+    ic_cdk::println!("Processing job {} failed: {}", job_id, err);
+
+    let Some(job) = transition(rt, job_id, expected, |job| {
+        job.attempts += 1;
+        job.last_error = Some(err);
+        job.state = restart;
+        job.next_attempt_time_unix = rt.time() + retry_delay(job.attempts).as_nanos();
+    }) else {
+        return;
+    };
+
+    if job.attempts >= MAX_JOB_ATTEMPTS {
+        fail(rt, job_id, restart, "Too many failed attempts");
+    }
+}
+
+/// Marks the job as failed for good, the meeting is unlocked and the uploader is notified.
+fn fail(rt: &impl Runtime, job_id: u128, expected: JobState, reason: &str) {
+    let Some(job) = transition(rt, job_id, expected, |job| {
+        job.state = JobState::Failed;
+        job.last_error = Some(match job.last_error.take() {
+            Some(last_error) => format!("{}: {}", reason, last_error),
+            None => reason.to_string(),
+        });
+    }) else {
+        return;
+    };
+
+    abandon(rt, &job);
+}
+
+/// Cleans up after a job that will not produce a result.
+fn abandon(rt: &impl Runtime, job: &Job) {
+    release_inputs(job);
+
+    if job.kind != JobKind::Thumbnail {
+        MEETINGS.with_borrow_mut(|meetings| {
+            if let Some(mut meeting) = meetings.get(&(job.group_id, job.meeting_id)) {
+                meeting.process_type = MeetingProcessType::None;
+                certification::certify_meeting(rt, job.group_id, &meeting);
+                meetings.insert((job.group_id, job.meeting_id), meeting);
+            }
+        });
+    }
+
+    websocket::send_processing_failed_notif(rt, job);
+    if job.kind != JobKind::Thumbnail {
+        notify_processing_finished(rt, job, Some(job.last_error.clone().unwrap_or_default()));
+    }
+}
+
+/// Thumbnails happen in the background, users only hear about the meeting being ready.
+fn notify_processing_finished(rt: &impl Runtime, job: &Job, error: Option<String>) {
+    notification::notify_user(
+        rt,
+        &job.requested_by,
+        NotificationKind::ProcessingFinished {
+            job_id: job.id,
+            group_id: job.group_id,
+            meeting_id: job.meeting_id,
+            error,
+        },
+    );
+}
+
+/// Applies the result and marks the job as done, a job that is not in the `expected` state
+/// anymore was cancelled while its step ran and drops the result.
+fn finish(rt: &impl Runtime, job_id: u128, expected: JobState, data: Vec<u8>) {
+    let Some(job) = get_job(job_id).filter(|job| job.state == expected) else {
+        return;
+    };
+
+    let applied = match job.kind {
+        JobKind::Concat => apply_concat(rt, &job, data),
+        JobKind::Subtitle => apply_subtitle(rt, &job, data),
+        JobKind::Thumbnail => apply_thumbnail(rt, &job, data),
+    };
+
+    if let Err(err) = applied {
+        ic_cdk::println!(
+            "Cannot apply the result of processing job {}: {}",
+            job_id,
+            err
+        );
+        let Some(job) = transition(rt, job_id, expected, |job| {
+            job.state = JobState::Failed;
+            job.last_error = Some(format!("Cannot apply the result: {}", err));
+        }) else {
+            return;
+        };
+        return abandon(rt, &job);
+    }
+
+    let Some(job) = set_state(rt, job_id, expected, JobState::Done) else {
+        return;
+    };
+    if job.kind != JobKind::Thumbnail && is_meeting_ready(&job) {
+        notify_processing_finished(rt, &job, None);
+    }
+
+    release_inputs(&job);
+}
+
+/// Subtitles on a later part are followed by a concat job, the meeting is only ready after that.
+fn is_meeting_ready(job: &Job) -> bool {
+    meeting::get_meeting(job.group_id, job.meeting_id)
+        .is_ok_and(|meeting| meeting.process_type == MeetingProcessType::None)
+}
+
+fn release_inputs(job: &Job) {
+    for input in job.inputs.iter() {
+        blob::release(input);
+    }
+}
+
+fn apply_concat(rt: &impl Runtime, job: &Job, data: Vec<u8>) -> Result<(), ApiError> {
+    let mut meeting = meeting::get_meeting(job.group_id, job.meeting_id)?;
+
+    meeting.process_type = MeetingProcessType::None;
+    blob::replace(&mut meeting.full_video, blob::store(&data));
+    certification::certify_meeting(rt, job.group_id, &meeting);
+    MEETINGS.with_borrow_mut(|meetings| meetings.insert((job.group_id, job.meeting_id), meeting));
+
+    Ok(())
+}
+
+fn apply_subtitle(rt: &impl Runtime, job: &Job, data: Vec<u8>) -> Result<(), ApiError> {
+    let mut meeting = meeting::get_meeting(job.group_id, job.meeting_id)?;
+
+    meeting.process_type = MeetingProcessType::None;
+    let frame = meeting
+        .frames
+        .get_mut(job.frame_index as usize)
+        .ok_or(ApiError::not_found("frame", job.frame_index))?;
+    let video = blob::store(&data);
+    blob::release(&frame.video);
+    frame.video = video.clone();
+
+    // the frame was the first part of the meeting, the full video is still the raw upload
+    if job.frame_index == 0 {
+        blob::retain(&video);
+        blob::replace(&mut meeting.full_video, video);
+    } else if let Some(full_video) = meeting.full_video.clone() {
+        meeting.process_type = MeetingProcessType::Concat;
+        enqueue(
+            rt,
+            JobKind::Concat,
+            job.group_id,
+            job.meeting_id,
+            job.frame_index as usize,
+            job.requested_by.clone(),
+            vec![full_video, video],
+        );
+    } else {
+        blob::retain(&video);
+        meeting.full_video = Some(video);
+    }
+
+    certification::certify_meeting(rt, job.group_id, &meeting);
+    MEETINGS.with_borrow_mut(|meetings| meetings.insert((job.group_id, job.meeting_id), meeting));
+
+    Ok(())
+}
+
+fn apply_thumbnail(rt: &impl Runtime, job: &Job, data: Vec<u8>) -> Result<(), ApiError> {
+    let group = GROUPS
+        .with_borrow(|groups| groups.get(&job.group_id))
+        .ok_or(ApiError::not_found("group", job.group_id))?;
+    let mut meeting = meeting::get_meeting(job.group_id, job.meeting_id)?;
+
+    if meeting.frames.len() <= job.frame_index as usize {
+        return Err(ApiError::not_found("frame", job.frame_index));
+    }
+
+    let thumbnail = blob::store(&data);
+    if meeting.thumbnail.is_none() {
+        blob::retain(&thumbnail);
+        meeting.thumbnail = Some(thumbnail.clone());
+    }
+
+    let frame = &mut meeting.frames[job.frame_index as usize];
+    blob::replace(&mut frame.thumbnail, thumbnail);
+
+    certification::certify_meeting(rt, job.group_id, &meeting);
+    MEETINGS.with_borrow_mut(|meetings| meetings.insert((job.group_id, job.meeting_id), meeting));
+
+    websocket::broadcast_thumbnail(rt, &group, job.meeting_id, job.frame_index as usize);
+
+    Ok(())
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct JobResponse {
+    pub id: u128,
+    pub kind: JobKind,
+    pub state: JobState,
+    pub frame_index: u128,
+    pub requested_by: String,
+    pub attempts: u32,
+    pub max_attempts: u32,
+    pub last_error: Option<String>,
+    pub next_attempt_time_unix: u128,
+    pub created_time_unix: u128,
+    pub updated_time_unix: u128,
+}
+
+impl From<&Job> for JobResponse {
+    fn from(value: &Job) -> Self {
+        Self {
+            id: value.id,
+            kind: value.kind,
+            state: value.state,
+            frame_index: value.frame_index,
+            requested_by: value.requested_by.clone(),
+            attempts: value.attempts,
+            max_attempts: MAX_JOB_ATTEMPTS,
+            last_error: value.last_error.clone(),
+            next_attempt_time_unix: value.next_attempt_time_unix,
+            created_time_unix: value.created_time_unix,
+            updated_time_unix: value.updated_time_unix,
+        }
+    }
+}
+
+/// Returns the job if the caller is an admin of the group it belongs to.
+fn get_job_as_admin(rt: &impl Runtime, job_id: u128) -> Result<(Job, String), ApiError> {
+    let job = get_job(job_id).ok_or(ApiError::not_found("job", job_id))?;
+
+    let selfname = user::get_selfname_force(rt)?;
+    let group = GROUPS
+        .with_borrow(|groups| groups.get(&job.group_id))
+        .ok_or(ApiError::not_found("group", job.group_id))?;
+    if !group.is_admin(&selfname) {
+        return Err(ApiError::forbidden(
+            "Only an admin can manage processing jobs!",
+        ));
+    }
+
+    Ok((job, selfname))
+}
+
+/// Deletes the jobs that finished more than [`JOB_RETENTION`] ago.
+///
+/// Job IDs grow with their creation time, so the walk stops at the first job that is too young
+/// to be deleted, and after [`MAX_PRUNED_JOBS_PER_RUN`] deletions the next run picks up the rest.
+pub fn prune_finished_jobs(rt: &impl Runtime) {
+    let cutoff = rt.time().saturating_sub(JOB_RETENTION.as_nanos());
+    let expired_jobs = JOBS.with_borrow(|jobs| {
+        jobs.iter()
+            .map(|(_, job)| job)
+            .take_while(|job| job.created_time_unix < cutoff)
+            .filter(|job| job.state.is_finished() && job.updated_time_unix < cutoff)
+            .take(MAX_PRUNED_JOBS_PER_RUN)
+            .collect::<Vec<_>>()
+    });
+
+    for job in expired_jobs {
+        JOBS.with_borrow_mut(|jobs| jobs.remove(&job.id));
+        MEETING_JOBS.with_borrow_mut(|meeting_jobs| {
+            meeting_jobs.remove(&(job.group_id, job.meeting_id, job.id))
+        });
+    }
+}
+
+/// Cancels the jobs of a deleted meeting and forgets which jobs ran for it,
+/// the finished ones are left to [`prune_finished_jobs`].
+pub fn cancel_meeting_jobs(rt: &impl Runtime, group_id: u128, meeting_id: u128) {
+    let keys = MEETING_JOBS.with_borrow(|meeting_jobs| {
+        meeting_jobs
+            .range((group_id, meeting_id, u128::MIN)..=(group_id, meeting_id, u128::MAX))
+            .map(|(key, _)| key)
+            .collect::<Vec<_>>()
+    });
+
+    for key in keys {
+        MEETING_JOBS.with_borrow_mut(|meeting_jobs| meeting_jobs.remove(&key));
+
+        let Some(job) = get_job(key.2).filter(|job| !job.state.is_finished()) else {
+            continue;
+        };
+        // a step that is still running notices the new state and drops its result
+        if let Some(job) = transition(rt, job.id, job.state, |job| {
+            job.state = JobState::Cancelled;
+            job.last_error = Some(String::from("The meeting was deleted"));
+        }) {
+            release_inputs(&job);
+        }
+    }
+}
+
+/// Every job that ran for the meeting, oldest first.
+pub fn get_meeting_jobs(
+    rt: &impl Runtime,
+    group_id: u128,
+    meeting_id: u128,
+) -> Result<Vec<JobResponse>, ApiError> {
+    user::assert_user_logged_in(rt)?;
+    meeting::assert_check_group(rt, group_id)?;
+
+    let job_ids = MEETING_JOBS.with_borrow(|meeting_jobs| {
+        meeting_jobs
+            .range((group_id, meeting_id, u128::MIN)..=(group_id, meeting_id, u128::MAX))
+            .map(|((_, _, job_id), _)| job_id)
+            .collect::<Vec<_>>()
+    });
+
+    Ok(job_ids
+        .into_iter()
+        .filter_map(get_job)
+        .map(|job| JobResponse::from(&job))
+        .collect())
+}
+
+pub fn get_job_detail(rt: &impl Runtime, job_id: u128) -> Result<JobResponse, ApiError> {
+    user::assert_user_logged_in(rt)?;
+
+    let job = get_job(job_id).ok_or(ApiError::not_found("job", job_id))?;
+    meeting::assert_check_group(rt, job.group_id)?;
+
+    Ok(JobResponse::from(&job))
+}
+
+pub fn cancel_job(rt: &impl Runtime, job_id: u128) -> Result<(), ApiError> {
+    user::assert_user_logged_in(rt)?;
+
+    let (job, selfname) = get_job_as_admin(rt, job_id)?;
+    if job.state.is_finished() {
+        return Err(ApiError::conflict("This job is already finished!"));
+    }
+
+    // a step that is still running notices the new state and drops its result
+    let job = transition(rt, job_id, job.state, |job| {
+        job.state = JobState::Cancelled;
+        job.last_error = Some(format!("Cancelled by {}", selfname));
+    })
+    .ok_or(ApiError::conflict(
+        "This job changed its state, please try again!",
+    ))?;
+
+    abandon(rt, &job);
+
+    Ok(())
+}
+
+/// Runs a failed or cancelled job again, as long as the meeting still looks the way it did.
+pub fn retry_job(rt: &impl Runtime, job_id: u128) -> Result<(), ApiError> {
+    user::assert_user_logged_in(rt)?;
+
+    let (job, _) = get_job_as_admin(rt, job_id)?;
+    if !matches!(job.state, JobState::Failed | JobState::Cancelled) {
+        return Err(ApiError::conflict(
+            "Only failed or cancelled jobs can be retried!",
+        ));
+    }
+
+    let mut meeting = meeting::get_meeting(job.group_id, job.meeting_id)?;
+    let frame = meeting
+        .frames
+        .get(job.frame_index as usize)
+        .ok_or(ApiError::not_found("frame", job.frame_index))?;
+
+    let is_outdated = match job.kind {
+        JobKind::Concat => meeting.full_video.as_ref() != job.inputs.first(),
+        JobKind::Subtitle => job.inputs.first() != Some(&frame.video),
+        JobKind::Thumbnail => false,
+    };
+    if is_outdated || job.inputs.iter().any(|x| blob::get_metadata(x).is_none()) {
+        return Err(ApiError::conflict(
+            "The meeting has changed since this job ran, it cannot be retried!",
+        ));
+    }
+
+    if job.kind != JobKind::Thumbnail {
+        if meeting.process_type != MeetingProcessType::None {
+            return Err(ApiError::Busy);
+        }
+
+        meeting.process_type = match job.kind {
+            JobKind::Subtitle => MeetingProcessType::Subtitle,
+            _ => MeetingProcessType::Concat,
+        };
+        MEETINGS
+            .with_borrow_mut(|meetings| meetings.insert((job.group_id, job.meeting_id), meeting));
+    }
+
+    for input in job.inputs.iter() {
+        blob::retain(input);
+    }
+
+    transition(rt, job_id, job.state, |job| {
+        job.state = JobState::Queued;
+        job.attempts = 0;
+        job.last_error = None;
+        job.processor_id = None;
+        job.next_attempt_time_unix = rt.time();
+    });
+    let worker = rt.clone();
+    rt.set_timer(Duration::ZERO, move || run_due_jobs(&worker));
+
+    Ok(())
+}
+
+mod endpoints {
+    use super::*;
+    use crate::runtime::IcRuntime;
+
+    #[ic_cdk::query]
+    fn get_meeting_jobs(group_id: u128, meeting_id: u128) -> Result<Vec<JobResponse>, ApiError> {
+        super::get_meeting_jobs(&IcRuntime, group_id, meeting_id)
+    }
+
+    #[ic_cdk::query]
+    fn get_job_detail(job_id: u128) -> Result<JobResponse, ApiError> {
+        super::get_job_detail(&IcRuntime, job_id)
+    }
+
+    #[ic_cdk::update]
+    fn cancel_job(job_id: u128) -> Result<(), ApiError> {
+        super::cancel_job(&IcRuntime, job_id)
+    }
+
+    #[ic_cdk::update]
+    fn retry_job(job_id: u128) -> Result<(), ApiError> {
+        super::retry_job(&IcRuntime, job_id)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::{
+        config::Config,
+        error::ApiError,
+        globals::CONFIG,
+        group,
+        processor::{ProcessorKind, FAKE_SUBTITLES},
+        runtime::TestRuntime,
+        user::tests::sign_in,
+    };
+
+    /// Jobs are processed inside the canister, so they finish without any HTTP outcall.
+    pub(crate) fn use_fake_processor() {
+        let config = Config {
+            processor: Some(ProcessorKind::Fake),
+            ..Default::default()
+        };
+        CONFIG.with_borrow_mut(|store| store.set(config)).unwrap();
+    }
+
+    /// Runs the queued jobs, then the polls that pick up what the processor finished.
+    pub(crate) fn run_jobs(rt: &TestRuntime) {
+        rt.run_timers();
+        while JOB_QUEUE.with_borrow(|job_queue| !job_queue.is_empty()) {
+            rt.advance_time(JOB_POLL_INTERVAL);
+            run_due_jobs(rt);
+            rt.run_timers();
+        }
+    }
+
+    fn full_video(group_id: u128, meeting_id: u128) -> Vec<u8> {
+        let meeting = meeting::get_meeting(group_id, meeting_id).unwrap();
+        assert_eq!(meeting.process_type, MeetingProcessType::None);
+        blob::read(&meeting.full_video.unwrap())
+    }
+
+    #[test]
+    fn backs_off_exponentially() {
+        assert_eq!(retry_delay(1), RETRY_BASE_DELAY);
+        assert_eq!(retry_delay(2), RETRY_BASE_DELAY * 2);
+        assert_eq!(retry_delay(4), RETRY_BASE_DELAY * 8);
+        assert_eq!(retry_delay(100), RETRY_MAX_DELAY);
+    }
+
+    #[test]
+    fn applies_the_processed_parts() {
+        let rt = TestRuntime::new();
+        use_fake_processor();
+        sign_in(&rt, 1, "alice");
+        let group_id = group::create_group(&rt, String::from("Team")).unwrap();
+        let meeting_id = meeting::create_meeting(&rt, group_id, String::from("Demo")).unwrap();
+        let alice = || String::from("alice");
+
+        meeting::add_video_part(
+            &rt,
+            group_id,
+            meeting_id,
+            alice(),
+            alice(),
+            blob::store(&[1]),
+            false,
+        )
+        .unwrap();
+        run_jobs(&rt);
+        assert_eq!(full_video(group_id, meeting_id), vec![1]);
+        let meeting = meeting::get_meeting(group_id, meeting_id).unwrap();
+        assert_eq!(
+            blob::read(&meeting.thumbnail.unwrap()),
+            blob::hash(&[1]).to_vec()
+        );
+        assert!(!rt.certified_data().is_empty());
+
+        meeting::add_video_part(
+            &rt,
+            group_id,
+            meeting_id,
+            alice(),
+            alice(),
+            blob::store(&[2]),
+            false,
+        )
+        .unwrap();
+        assert!(matches!(
+            meeting::add_video_part(
+                &rt,
+                group_id,
+                meeting_id,
+                alice(),
+                alice(),
+                blob::store(&[3]),
+                false
+            ),
+            Err(ApiError::Busy)
+        ));
+        // the rejected part does not stay in the blob store
+        assert!(blob::get_metadata(&hex::encode(blob::hash(&[3]))).is_none());
+        run_jobs(&rt);
+        assert_eq!(full_video(group_id, meeting_id), vec![1, 2]);
+
+        // subtitles on a later part are concatenated once they are done
+        meeting::add_video_part(
+            &rt,
+            group_id,
+            meeting_id,
+            alice(),
+            alice(),
+            blob::store(&[3]),
+            true,
+        )
+        .unwrap();
+        run_jobs(&rt);
+        assert_eq!(
+            full_video(group_id, meeting_id),
+            [&[1, 2, 3], FAKE_SUBTITLES].concat()
+        );
+
+        let jobs = get_meeting_jobs(&rt, group_id, meeting_id).unwrap();
+        assert_eq!(jobs.len(), 6);
+        assert!(jobs.iter().all(|job| job.state == JobState::Done));
+    }
+
+    #[test]
+    fn prunes_finished_jobs_after_retention() {
+        let rt = TestRuntime::new();
+        use_fake_processor();
+        sign_in(&rt, 1, "alice");
+        let group_id = group::create_group(&rt, String::from("Team")).unwrap();
+        let meeting_id = meeting::create_meeting(&rt, group_id, String::from("Demo")).unwrap();
+        let alice = || String::from("alice");
+
+        meeting::add_video_part(
+            &rt,
+            group_id,
+            meeting_id,
+            alice(),
+            alice(),
+            blob::store(&[1]),
+            false,
+        )
+        .unwrap();
+        run_jobs(&rt);
+
+        rt.advance_time(JOB_RETENTION);
+        meeting::add_video_part(
+            &rt,
+            group_id,
+            meeting_id,
+            alice(),
+            alice(),
+            blob::store(&[2]),
+            false,
+        )
+        .unwrap();
+        prune_finished_jobs(&rt);
+        assert_eq!(
+            get_meeting_jobs(&rt, group_id, meeting_id).unwrap().len(),
+            3
+        );
+
+        rt.advance_time(Duration::from_secs(1));
+        prune_finished_jobs(&rt);
+        let jobs = get_meeting_jobs(&rt, group_id, meeting_id).unwrap();
+        assert_eq!(jobs.len(), 2);
+        assert!(jobs.iter().all(|job| !job.state.is_finished()));
+
+        run_jobs(&rt);
+        assert!(JOB_QUEUE.with_borrow(|job_queue| job_queue.is_empty()));
+    }
+
+    #[test]
+    fn deleting_a_meeting_cancels_its_jobs() {
+        let rt = TestRuntime::new();
+        use_fake_processor();
+        sign_in(&rt, 1, "alice");
+        let group_id = group::create_group(&rt, String::from("Team")).unwrap();
+        let meeting_id = meeting::create_meeting(&rt, group_id, String::from("Demo")).unwrap();
+        let alice = || String::from("alice");
+
+        meeting::add_video_part(
+            &rt,
+            group_id,
+            meeting_id,
+            alice(),
+            alice(),
+            blob::store(&[1]),
+            false,
+        )
+        .unwrap();
+        // the thumbnail job of the first part does not lock the meeting
+        meeting::delete_meeting(&rt, group_id, meeting_id).unwrap();
+        assert!(MEETING_JOBS.with_borrow(|meeting_jobs| meeting_jobs.is_empty()));
+        assert!(JOB_QUEUE.with_borrow(|job_queue| job_queue.is_empty()));
+        assert!(
+            JOBS.with_borrow(|jobs| jobs.iter().all(|(_, job)| job.state == JobState::Cancelled))
+        );
+        assert!(blob::get_metadata(&hex::encode(blob::hash(&[1]))).is_none());
+
+        run_jobs(&rt);
+        assert!(
+            JOBS.with_borrow(|jobs| jobs.iter().all(|(_, job)| job.state == JobState::Cancelled))
+        );
+    }
+
+    #[test]
+    fn results_that_cannot_be_applied_fail_the_job() {
+        let rt = TestRuntime::new();
+        use_fake_processor();
+        sign_in(&rt, 1, "alice");
+        let group_id = group::create_group(&rt, String::from("Team")).unwrap();
+        let meeting_id = meeting::create_meeting(&rt, group_id, String::from("Demo")).unwrap();
+
+        // the meeting has no part the subtitles could go to
+        let input = blob::store(&[9]);
+        let job_id = enqueue(
+            &rt,
+            JobKind::Subtitle,
+            group_id,
+            meeting_id,
+            0,
+            String::from("alice"),
+            vec![input.clone()],
+        );
+        blob::release(&input);
+        run_jobs(&rt);
+
+        let job = get_job(job_id).unwrap();
+        assert_eq!(job.state, JobState::Failed);
+        assert!(job
+            .last_error
+            .is_some_and(|err| err.starts_with("Cannot apply the result")));
+        assert!(blob::get_metadata(&input).is_none());
+        let output = [&[9], FAKE_SUBTITLES].concat();
+        assert!(blob::get_metadata(&hex::encode(blob::hash(&output))).is_none());
+    }
+}
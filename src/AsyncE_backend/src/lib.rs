@@ -1,25 +1,56 @@
 #![allow(non_snake_case)]
 
+pub mod attachment;
+pub mod blob;
+pub mod certification;
 pub mod chat;
 pub mod chunk;
+pub mod config;
+pub mod error;
+pub mod event;
 pub mod globals;
 pub mod group;
 pub mod http;
 pub mod invite;
+pub mod job;
+pub mod media;
 pub mod meeting;
+pub mod memory;
+pub mod migration;
+pub mod notification;
+pub mod presence;
 pub mod primary_key;
+pub mod processor;
+pub mod reaction;
+pub mod receipt;
+pub mod runtime;
+pub mod search;
+pub mod upload;
 pub mod user;
 pub mod websocket;
 
 use crate::{
-    chat::Chat,
+    attachment::AttachmentUpload,
+    chat::{Chat, ChatCursor, ChatPage, ChatRevision, ChatThread},
+    config::{Config, ConfigUpdate, InitArgs},
+    error::ApiError,
+    event::EventPage,
     group::{GroupMemberRole, GroupQueryResponse},
     invite::GroupInviteResponse,
+    job::JobResponse,
+    media::{
+        HttpRequest, HttpResponse, MediaToken, StreamingCallbackHttpResponse,
+        StreamingCallbackToken,
+    },
     meeting::{MeetingHeader, VideoFrameHeader},
+    notification::NotificationPage,
+    presence::UserPresence,
+    receipt::{MemberReadReceipt, ReadMark},
+    search::{SearchDoc, SearchPage},
+    upload::{VideoUploadRequest, VideoUploadStatus},
     user::UserCredentialsResponse,
     websocket::WebsocketEventMessage,
 };
-use globals::{CHATS, GROUPS, GROUP_INVITES, MEETINGS, PRIMARY_KEY_CONTAINERS, USERS};
 use ic_websocket_cdk::{
     CanisterWsCloseArguments, CanisterWsCloseResult, CanisterWsGetMessagesArguments,
     CanisterWsGetMessagesResult, CanisterWsMessageArguments, CanisterWsMessageResult,
@@ -27,67 +58,50 @@ use ic_websocket_cdk::{
 };
 
 #[ic_cdk::init]
-fn init() {
-    let handlers = WsHandlers {
-        on_open: Some(websocket::on_open),
-        on_message: Some(websocket::on_message),
-        on_close: Some(websocket::on_close),
-    };
-
-    ic_websocket_cdk::init(WsInitParams::new(handlers));
-    http::poll_concat_requests();
-    http::poll_subtitle_requests();
+fn init(args: Option<InitArgs>) {
+    init_websocket();
+    migration::init_schema_version();
+    config::init_config(args);
+    certification::init_certified_media(&runtime::IcRuntime);
+    job::poll_jobs();
     user::poll_user_subscriptions();
-}
-
-#[ic_cdk::pre_upgrade]
-fn pre_upgrade() {
-    let users_store = USERS.with_borrow(|users| users.clone());
-    let groups_store = GROUPS.with_borrow(|groups| groups.clone());
-    let meetings_store = MEETINGS.lock().unwrap().clone();
-    let group_invites_store = GROUP_INVITES.with_borrow(|group_invites| group_invites.clone());
-    let chat_store = CHATS.with_borrow(|chats| chats.clone());
-    let primary_key_store =
-        PRIMARY_KEY_CONTAINERS.with_borrow(|primary_key_containers| primary_key_containers.clone());
-
-    ic_cdk::storage::stable_save((
-        users_store,
-        groups_store,
-        meetings_store,
-        group_invites_store,
-        chat_store,
-        primary_key_store,
-    ))
-    .expect("FAILED TO STABLE SAVE DATA!");
+    upload::poll_expired_upload_sessions();
+    event::poll_event_compaction();
+    attachment::poll_expired_staged_attachments();
+    chunk::poll_expired_upload_buffers();
+    media::init_media_token_secret();
 }
 
 #[ic_cdk::post_upgrade]
-fn post_upgrade() {
-    let (
-        user_store,
-        group_store,
-        meetings_store,
-        group_invites_store,
-        chat_store,
-        primary_key_containers_store,
-    ) = ic_cdk::storage::stable_restore().expect("FAILED TO STABLE RESTORE DATA!");
-
-    USERS.with_borrow_mut(|users| *users = user_store);
-    GROUPS.with_borrow_mut(|groups| *groups = group_store);
-    *MEETINGS.lock().unwrap() = meetings_store;
-    GROUP_INVITES.with_borrow_mut(|group_invites| *group_invites = group_invites_store);
-    CHATS.with_borrow_mut(|chats| *chats = chat_store);
-    PRIMARY_KEY_CONTAINERS.with_borrow_mut(|primary_key_containers| {
-        *primary_key_containers = primary_key_containers_store
-    });
-
-    http::poll_concat_requests();
-    http::poll_subtitle_requests();
+fn post_upgrade(args: Option<InitArgs>) {
+    // has to run before anything else touches the stores
+    migration::migrate();
+    // the websocket handlers live on the heap and are gone after an upgrade
+    init_websocket();
+    config::init_config(args);
+    certification::init_certified_media(&runtime::IcRuntime);
+
+    job::poll_jobs();
     user::poll_user_subscriptions();
+    upload::poll_expired_upload_sessions();
+    event::poll_event_compaction();
+    attachment::poll_expired_staged_attachments();
+    chunk::poll_expired_upload_buffers();
+    media::init_media_token_secret();
 
     // init_rng()
 }
 
+fn init_websocket() {
+    let handlers = WsHandlers {
+        on_open: Some(websocket::on_open),
+        on_message: Some(websocket::on_message),
+        on_close: Some(websocket::on_close),
+    };
+
+    ic_websocket_cdk::init(WsInitParams::new(handlers));
+}
+
 // thread_local! {
 //     static RNG: RefCell<Option<StdRng>> = RefCell::new(None);
 // }
@@ -0,0 +1,804 @@
+use std::time::Duration;
+
+use candid::{CandidType, Principal};
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::Sha256;
+
+use crate::{
+    blob::{self, BlobId},
+    certification, chunk,
+    error::ApiError,
+    globals::{GROUPS, MEDIA_TOKEN_SECRET, MEETINGS, USERS},
+    runtime::{IcRuntime, Runtime},
+    user,
+};
+
+/// Tokens are only meant to be put into a `src` attribute right away, so they expire quickly.
+pub const MEDIA_TOKEN_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// Largest body sent in a single response, anything bigger is streamed or cut by the range.
+const MAX_BODY_SIZE: u64 = chunk::MB as u64;
+
+pub type HeaderField = (String, String);
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct MediaToken {
+    pub token: String,
+    pub expires_time_unix: u128,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct HttpRequest {
+    pub method: String,
+    pub url: String,
+    pub headers: Vec<HeaderField>,
+    pub body: Vec<u8>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct HttpResponse {
+    pub status_code: u16,
+    pub headers: Vec<HeaderField>,
+    pub body: Vec<u8>,
+    pub streaming_strategy: Option<StreamingStrategy>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct StreamingCallbackToken {
+    pub path: String,
+    pub media_token: String,
+    pub chunk_index: u32,
+}
+
+candid::define_function!(pub StreamingCallback : (StreamingCallbackToken) -> (StreamingCallbackHttpResponse) query);
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub enum StreamingStrategy {
+    Callback {
+        callback: StreamingCallback,
+        token: StreamingCallbackToken,
+    },
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct StreamingCallbackHttpResponse {
+    pub body: Vec<u8>,
+    pub token: Option<StreamingCallbackToken>,
+}
+
+/// Every media file that can be fetched over HTTP, all of them live under a group.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MediaRoute {
+    GroupProfilePicture {
+        group_id: u128,
+    },
+    MemberProfilePicture {
+        group_id: u128,
+        username: String,
+    },
+    MeetingVideo {
+        group_id: u128,
+        meeting_id: u128,
+    },
+    MeetingThumbnail {
+        group_id: u128,
+        meeting_id: u128,
+    },
+    FrameVideo {
+        group_id: u128,
+        meeting_id: u128,
+        frame_index: usize,
+    },
+    FrameThumbnail {
+        group_id: u128,
+        meeting_id: u128,
+        frame_index: usize,
+    },
+}
+
+impl MediaRoute {
+    pub fn parse(path: &str) -> Option<Self> {
+        let segments = path.trim_matches('/').split('/').collect::<Vec<_>>();
+        let id = |x: &str| x.parse::<u128>().ok();
+
+        match segments.as_slice() {
+            ["media", "groups", group_id, "profile-picture"] => Some(Self::GroupProfilePicture {
+                group_id: id(group_id)?,
+            }),
+            ["media", "groups", group_id, "members", username, "profile-picture"] => {
+                Some(Self::MemberProfilePicture {
+                    group_id: id(group_id)?,
+                    username: username.to_string(),
+                })
+            }
+            ["media", "groups", group_id, "meetings", meeting_id, "video"] => {
+                Some(Self::MeetingVideo {
+                    group_id: id(group_id)?,
+                    meeting_id: id(meeting_id)?,
+                })
+            }
+            ["media", "groups", group_id, "meetings", meeting_id, "thumbnail"] => {
+                Some(Self::MeetingThumbnail {
+                    group_id: id(group_id)?,
+                    meeting_id: id(meeting_id)?,
+                })
+            }
+            ["media", "groups", group_id, "meetings", meeting_id, "frames", frame_index, "video"] => {
+                Some(Self::FrameVideo {
+                    group_id: id(group_id)?,
+                    meeting_id: id(meeting_id)?,
+                    frame_index: frame_index.parse().ok()?,
+                })
+            }
+            ["media", "groups", group_id, "meetings", meeting_id, "frames", frame_index, "thumbnail"] => {
+                Some(Self::FrameThumbnail {
+                    group_id: id(group_id)?,
+                    meeting_id: id(meeting_id)?,
+                    frame_index: frame_index.parse().ok()?,
+                })
+            }
+            _ => None,
+        }
+    }
+
+    pub fn path(&self) -> String {
+        match self {
+            Self::GroupProfilePicture { group_id } => {
+                format!("/media/groups/{}/profile-picture", group_id)
+            }
+            Self::MemberProfilePicture { group_id, username } => {
+                format!(
+                    "/media/groups/{}/members/{}/profile-picture",
+                    group_id, username
+                )
+            }
+            Self::MeetingVideo {
+                group_id,
+                meeting_id,
+            } => format!("/media/groups/{}/meetings/{}/video", group_id, meeting_id),
+            Self::MeetingThumbnail {
+                group_id,
+                meeting_id,
+            } => format!(
+                "/media/groups/{}/meetings/{}/thumbnail",
+                group_id, meeting_id
+            ),
+            Self::FrameVideo {
+                group_id,
+                meeting_id,
+                frame_index,
+            } => format!(
+                "/media/groups/{}/meetings/{}/frames/{}/video",
+                group_id, meeting_id, frame_index
+            ),
+            Self::FrameThumbnail {
+                group_id,
+                meeting_id,
+                frame_index,
+            } => format!(
+                "/media/groups/{}/meetings/{}/frames/{}/thumbnail",
+                group_id, meeting_id, frame_index
+            ),
+        }
+    }
+
+    pub fn group_id(&self) -> u128 {
+        match self {
+            Self::GroupProfilePicture { group_id }
+            | Self::MemberProfilePicture { group_id, .. }
+            | Self::MeetingVideo { group_id, .. }
+            | Self::MeetingThumbnail { group_id, .. }
+            | Self::FrameVideo { group_id, .. }
+            | Self::FrameThumbnail { group_id, .. } => *group_id,
+        }
+    }
+
+    /// Finds the blob behind this route together with its content type.
+    pub fn resolve(&self) -> Option<(BlobId, &'static str)> {
+        match self {
+            Self::GroupProfilePicture { group_id } => {
+                let id = GROUPS
+                    .with_borrow(|groups| groups.get(group_id))?
+                    .profile_picture?;
+                let content_type = sniff_image_content_type(&id);
+                Some((id, content_type))
+            }
+            Self::MemberProfilePicture { group_id, username } => {
+                let group = GROUPS.with_borrow(|groups| groups.get(group_id))?;
+                if !group.is_member(username) {
+                    return None;
+                }
+
+                let principal = user::get_principal(username)?;
+                let id = USERS
+                    .with_borrow(|users| users.get(&principal))?
+                    .profile_picture?;
+                let content_type = sniff_image_content_type(&id);
+                Some((id, content_type))
+            }
+            Self::MeetingVideo {
+                group_id,
+                meeting_id,
+            } => {
+                let meeting =
+                    MEETINGS.with_borrow(|meetings| meetings.get(&(*group_id, *meeting_id)))?;
+                Some((meeting.full_video?, "video/webm"))
+            }
+            Self::MeetingThumbnail {
+                group_id,
+                meeting_id,
+            } => {
+                let meeting =
+                    MEETINGS.with_borrow(|meetings| meetings.get(&(*group_id, *meeting_id)))?;
+                Some((meeting.thumbnail?, "image/jpeg"))
+            }
+            Self::FrameVideo {
+                group_id,
+                meeting_id,
+                frame_index,
+            } => {
+                let meeting =
+                    MEETINGS.with_borrow(|meetings| meetings.get(&(*group_id, *meeting_id)))?;
+                let frame = meeting.frames.get(*frame_index)?;
+                Some((frame.video.clone(), "video/webm"))
+            }
+            Self::FrameThumbnail {
+                group_id,
+                meeting_id,
+                frame_index,
+            } => {
+                let meeting =
+                    MEETINGS.with_borrow(|meetings| meetings.get(&(*group_id, *meeting_id)))?;
+                let frame = meeting.frames.get(*frame_index)?;
+                Some((frame.thumbnail.clone()?, "image/jpeg"))
+            }
+        }
+    }
+}
+
+/// Profile pictures can be any image the user picked, so the type is read from the magic bytes.
+pub(crate) fn sniff_image_content_type(id: &BlobId) -> &'static str {
+    let header = blob::read_range(id, 0, 12);
+
+    match header.as_slice() {
+        [0x89, b'P', b'N', b'G', ..] => "image/png",
+        [0xFF, 0xD8, 0xFF, ..] => "image/jpeg",
+        [b'G', b'I', b'F', b'8', ..] => "image/gif",
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P'] => "image/webp",
+        _ => "application/octet-stream",
+    }
+}
+
+fn get_secret() -> Option<Vec<u8>> {
+    let secret = MEDIA_TOKEN_SECRET.with_borrow(|secret| secret.get().clone());
+    (!secret.is_empty()).then_some(secret)
+}
+
+/// Generates the secret once, later calls keep the existing one.
+pub async fn generate_media_token_secret(rt: impl Runtime) {
+    if get_secret().is_some() {
+        return;
+    }
+
+    let secret = match rt.random_bytes().await {
+        Ok(secret) => secret,
+        Err(err) => {
+            ic_cdk::eprintln!("Failed to generate media token secret: {}", err);
+            return;
+        }
+    };
+
+    MEDIA_TOKEN_SECRET
+        .with_borrow_mut(|media_token_secret| media_token_secret.set(secret))
+        .expect("FAILED TO SAVE MEDIA TOKEN SECRET!");
+}
+
+/// The secret comes from `raw_rand`, which cannot be called from `init` or `post_upgrade` directly.
+pub fn init_media_token_secret() {
+    ic_cdk_timers::set_timer(Duration::ZERO, || {
+        IcRuntime.spawn(generate_media_token_secret(IcRuntime))
+    });
+}
+
+fn sign(secret: &[u8], payload: &str) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts keys of any size");
+    mac.update(payload.as_bytes());
+    mac
+}
+
+/// Tokens look like `{group_id}.{principal}.{expires_time_unix}.{signature}`.
+pub fn issue_media_token(
+    secret: &[u8],
+    group_id: u128,
+    principal: Principal,
+    now: u128,
+) -> MediaToken {
+    let expires_time_unix = now + MEDIA_TOKEN_TTL.as_nanos();
+    let payload = format!("{}.{}.{}", group_id, principal, expires_time_unix);
+    let signature = hex::encode(sign(secret, &payload).finalize().into_bytes());
+
+    MediaToken {
+        token: format!("{}.{}", payload, signature),
+        expires_time_unix,
+    }
+}
+
+/// Returns the group and principal the token was issued for.
+pub fn verify_media_token(
+    secret: &[u8],
+    token: &str,
+    now: u128,
+) -> Result<(u128, Principal), String> {
+    let invalid = || String::from("Media token is invalid!");
+
+    let (payload, signature) = token.rsplit_once('.').ok_or_else(invalid)?;
+    let signature = hex::decode(signature).map_err(|_| invalid())?;
+    sign(secret, payload)
+        .verify_slice(&signature)
+        .map_err(|_| invalid())?;
+
+    let mut parts = payload.split('.');
+    let group_id = parts.next().and_then(|x| x.parse::<u128>().ok());
+    let principal = parts.next().and_then(|x| Principal::from_text(x).ok());
+    let expires_time_unix = parts.next().and_then(|x| x.parse::<u128>().ok());
+    let (Some(group_id), Some(principal), Some(expires_time_unix)) =
+        (group_id, principal, expires_time_unix)
+    else {
+        return Err(invalid());
+    };
+
+    if now > expires_time_unix {
+        return Err(String::from("Media token has expired!"));
+    }
+
+    Ok((group_id, principal))
+}
+
+pub fn create_media_token(rt: &impl Runtime, group_id: u128) -> Result<MediaToken, ApiError> {
+    user::assert_user_logged_in(rt)?;
+
+    let selfname = user::get_selfname_force(rt)?;
+    let group = GROUPS
+        .with_borrow(|groups| groups.get(&group_id))
+        .ok_or(ApiError::not_found("group", group_id))?;
+    if !group.is_member(&selfname) {
+        return Err(ApiError::forbidden("This user is not in this group!"));
+    }
+
+    let secret = get_secret().ok_or(ApiError::conflict(
+        "Media tokens are not available yet, please try again later!",
+    ))?;
+
+    Ok(issue_media_token(&secret, group_id, rt.caller(), rt.time()))
+}
+
+/// Checks the token against the route, the token owner has to still be in the group.
+fn authorize(
+    rt: &impl Runtime,
+    route: &MediaRoute,
+    media_token: &str,
+) -> Result<(), (u16, String)> {
+    let secret = get_secret().ok_or((503, String::from("Media is not available yet")))?;
+    let (group_id, principal) =
+        verify_media_token(&secret, media_token, rt.time()).map_err(|err| (401, err))?;
+
+    if group_id != route.group_id() {
+        return Err((403, String::from("Media token is for another group!")));
+    }
+
+    let username = USERS
+        .with_borrow(|users| users.get(&principal).map(|x| x.username))
+        .ok_or((403, String::from("Cannot find user with this principal!")))?;
+    let is_member = GROUPS.with_borrow(|groups| {
+        groups
+            .get(&group_id)
+            .is_some_and(|group| group.is_member(&username))
+    });
+    if !is_member {
+        return Err((403, String::from("This user is not in this group!")));
+    }
+
+    Ok(())
+}
+
+fn split_url(url: &str) -> (&str, Option<String>) {
+    let (path, query) = url.split_once('?').unwrap_or((url, ""));
+    let token = query
+        .split('&')
+        .filter_map(|x| x.split_once('='))
+        .find(|(key, _)| *key == "token")
+        .map(|(_, value)| value.to_string());
+
+    (path, token)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ByteRange {
+    /// No usable range was requested, the whole content is sent
+    Full,
+    /// Inclusive `(start, end)` offsets
+    Partial(u64, u64),
+    Unsatisfiable,
+}
+
+/// Parses the `Range` header, only a single `bytes=` range is supported.
+pub fn parse_range(header: &str, size: u64) -> ByteRange {
+    let Some(range) = header.trim().strip_prefix("bytes=") else {
+        return ByteRange::Full;
+    };
+
+    // multiple ranges are allowed to be ignored, the whole content is sent instead
+    if range.contains(',') {
+        return ByteRange::Full;
+    }
+
+    let Some((start, end)) = range.trim().split_once('-') else {
+        return ByteRange::Full;
+    };
+
+    let (start, end) = match (start.parse::<u64>(), end.parse::<u64>()) {
+        (Ok(start), Ok(end)) => (start, end.min(size.saturating_sub(1))),
+        (Ok(start), Err(_)) if end.is_empty() => (start, size.saturating_sub(1)),
+        (Err(_), Ok(suffix)) if start.is_empty() => {
+            (size.saturating_sub(suffix), size.saturating_sub(1))
+        }
+        _ => return ByteRange::Full,
+    };
+
+    if size == 0 || start > end || start >= size {
+        return ByteRange::Unsatisfiable;
+    }
+
+    ByteRange::Partial(start, end)
+}
+
+/// Whether the request came in through the raw domain (`<canister id>.raw.icp0.io`),
+/// where boundary nodes pass responses along without verifying them.
+///
+/// Only whole files are certified, a partial body can never match the certified hash, so byte
+/// ranges are only served on the raw domain. Players that need to seek through long videos
+/// have to load them from there, everywhere else the whole file is sent (and verified).
+/// Files that are not certified at all, like profile pictures and meeting videos that are still
+/// being processed, are only served on the raw domain.
+fn is_raw_domain(request: &HttpRequest) -> bool {
+    request
+        .headers
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case("Host"))
+        .is_some_and(|(_, host)| host.split('.').nth(1) == Some("raw"))
+}
+
+fn error_response(status_code: u16, message: impl Into<String>) -> HttpResponse {
+    HttpResponse {
+        status_code,
+        headers: vec![(
+            String::from("Content-Type"),
+            String::from("text/plain; charset=utf-8"),
+        )],
+        body: message.into().into_bytes(),
+        streaming_strategy: None,
+    }
+}
+
+fn serve_media(rt: &impl Runtime, request: &HttpRequest) -> Result<HttpResponse, (u16, String)> {
+    let is_head = request.method.eq_ignore_ascii_case("HEAD");
+    if !is_head && !request.method.eq_ignore_ascii_case("GET") {
+        return Err((405, String::from("Method not allowed")));
+    }
+
+    let (path, media_token) = split_url(&request.url);
+    let route = MediaRoute::parse(path).ok_or((404, String::from("Not found")))?;
+    let media_token = media_token.ok_or((401, String::from("Media token is missing!")))?;
+    authorize(rt, &route, &media_token)?;
+
+    let (id, content_type) = route.resolve().ok_or((404, String::from("Not found")))?;
+    let size = blob::get_size(&id) as u64;
+
+    let is_certified = certification::is_certified(path);
+    if !is_certified && !is_raw_domain(request) {
+        return Err((
+            421,
+            String::from("This file is only served on the raw domain!"),
+        ));
+    }
+
+    let mut headers = vec![
+        (String::from("Content-Type"), content_type.to_string()),
+        (
+            String::from("Accept-Ranges"),
+            String::from(if is_raw_domain(request) {
+                "bytes"
+            } else {
+                "none"
+            }),
+        ),
+    ];
+
+    let range = request
+        .headers
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case("Range"))
+        .filter(|_| is_raw_domain(request))
+        .map(|(_, value)| parse_range(value, size))
+        .unwrap_or(ByteRange::Full);
+
+    match range {
+        ByteRange::Unsatisfiable => {
+            headers.push((String::from("Content-Range"), format!("bytes */{}", size)));
+            Ok(HttpResponse {
+                status_code: 416,
+                headers,
+                body: Vec::new(),
+                streaming_strategy: None,
+            })
+        }
+        ByteRange::Partial(start, end) => {
+            // a partial response is allowed to be shorter than the range, the client asks for the rest
+            let end = end.min(start + MAX_BODY_SIZE - 1);
+            let length = end - start + 1;
+
+            headers.push((
+                String::from("Content-Range"),
+                format!("bytes {}-{}/{}", start, end, size),
+            ));
+            headers.push((String::from("Content-Length"), length.to_string()));
+
+            Ok(HttpResponse {
+                status_code: 206,
+                headers,
+                body: if is_head {
+                    Vec::new()
+                } else {
+                    blob::read_range(&id, start, length)
+                },
+                streaming_strategy: None,
+            })
+        }
+        ByteRange::Full => {
+            headers.push((String::from("Content-Length"), size.to_string()));
+            if is_certified {
+                headers.extend(certification::certificate_header(rt, path));
+            }
+
+            let streaming_strategy =
+                (!is_head && size > MAX_BODY_SIZE).then(|| StreamingStrategy::Callback {
+                    callback: StreamingCallback::new(
+                        rt.canister_id(),
+                        String::from("http_request_streaming_callback"),
+                    ),
+                    token: StreamingCallbackToken {
+                        path: path.to_string(),
+                        media_token,
+                        chunk_index: 1,
+                    },
+                });
+
+            Ok(HttpResponse {
+                status_code: 200,
+                headers,
+                body: if is_head {
+                    Vec::new()
+                } else {
+                    blob::get_chunk(&id, 0)
+                },
+                streaming_strategy,
+            })
+        }
+    }
+}
+
+pub fn http_request(rt: &impl Runtime, request: HttpRequest) -> HttpResponse {
+    serve_media(rt, &request)
+        .unwrap_or_else(|(status_code, message)| error_response(status_code, message))
+}
+
+pub fn http_request_streaming_callback(
+    rt: &impl Runtime,
+    token: StreamingCallbackToken,
+) -> StreamingCallbackHttpResponse {
+    let route = MediaRoute::parse(&token.path).unwrap_or_else(|| ic_cdk::trap("Not found"));
+    if let Err((_, message)) = authorize(rt, &route, &token.media_token) {
+        ic_cdk::trap(&message);
+    }
+
+    let (id, _) = route.resolve().unwrap_or_else(|| ic_cdk::trap("Not found"));
+    let chunk_count = blob::get_metadata(&id)
+        .map(|x| x.chunk_count)
+        .unwrap_or_default();
+
+    StreamingCallbackHttpResponse {
+        body: blob::get_chunk(&id, token.chunk_index as u128),
+        token: (token.chunk_index + 1 < chunk_count).then(|| StreamingCallbackToken {
+            chunk_index: token.chunk_index + 1,
+            ..token
+        }),
+    }
+}
+
+mod endpoints {
+    use super::*;
+    use crate::runtime::IcRuntime;
+
+    #[ic_cdk::query]
+    fn create_media_token(group_id: u128) -> Result<MediaToken, ApiError> {
+        super::create_media_token(&IcRuntime, group_id)
+    }
+
+    #[ic_cdk::query]
+    fn http_request(request: HttpRequest) -> HttpResponse {
+        super::http_request(&IcRuntime, request)
+    }
+
+    #[ic_cdk::query]
+    fn http_request_streaming_callback(
+        token: StreamingCallbackToken,
+    ) -> StreamingCallbackHttpResponse {
+        super::http_request_streaming_callback(&IcRuntime, token)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+    use ic_certification::{HashTree, LookupResult};
+
+    use crate::{group, meeting, runtime::TestRuntime, user::tests::sign_in};
+
+    fn get(rt: &TestRuntime, route: &MediaRoute, host: &str) -> HttpResponse {
+        let token = issue_media_token(b"secret", route.group_id(), rt.caller(), rt.time());
+        http_request(
+            rt,
+            HttpRequest {
+                method: String::from("GET"),
+                url: format!("{}?token={}", route.path(), token.token),
+                headers: vec![
+                    (String::from("Host"), host.to_string()),
+                    (String::from("Range"), String::from("bytes=1-")),
+                ],
+                body: Vec::new(),
+            },
+        )
+    }
+
+    fn header<'a>(response: &'a HttpResponse, name: &str) -> Option<&'a str> {
+        response
+            .headers
+            .iter()
+            .find(|(x, _)| x == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    #[test]
+    fn parses_byte_ranges() {
+        assert_eq!(parse_range("bytes=0-99", 1000), ByteRange::Partial(0, 99));
+        assert_eq!(
+            parse_range("bytes=900-", 1000),
+            ByteRange::Partial(900, 999)
+        );
+        assert_eq!(
+            parse_range("bytes=-100", 1000),
+            ByteRange::Partial(900, 999)
+        );
+        assert_eq!(
+            parse_range("bytes=500-5000", 1000),
+            ByteRange::Partial(500, 999)
+        );
+        assert_eq!(parse_range("bytes=1000-", 1000), ByteRange::Unsatisfiable);
+        assert_eq!(parse_range("bytes=0-1,5-6", 1000), ByteRange::Full);
+        assert_eq!(parse_range("items=0-1", 1000), ByteRange::Full);
+    }
+
+    #[test]
+    fn parses_media_routes() {
+        assert_eq!(
+            MediaRoute::parse("/media/groups/1/meetings/2/frames/3/video"),
+            Some(MediaRoute::FrameVideo {
+                group_id: 1,
+                meeting_id: 2,
+                frame_index: 3
+            })
+        );
+        assert_eq!(MediaRoute::parse("/media/groups/x/profile-picture"), None);
+    }
+
+    #[test]
+    fn resolves_member_profile_pictures() {
+        let rt = TestRuntime::new();
+        sign_in(&rt, 2, "bob");
+        sign_in(&rt, 1, "alice");
+        user::upload_profile_picture(&rt, vec![1, 2, 3], 0, 3).unwrap();
+        let group_id = group::create_group(&rt, String::from("Team")).unwrap();
+
+        let route = |username: &str| MediaRoute::MemberProfilePicture {
+            group_id,
+            username: username.to_string(),
+        };
+        let (id, _) = route("Alice").resolve().unwrap();
+        assert_eq!(blob::read(&id), vec![1, 2, 3]);
+        assert_eq!(route("bob").resolve(), None);
+    }
+
+    /// The hash the `IC-Certificate` header of the response proves for `path`.
+    fn certified_hash(response: &HttpResponse, path: &str) -> Option<Vec<u8>> {
+        let header = header(response, "IC-Certificate")?;
+        let tree = header.split("tree=:").nth(1)?.trim_end_matches(':');
+        let tree: HashTree = serde_cbor::from_slice(&BASE64.decode(tree).ok()?).ok()?;
+
+        match tree.lookup_path([b"http_assets".as_slice(), path.as_bytes()]) {
+            LookupResult::Found(hash) => Some(hash.to_vec()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn serves_ranges_only_on_the_raw_domain() {
+        let rt = TestRuntime::new();
+        sign_in(&rt, 1, "alice");
+        user::upload_profile_picture(&rt, vec![1, 2, 3], 0, 3).unwrap();
+        let group_id = group::create_group(&rt, String::from("Team")).unwrap();
+        let meeting_id = meeting::create_meeting(&rt, group_id, String::from("Sync")).unwrap();
+        let video = vec![4, 5, 6];
+        meeting::add_video_part(
+            &rt,
+            group_id,
+            meeting_id,
+            String::from("alice"),
+            String::from("Intro"),
+            blob::store(&video),
+            false,
+        )
+        .unwrap();
+        MEDIA_TOKEN_SECRET
+            .with_borrow_mut(|secret| secret.set(b"secret".to_vec()))
+            .unwrap();
+
+        let frame_video = MediaRoute::FrameVideo {
+            group_id,
+            meeting_id,
+            frame_index: 0,
+        };
+        let response = get(&rt, &frame_video, "aaaaa-aa.icp0.io");
+        assert_eq!(response.status_code, 200);
+        assert_eq!(response.body, video);
+        assert_eq!(
+            certified_hash(&response, &frame_video.path()),
+            Some(blob::hash(&video).to_vec())
+        );
+
+        let response = get(&rt, &frame_video, "aaaaa-aa.raw.icp0.io");
+        assert_eq!(response.status_code, 206);
+        assert_eq!(response.body, vec![5, 6]);
+        assert_eq!(header(&response, "Content-Range"), Some("bytes 1-2/3"));
+        assert!(header(&response, "IC-Certificate").is_none());
+
+        // profile pictures are not certified, so they only come from the raw domain
+        let profile_picture = MediaRoute::MemberProfilePicture {
+            group_id,
+            username: String::from("alice"),
+        };
+        let response = get(&rt, &profile_picture, "aaaaa-aa.icp0.io");
+        assert_eq!(response.status_code, 421);
+        assert!(header(&response, "IC-Certificate").is_none());
+
+        let response = get(&rt, &profile_picture, "aaaaa-aa.raw.icp0.io");
+        assert_eq!(response.status_code, 206);
+        assert_eq!(response.body, vec![2, 3]);
+        assert!(header(&response, "IC-Certificate").is_none());
+    }
+
+    #[test]
+    fn verifies_media_tokens() {
+        let principal = Principal::from_slice(&[1; 29]);
+        let token = issue_media_token(b"secret", 7, principal, 0);
+
+        assert_eq!(
+            verify_media_token(b"secret", &token.token, 1),
+            Ok((7, principal))
+        );
+        assert!(verify_media_token(b"other", &token.token, 1).is_err());
+        assert!(verify_media_token(b"secret", &token.token, token.expires_time_unix + 1).is_err());
+        assert!(verify_media_token(b"secret", &token.token.replacen('7', "8", 1), 1).is_err());
+    }
+}
@@ -2,10 +2,18 @@ use candid::CandidType;
 use serde::Deserialize;
 
 use crate::{
-    chunk,
-    globals::{GROUPS, MEETINGS, VIDEO_UPLOADS},
-    group, http,
+    blob::{self, BlobId},
+    certification,
+    error::ApiError,
+    globals::{GROUPS, MEETINGS},
+    impl_candid_storable,
+    job::{self, JobKind},
+    notification::{self, NotificationKind},
     primary_key::{self, PrimaryKeyType},
+    reaction::{self, Reaction, ReactionCount},
+    receipt,
+    runtime::Runtime,
+    search::{self, SearchDoc},
     user, websocket,
 };
 
@@ -20,8 +28,8 @@ pub enum MeetingProcessType {
 #[derive(Clone, Debug, Default, CandidType, Deserialize)]
 pub struct Meeting {
     pub id: u128,
-    pub thumbnail_data: Vec<u8>,
-    pub full_video_data: Vec<u8>,
+    pub thumbnail: Option<BlobId>,
+    pub full_video: Option<BlobId>,
 
     pub title: String,
     pub created_by: String,
@@ -39,16 +47,20 @@ pub struct MeetingHeader {
     pub created_by: String,
     pub frames_count: u128,
     pub created_time_unix: u128,
-    pub process_type: MeetingProcessType
+    pub process_type: MeetingProcessType,
+    /// Reactions on every video part of the meeting, added up
+    pub reactions: Vec<ReactionCount>,
 }
 
 #[derive(Clone, Debug, Default, CandidType, Deserialize)]
 pub struct VideoFrame {
-    pub data: Vec<u8>,
+    pub video: BlobId,
     pub title: String,
     pub created_by: String,
-    pub thumbnail_data: Vec<u8>,
+    pub thumbnail: Option<BlobId>,
     pub created_time_unix: u128,
+    /// Absent until the first reaction
+    pub reactions: Option<Vec<Reaction>>,
 }
 
 #[derive(Clone, Debug, Default, CandidType, Deserialize)]
@@ -56,43 +68,72 @@ pub struct VideoFrameHeader {
     pub title: String,
     pub created_by: String,
     pub created_time_unix: u128,
+    pub reactions: Vec<Reaction>,
 }
 
+impl_candid_storable!(Meeting);
+
 impl From<&VideoFrame> for VideoFrameHeader {
     fn from(value: &VideoFrame) -> Self {
         Self {
             title: value.title.clone(),
             created_by: value.created_by.clone(),
             created_time_unix: value.created_time_unix,
+            reactions: value.reactions.clone().unwrap_or_default(),
         }
     }
 }
 
 impl VideoFrame {
-    fn new(username: String, title: String) -> Self {
+    fn new(rt: &impl Runtime, username: String, title: String, video: BlobId) -> Self {
         Self {
-            data: Vec::new(),
+            video,
             title,
             created_by: username,
-            thumbnail_data: Vec::new(),
-            created_time_unix: ic_cdk::api::time() as u128,
+            thumbnail: None,
+            created_time_unix: rt.time(),
+            reactions: None,
         }
     }
 }
 
 impl Meeting {
-    pub fn new(username: String, title: String) -> Self {
+    pub fn new(rt: &impl Runtime, username: String, title: String) -> Self {
         Self {
             id: primary_key::get_primary_key(PrimaryKeyType::Video),
-            full_video_data: Vec::new(),
-            thumbnail_data: Vec::new(),
+            full_video: None,
+            thumbnail: None,
             frames: Vec::new(),
             title,
             created_by: username,
-            created_time_unix: ic_cdk::api::time() as u128,
+            created_time_unix: rt.time(),
             process_type: MeetingProcessType::None
         }
     }
+
+    /// Drops every reference this meeting holds in the blob store.
+    pub fn release_blobs(&self) {
+        for id in self.thumbnail.iter().chain(self.full_video.iter()) {
+            blob::release(id);
+        }
+
+        for frame in self.frames.iter() {
+            blob::release(&frame.video);
+            if let Some(thumbnail) = frame.thumbnail.as_ref() {
+                blob::release(thumbnail);
+            }
+        }
+    }
+
+    /// Removes the meeting and every video part of it from the search index.
+    pub fn unindex(&self, group_id: u128) {
+        search::unindex(group_id, SearchDoc::Meeting { meeting_id: self.id }, &self.title);
+
+        for (frame_index, frame) in self.frames.iter().enumerate() {
+            let doc = SearchDoc::VideoFrame { meeting_id: self.id, frame_index: frame_index as u128 };
+            search::unindex(group_id, doc, &frame.title);
+        }
+    }
 }
 
 impl From<&Meeting> for MeetingHeader {
@@ -103,412 +144,428 @@ impl From<&Meeting> for MeetingHeader {
             created_by: value.created_by.clone(),
             frames_count: value.frames.len() as u128,
             created_time_unix: value.created_time_unix,
-            process_type: value.process_type
+            process_type: value.process_type,
+            reactions: reaction::count(
+                value
+                    .frames
+                    .iter()
+                    .flat_map(|frame| frame.reactions.iter().flatten()),
+            ),
         }
     }
 }
 
-fn assert_check_group(group_id: u128) -> Result<(), String> {
-    let group =
-        group::get_group(group_id)?.ok_or(String::from("Group with this ID is not found"))?;
+pub fn get_meeting(group_id: u128, meeting_id: u128) -> Result<Meeting, ApiError> {
+    MEETINGS
+        .with_borrow(|meetings| meetings.get(&(group_id, meeting_id)))
+        .ok_or(ApiError::not_found("meeting", meeting_id))
+}
+
+pub fn assert_check_group(rt: &impl Runtime, group_id: u128) -> Result<(), ApiError> {
+    let group = GROUPS.with_borrow(|groups| groups.get(&group_id)).ok_or(ApiError::not_found("group", group_id))?;
 
-    let name = user::get_selfname_force()?;
+    let name = user::get_selfname_force(rt)?;
 
     if group.owner != name && !group.members.iter().any(|x| x.username.eq_ignore_ascii_case(&name)) {
-        return Err(String::from("Current user does not belong to this group!"));
+        return Err(ApiError::forbidden("Current user does not belong to this group!"));
     }
 
     Ok(())
 }
 
-#[ic_cdk::query]
-pub fn get_meetings(group_id: u128) -> Result<Vec<MeetingHeader>, String> {
-    user::assert_user_logged_in()?;
-    assert_check_group(group_id)?;
-
-    Ok(MEETINGS
-        .lock()
-        .unwrap()
-        .get(&group_id)
-        .cloned()
-        .unwrap_or_default()
-        .values()
-        .map(MeetingHeader::from)
-        .collect::<Vec<_>>())
-    
+pub fn get_meetings(rt: &impl Runtime, group_id: u128) -> Result<Vec<MeetingHeader>, ApiError> {
+    user::assert_user_logged_in(rt)?;
+    assert_check_group(rt, group_id)?;
+
+    Ok(MEETINGS.with_borrow(|meetings| {
+        meetings
+            .range((group_id, u128::MIN)..=(group_id, u128::MAX))
+            .map(|(_, meeting)| MeetingHeader::from(&meeting))
+            .collect::<Vec<_>>()
+    }))
 }
 
-#[ic_cdk::query]
-pub fn get_meeting_detail(group_id: u128, meeting_id: u128) -> Result<MeetingHeader, String> {
-    user::assert_user_logged_in()?;
-    assert_check_group(group_id)?;
+pub fn get_meeting_detail(
+    rt: &impl Runtime,
+    group_id: u128,
+    meeting_id: u128,
+) -> Result<MeetingHeader, ApiError> {
+    user::assert_user_logged_in(rt)?;
+    assert_check_group(rt, group_id)?;
 
-    MEETINGS
-        .lock()
-        .unwrap()
-        .get(&group_id)
-        .ok_or(String::from("No meetings found on this group!"))?
-        .get(&meeting_id)
-        .ok_or(String::from("No meeting found on this meeting ID!"))
-        .map(MeetingHeader::from)
-    
+    get_meeting(group_id, meeting_id).map(|meeting| MeetingHeader::from(&meeting))
 }
 
-#[ic_cdk::update]
-pub fn create_meeting(group_id: u128, title: String) -> Result<u128, String> {
-    user::assert_user_logged_in()?;
-    assert_check_group(group_id)?;
+pub fn create_meeting(rt: &impl Runtime, group_id: u128, title: String) -> Result<u128, ApiError> {
+    user::assert_user_logged_in(rt)?;
+    assert_check_group(rt, group_id)?;
 
-    let selfname = user::get_selfname_force()?;
-    let meeting = Meeting::new(selfname.clone(), title.clone());
+    let selfname = user::get_selfname_force(rt)?;
+    let meeting = Meeting::new(rt, selfname.clone(), title.clone());
     let meeting_id = meeting.id;
 
-    MEETINGS
-        .lock()
-        .unwrap()
-        .entry(group_id)
-        .or_default()
-        .insert(meeting_id, meeting);
+    search::index(group_id, SearchDoc::Meeting { meeting_id }, &meeting.title);
+    MEETINGS.with_borrow_mut(|meetings| meetings.insert((group_id, meeting_id), meeting));
 
     Ok(meeting_id)
 }
 
-#[ic_cdk::update]
-pub fn upload_video(
+/// Appends a fully uploaded video as a new part of the meeting and kicks off its processing.
+///
+/// The meeting takes over the reference the caller holds to `video`,
+/// it is released again when the part cannot be added.
+pub fn add_video_part(
+    rt: &impl Runtime,
     group_id: u128,
     meeting_id: u128,
-    data: Vec<u8>,
-    finish: bool,
+    username: String,
     title: String,
-    video_upload_uuid: String,
-    chunk_index: u128,
-    total_data_length: u128,
-    with_subtitles: bool
-) -> Result<(), String> {
-    user::assert_user_logged_in()?;
-    assert_check_group(group_id)?;
-
-    let selfuser =
-        user::get_selfuser()?.ok_or(String::from("This user does not have a username!"))?;
-    if with_subtitles && selfuser.subscription.is_none() {
-        return Err(String::from("User must be subscribed to use the subtitles AI feature!"));
-    }
+    video: BlobId,
+    with_subtitles: bool,
+) -> Result<(), ApiError> {
+    let mut meeting = match get_meeting(group_id, meeting_id) {
+        Ok(meeting) if meeting.process_type == MeetingProcessType::None => meeting,
+        Ok(_) => {
+            blob::release(&video);
+            return Err(ApiError::Busy)
+        }
+        Err(err) => {
+            blob::release(&video);
+            return Err(err)
+        }
+    };
 
-    let mut meetings = MEETINGS.lock().unwrap();
-    let meetings = meetings
-        .get_mut(&group_id)
-        .ok_or(String::from("No meetings found on this group!"))?;
+    let frame_index = meeting.frames.len();
+    if let Some(full_video) = meeting.full_video.clone() {
+        if !with_subtitles {
+            meeting.process_type = MeetingProcessType::Concat;
+            job::enqueue(rt, JobKind::Concat, group_id, meeting_id, frame_index, username.clone(), vec![full_video, video.clone()]);
+        }
+    } else {
+        blob::retain(&video);
+        meeting.full_video = Some(video.clone());
+    }
 
-    let meeting = meetings
-        .get_mut(&meeting_id)
-        .ok_or(String::from("No meeting found on this video ID!"))?;
+    let frame_doc = SearchDoc::VideoFrame { meeting_id, frame_index: frame_index as u128 };
+    search::index(group_id, frame_doc, &title);
+    meeting.frames.push(VideoFrame::new(rt, username.clone(), title, video.clone()));
+    receipt::index_frame(group_id, meeting_id, frame_index, rt.time());
 
-    if meeting.process_type != MeetingProcessType::None {
-        return Err(String::from("Video is still on procesing... Please try again later.."))
+    if with_subtitles {
+        meeting.process_type = MeetingProcessType::Subtitle;
+        job::enqueue(rt, JobKind::Subtitle, group_id, meeting_id, frame_index, username.clone(), vec![video.clone()]);
     }
 
-    VIDEO_UPLOADS.with_borrow_mut(|video_uploads| {
-        let video_upload = video_uploads
-            .entry(video_upload_uuid.clone())
-            .or_default();
-
-        if video_upload.capacity() != total_data_length as usize {
-            video_upload.reserve_exact(total_data_length as usize);
+    job::enqueue(rt, JobKind::Thumbnail, group_id, meeting_id, frame_index, username.clone(), vec![video]);
+    if let Some(group) = GROUPS.with_borrow(|groups| groups.get(&group_id)) {
+        let uploader = user::get_principal(&username);
+        if let Some(uploader) = uploader {
+            receipt::mark_frame_seen(group_id, uploader, rt.time());
         }
-
-        let offset = chunk_index as usize * chunk::MB;
-        video_upload.splice(offset..offset, data);
-
-        if finish {
-            let data = video_uploads.remove(&video_upload_uuid).ok_or(String::from(
-                "Cannot find existing upload process with given UUID (This should never happen though)",
-            ))?;
-
-            if meeting.full_video_data.is_empty() {                
-                meeting.full_video_data = data.clone();
-            } else if !with_subtitles {
-                meeting.process_type = MeetingProcessType::Concat;
-                http::send_concat_video_request(group_id, meeting_id, meeting.full_video_data.clone(), data.clone())
-            }
-
-            let mut video_frame = VideoFrame::new(selfuser.username.clone(), title);
-            video_frame.data = data.clone();
-            meeting.frames.push(video_frame);
-
-            if with_subtitles {
-                meeting.process_type = MeetingProcessType::Subtitle;
-                send_process_subtitles_request(group_id, meeting_id, meeting.frames.len() - 1, data.clone());
-            }
-            
-            get_thumbnail_from_video_data(group_id, meeting_id, meeting.frames.len() - 1, data.clone());
-            websocket::broadcast_new_video_part(group_id, meeting_id, selfuser.username);
+        for principal in group.member_principals().into_iter().filter(|x| Some(*x) != uploader) {
+            notification::notify(rt, principal, NotificationKind::NewVideoPart { group_id, meeting_id, created_by: username.clone() });
         }
+        websocket::broadcast_new_video_part(rt, &group, meeting_id, username);
+    }
+    certification::certify_meeting(rt, group_id, &meeting);
+    MEETINGS.with_borrow_mut(|meetings| meetings.insert((group_id, meeting_id), meeting));
 
-        Ok(())
-    })
+    Ok(())
 }
 
-fn send_process_subtitles_request(group_id: u128, meeting_id: u128, index: usize, data: Vec<u8>) {
-    ic_cdk::spawn(async move {
-        if let Err(err) = http::send_process_subtitles_request(group_id, meeting_id, index, data).await {
-            ic_cdk::eprintln!("Failed to send process subtitles request: {}", err)
-        }
+pub fn delete_meeting(rt: &impl Runtime, group_id: u128, meeting_id: u128) -> Result<(), ApiError> {
+    user::assert_user_logged_in(rt)?;
+    assert_check_group(rt, group_id)?;
+
+    let selfname = user::get_selfname_force(rt)?;
+    let meeting = get_meeting(group_id, meeting_id)?;
+
+    let is_admin = GROUPS.with_borrow(|groups| {
+        groups.get(&group_id).is_some_and(|group| group.is_admin(&selfname))
     });
-}
+    if !is_admin && !meeting.created_by.eq_ignore_ascii_case(&selfname) {
+        return Err(ApiError::forbidden("Only an admin or the creator can delete a meeting!"));
+    }
 
-fn get_thumbnail_from_video_data(group_id: u128, meeting_id: u128, frame_index: usize, data: Vec<u8>) {
-    ic_cdk::spawn(async move {
-        let thumbnail_data = match http::send_thumbnail_request(data).await {
-            Ok(thumbnail_data) => thumbnail_data,
-            Err(err) => {
-                ic_cdk::eprintln!("Error while sending thumbnail request: {}", err);
-                return;
-            }
-        };
-
-        GROUPS.with_borrow(|groups| {
-            let group = groups.get(&group_id).ok_or(String::from("Cannot find group with this ID!")).unwrap();
-            let mut meetings = MEETINGS.lock().unwrap();
-            let meetings = meetings
-                .get_mut(&group_id)
-                .ok_or(String::from("No meetings found on this group!"))
-                .unwrap();
-
-            let meeting = meetings
-                .get_mut(&meeting_id)
-                .ok_or(String::from("No meeting found on this video ID!"))
-                .unwrap();
-            if meeting.thumbnail_data.is_empty() {
-                meeting.thumbnail_data = thumbnail_data.clone();
-            }
+    if meeting.process_type != MeetingProcessType::None {
+        return Err(ApiError::Busy)
+    }
 
-            meeting
-                .frames
-                .get_mut(frame_index)
-                .ok_or(String::from("No frame found on this meeting index!"))
-                .unwrap()
-                .thumbnail_data = thumbnail_data;
+    MEETINGS.with_borrow_mut(|meetings| meetings.remove(&(group_id, meeting_id)));
+    job::cancel_meeting_jobs(rt, group_id, meeting_id);
+    meeting.unindex(group_id);
+    receipt::unindex_frames(group_id, &meeting);
+    certification::uncertify_meeting(rt, group_id, meeting_id);
+    meeting.release_blobs();
 
-            websocket::broadcast_thumbnail(group, meeting_id, frame_index);
-        })
-    })
+    Ok(())
 }
 
-#[ic_cdk::query]
-pub fn get_video_meeting_size(group_id: u128, meeting_id: u128) -> Result<u128, String> {
-    user::assert_user_logged_in()?;
-    assert_check_group(group_id)?;
-
-    let meetings = MEETINGS.lock().unwrap();
-    let meetings = meetings
-        .get(&group_id)
-        .ok_or(String::from("No meetings found on this group!"))?;
+pub fn get_video_meeting_size(
+    rt: &impl Runtime,
+    group_id: u128,
+    meeting_id: u128,
+) -> Result<u128, ApiError> {
+    user::assert_user_logged_in(rt)?;
+    assert_check_group(rt, group_id)?;
 
-    let meeting = meetings
-        .get(&meeting_id)
-        .ok_or(String::from("No meeting found on this meeting ID!"))?;
+    let meeting = get_meeting(group_id, meeting_id)?;
 
-    Ok(meeting.full_video_data.len() as u128)
+    Ok(meeting.full_video.as_ref().map(blob::get_size).unwrap_or_default())
 }
 
-#[ic_cdk::query]
 pub fn get_video_meeting_chunk_blob(
+    rt: &impl Runtime,
     group_id: u128,
     meeting_id: u128,
     index: u128,
-) -> Result<Vec<u8>, String> {
-    user::assert_user_logged_in()?;
-    assert_check_group(group_id)?;
-
-    let meetings = MEETINGS.lock().unwrap();
-    let meetings = meetings
-        .get(&group_id)
-        .ok_or(String::from("No meetings found on this group!"))?;
+) -> Result<Vec<u8>, ApiError> {
+    user::assert_user_logged_in(rt)?;
+    assert_check_group(rt, group_id)?;
 
-    let meeting = meetings
-        .get(&meeting_id)
-        .ok_or(String::from("No meeting found on this meeting ID!"))?;
+    let meeting = get_meeting(group_id, meeting_id)?;
 
     Ok(meeting
-        .full_video_data
-        .iter()
-        .skip(index as usize * chunk::MB)
-        .take(chunk::MB)
-        .cloned()
-        .collect())
+        .full_video
+        .map(|id| blob::get_chunk(&id, index))
+        .unwrap_or_default())
 }
 
-#[ic_cdk::query]
 pub fn get_video_frame_size(
+    rt: &impl Runtime,
     group_id: u128,
     meeting_id: u128,
     frame_index: u128,
-) -> Result<u128, String> {
-    user::assert_user_logged_in()?;
-    assert_check_group(group_id)?;
-
-    let meetings = MEETINGS.lock().unwrap();
-    let meetings = meetings
-        .get(&group_id)
-        .ok_or(String::from("No meetings found on this group!"))?;
-
-    let meeting = meetings
-        .get(&meeting_id)
-        .ok_or(String::from("No meeting found on this meeting ID!"))?;
-
-    Ok(meeting
-        .frames
-        .get(frame_index as usize)
-        .ok_or(String::from("Frame index is out of bounds!"))?
-        .data
-        .len() as u128)
+) -> Result<u128, ApiError> {
+    user::assert_user_logged_in(rt)?;
+    assert_check_group(rt, group_id)?;
+
+    let meeting = get_meeting(group_id, meeting_id)?;
+
+    Ok(blob::get_size(
+        &meeting
+            .frames
+            .get(frame_index as usize)
+            .ok_or(ApiError::not_found("frame", frame_index))?
+            .video,
+    ))
 }
 
-#[ic_cdk::query]
 pub fn get_video_frame_chunk_blob(
+    rt: &impl Runtime,
     group_id: u128,
     meeting_id: u128,
     frame_index: u128,
     index: u128,
-) -> Result<Vec<u8>, String> {
-    user::assert_user_logged_in()?;
-    assert_check_group(group_id)?;
-
-    let meetings = MEETINGS.lock().unwrap();
-    let meetings = meetings
-        .get(&group_id)
-        .ok_or(String::from("No meetings found on this group!"))?;
-
-    let meeting = meetings
-        .get(&meeting_id)
-        .ok_or(String::from("No meeting found on this meeting ID!"))?;
-
-    Ok(meeting
-        .frames
-        .get(frame_index as usize)
-        .ok_or(String::from("Frame index is out of bounds!"))?
-        .data
-        .iter()
-        .skip(index as usize * chunk::MB)
-        .take(chunk::MB)
-        .cloned()
-        .collect())
+) -> Result<Vec<u8>, ApiError> {
+    user::assert_user_logged_in(rt)?;
+    assert_check_group(rt, group_id)?;
+
+    let meeting = get_meeting(group_id, meeting_id)?;
+
+    Ok(blob::get_chunk(
+        &meeting
+            .frames
+            .get(frame_index as usize)
+            .ok_or(ApiError::not_found("frame", frame_index))?
+            .video,
+        index,
+    ))
 }
 
-#[ic_cdk::query]
-pub fn get_meeting_thumbnail_size(group_id: u128, meeting_id: u128) -> Result<u128, String> {
-    user::assert_user_logged_in()?;
-    assert_check_group(group_id)?;
-
-    let meetings = MEETINGS.lock().map_err(|err| format!("Failed to lock meeting: {}", err))?;
-    let meetings = meetings
-        .get(&group_id)
-        .ok_or(String::from("No meetings found on this group!"))?;
+pub fn get_meeting_thumbnail_size(
+    rt: &impl Runtime,
+    group_id: u128,
+    meeting_id: u128,
+) -> Result<u128, ApiError> {
+    user::assert_user_logged_in(rt)?;
+    assert_check_group(rt, group_id)?;
 
-    let meeting = meetings
-        .get(&meeting_id)
-        .ok_or(String::from("No meeting found on this meeting ID!"))?;
+    let meeting = get_meeting(group_id, meeting_id)?;
 
-    Ok(meeting.thumbnail_data.len() as u128)
+    Ok(meeting.thumbnail.as_ref().map(blob::get_size).unwrap_or_default())
 }
 
-#[ic_cdk::query]
 pub fn get_meeting_thumbnail_chunk_blob(
+    rt: &impl Runtime,
     group_id: u128,
     meeting_id: u128,
     index: u128,
-) -> Result<Vec<u8>, String> {
-    user::assert_user_logged_in()?;
-    assert_check_group(group_id)?;
-
-    let meetings = MEETINGS.lock().unwrap();
-    let meetings = meetings
-        .get(&group_id)
-        .ok_or(String::from("No meetings found on this group!"))?;
+) -> Result<Vec<u8>, ApiError> {
+    user::assert_user_logged_in(rt)?;
+    assert_check_group(rt, group_id)?;
 
-    let meeting = meetings
-        .get(&meeting_id)
-        .ok_or(String::from("No meeting found on this meeting ID!"))?;
+    let meeting = get_meeting(group_id, meeting_id)?;
 
     Ok(meeting
-        .thumbnail_data
-        .iter()
-        .skip(index as usize * chunk::MB)
-        .take(chunk::MB)
-        .cloned()
-        .collect())
+        .thumbnail
+        .map(|id| blob::get_chunk(&id, index))
+        .unwrap_or_default())
 }
 
-#[ic_cdk::query]
-pub fn get_video_frame_detail(group_id: u128, meeting_id: u128, frame_index: u128) -> Result<VideoFrameHeader, String> {
-    user::assert_user_logged_in()?;
-    assert_check_group(group_id)?;
-
-    let meetings = MEETINGS.lock().map_err(|err| format!("Failed to lock meeting: {}", err))?;
-    let meetings = meetings
-        .get(&group_id)
-        .ok_or(String::from("No meetings found on this group!"))?;
+pub fn get_video_frame_detail(
+    rt: &impl Runtime,
+    group_id: u128,
+    meeting_id: u128,
+    frame_index: u128,
+) -> Result<VideoFrameHeader, ApiError> {
+    user::assert_user_logged_in(rt)?;
+    assert_check_group(rt, group_id)?;
 
-    let meeting = meetings
-        .get(&meeting_id)
-        .ok_or(String::from("No meeting found on this meeting ID!"))?;
+    let meeting = get_meeting(group_id, meeting_id)?;
 
     meeting
         .frames
         .get(frame_index as usize)
         .map(VideoFrameHeader::from)
-        .ok_or(String::from("Cannot find this meeting with the provided index!"))
+        .ok_or(ApiError::not_found("frame", frame_index))
 }
 
-#[ic_cdk::query]
-pub fn get_meeting_video_frame_thumbnail_size(group_id: u128, meeting_id: u128, frame_index: u128) -> Result<u128, String> {
-    user::assert_user_logged_in()?;
-    assert_check_group(group_id)?;
-
-    let meetings = MEETINGS.lock().map_err(|err| format!("Failed to lock meeting: {}", err))?;
-    let meetings = meetings
-        .get(&group_id)
-        .ok_or(String::from("No meetings found on this group!"))?;
+pub fn get_meeting_video_frame_thumbnail_size(
+    rt: &impl Runtime,
+    group_id: u128,
+    meeting_id: u128,
+    frame_index: u128,
+) -> Result<u128, ApiError> {
+    user::assert_user_logged_in(rt)?;
+    assert_check_group(rt, group_id)?;
 
-    let meeting = meetings
-        .get(&meeting_id)
-        .ok_or(String::from("No meeting found on this meeting ID!"))?;
+    let meeting = get_meeting(group_id, meeting_id)?;
 
     Ok(meeting
         .frames
         .get(frame_index as usize)
-        .ok_or(String::from("Cannot find this meeting with the provided index!"))?
-        .thumbnail_data.len() as u128)
+        .ok_or(ApiError::not_found("frame", frame_index))?
+        .thumbnail
+        .as_ref()
+        .map(blob::get_size)
+        .unwrap_or_default())
 }
 
-#[ic_cdk::query]
 pub fn get_meeting_video_frame_thumbnail_chunk_blob(
+    rt: &impl Runtime,
     group_id: u128,
     meeting_id: u128,
     frame_index: u128,
     index: u128,
-) -> Result<Vec<u8>, String> {
-    user::assert_user_logged_in()?;
-    assert_check_group(group_id)?;
+) -> Result<Vec<u8>, ApiError> {
+    user::assert_user_logged_in(rt)?;
+    assert_check_group(rt, group_id)?;
 
-    let meetings = MEETINGS.lock().unwrap();
-    let meetings = meetings
-        .get(&group_id)
-        .ok_or(String::from("No meetings found on this group!"))?;
-
-    let meeting = meetings
-        .get(&meeting_id)
-        .ok_or(String::from("No meeting found on this meeting ID!"))?;
+    let meeting = get_meeting(group_id, meeting_id)?;
 
     Ok(meeting
         .frames
         .get(frame_index as usize)
-        .ok_or(String::from("Cannot find this meeting with the provided index!"))?
-        .thumbnail_data
-        .iter()
-        .skip(index as usize * chunk::MB)
-        .take(chunk::MB)
-        .cloned()
-        .collect())
+        .ok_or(ApiError::not_found("frame", frame_index))?
+        .thumbnail
+        .as_ref()
+        .map(|id| blob::get_chunk(id, index))
+        .unwrap_or_default())
 }
 
+mod endpoints {
+    use super::*;
+    use crate::runtime::IcRuntime;
+
+    #[ic_cdk::query]
+    fn get_meetings(group_id: u128) -> Result<Vec<MeetingHeader>, ApiError> {
+        super::get_meetings(&IcRuntime, group_id)
+    }
+
+    #[ic_cdk::query]
+    fn get_meeting_detail(group_id: u128, meeting_id: u128) -> Result<MeetingHeader, ApiError> {
+        super::get_meeting_detail(&IcRuntime, group_id, meeting_id)
+    }
+
+    #[ic_cdk::update]
+    fn create_meeting(group_id: u128, title: String) -> Result<u128, ApiError> {
+        super::create_meeting(&IcRuntime, group_id, title)
+    }
+
+    #[ic_cdk::update]
+    fn delete_meeting(group_id: u128, meeting_id: u128) -> Result<(), ApiError> {
+        super::delete_meeting(&IcRuntime, group_id, meeting_id)
+    }
+
+    #[ic_cdk::query]
+    fn get_video_meeting_size(group_id: u128, meeting_id: u128) -> Result<u128, ApiError> {
+        super::get_video_meeting_size(&IcRuntime, group_id, meeting_id)
+    }
+
+    #[ic_cdk::query]
+    fn get_video_meeting_chunk_blob(
+        group_id: u128,
+        meeting_id: u128,
+        index: u128,
+    ) -> Result<Vec<u8>, ApiError> {
+        super::get_video_meeting_chunk_blob(&IcRuntime, group_id, meeting_id, index)
+    }
+
+    #[ic_cdk::query]
+    fn get_video_frame_size(
+        group_id: u128,
+        meeting_id: u128,
+        frame_index: u128,
+    ) -> Result<u128, ApiError> {
+        super::get_video_frame_size(&IcRuntime, group_id, meeting_id, frame_index)
+    }
+
+    #[ic_cdk::query]
+    fn get_video_frame_chunk_blob(
+        group_id: u128,
+        meeting_id: u128,
+        frame_index: u128,
+        index: u128,
+    ) -> Result<Vec<u8>, ApiError> {
+        super::get_video_frame_chunk_blob(&IcRuntime, group_id, meeting_id, frame_index, index)
+    }
+
+    #[ic_cdk::query]
+    fn get_meeting_thumbnail_size(group_id: u128, meeting_id: u128) -> Result<u128, ApiError> {
+        super::get_meeting_thumbnail_size(&IcRuntime, group_id, meeting_id)
+    }
+
+    #[ic_cdk::query]
+    fn get_meeting_thumbnail_chunk_blob(
+        group_id: u128,
+        meeting_id: u128,
+        index: u128,
+    ) -> Result<Vec<u8>, ApiError> {
+        super::get_meeting_thumbnail_chunk_blob(&IcRuntime, group_id, meeting_id, index)
+    }
+
+    #[ic_cdk::query]
+    fn get_video_frame_detail(
+        group_id: u128,
+        meeting_id: u128,
+        frame_index: u128,
+    ) -> Result<VideoFrameHeader, ApiError> {
+        super::get_video_frame_detail(&IcRuntime, group_id, meeting_id, frame_index)
+    }
+
+    #[ic_cdk::query]
+    fn get_meeting_video_frame_thumbnail_size(
+        group_id: u128,
+        meeting_id: u128,
+        frame_index: u128,
+    ) -> Result<u128, ApiError> {
+        super::get_meeting_video_frame_thumbnail_size(&IcRuntime, group_id, meeting_id, frame_index)
+    }
+
+    #[ic_cdk::query]
+    fn get_meeting_video_frame_thumbnail_chunk_blob(
+        group_id: u128,
+        meeting_id: u128,
+        frame_index: u128,
+        index: u128,
+    ) -> Result<Vec<u8>, ApiError> {
+        super::get_meeting_video_frame_thumbnail_chunk_blob(
+            &IcRuntime,
+            group_id,
+            meeting_id,
+            frame_index,
+            index,
+        )
+    }
+}
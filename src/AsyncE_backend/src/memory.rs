@@ -0,0 +1,80 @@
+use std::cell::RefCell;
+
+use ic_stable_structures::{
+    memory_manager::{MemoryId, MemoryManager, VirtualMemory},
+    DefaultMemoryImpl,
+};
+
+pub type Memory = VirtualMemory<DefaultMemoryImpl>;
+
+pub const USERS_MEMORY_ID: MemoryId = MemoryId::new(0);
+pub const GROUPS_MEMORY_ID: MemoryId = MemoryId::new(1);
+pub const MEETINGS_MEMORY_ID: MemoryId = MemoryId::new(2);
+pub const GROUP_INVITES_MEMORY_ID: MemoryId = MemoryId::new(3);
+pub const CHATS_MEMORY_ID: MemoryId = MemoryId::new(4);
+pub const PRIMARY_KEY_CONTAINERS_MEMORY_ID: MemoryId = MemoryId::new(5);
+pub const UPLOAD_BUFFERS_MEMORY_ID: MemoryId = MemoryId::new(6);
+pub const SCHEMA_VERSION_MEMORY_ID: MemoryId = MemoryId::new(7);
+pub const BLOBS_MEMORY_ID: MemoryId = MemoryId::new(8);
+pub const BLOB_CHUNKS_MEMORY_ID: MemoryId = MemoryId::new(9);
+pub const UPLOAD_SESSIONS_MEMORY_ID: MemoryId = MemoryId::new(10);
+pub const UPLOAD_CHUNKS_MEMORY_ID: MemoryId = MemoryId::new(11);
+pub const MEDIA_TOKEN_SECRET_MEMORY_ID: MemoryId = MemoryId::new(12);
+pub const CONFIG_MEMORY_ID: MemoryId = MemoryId::new(13);
+pub const JOBS_MEMORY_ID: MemoryId = MemoryId::new(14);
+pub const USERNAMES_MEMORY_ID: MemoryId = MemoryId::new(15);
+pub const USER_EVENTS_MEMORY_ID: MemoryId = MemoryId::new(16);
+pub const EVENT_CURSORS_MEMORY_ID: MemoryId = MemoryId::new(17);
+pub const LAST_SEEN_MEMORY_ID: MemoryId = MemoryId::new(18);
+pub const CHAT_REPLIES_MEMORY_ID: MemoryId = MemoryId::new(19);
+pub const SEARCH_INDEX_MEMORY_ID: MemoryId = MemoryId::new(20);
+pub const NOTIFICATIONS_MEMORY_ID: MemoryId = MemoryId::new(21);
+pub const READ_RECEIPTS_MEMORY_ID: MemoryId = MemoryId::new(22);
+pub const CHAT_HISTORY_MEMORY_ID: MemoryId = MemoryId::new(23);
+pub const STAGED_ATTACHMENTS_MEMORY_ID: MemoryId = MemoryId::new(24);
+pub const UPLOAD_BUFFER_CHUNKS_MEMORY_ID: MemoryId = MemoryId::new(25);
+pub const JOB_QUEUE_MEMORY_ID: MemoryId = MemoryId::new(26);
+pub const MEETING_JOBS_MEMORY_ID: MemoryId = MemoryId::new(27);
+pub const UNREAD_NOTIFICATIONS_MEMORY_ID: MemoryId = MemoryId::new(28);
+pub const FRAME_TIMES_MEMORY_ID: MemoryId = MemoryId::new(29);
+
+thread_local! {
+    // the raw stable memory is kept around (instead of creating a new handle every time)
+    // so the legacy migration can read the old layout before the memory manager takes over
+    static STABLE_MEMORY: DefaultMemoryImpl = DefaultMemoryImpl::default();
+
+    static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> =
+        RefCell::new(MemoryManager::init(stable_memory()));
+}
+
+pub fn stable_memory() -> DefaultMemoryImpl {
+    STABLE_MEMORY.with(|stable_memory| stable_memory.clone())
+}
+
+pub fn get_memory(id: MemoryId) -> Memory {
+    MEMORY_MANAGER.with_borrow(|memory_manager| memory_manager.get(id))
+}
+
+/// Implements `Storable` for types by encoding them as candid,
+/// which lets us add optional fields later without rewriting the stores.
+#[macro_export]
+macro_rules! impl_candid_storable {
+    ($($ty:ty),* $(,)?) => {
+        $(
+            impl ic_stable_structures::Storable for $ty {
+                fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+                    std::borrow::Cow::Owned(
+                        candid::encode_one(self).expect("Cannot encode stored data to candid!"),
+                    )
+                }
+
+                fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+                    candid::decode_one(&bytes).expect("Cannot decode stored data from candid!")
+                }
+
+                const BOUND: ic_stable_structures::storable::Bound =
+                    ic_stable_structures::storable::Bound::Unbounded;
+            }
+        )*
+    };
+}
@@ -0,0 +1,111 @@
+//! Versioned layout of everything kept in stable memory.
+//!
+//! Each entry of [`MIGRATIONS`] upgrades the stores by exactly one version, `post_upgrade`
+//! runs every migration between the stored schema version and [`CURRENT_SCHEMA_VERSION`].
+//! Migrations must open the stores they rewrite through [`open_store`] (never the ones in
+//! `globals`), since those would cache the layout from before the migration ran.
+
+mod v0;
+mod v1;
+mod v2;
+mod v3;
+mod v4;
+mod v5;
+mod v6;
+mod v7;
+mod v8;
+
+#[cfg(test)]
+mod tests;
+
+use ic_stable_structures::{memory_manager::MemoryId, StableBTreeMap, Storable};
+
+use crate::{
+    globals::SCHEMA_VERSION,
+    memory::{self, Memory},
+};
+
+/// The first schema version that lives in stable structures. Canisters that were upgraded
+/// to stable structures before the version header existed are on this version.
+pub const STABLE_STRUCTURES_SCHEMA_VERSION: u32 = 1;
+
+pub const CURRENT_SCHEMA_VERSION: u32 = MIGRATIONS.len() as u32;
+
+/// `MIGRATIONS[n]` migrates the stores from version `n` to version `n + 1`.
+const MIGRATIONS: &[fn()] = &[
+    v0::migrate_to_v1,
+    v1::migrate_to_v2,
+    v2::migrate_to_v3,
+    v3::migrate_to_v4,
+    v4::migrate_to_v5,
+    v5::migrate_to_v6,
+    v6::migrate_to_v7,
+    v7::migrate_to_v8,
+    v8::migrate_to_v9,
+];
+
+pub fn open_store<K: Storable + Ord + Clone, V: Storable>(
+    id: MemoryId,
+) -> StableBTreeMap<K, V, Memory> {
+    StableBTreeMap::init(memory::get_memory(id))
+}
+
+/// Rewrites every value of a store from its old type `V1` to the new type `V2`.
+///
+/// Values are converted one by one (reopening the store each time) so an upgrade
+/// never has to hold a whole store on the heap.
+fn migrate_store<K, V1, V2>(id: MemoryId, migrate: impl Fn(&K, V1) -> V2)
+where
+    K: Storable + Ord + Clone,
+    V1: Storable,
+    V2: Storable,
+{
+    let keys = open_store::<K, V1>(id).keys().collect::<Vec<_>>();
+    for key in keys {
+        // the old value has to be removed through the old type,
+        // since `insert` would try to decode it as the new one
+        let Some(value) = open_store::<K, V1>(id).remove(&key) else {
+            continue;
+        };
+
+        let value = migrate(&key, value);
+        open_store::<K, V2>(id).insert(key, value);
+    }
+}
+
+pub fn get_schema_version() -> u32 {
+    SCHEMA_VERSION.with_borrow(|schema_version| *schema_version.get())
+}
+
+fn set_schema_version(version: u32) {
+    SCHEMA_VERSION
+        .with_borrow_mut(|schema_version| schema_version.set(version))
+        .expect("FAILED TO SAVE SCHEMA VERSION!");
+}
+
+/// Fresh installs start on the latest layout, there is nothing to migrate.
+pub fn init_schema_version() {
+    set_schema_version(CURRENT_SCHEMA_VERSION);
+}
+
+pub fn migrate() {
+    // the legacy layout has to be detected before anything touches the memory manager
+    let mut version = if v0::is_legacy_heap_layout() {
+        0
+    } else {
+        get_schema_version()
+    };
+
+    if version > CURRENT_SCHEMA_VERSION {
+        panic!(
+            "Stable memory is on schema version {} but this canister only supports up to {}!",
+            version, CURRENT_SCHEMA_VERSION
+        );
+    }
+
+    while version < CURRENT_SCHEMA_VERSION {
+        MIGRATIONS[version as usize]();
+        version += 1;
+        set_schema_version(version);
+    }
+}
@@ -0,0 +1,420 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use candid::Principal;
+use ic_stable_structures::Memory;
+
+use crate::{
+    blob,
+    chat::Chat,
+    globals::{
+        CHATS, FRAME_TIMES, GROUPS, GROUP_INVITES, JOB_QUEUE, MEETINGS, MEETING_JOBS,
+        PRIMARY_KEY_CONTAINERS, SEARCH_INDEX, UNREAD_NOTIFICATIONS, UPLOAD_BUFFERS, USERNAMES,
+        USERS,
+    },
+    group::{GroupMember, GroupMemberRole},
+    job::{Job, JobKind, JobState},
+    meeting::{self, MeetingProcessType},
+    memory,
+    notification::{Notification, NotificationKind},
+    primary_key::PrimaryKeyType,
+    search::SearchDoc,
+    user,
+};
+
+use super::{
+    v1::{Group, Meeting, User, VideoFrame},
+    *,
+};
+
+const WASM_PAGE_SIZE: u64 = 64 * 1024;
+
+fn principal(id: u8) -> Principal {
+    Principal::from_slice(&[id; 29])
+}
+
+fn fixture_user(username: &str) -> User {
+    User {
+        balance: 10,
+        username: username.to_string(),
+        subscription: None,
+        created_time_unix: 1,
+        profile_picture_blob: vec![1, 2, 3],
+    }
+}
+
+fn fixture_group() -> Group {
+    Group {
+        id: 1,
+        name: String::from("group"),
+        owner: String::from("alice"),
+        members: vec![
+            GroupMember::new("alice", GroupMemberRole::Admin),
+            GroupMember::new("bob", GroupMemberRole::Member),
+        ],
+        created_time_unix: 2,
+        profile_picture_blob: Vec::new(),
+    }
+}
+
+fn fixture_meeting() -> Meeting {
+    Meeting {
+        id: 1,
+        thumbnail_data: vec![4],
+        full_video_data: vec![5, 6],
+        title: String::from("standup"),
+        created_by: String::from("alice"),
+        frames: vec![VideoFrame {
+            data: vec![5, 6],
+            title: String::from("part 1"),
+            created_by: String::from("alice"),
+            thumbnail_data: vec![4],
+            created_time_unix: 3,
+        }],
+        created_time_unix: 3,
+        ..Default::default()
+    }
+}
+
+fn fixture_chat(id: u128) -> Chat {
+    Chat {
+        id,
+        uuid: format!("uuid-{}", id),
+        content: format!("message {}", id),
+        group_id: 1,
+        username: String::from("bob"),
+        created_time_unix: 4,
+        ..Default::default()
+    }
+}
+
+fn write_legacy_heap_state(state: v0::LegacyHeapState) {
+    let bytes = candid::encode_args(state).unwrap();
+
+    let stable_memory = memory::stable_memory();
+    stable_memory.grow(bytes.len() as u64 / WASM_PAGE_SIZE + 1);
+    stable_memory.write(0, &bytes);
+}
+
+#[test]
+fn restores_legacy_heap_layout() {
+    write_legacy_heap_state((
+        BTreeMap::from([
+            (principal(1), fixture_user("alice")),
+            (principal(2), fixture_user("bob")),
+        ]),
+        BTreeMap::from([(1, fixture_group())]),
+        BTreeMap::from([(1, BTreeMap::from([(1, fixture_meeting())]))]),
+        BTreeMap::from([(String::from("carol"), BTreeSet::from([1]))]),
+        BTreeMap::from([(
+            1,
+            BTreeMap::from([(1, fixture_chat(1)), (2, fixture_chat(2))]),
+        )]),
+        BTreeMap::from([(PrimaryKeyType::Group, 2), (PrimaryKeyType::Chat, 3)]),
+    ));
+
+    migrate();
+
+    assert_eq!(get_schema_version(), CURRENT_SCHEMA_VERSION);
+
+    USERS.with_borrow(|users| {
+        assert_eq!(users.len(), 2);
+        assert_eq!(users.get(&principal(1)).unwrap().username, "alice");
+        assert_eq!(users.get(&principal(2)).unwrap().username, "bob");
+    });
+
+    GROUPS.with_borrow(|groups| {
+        let group = groups.get(&1).unwrap();
+        assert_eq!(group.name, "group");
+        assert!(group.is_member("bob"));
+    });
+
+    MEETINGS.with_borrow(|meetings| {
+        let meeting = meetings.get(&(1, 1)).unwrap();
+        assert_eq!(meeting.title, "standup");
+        assert_eq!(meeting.frames.len(), 1);
+        assert_eq!(blob::read(&meeting.full_video.unwrap()), vec![5, 6]);
+    });
+
+    GROUP_INVITES.with_borrow(|group_invites| {
+        assert_eq!(
+            group_invites.get(&String::from("carol")).unwrap().0,
+            BTreeSet::from([1])
+        );
+    });
+
+    CHATS.with_borrow(|chats| {
+        let chats = chats
+            .iter()
+            .map(|(key, chat)| (key, chat.content))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            chats,
+            vec![
+                ((1, 1), String::from("message 1")),
+                ((1, 2), String::from("message 2"))
+            ]
+        );
+    });
+
+    PRIMARY_KEY_CONTAINERS.with_borrow(|primary_key_containers| {
+        assert_eq!(primary_key_containers.get(&PrimaryKeyType::Group), Some(2));
+        assert_eq!(primary_key_containers.get(&PrimaryKeyType::Chat), Some(3));
+        assert_eq!(primary_key_containers.get(&PrimaryKeyType::Video), None);
+    });
+}
+
+fn init_v1_schema() {
+    set_schema_version(STABLE_STRUCTURES_SCHEMA_VERSION);
+}
+
+#[test]
+fn upgrades_unversioned_stable_layout() {
+    let mut users = open_store(memory::USERS_MEMORY_ID);
+    users.insert(principal(1), fixture_user("alice"));
+    let mut chats = open_store(memory::CHATS_MEMORY_ID);
+    chats.insert((1u128, 1u128), fixture_chat(1));
+
+    migrate();
+
+    assert_eq!(get_schema_version(), CURRENT_SCHEMA_VERSION);
+    USERS.with_borrow(|users| assert_eq!(users.get(&principal(1)).unwrap().username, "alice"));
+    CHATS.with_borrow(|chats| assert_eq!(chats.get(&(1, 1)).unwrap().content, "message 1"));
+}
+
+#[test]
+fn fresh_install_starts_on_current_version() {
+    init_schema_version();
+    assert_eq!(get_schema_version(), CURRENT_SCHEMA_VERSION);
+
+    migrate();
+    assert_eq!(get_schema_version(), CURRENT_SCHEMA_VERSION);
+}
+
+#[test]
+#[should_panic(expected = "only supports up to")]
+fn refuses_newer_schema_version() {
+    set_schema_version(CURRENT_SCHEMA_VERSION + 1);
+    migrate();
+}
+
+#[test]
+fn moves_inline_bytes_to_blob_store() {
+    init_v1_schema();
+    open_store(memory::USERS_MEMORY_ID).insert(principal(1), fixture_user("alice"));
+    open_store(memory::USERS_MEMORY_ID).insert(principal(2), fixture_user("bob"));
+    open_store(memory::GROUPS_MEMORY_ID).insert(1u128, fixture_group());
+    open_store(memory::MEETINGS_MEMORY_ID).insert((1u128, 1u128), fixture_meeting());
+
+    migrate();
+
+    assert_eq!(get_schema_version(), CURRENT_SCHEMA_VERSION);
+
+    // both users had the same picture, the blob store keeps it once
+    let alice = USERS.with_borrow(|users| users.get(&principal(1)).unwrap());
+    let bob = USERS.with_borrow(|users| users.get(&principal(2)).unwrap());
+    let profile_picture = alice.profile_picture.unwrap();
+    assert_eq!(bob.profile_picture.as_ref(), Some(&profile_picture));
+    assert_eq!(blob::read(&profile_picture), vec![1, 2, 3]);
+    assert_eq!(blob::get_metadata(&profile_picture).unwrap().ref_count, 2);
+
+    let group = GROUPS.with_borrow(|groups| groups.get(&1).unwrap());
+    assert_eq!(group.profile_picture, None);
+
+    let meeting = MEETINGS.with_borrow(|meetings| meetings.get(&(1, 1)).unwrap());
+    let full_video = meeting.full_video.clone().unwrap();
+    let thumbnail = meeting.thumbnail.clone().unwrap();
+    assert_eq!(meeting.frames[0].video, full_video);
+    assert_eq!(meeting.frames[0].thumbnail.as_ref(), Some(&thumbnail));
+    assert_eq!(blob::read(&full_video), vec![5, 6]);
+    assert_eq!(blob::read(&thumbnail), vec![4]);
+    assert_eq!(blob::get_metadata(&full_video).unwrap().ref_count, 2);
+
+    meeting.release_blobs();
+    assert!(blob::get_metadata(&full_video).is_none());
+    assert!(blob::get_metadata(&thumbnail).is_none());
+    assert!(blob::read(&full_video).is_empty());
+}
+
+#[test]
+fn unlocks_meetings_stuck_in_processing() {
+    set_schema_version(2);
+    open_store(memory::MEETINGS_MEMORY_ID).insert(
+        (1u128, 1u128),
+        meeting::Meeting {
+            id: 1,
+            process_type: MeetingProcessType::Concat,
+            ..Default::default()
+        },
+    );
+
+    migrate();
+
+    assert_eq!(get_schema_version(), CURRENT_SCHEMA_VERSION);
+    let meeting = MEETINGS.with_borrow(|meetings| meetings.get(&(1, 1)).unwrap());
+    assert_eq!(meeting.process_type, MeetingProcessType::None);
+}
+
+#[test]
+fn indexes_existing_usernames() {
+    set_schema_version(3);
+    open_store(memory::USERS_MEMORY_ID).insert(
+        principal(1),
+        user::User {
+            username: String::from("Alice"),
+            ..Default::default()
+        },
+    );
+
+    migrate();
+
+    assert_eq!(get_schema_version(), CURRENT_SCHEMA_VERSION);
+    assert_eq!(
+        USERNAMES.with_borrow(|usernames| usernames.get(&String::from("alice"))),
+        Some(principal(1))
+    );
+    assert_eq!(user::get_principal("ALICE"), Some(principal(1)));
+}
+
+#[test]
+fn indexes_existing_chats_and_meetings() {
+    set_schema_version(4);
+    open_store(memory::CHATS_MEMORY_ID).insert((1u128, 1u128), fixture_chat(1));
+    let meeting = meeting::Meeting {
+        id: 2,
+        title: String::from("Weekly sync"),
+        frames: vec![meeting::VideoFrame {
+            title: String::from("Intro"),
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+    open_store(memory::MEETINGS_MEMORY_ID).insert((1u128, 2u128), meeting);
+
+    migrate();
+
+    assert_eq!(get_schema_version(), CURRENT_SCHEMA_VERSION);
+    let indexed = SEARCH_INDEX.with_borrow(|search_index| {
+        search_index
+            .keys()
+            .map(|(group_id, _, doc)| (group_id, doc))
+            .collect::<BTreeSet<_>>()
+    });
+    assert_eq!(
+        indexed,
+        BTreeSet::from([
+            (1, SearchDoc::Chat { chat_id: 1 }),
+            (
+                1,
+                SearchDoc::VideoFrame {
+                    meeting_id: 2,
+                    frame_index: 0
+                }
+            ),
+            (1, SearchDoc::Meeting { meeting_id: 2 }),
+        ])
+    );
+    // "message 1", "weekly sync" and "intro"
+    assert_eq!(
+        SEARCH_INDEX.with_borrow(|search_index| search_index.len()),
+        5
+    );
+}
+
+#[test]
+fn drops_half_sent_upload_buffers() {
+    set_schema_version(5);
+    open_store::<String, Vec<u8>>(memory::UPLOAD_BUFFERS_MEMORY_ID)
+        .insert(String::from("profile_picture/1"), vec![1, 2, 3]);
+
+    migrate();
+
+    assert_eq!(get_schema_version(), CURRENT_SCHEMA_VERSION);
+    assert!(UPLOAD_BUFFERS.with_borrow(|upload_buffers| upload_buffers.is_empty()));
+}
+
+#[test]
+fn indexes_existing_jobs() {
+    set_schema_version(6);
+    let job = |id: u128, state: JobState| Job {
+        id,
+        kind: JobKind::Thumbnail,
+        group_id: 1,
+        meeting_id: 2,
+        frame_index: 0,
+        requested_by: String::from("alice"),
+        inputs: Vec::new(),
+        state,
+        processor_id: None,
+        attempts: 0,
+        last_error: None,
+        next_attempt_time_unix: 10 * id,
+        created_time_unix: 0,
+        updated_time_unix: 0,
+    };
+    let mut jobs = open_store(memory::JOBS_MEMORY_ID);
+    jobs.insert(1u128, job(1, JobState::Done));
+    jobs.insert(2u128, job(2, JobState::Processing));
+
+    migrate();
+
+    assert_eq!(get_schema_version(), CURRENT_SCHEMA_VERSION);
+    assert_eq!(
+        JOB_QUEUE.with_borrow(|job_queue| job_queue.keys().collect::<Vec<_>>()),
+        vec![(20, 2)]
+    );
+    assert_eq!(
+        MEETING_JOBS.with_borrow(|meeting_jobs| meeting_jobs.keys().collect::<Vec<_>>()),
+        vec![(1, 2, 1), (1, 2, 2)]
+    );
+}
+
+#[test]
+fn counts_unread_notifications() {
+    set_schema_version(7);
+    let notification = |id: u128, read: bool| Notification {
+        id,
+        kind: NotificationKind::GroupInvited {
+            group_id: 1,
+            group_name: String::from("group"),
+        },
+        created_time_unix: 0,
+        read,
+    };
+    let mut notifications = open_store(memory::NOTIFICATIONS_MEMORY_ID);
+    notifications.insert((principal(1), 1u128), notification(1, false));
+    notifications.insert((principal(1), 2u128), notification(2, true));
+    notifications.insert((principal(1), 3u128), notification(3, false));
+    notifications.insert((principal(2), 4u128), notification(4, true));
+
+    migrate();
+
+    assert_eq!(get_schema_version(), CURRENT_SCHEMA_VERSION);
+    assert_eq!(
+        UNREAD_NOTIFICATIONS
+            .with_borrow(|unread_notifications| unread_notifications.iter().collect::<Vec<_>>()),
+        vec![(principal(1), 2)]
+    );
+}
+
+#[test]
+fn indexes_existing_video_parts_by_time() {
+    set_schema_version(8);
+    let frame = |created_time_unix| meeting::VideoFrame {
+        created_time_unix,
+        ..Default::default()
+    };
+    let meeting = meeting::Meeting {
+        id: 2,
+        frames: vec![frame(10), frame(20)],
+        ..Default::default()
+    };
+    open_store(memory::MEETINGS_MEMORY_ID).insert((1u128, 2u128), meeting);
+
+    migrate();
+
+    assert_eq!(get_schema_version(), CURRENT_SCHEMA_VERSION);
+    assert_eq!(
+        FRAME_TIMES.with_borrow(|frame_times| frame_times.keys().collect::<Vec<_>>()),
+        vec![(1, 10, (2, 0)), (1, 20, (2, 1))]
+    );
+}
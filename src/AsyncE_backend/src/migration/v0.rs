@@ -0,0 +1,99 @@
+//! Version 0 is the heap layout written by `stable_save` in the old `pre_upgrade` hook,
+//! before every store was moved to stable structures.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use candid::Principal;
+use ic_stable_structures::Memory;
+
+use crate::{chat::Chat, invite::GroupInviteSet, memory, primary_key::PrimaryKeyContainer};
+
+use super::{
+    open_store,
+    v1::{Group, Meeting, User},
+};
+
+const WASM_PAGE_SIZE: u64 = 64 * 1024;
+const CANDID_MAGIC: &[u8; 4] = b"DIDL";
+
+pub type LegacyHeapState = (
+    BTreeMap<Principal, User>,
+    BTreeMap<u128, Group>,
+    BTreeMap<u128, BTreeMap<u128, Meeting>>,
+    BTreeMap<String, BTreeSet<u128>>,
+    BTreeMap<u128, BTreeMap<u128, Chat>>,
+    PrimaryKeyContainer,
+);
+
+/// Whether stable memory still holds the candid tuple saved by `stable_save`.
+pub fn is_legacy_heap_layout() -> bool {
+    let stable_memory = memory::stable_memory();
+    if stable_memory.size() == 0 {
+        return false;
+    }
+
+    let mut magic = [0; CANDID_MAGIC.len()];
+    stable_memory.read(0, &mut magic);
+    &magic == CANDID_MAGIC
+}
+
+/// Moves the data saved by the old `pre_upgrade` hook into the stable stores.
+pub fn migrate_to_v1() {
+    let (users, groups, meetings, group_invites, chats, primary_key_containers) =
+        read_legacy_heap_state();
+
+    let mut store = open_store(memory::USERS_MEMORY_ID);
+    for (principal, user) in users {
+        store.insert(principal, user);
+    }
+
+    let mut store = open_store(memory::GROUPS_MEMORY_ID);
+    for (group_id, group) in groups {
+        store.insert(group_id, group);
+    }
+
+    let mut store = open_store(memory::MEETINGS_MEMORY_ID);
+    for (group_id, meetings) in meetings {
+        for (meeting_id, meeting) in meetings {
+            store.insert((group_id, meeting_id), meeting);
+        }
+    }
+
+    let mut store = open_store(memory::GROUP_INVITES_MEMORY_ID);
+    for (username, group_ids) in group_invites {
+        store.insert(username, GroupInviteSet(group_ids));
+    }
+
+    let mut store = open_store(memory::CHATS_MEMORY_ID);
+    for (group_id, chats) in chats {
+        for (chat_id, chat) in chats {
+            store.insert((group_id, chat_id), chat);
+        }
+    }
+
+    let mut store = open_store(memory::PRIMARY_KEY_CONTAINERS_MEMORY_ID);
+    for (ty, key) in primary_key_containers {
+        store.insert(ty, key);
+    }
+}
+
+/// Reads the legacy tuple and wipes its header, this has to happen before the memory manager
+/// is initialized since it claims the beginning of stable memory for its header.
+///
+/// Only the first page is cleared: once the candid magic is gone the memory manager sets up a
+/// fresh layout there and never reads the stale bytes behind it.
+fn read_legacy_heap_state() -> LegacyHeapState {
+    let stable_memory = memory::stable_memory();
+
+    let mut bytes = vec![0; (stable_memory.size() * WASM_PAGE_SIZE) as usize];
+    stable_memory.read(0, &mut bytes);
+
+    let mut de = candid::de::IDLDeserialize::new(&bytes).expect("FAILED TO READ LEGACY DATA!");
+    let legacy_state = candid::utils::ArgumentDecoder::decode(&mut de)
+        .expect("FAILED TO STABLE RESTORE LEGACY DATA!");
+
+    drop(bytes);
+    stable_memory.write(0, &[0; WASM_PAGE_SIZE as usize]);
+
+    legacy_state
+}
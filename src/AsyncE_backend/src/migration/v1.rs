@@ -0,0 +1,106 @@
+//! Version 1 moved every store to stable structures, but still kept the raw bytes of
+//! videos, thumbnails and profile pictures inline in the records.
+
+use candid::{CandidType, Principal};
+use serde::Deserialize;
+
+use crate::{
+    blob::{self, BlobId},
+    group::GroupMember,
+    impl_candid_storable,
+    meeting::MeetingProcessType,
+    memory,
+    user::UserSubscription,
+};
+
+use super::{migrate_store, v2};
+
+#[derive(Clone, Debug, Default, CandidType, Deserialize)]
+pub struct User {
+    pub balance: u128,
+    pub username: String,
+    pub subscription: Option<UserSubscription>,
+    pub created_time_unix: u128,
+    pub profile_picture_blob: Vec<u8>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct Group {
+    pub id: u128,
+    pub name: String,
+    pub owner: String,
+    pub members: Vec<GroupMember>,
+    pub created_time_unix: u128,
+    pub profile_picture_blob: Vec<u8>,
+}
+
+#[derive(Clone, Debug, Default, CandidType, Deserialize)]
+pub struct Meeting {
+    pub id: u128,
+    pub thumbnail_data: Vec<u8>,
+    pub full_video_data: Vec<u8>,
+    pub title: String,
+    pub created_by: String,
+    pub frames: Vec<VideoFrame>,
+    pub created_time_unix: u128,
+    pub process_type: MeetingProcessType,
+}
+
+#[derive(Clone, Debug, Default, CandidType, Deserialize)]
+pub struct VideoFrame {
+    pub data: Vec<u8>,
+    pub title: String,
+    pub created_by: String,
+    pub thumbnail_data: Vec<u8>,
+    pub created_time_unix: u128,
+}
+
+impl_candid_storable!(User, Group, Meeting);
+
+fn store_blob(data: Vec<u8>) -> Option<BlobId> {
+    (!data.is_empty()).then(|| blob::store(&data))
+}
+
+/// Moves the inline bytes into the blob store and keeps only their IDs in the records.
+pub fn migrate_to_v2() {
+    migrate_store(memory::USERS_MEMORY_ID, |_: &Principal, x: User| v2::User {
+        balance: x.balance,
+        username: x.username,
+        subscription: x.subscription,
+        created_time_unix: x.created_time_unix,
+        profile_picture: store_blob(x.profile_picture_blob),
+    });
+
+    migrate_store(memory::GROUPS_MEMORY_ID, |_: &u128, x: Group| v2::Group {
+        id: x.id,
+        name: x.name,
+        owner: x.owner,
+        members: x.members,
+        created_time_unix: x.created_time_unix,
+        profile_picture: store_blob(x.profile_picture_blob),
+    });
+
+    migrate_store(
+        memory::MEETINGS_MEMORY_ID,
+        |_: &(u128, u128), x: Meeting| v2::Meeting {
+            id: x.id,
+            thumbnail: store_blob(x.thumbnail_data),
+            full_video: store_blob(x.full_video_data),
+            title: x.title,
+            created_by: x.created_by,
+            frames: x
+                .frames
+                .into_iter()
+                .map(|frame| v2::VideoFrame {
+                    video: blob::store(&frame.data),
+                    title: frame.title,
+                    created_by: frame.created_by,
+                    thumbnail: store_blob(frame.thumbnail_data),
+                    created_time_unix: frame.created_time_unix,
+                })
+                .collect(),
+            created_time_unix: x.created_time_unix,
+            process_type: x.process_type,
+        },
+    );
+}
@@ -0,0 +1,65 @@
+//! Version 2 kept the concat and subtitle requests on the heap, so a meeting that was being
+//! processed during an upgrade stayed locked forever. Processing now runs as durable jobs.
+
+use candid::CandidType;
+use serde::Deserialize;
+
+use crate::{
+    blob::BlobId, group::GroupMember, impl_candid_storable, meeting::MeetingProcessType, memory,
+    user::UserSubscription,
+};
+
+use super::migrate_store;
+
+#[derive(Clone, Debug, Default, CandidType, Deserialize)]
+pub struct User {
+    pub balance: u128,
+    pub username: String,
+    pub subscription: Option<UserSubscription>,
+    pub created_time_unix: u128,
+    pub profile_picture: Option<BlobId>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct Group {
+    pub id: u128,
+    pub name: String,
+    pub owner: String,
+    pub members: Vec<GroupMember>,
+    pub created_time_unix: u128,
+    pub profile_picture: Option<BlobId>,
+}
+
+#[derive(Clone, Debug, Default, CandidType, Deserialize)]
+pub struct Meeting {
+    pub id: u128,
+    pub thumbnail: Option<BlobId>,
+    pub full_video: Option<BlobId>,
+    pub title: String,
+    pub created_by: String,
+    pub frames: Vec<VideoFrame>,
+    pub created_time_unix: u128,
+    pub process_type: MeetingProcessType,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct VideoFrame {
+    pub video: BlobId,
+    pub title: String,
+    pub created_by: String,
+    pub thumbnail: Option<BlobId>,
+    pub created_time_unix: u128,
+}
+
+impl_candid_storable!(User, Group, Meeting);
+
+/// Unlocks meetings whose processing request was lost, there is no job that would finish them.
+pub fn migrate_to_v3() {
+    migrate_store(
+        memory::MEETINGS_MEMORY_ID,
+        |_: &(u128, u128), mut x: Meeting| {
+            x.process_type = MeetingProcessType::None;
+            x
+        },
+    );
+}
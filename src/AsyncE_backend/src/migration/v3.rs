@@ -0,0 +1,17 @@
+//! Version 3 could only find a user by username by scanning every user, version 4 keeps a
+//! username index next to the users.
+
+use candid::Principal;
+
+use crate::{memory, user::User};
+
+use super::open_store;
+
+pub fn migrate_to_v4() {
+    let users = open_store::<Principal, User>(memory::USERS_MEMORY_ID);
+    let mut usernames = open_store::<String, Principal>(memory::USERNAMES_MEMORY_ID);
+
+    for (principal, user) in users.iter() {
+        usernames.insert(user.username.to_ascii_lowercase(), principal);
+    }
+}
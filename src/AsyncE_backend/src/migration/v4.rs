@@ -0,0 +1,45 @@
+//! Version 4 had no way to search a group, version 5 keeps an inverted index over the chats,
+//! meeting titles and video part titles.
+
+use crate::{
+    chat::Chat,
+    meeting::Meeting,
+    memory,
+    search::{self, SearchDoc, SearchToken},
+};
+
+use super::open_store;
+
+pub fn migrate_to_v5() {
+    let mut search_index =
+        open_store::<(u128, SearchToken, SearchDoc), ()>(memory::SEARCH_INDEX_MEMORY_ID);
+
+    let chats = open_store::<(u128, u128), Chat>(memory::CHATS_MEMORY_ID);
+    for ((group_id, chat_id), chat) in chats.iter() {
+        for key in search::index_keys(group_id, SearchDoc::Chat { chat_id }, &chat.content) {
+            search_index.insert(key, ());
+        }
+    }
+
+    let meetings = open_store::<(u128, u128), Meeting>(memory::MEETINGS_MEMORY_ID);
+    for ((group_id, meeting_id), meeting) in meetings.iter() {
+        let docs = meeting
+            .frames
+            .iter()
+            .enumerate()
+            .map(|(frame_index, frame)| {
+                let doc = SearchDoc::VideoFrame {
+                    meeting_id,
+                    frame_index: frame_index as u128,
+                };
+                (doc, &frame.title)
+            })
+            .chain([(SearchDoc::Meeting { meeting_id }, &meeting.title)]);
+
+        for (doc, text) in docs {
+            for key in search::index_keys(group_id, doc, text) {
+                search_index.insert(key, ());
+            }
+        }
+    }
+}
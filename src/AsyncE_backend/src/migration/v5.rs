@@ -0,0 +1,17 @@
+//! Version 5 kept every unfinished chunked upload as one value that grew with each chunk,
+//! version 6 stores the chunks one by one next to a small record of what has arrived.
+//! Half sent uploads cannot be told apart from their chunks, so they are dropped and
+//! the clients have to send them again.
+
+use crate::memory;
+
+use super::open_store;
+
+pub fn migrate_to_v6() {
+    let mut upload_buffers = open_store::<String, Vec<u8>>(memory::UPLOAD_BUFFERS_MEMORY_ID);
+
+    let keys = upload_buffers.keys().collect::<Vec<_>>();
+    for key in keys {
+        upload_buffers.remove(&key);
+    }
+}
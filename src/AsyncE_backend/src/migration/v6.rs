@@ -0,0 +1,19 @@
+//! Version 6 found due jobs and the jobs of a meeting by decoding every job, version 7 keeps
+//! a queue of the unfinished jobs and an index of the jobs of every meeting.
+
+use crate::{job::Job, memory};
+
+use super::open_store;
+
+pub fn migrate_to_v7() {
+    let jobs = open_store::<u128, Job>(memory::JOBS_MEMORY_ID);
+    let mut job_queue = open_store::<(u128, u128), ()>(memory::JOB_QUEUE_MEMORY_ID);
+    let mut meeting_jobs = open_store::<(u128, u128, u128), ()>(memory::MEETING_JOBS_MEMORY_ID);
+
+    for (job_id, job) in jobs.iter() {
+        meeting_jobs.insert((job.group_id, job.meeting_id, job_id), ());
+        if !job.state.is_finished() {
+            job_queue.insert((job.next_attempt_time_unix, job_id), ());
+        }
+    }
+}
@@ -0,0 +1,28 @@
+//! Version 7 counted the unread notifications of a user by decoding their whole inbox,
+//! version 8 keeps the count next to the inbox.
+
+use std::collections::BTreeMap;
+
+use candid::Principal;
+
+use crate::{memory, notification::Notification};
+
+use super::open_store;
+
+pub fn migrate_to_v8() {
+    let notifications =
+        open_store::<(Principal, u128), Notification>(memory::NOTIFICATIONS_MEMORY_ID);
+    let mut unread_notifications =
+        open_store::<Principal, u64>(memory::UNREAD_NOTIFICATIONS_MEMORY_ID);
+
+    let mut counts = BTreeMap::new();
+    for ((principal, _), notification) in notifications.iter() {
+        if !notification.read {
+            *counts.entry(principal).or_insert(0) += 1;
+        }
+    }
+
+    for (principal, count) in counts {
+        unread_notifications.insert(principal, count);
+    }
+}
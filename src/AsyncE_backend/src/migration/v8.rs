@@ -0,0 +1,20 @@
+//! Version 8 counted the unseen video parts of a member by decoding every meeting of the group,
+//! version 9 keeps the video parts of each group ordered by their creation time.
+
+use crate::{meeting::Meeting, memory, receipt};
+
+use super::open_store;
+
+pub fn migrate_to_v9() {
+    let mut frame_times =
+        open_store::<(u128, u128, (u128, u128)), ()>(memory::FRAME_TIMES_MEMORY_ID);
+
+    let meetings = open_store::<(u128, u128), Meeting>(memory::MEETINGS_MEMORY_ID);
+    for ((group_id, meeting_id), meeting) in meetings.iter() {
+        for (frame_index, frame) in meeting.frames.iter().enumerate() {
+            let key =
+                receipt::frame_time_key(group_id, meeting_id, frame_index, frame.created_time_unix);
+            frame_times.insert(key, ());
+        }
+    }
+}
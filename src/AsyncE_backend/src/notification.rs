@@ -0,0 +1,359 @@
+use std::collections::BTreeSet;
+
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::ApiError,
+    globals::{NOTIFICATIONS, UNREAD_NOTIFICATIONS},
+    impl_candid_storable,
+    primary_key::{self, PrimaryKeyType},
+    runtime::Runtime,
+    user,
+    websocket::{self, WebsocketEventMessage},
+};
+
+/// Pages never hold more notifications than this, whatever limit the client asks for.
+pub const MAX_NOTIFICATION_PAGE_SIZE: u32 = 50;
+/// The oldest notifications are dropped once an inbox holds more than this.
+pub const MAX_NOTIFICATIONS_PER_USER: usize = 500;
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize, PartialEq, Eq)]
+pub enum NotificationKind {
+    Mention {
+        group_id: u128,
+        chat_id: u128,
+        mentioned_by: String,
+    },
+    GroupInvited {
+        group_id: u128,
+        group_name: String,
+    },
+    NewVideoPart {
+        group_id: u128,
+        meeting_id: u128,
+        created_by: String,
+    },
+    /// The meeting is ready to watch, or the processing gave up with `error`
+    ProcessingFinished {
+        job_id: u128,
+        group_id: u128,
+        meeting_id: u128,
+        error: Option<String>,
+    },
+}
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+pub struct Notification {
+    pub id: u128,
+    pub kind: NotificationKind,
+    pub created_time_unix: u128,
+    pub read: bool,
+}
+
+impl_candid_storable!(Notification);
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct NotificationPage {
+    /// Newest first
+    pub notifications: Vec<Notification>,
+    /// Pass it back to get the next page, absent on the last one
+    pub next_cursor: Option<u128>,
+}
+
+fn unread_count(principal: Principal) -> u64 {
+    UNREAD_NOTIFICATIONS
+        .with_borrow(|unread_notifications| unread_notifications.get(&principal))
+        .unwrap_or(0)
+}
+
+fn set_unread_count(principal: Principal, count: u64) {
+    UNREAD_NOTIFICATIONS.with_borrow_mut(|unread_notifications| {
+        if count == 0 {
+            unread_notifications.remove(&principal);
+        } else {
+            unread_notifications.insert(principal, count);
+        }
+    });
+}
+
+/// Drops the notifications that do not fit into the inbox anymore, oldest first.
+fn prune_inbox(principal: Principal) {
+    let dropped = NOTIFICATIONS.with_borrow_mut(|notifications| {
+        let overflow = notifications
+            .keys_range((principal, 0)..=(principal, u128::MAX))
+            .rev()
+            .skip(MAX_NOTIFICATIONS_PER_USER)
+            .collect::<Vec<_>>();
+
+        overflow
+            .into_iter()
+            .filter_map(|key| notifications.remove(&key))
+            .filter(|notification| !notification.read)
+            .count() as u64
+    });
+
+    if dropped > 0 {
+        set_unread_count(principal, unread_count(principal).saturating_sub(dropped));
+    }
+}
+
+/// Puts the notification into the inbox of the user and sends it as a websocket event.
+pub fn notify(rt: &impl Runtime, principal: Principal, kind: NotificationKind) {
+    let notification = Notification {
+        id: primary_key::get_primary_key(PrimaryKeyType::Notification),
+        kind,
+        created_time_unix: rt.time(),
+        read: false,
+    };
+
+    NOTIFICATIONS.with_borrow_mut(|notifications| {
+        notifications.insert((principal, notification.id), notification.clone())
+    });
+    set_unread_count(principal, unread_count(principal) + 1);
+    prune_inbox(principal);
+
+    websocket::send_user_event(
+        rt,
+        principal,
+        WebsocketEventMessage::Notification(notification),
+    );
+}
+
+/// Takes the mentions of a chat that is gone back out of the inboxes of the mentioned users.
+pub fn remove_mentions(group_id: u128, chat_id: u128, usernames: &[String]) {
+    for principal in usernames.iter().filter_map(|x| user::get_principal(x)) {
+        let removed = NOTIFICATIONS.with_borrow_mut(|notifications| {
+            let mentions = notifications
+                .range((principal, 0)..=(principal, u128::MAX))
+                .filter(|(_, notification)| {
+                    matches!(
+                        notification.kind,
+                        NotificationKind::Mention { group_id: x, chat_id: y, .. }
+                            if x == group_id && y == chat_id
+                    )
+                })
+                .map(|(key, _)| key)
+                .collect::<Vec<_>>();
+
+            mentions
+                .into_iter()
+                .filter_map(|key| notifications.remove(&key))
+                .filter(|notification| !notification.read)
+                .count() as u64
+        });
+
+        if removed > 0 {
+            set_unread_count(principal, unread_count(principal).saturating_sub(removed));
+        }
+    }
+}
+
+pub fn notify_user(rt: &impl Runtime, username: &str, kind: NotificationKind) {
+    if let Some(principal) = user::get_principal(username) {
+        notify(rt, principal, kind);
+    }
+}
+
+pub fn get_notifications(
+    rt: &impl Runtime,
+    cursor: Option<u128>,
+    limit: u32,
+) -> Result<NotificationPage, ApiError> {
+    user::assert_user_logged_in(rt)?;
+
+    if limit == 0 {
+        return Err(ApiError::invalid_input("limit", "must be at least 1"));
+    }
+    let limit = limit.min(MAX_NOTIFICATION_PAGE_SIZE);
+
+    let principal = rt.caller();
+    let end = cursor.unwrap_or(u128::MAX);
+    let mut notifications = NOTIFICATIONS.with_borrow(|notifications| {
+        notifications
+            .range((principal, 0)..(principal, end))
+            .rev()
+            .take(limit as usize + 1)
+            .map(|(_, notification)| notification)
+            .collect::<Vec<_>>()
+    });
+
+    let next_cursor = if notifications.len() > limit as usize {
+        notifications.truncate(limit as usize);
+        notifications.last().map(|notification| notification.id)
+    } else {
+        None
+    };
+
+    Ok(NotificationPage {
+        notifications,
+        next_cursor,
+    })
+}
+
+pub fn get_unread_notification_count(rt: &impl Runtime) -> Result<u64, ApiError> {
+    user::assert_user_logged_in(rt)?;
+
+    Ok(unread_count(rt.caller()))
+}
+
+/// Unknown IDs are rejected before anything is marked.
+pub fn mark_notifications_read(rt: &impl Runtime, ids: Vec<u128>) -> Result<(), ApiError> {
+    user::assert_user_logged_in(rt)?;
+
+    let principal = rt.caller();
+    let marked = NOTIFICATIONS.with_borrow_mut(|notifications| {
+        let mut unread = BTreeSet::new();
+        for id in ids {
+            let notification = notifications
+                .get(&(principal, id))
+                .ok_or(ApiError::not_found("notification", id))?;
+            if !notification.read {
+                unread.insert(id);
+            }
+        }
+
+        for id in &unread {
+            if let Some(mut notification) = notifications.get(&(principal, *id)) {
+                notification.read = true;
+                notifications.insert((principal, *id), notification);
+            }
+        }
+
+        Ok(unread.len() as u64)
+    })?;
+
+    set_unread_count(principal, unread_count(principal).saturating_sub(marked));
+    Ok(())
+}
+
+pub fn mark_all_notifications_read(rt: &impl Runtime) -> Result<(), ApiError> {
+    user::assert_user_logged_in(rt)?;
+
+    let principal = rt.caller();
+    NOTIFICATIONS.with_borrow_mut(|notifications| {
+        let unread = notifications
+            .range((principal, 0)..=(principal, u128::MAX))
+            .map(|(_, notification)| notification)
+            .filter(|notification| !notification.read)
+            .collect::<Vec<_>>();
+
+        for mut notification in unread {
+            notification.read = true;
+            notifications.insert((principal, notification.id), notification);
+        }
+    });
+    set_unread_count(principal, 0);
+
+    Ok(())
+}
+
+mod endpoints {
+    use super::*;
+    use crate::runtime::IcRuntime;
+
+    #[ic_cdk::query]
+    fn get_notifications(cursor: Option<u128>, limit: u32) -> Result<NotificationPage, ApiError> {
+        super::get_notifications(&IcRuntime, cursor, limit)
+    }
+
+    #[ic_cdk::query]
+    fn get_unread_notification_count() -> Result<u64, ApiError> {
+        super::get_unread_notification_count(&IcRuntime)
+    }
+
+    #[ic_cdk::update]
+    fn mark_notifications_read(ids: Vec<u128>) -> Result<(), ApiError> {
+        super::mark_notifications_read(&IcRuntime, ids)
+    }
+
+    #[ic_cdk::update]
+    fn mark_all_notifications_read() -> Result<(), ApiError> {
+        super::mark_all_notifications_read(&IcRuntime)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{runtime::TestRuntime, user::tests::sign_in};
+
+    fn invited(group_id: u128) -> NotificationKind {
+        NotificationKind::GroupInvited {
+            group_id,
+            group_name: format!("group {}", group_id),
+        }
+    }
+
+    fn ids(page: &NotificationPage) -> Vec<u128> {
+        page.notifications
+            .iter()
+            .map(|notification| notification.id)
+            .collect()
+    }
+
+    #[test]
+    fn pages_and_marks_the_inbox() {
+        let rt = TestRuntime::new();
+        let bob = sign_in(&rt, 2, "bob");
+        let alice = sign_in(&rt, 1, "alice");
+        for group_id in 1..=5 {
+            notify(&rt, alice, invited(group_id));
+        }
+        notify(&rt, bob, invited(6));
+
+        let first = get_notifications(&rt, None, 2).unwrap();
+        assert_eq!(ids(&first), vec![5, 4]);
+        let second = get_notifications(&rt, first.next_cursor, 2).unwrap();
+        assert_eq!(ids(&second), vec![3, 2]);
+        let last = get_notifications(&rt, second.next_cursor, 2).unwrap();
+        assert_eq!(ids(&last), vec![1]);
+        assert_eq!(last.next_cursor, None);
+        assert_eq!(last.notifications[0].kind, invited(1));
+
+        assert_eq!(get_unread_notification_count(&rt).unwrap(), 5);
+        mark_notifications_read(&rt, vec![2, 4]).unwrap();
+        assert_eq!(get_unread_notification_count(&rt).unwrap(), 3);
+
+        // the notification of bob is not in the inbox of alice
+        assert!(matches!(
+            mark_notifications_read(&rt, vec![1, 6]),
+            Err(ApiError::NotFound { .. })
+        ));
+        assert_eq!(get_unread_notification_count(&rt).unwrap(), 3);
+
+        mark_all_notifications_read(&rt).unwrap();
+        assert_eq!(get_unread_notification_count(&rt).unwrap(), 0);
+
+        rt.set_caller(bob);
+        assert_eq!(get_unread_notification_count(&rt).unwrap(), 1);
+    }
+
+    #[test]
+    fn drops_the_oldest_notifications_of_a_full_inbox() {
+        let rt = TestRuntime::new();
+        let alice = sign_in(&rt, 1, "alice");
+        notify(&rt, alice, invited(0));
+        mark_all_notifications_read(&rt).unwrap();
+        for group_id in 1..=MAX_NOTIFICATIONS_PER_USER as u128 + 1 {
+            notify(&rt, alice, invited(group_id));
+        }
+
+        let inbox = NOTIFICATIONS.with_borrow(|notifications| {
+            notifications.keys().map(|(_, id)| id).collect::<Vec<_>>()
+        });
+        assert_eq!(inbox.len(), MAX_NOTIFICATIONS_PER_USER);
+        assert_eq!(inbox[0], 3);
+        assert_eq!(
+            get_unread_notification_count(&rt).unwrap(),
+            MAX_NOTIFICATIONS_PER_USER as u64
+        );
+
+        // marking the same notification twice only counts it once
+        mark_notifications_read(&rt, vec![3, 3]).unwrap();
+        assert_eq!(
+            get_unread_notification_count(&rt).unwrap(),
+            MAX_NOTIFICATIONS_PER_USER as u64 - 1
+        );
+    }
+}
@@ -0,0 +1,163 @@
+use std::time::Duration;
+
+use candid::{CandidType, Principal};
+use serde::Deserialize;
+
+use crate::{
+    error::ApiError,
+    globals::{GROUPS, LAST_SEEN, USERS, WEBSOCKET_CLIENTS},
+    runtime::Runtime,
+    user,
+};
+
+/// Connected users that sent nothing for this long are shown as away.
+pub const AWAY_AFTER: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq, Eq)]
+pub enum PresenceStatus {
+    Online,
+    Away,
+    Offline,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct UserPresence {
+    pub username: String,
+    pub status: PresenceStatus,
+    pub last_seen_time_unix: u128,
+}
+
+/// Marks the user as active right now, unregistered principals are not tracked.
+pub fn touch(rt: &impl Runtime, principal: Principal) {
+    if USERS.with_borrow(|users| users.contains_key(&principal)) {
+        LAST_SEEN.with_borrow_mut(|last_seen| last_seen.insert(principal, rt.time()));
+    }
+}
+
+pub fn get_status(rt: &impl Runtime, principal: Principal) -> PresenceStatus {
+    if !WEBSOCKET_CLIENTS.with_borrow(|websocket_clients| websocket_clients.contains(&principal)) {
+        return PresenceStatus::Offline;
+    }
+
+    let last_seen = LAST_SEEN
+        .with_borrow(|last_seen| last_seen.get(&principal))
+        .unwrap_or_default();
+    if rt.time().saturating_sub(last_seen) > AWAY_AFTER.as_nanos() {
+        PresenceStatus::Away
+    } else {
+        PresenceStatus::Online
+    }
+}
+
+fn get_presence(rt: &impl Runtime, principal: Principal) -> Option<UserPresence> {
+    let username = USERS.with_borrow(|users| users.get(&principal))?.username;
+
+    Some(UserPresence {
+        username,
+        status: get_status(rt, principal),
+        last_seen_time_unix: LAST_SEEN
+            .with_borrow(|last_seen| last_seen.get(&principal))
+            .unwrap_or_default(),
+    })
+}
+
+/// Members of the group that are connected right now, including the away ones.
+pub fn get_online_members(
+    rt: &impl Runtime,
+    group_id: u128,
+) -> Result<Vec<UserPresence>, ApiError> {
+    user::assert_user_logged_in(rt)?;
+
+    let selfname = user::get_selfname_force(rt)?;
+
+    let group = GROUPS
+        .with_borrow(|groups| groups.get(&group_id))
+        .ok_or(ApiError::not_found("group", group_id))?;
+    if !group.is_member(&selfname) {
+        return Err(ApiError::forbidden("This user is not in this group!"));
+    }
+
+    Ok(group
+        .member_principals()
+        .into_iter()
+        .filter_map(|principal| get_presence(rt, principal))
+        .filter(|presence| presence.status != PresenceStatus::Offline)
+        .collect())
+}
+
+mod endpoints {
+    use super::*;
+    use crate::runtime::IcRuntime;
+
+    #[ic_cdk::query]
+    fn get_online_members(group_id: u128) -> Result<Vec<UserPresence>, ApiError> {
+        super::get_online_members(&IcRuntime, group_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        group::{self, GroupMember, GroupMemberRole},
+        runtime::TestRuntime,
+        user::tests::sign_in,
+        websocket,
+    };
+
+    fn statuses(rt: &TestRuntime, group_id: u128) -> Vec<(String, PresenceStatus)> {
+        get_online_members(rt, group_id)
+            .unwrap()
+            .into_iter()
+            .map(|presence| (presence.username, presence.status))
+            .collect()
+    }
+
+    #[test]
+    fn tracks_connected_members() {
+        let rt = TestRuntime::new();
+        let bob = sign_in(&rt, 2, "bob");
+        let alice = sign_in(&rt, 1, "alice");
+        let group_id = group::create_group(&rt, String::from("Team")).unwrap();
+        GROUPS.with_borrow_mut(|groups| {
+            let mut group = groups.get(&group_id).unwrap();
+            group
+                .members
+                .push(GroupMember::new("bob", GroupMemberRole::Member));
+            groups.insert(group_id, group);
+        });
+
+        assert!(statuses(&rt, group_id).is_empty());
+
+        websocket::handle_open(&rt, alice);
+        websocket::handle_open(&rt, bob);
+        rt.advance_time(Duration::from_secs(60));
+        assert_eq!(
+            statuses(&rt, group_id),
+            vec![
+                (String::from("alice"), PresenceStatus::Online),
+                (String::from("bob"), PresenceStatus::Online),
+            ]
+        );
+
+        rt.advance_time(AWAY_AFTER);
+        websocket::handle_close(&rt, bob);
+        assert_eq!(
+            statuses(&rt, group_id),
+            vec![(String::from("alice"), PresenceStatus::Away)]
+        );
+
+        let closed_time = rt.time();
+        rt.advance_time(Duration::from_secs(60));
+        let last_seen = LAST_SEEN.with_borrow(|last_seen| last_seen.get(&bob));
+        assert_eq!(last_seen, Some(closed_time));
+        assert_eq!(get_status(&rt, bob), PresenceStatus::Offline);
+
+        let eve = sign_in(&rt, 3, "eve");
+        rt.set_caller(eve);
+        assert!(matches!(
+            get_online_members(&rt, group_id),
+            Err(ApiError::Forbidden { .. })
+        ));
+    }
+}
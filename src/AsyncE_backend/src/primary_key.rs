@@ -1,25 +1,53 @@
-use std::collections::BTreeMap;
+use std::{borrow::Cow, collections::BTreeMap};
 
 use candid::CandidType;
+use ic_stable_structures::{storable::Bound, Storable};
 use serde::Deserialize;
 
 use crate::globals::PRIMARY_KEY_CONTAINERS;
 
-#[derive(PartialEq, Eq, PartialOrd, Ord, CandidType, Deserialize, Clone)]
+#[derive(Copy, PartialEq, Eq, PartialOrd, Ord, CandidType, Deserialize, Clone, Debug)]
 pub enum PrimaryKeyType {
     Group,
     Video,
     Chat,
     VideoFrame,
+    UploadSession,
+    Job,
+    Notification,
 }
 
+impl Storable for PrimaryKeyType {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(vec![*self as u8])
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        match bytes[0] {
+            0 => Self::Group,
+            1 => Self::Video,
+            2 => Self::Chat,
+            3 => Self::VideoFrame,
+            4 => Self::UploadSession,
+            5 => Self::Job,
+            6 => Self::Notification,
+            x => panic!("Unknown primary key type: {}", x),
+        }
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 1,
+        is_fixed_size: true,
+    };
+}
+
+/// Heap layout of the primary key counters, only used to read the legacy upgrade data.
 pub type PrimaryKeyContainer = BTreeMap<PrimaryKeyType, u128>;
 
 pub fn get_primary_key(ty: PrimaryKeyType) -> u128 {
     PRIMARY_KEY_CONTAINERS.with_borrow_mut(|primary_key_containers| {
-        let entry = primary_key_containers.entry(ty).or_insert(1);
-        let copy = *entry;
-        *entry += 1;
-        copy
+        let key = primary_key_containers.get(&ty).unwrap_or(1);
+        primary_key_containers.insert(ty, key + 1);
+        key
     })
 }
@@ -0,0 +1,282 @@
+use std::{cell::RefCell, collections::BTreeMap};
+
+use candid::CandidType;
+use serde::Deserialize;
+
+use crate::{
+    blob, chunk, config,
+    http::{ChunkInfoResponse, HttpProcessor},
+    job::JobKind,
+    runtime::Runtime,
+};
+
+/// Which [`VideoProcessor`] the canister sends its jobs to.
+#[derive(Copy, Clone, Debug, Default, CandidType, Deserialize, PartialEq, Eq)]
+pub enum ProcessorKind {
+    /// The video editor service at `Config::processor_base_url`
+    #[default]
+    Http,
+    /// Processes everything inside the canister, for tests and local development
+    Fake,
+}
+
+/// The protocol spoken with the video editor service.
+///
+/// A job is opened with `start`, receives its input videos chunk by chunk through `add`
+/// and is closed by `end`. Thumbnails come back from `end` directly, for the other kinds
+/// `end` returns the ID to `poll` until the result can be `fetch`ed chunk by chunk.
+// canisters are single threaded, so the futures never have to be `Send`
+#[allow(async_fn_in_trait)]
+pub trait VideoProcessor {
+    async fn start(&self, kind: JobKind) -> Result<String, String>;
+
+    async fn add(
+        &self,
+        kind: JobKind,
+        id: &str,
+        input_index: usize,
+        chunk_index: usize,
+        chunk: &[u8],
+    ) -> Result<(), String>;
+
+    async fn end(&self, kind: JobKind, id: &str) -> Result<Vec<u8>, String>;
+
+    /// Returns `None` while the processor is still working on the job.
+    async fn poll(&self, kind: JobKind, id: &str) -> Result<Option<ChunkInfoResponse>, String>;
+
+    async fn fetch(&self, kind: JobKind, id: &str, chunk_index: usize) -> Result<Vec<u8>, String>;
+}
+
+pub enum Processor<R> {
+    Http(HttpProcessor<R>),
+    Fake(FakeProcessor),
+}
+
+pub fn get_processor<R: Runtime>(rt: &R) -> Processor<R> {
+    match config::get_config().processor() {
+        ProcessorKind::Http => Processor::Http(HttpProcessor::new(rt.clone())),
+        ProcessorKind::Fake => Processor::Fake(FakeProcessor),
+    }
+}
+
+impl<R: Runtime> VideoProcessor for Processor<R> {
+    async fn start(&self, kind: JobKind) -> Result<String, String> {
+        match self {
+            Self::Http(x) => x.start(kind).await,
+            Self::Fake(x) => x.start(kind).await,
+        }
+    }
+
+    async fn add(
+        &self,
+        kind: JobKind,
+        id: &str,
+        input_index: usize,
+        chunk_index: usize,
+        chunk: &[u8],
+    ) -> Result<(), String> {
+        match self {
+            Self::Http(x) => x.add(kind, id, input_index, chunk_index, chunk).await,
+            Self::Fake(x) => x.add(kind, id, input_index, chunk_index, chunk).await,
+        }
+    }
+
+    async fn end(&self, kind: JobKind, id: &str) -> Result<Vec<u8>, String> {
+        match self {
+            Self::Http(x) => x.end(kind, id).await,
+            Self::Fake(x) => x.end(kind, id).await,
+        }
+    }
+
+    async fn poll(&self, kind: JobKind, id: &str) -> Result<Option<ChunkInfoResponse>, String> {
+        match self {
+            Self::Http(x) => x.poll(kind, id).await,
+            Self::Fake(x) => x.poll(kind, id).await,
+        }
+    }
+
+    async fn fetch(&self, kind: JobKind, id: &str, chunk_index: usize) -> Result<Vec<u8>, String> {
+        match self {
+            Self::Http(x) => x.fetch(kind, id, chunk_index).await,
+            Self::Fake(x) => x.fetch(kind, id, chunk_index).await,
+        }
+    }
+}
+
+/// Sends every input video to a new processor job and returns what `end` answered.
+pub async fn upload(
+    processor: &impl VideoProcessor,
+    kind: JobKind,
+    inputs: &[Vec<u8>],
+) -> Result<Vec<u8>, String> {
+    let id = processor.start(kind).await?;
+
+    for (input_index, input) in inputs.iter().enumerate() {
+        for (chunk_index, chunk) in input.chunks(chunk::MB).enumerate() {
+            processor
+                .add(kind, &id, input_index, chunk_index, chunk)
+                .await?;
+        }
+    }
+
+    processor.end(kind, &id).await
+}
+
+pub async fn download(
+    processor: &impl VideoProcessor,
+    kind: JobKind,
+    id: &str,
+    info: &ChunkInfoResponse,
+) -> Result<Vec<u8>, String> {
+    let mut data = Vec::with_capacity(info.file_size);
+    for chunk_index in 0..info.chunk_count {
+        data.extend(processor.fetch(kind, id, chunk_index).await?);
+    }
+
+    Ok(data)
+}
+
+/// Appended to every video the fake processor adds subtitles to.
+pub const FAKE_SUBTITLES: &[u8] = b"[subtitles]";
+
+#[derive(Default)]
+struct FakeJob {
+    inputs: Vec<Vec<u8>>,
+    ended: bool,
+}
+
+thread_local! {
+    static FAKE_JOBS: RefCell<BTreeMap<String, FakeJob>> = RefCell::default();
+}
+
+/// Deterministic stand-in for the video editor service.
+///
+/// Concat joins the inputs, subtitles append [`FAKE_SUBTITLES`] and the thumbnail is the
+/// SHA-256 of the video. Jobs are finished as soon as they end.
+pub struct FakeProcessor;
+
+impl FakeProcessor {
+    fn result(kind: JobKind, job: &FakeJob) -> Vec<u8> {
+        match kind {
+            JobKind::Concat => job.inputs.concat(),
+            JobKind::Subtitle => [job.inputs.concat().as_slice(), FAKE_SUBTITLES].concat(),
+            JobKind::Thumbnail => blob::hash(&job.inputs.concat()).to_vec(),
+        }
+    }
+
+    fn with_job<T>(id: &str, f: impl FnOnce(&mut FakeJob) -> T) -> Result<T, String> {
+        FAKE_JOBS.with_borrow_mut(|fake_jobs| {
+            fake_jobs
+                .get_mut(id)
+                .map(f)
+                .ok_or(format!("Unknown fake processor job: {}", id))
+        })
+    }
+}
+
+impl VideoProcessor for FakeProcessor {
+    async fn start(&self, _: JobKind) -> Result<String, String> {
+        Ok(FAKE_JOBS.with_borrow_mut(|fake_jobs| {
+            let id = format!("fake-{}", fake_jobs.len() + 1);
+            fake_jobs.insert(id.clone(), FakeJob::default());
+            id
+        }))
+    }
+
+    async fn add(
+        &self,
+        _: JobKind,
+        id: &str,
+        input_index: usize,
+        _: usize,
+        chunk: &[u8],
+    ) -> Result<(), String> {
+        Self::with_job(id, |job| {
+            if job.inputs.len() <= input_index {
+                job.inputs.resize(input_index + 1, Vec::new());
+            }
+
+            job.inputs[input_index].extend_from_slice(chunk);
+        })
+    }
+
+    async fn end(&self, kind: JobKind, id: &str) -> Result<Vec<u8>, String> {
+        Self::with_job(id, |job| {
+            job.ended = true;
+            match kind {
+                JobKind::Thumbnail => Self::result(kind, job),
+                _ => id.as_bytes().to_vec(),
+            }
+        })
+    }
+
+    async fn poll(&self, kind: JobKind, id: &str) -> Result<Option<ChunkInfoResponse>, String> {
+        Self::with_job(id, |job| {
+            job.ended.then(|| {
+                let file_size = Self::result(kind, job).len();
+                ChunkInfoResponse {
+                    chunk_count: file_size.div_ceil(chunk::MB),
+                    file_size,
+                }
+            })
+        })
+    }
+
+    async fn fetch(&self, kind: JobKind, id: &str, chunk_index: usize) -> Result<Vec<u8>, String> {
+        Self::with_job(id, |job| {
+            Self::result(kind, job)
+                .chunks(chunk::MB)
+                .nth(chunk_index)
+                .map(|x| x.to_vec())
+                .unwrap_or_default()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::block_on;
+
+    fn process(kind: JobKind, inputs: &[Vec<u8>]) -> Vec<u8> {
+        block_on(async {
+            let id = String::from_utf8(upload(&FakeProcessor, kind, inputs).await?).unwrap();
+            let info = FakeProcessor.poll(kind, &id).await?.unwrap();
+            download(&FakeProcessor, kind, &id, &info).await
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn fake_concat_joins_inputs() {
+        let first = vec![1; chunk::MB + 1];
+        let second = vec![2; 10];
+
+        let result = process(JobKind::Concat, &[first.clone(), second.clone()]);
+        assert_eq!(result, [first, second].concat());
+    }
+
+    #[test]
+    fn fake_subtitles_are_appended() {
+        let result = process(JobKind::Subtitle, &[vec![1, 2, 3]]);
+        assert_eq!(result, [&[1, 2, 3], FAKE_SUBTITLES].concat());
+    }
+
+    #[test]
+    fn fake_thumbnail_is_returned_on_end() {
+        let thumbnail = block_on(upload(&FakeProcessor, JobKind::Thumbnail, &[vec![1, 2, 3]]));
+        assert_eq!(thumbnail.unwrap(), blob::hash(&[1, 2, 3]).to_vec());
+    }
+
+    #[test]
+    fn fake_jobs_are_not_ready_before_end() {
+        block_on(async {
+            let id = FakeProcessor.start(JobKind::Concat).await.unwrap();
+            assert!(FakeProcessor
+                .poll(JobKind::Concat, &id)
+                .await
+                .unwrap()
+                .is_none());
+        });
+    }
+}
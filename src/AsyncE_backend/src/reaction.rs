@@ -0,0 +1,476 @@
+use std::collections::BTreeMap;
+
+use candid::CandidType;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::ApiError,
+    globals::{CHATS, GROUPS, MEETINGS},
+    group::Group,
+    runtime::Runtime,
+    user, websocket,
+};
+
+/// Long enough for emojis joined with zero width joiners and skin tone modifiers.
+pub const MAX_EMOJI_BYTES: usize = 32;
+
+/// Different emojis one chat or video part can collect, every user can still join the existing ones.
+pub const MAX_EMOJIS_PER_TARGET: usize = 20;
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Reaction {
+    pub emoji: String,
+    /// In the order the users reacted
+    pub usernames: Vec<String>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq, Eq)]
+pub struct ReactionCount {
+    pub emoji: String,
+    pub count: u64,
+}
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize, PartialEq, Eq)]
+pub enum ReactionTarget {
+    Chat { chat_id: u128 },
+    VideoFrame { meeting_id: u128, frame_index: u128 },
+}
+
+/// Returns whether the user had not reacted with the emoji yet.
+fn add(reactions: &mut Vec<Reaction>, emoji: &str, username: &str) -> Result<bool, ApiError> {
+    let emoji_count = reactions.len();
+    match reactions
+        .iter_mut()
+        .find(|reaction| reaction.emoji == emoji)
+    {
+        Some(reaction) if reaction.usernames.iter().any(|x| x == username) => Ok(false),
+        Some(reaction) => {
+            reaction.usernames.push(username.to_string());
+            Ok(true)
+        }
+        None if emoji_count >= MAX_EMOJIS_PER_TARGET => Err(ApiError::conflict(&format!(
+            "Cannot react with more than {} different emojis!",
+            MAX_EMOJIS_PER_TARGET
+        ))),
+        None => {
+            reactions.push(Reaction {
+                emoji: emoji.to_string(),
+                usernames: Vec::from([username.to_string()]),
+            });
+            Ok(true)
+        }
+    }
+}
+
+/// Returns whether the user had reacted with the emoji.
+fn remove(reactions: &mut Vec<Reaction>, emoji: &str, username: &str) -> Result<bool, ApiError> {
+    let Some(reaction) = reactions
+        .iter_mut()
+        .find(|reaction| reaction.emoji == emoji)
+    else {
+        return Ok(false);
+    };
+
+    let count = reaction.usernames.len();
+    reaction.usernames.retain(|x| x != username);
+    let removed = reaction.usernames.len() != count;
+
+    reactions.retain(|reaction| !reaction.usernames.is_empty());
+
+    Ok(removed)
+}
+
+/// Sums the reactions of several targets, e.g. every video part of a meeting.
+pub fn count<'a>(reactions: impl IntoIterator<Item = &'a Reaction>) -> Vec<ReactionCount> {
+    let mut counts = BTreeMap::<&str, u64>::new();
+    for reaction in reactions {
+        *counts.entry(&reaction.emoji).or_default() += reaction.usernames.len() as u64;
+    }
+
+    counts
+        .into_iter()
+        .map(|(emoji, count)| ReactionCount {
+            emoji: emoji.to_string(),
+            count,
+        })
+        .collect()
+}
+
+fn validate_emoji(emoji: &str) -> Result<(), ApiError> {
+    if emoji.is_empty() || emoji.len() > MAX_EMOJI_BYTES {
+        return Err(ApiError::invalid_input(
+            "emoji",
+            &format!("must be between 1 and {} bytes long", MAX_EMOJI_BYTES),
+        ));
+    }
+
+    // every emoji has a character outside of ASCII, even keycaps like 1️⃣
+    if emoji.is_ascii() {
+        return Err(ApiError::invalid_input("emoji", "must be an emoji"));
+    }
+
+    if emoji.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        return Err(ApiError::invalid_input(
+            "emoji",
+            "cannot contain whitespace",
+        ));
+    }
+
+    Ok(())
+}
+
+/// Returns the username of the caller and the group, as long as the caller is a member.
+fn get_member_group(rt: &impl Runtime, group_id: u128) -> Result<(String, Group), ApiError> {
+    user::assert_user_logged_in(rt)?;
+
+    let selfname = user::get_selfname_force(rt)?;
+
+    let group = GROUPS
+        .with_borrow(|groups| groups.get(&group_id))
+        .ok_or(ApiError::not_found("group", group_id))?;
+    if !group.is_member(&selfname) {
+        return Err(ApiError::forbidden("This user is not in this group!"));
+    }
+
+    Ok((selfname, group))
+}
+
+/// Applies `update` to the reactions of the target and tells the group when they changed.
+fn update_reactions(
+    rt: &impl Runtime,
+    group_id: u128,
+    target: ReactionTarget,
+    emoji: &str,
+    update: fn(&mut Vec<Reaction>, &str, &str) -> Result<bool, ApiError>,
+) -> Result<(), ApiError> {
+    validate_emoji(emoji)?;
+    let (selfname, group) = get_member_group(rt, group_id)?;
+
+    let reactions = match target {
+        ReactionTarget::Chat { chat_id } => CHATS.with_borrow_mut(|chats| {
+            let mut chat = chats
+                .get(&(group_id, chat_id))
+                .ok_or(ApiError::not_found("chat", chat_id))?;
+            if chat.deleted_time_unix.is_some() {
+                return Err(ApiError::conflict("Cannot react to a deleted chat!"));
+            }
+
+            let mut reactions = chat.reactions.unwrap_or_default();
+            if !update(&mut reactions, emoji, &selfname)? {
+                return Ok(None);
+            }
+
+            chat.reactions = Some(reactions.clone());
+            chats.insert((group_id, chat_id), chat);
+
+            Ok(Some(reactions))
+        }),
+        ReactionTarget::VideoFrame {
+            meeting_id,
+            frame_index,
+        } => MEETINGS.with_borrow_mut(|meetings| {
+            let mut meeting = meetings
+                .get(&(group_id, meeting_id))
+                .ok_or(ApiError::not_found("meeting", meeting_id))?;
+            let frame = meeting
+                .frames
+                .get_mut(frame_index as usize)
+                .ok_or(ApiError::not_found("frame", frame_index))?;
+
+            let mut reactions = frame.reactions.clone().unwrap_or_default();
+            if !update(&mut reactions, emoji, &selfname)? {
+                return Ok(None);
+            }
+
+            frame.reactions = Some(reactions.clone());
+            meetings.insert((group_id, meeting_id), meeting);
+
+            Ok(Some(reactions))
+        }),
+    }?;
+
+    if let Some(reactions) = reactions {
+        websocket::broadcast_reaction_changed(rt, &group, target, reactions);
+    }
+
+    Ok(())
+}
+
+pub fn add_chat_reaction(
+    rt: &impl Runtime,
+    group_id: u128,
+    chat_id: u128,
+    emoji: String,
+) -> Result<(), ApiError> {
+    update_reactions(rt, group_id, ReactionTarget::Chat { chat_id }, &emoji, add)
+}
+
+pub fn remove_chat_reaction(
+    rt: &impl Runtime,
+    group_id: u128,
+    chat_id: u128,
+    emoji: String,
+) -> Result<(), ApiError> {
+    update_reactions(
+        rt,
+        group_id,
+        ReactionTarget::Chat { chat_id },
+        &emoji,
+        remove,
+    )
+}
+
+pub fn add_video_frame_reaction(
+    rt: &impl Runtime,
+    group_id: u128,
+    meeting_id: u128,
+    frame_index: u128,
+    emoji: String,
+) -> Result<(), ApiError> {
+    let target = ReactionTarget::VideoFrame {
+        meeting_id,
+        frame_index,
+    };
+
+    update_reactions(rt, group_id, target, &emoji, add)
+}
+
+pub fn remove_video_frame_reaction(
+    rt: &impl Runtime,
+    group_id: u128,
+    meeting_id: u128,
+    frame_index: u128,
+    emoji: String,
+) -> Result<(), ApiError> {
+    let target = ReactionTarget::VideoFrame {
+        meeting_id,
+        frame_index,
+    };
+
+    update_reactions(rt, group_id, target, &emoji, remove)
+}
+
+mod endpoints {
+    use super::*;
+    use crate::runtime::IcRuntime;
+
+    #[ic_cdk::update]
+    fn add_chat_reaction(group_id: u128, chat_id: u128, emoji: String) -> Result<(), ApiError> {
+        super::add_chat_reaction(&IcRuntime, group_id, chat_id, emoji)
+    }
+
+    #[ic_cdk::update]
+    fn remove_chat_reaction(group_id: u128, chat_id: u128, emoji: String) -> Result<(), ApiError> {
+        super::remove_chat_reaction(&IcRuntime, group_id, chat_id, emoji)
+    }
+
+    #[ic_cdk::update]
+    fn add_video_frame_reaction(
+        group_id: u128,
+        meeting_id: u128,
+        frame_index: u128,
+        emoji: String,
+    ) -> Result<(), ApiError> {
+        super::add_video_frame_reaction(&IcRuntime, group_id, meeting_id, frame_index, emoji)
+    }
+
+    #[ic_cdk::update]
+    fn remove_video_frame_reaction(
+        group_id: u128,
+        meeting_id: u128,
+        frame_index: u128,
+        emoji: String,
+    ) -> Result<(), ApiError> {
+        super::remove_video_frame_reaction(&IcRuntime, group_id, meeting_id, frame_index, emoji)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use candid::Principal;
+
+    use super::*;
+    use crate::{
+        chat::{self, Chat},
+        group::{self, GroupMember, GroupMemberRole},
+        meeting::{self, Meeting, VideoFrame},
+        runtime::TestRuntime,
+        user::tests::sign_in,
+        websocket::{self, WebsocketEventMessage},
+    };
+
+    struct Members {
+        alice: Principal,
+        bob: Principal,
+        group_id: u128,
+    }
+
+    /// Alice and Bob share a group with one chat and a meeting with one video part.
+    fn setup(rt: &TestRuntime) -> Members {
+        let bob = sign_in(rt, 2, "bob");
+        let alice = sign_in(rt, 1, "alice");
+        let group_id = group::create_group(rt, String::from("Team")).unwrap();
+        GROUPS.with_borrow_mut(|groups| {
+            let mut group = groups.get(&group_id).unwrap();
+            group
+                .members
+                .push(GroupMember::new("bob", GroupMemberRole::Member));
+            groups.insert(group_id, group);
+        });
+
+        let chat = Chat {
+            id: 1,
+            uuid: String::from("uuid"),
+            content: String::from("Hello!"),
+            group_id,
+            username: String::from("alice"),
+            ..Default::default()
+        };
+        CHATS.with_borrow_mut(|chats| chats.insert((group_id, 1), chat));
+        let meeting = Meeting {
+            id: 1,
+            frames: vec![VideoFrame::default()],
+            ..Default::default()
+        };
+        MEETINGS.with_borrow_mut(|meetings| meetings.insert((group_id, 1), meeting));
+
+        Members {
+            alice,
+            bob,
+            group_id,
+        }
+    }
+
+    fn thumbs_up(usernames: &[&str]) -> Vec<Reaction> {
+        vec![Reaction {
+            emoji: String::from("👍"),
+            usernames: usernames.iter().map(|x| x.to_string()).collect(),
+        }]
+    }
+
+    fn chat_reactions(rt: &TestRuntime, group_id: u128) -> Vec<Reaction> {
+        chat::get_chats(rt, group_id).unwrap()[0]
+            .reactions
+            .clone()
+            .unwrap_or_default()
+    }
+
+    fn reaction_events(rt: &TestRuntime) -> usize {
+        rt.take_websocket_messages()
+            .into_iter()
+            .filter(|(_, bytes)| {
+                let msg = candid::decode_one::<WebsocketEventMessage>(bytes).unwrap();
+                matches!(
+                    msg,
+                    WebsocketEventMessage::Event { message, .. }
+                        if matches!(*message, WebsocketEventMessage::ReactionChanged { .. })
+                )
+            })
+            .count()
+    }
+
+    #[test]
+    fn reacts_to_chats() {
+        let rt = TestRuntime::new();
+        let members = setup(&rt);
+        websocket::handle_open(&rt, members.alice);
+        rt.take_websocket_messages();
+
+        let thumbs_up_emoji = || String::from("👍");
+        add_chat_reaction(&rt, members.group_id, 1, thumbs_up_emoji()).unwrap();
+        assert_eq!(reaction_events(&rt), 1);
+        // reacting twice changes nothing
+        add_chat_reaction(&rt, members.group_id, 1, thumbs_up_emoji()).unwrap();
+        assert_eq!(reaction_events(&rt), 0);
+
+        rt.set_caller(members.bob);
+        add_chat_reaction(&rt, members.group_id, 1, thumbs_up_emoji()).unwrap();
+        assert_eq!(
+            chat_reactions(&rt, members.group_id),
+            thumbs_up(&["alice", "bob"])
+        );
+
+        rt.set_caller(members.alice);
+        remove_chat_reaction(&rt, members.group_id, 1, thumbs_up_emoji()).unwrap();
+        remove_chat_reaction(&rt, members.group_id, 1, thumbs_up_emoji()).unwrap();
+        assert_eq!(chat_reactions(&rt, members.group_id), thumbs_up(&["bob"]));
+
+        rt.set_caller(members.bob);
+        remove_chat_reaction(&rt, members.group_id, 1, thumbs_up_emoji()).unwrap();
+        assert!(chat_reactions(&rt, members.group_id).is_empty());
+
+        assert!(matches!(
+            add_chat_reaction(&rt, members.group_id, 1, String::from("a b")),
+            Err(ApiError::InvalidInput { .. })
+        ));
+        assert!(matches!(
+            add_chat_reaction(&rt, members.group_id, 1, String::from("lol")),
+            Err(ApiError::InvalidInput { .. })
+        ));
+        assert!(matches!(
+            add_chat_reaction(&rt, members.group_id, 2, thumbs_up_emoji()),
+            Err(ApiError::NotFound { .. })
+        ));
+    }
+
+    #[test]
+    fn limits_different_emojis_per_target() {
+        let rt = TestRuntime::new();
+        let members = setup(&rt);
+
+        let emojis = ('😀'..).take(MAX_EMOJIS_PER_TARGET + 1).collect::<Vec<_>>();
+        for emoji in emojis.iter().take(MAX_EMOJIS_PER_TARGET) {
+            add_chat_reaction(&rt, members.group_id, 1, emoji.to_string()).unwrap();
+        }
+        assert!(matches!(
+            add_chat_reaction(
+                &rt,
+                members.group_id,
+                1,
+                emojis[MAX_EMOJIS_PER_TARGET].to_string()
+            ),
+            Err(ApiError::Conflict { .. })
+        ));
+
+        // joining an emoji that is already there still works
+        rt.set_caller(members.bob);
+        add_chat_reaction(&rt, members.group_id, 1, emojis[0].to_string()).unwrap();
+        assert_eq!(
+            chat_reactions(&rt, members.group_id)[0].usernames,
+            vec!["alice", "bob"]
+        );
+    }
+
+    #[test]
+    fn meetings_add_up_the_reactions_of_their_video_parts() {
+        let rt = TestRuntime::new();
+        let members = setup(&rt);
+
+        add_video_frame_reaction(&rt, members.group_id, 1, 0, String::from("👍")).unwrap();
+        add_video_frame_reaction(&rt, members.group_id, 1, 0, String::from("🎉")).unwrap();
+        rt.set_caller(members.bob);
+        add_video_frame_reaction(&rt, members.group_id, 1, 0, String::from("👍")).unwrap();
+
+        let header = meeting::get_meeting_detail(&rt, members.group_id, 1).unwrap();
+        assert_eq!(
+            header.reactions,
+            vec![
+                ReactionCount {
+                    emoji: String::from("🎉"),
+                    count: 1,
+                },
+                ReactionCount {
+                    emoji: String::from("👍"),
+                    count: 2,
+                },
+            ]
+        );
+        let frame = meeting::get_video_frame_detail(&rt, members.group_id, 1, 0).unwrap();
+        assert_eq!(frame.reactions[0].usernames, vec!["alice", "bob"]);
+
+        assert!(matches!(
+            add_video_frame_reaction(&rt, members.group_id, 1, 1, String::from("👍")),
+            Err(ApiError::NotFound { .. })
+        ));
+    }
+}
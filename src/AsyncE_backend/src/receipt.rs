@@ -0,0 +1,308 @@
+use candid::{CandidType, Principal};
+use serde::Deserialize;
+
+use crate::{
+    error::ApiError,
+    globals::{CHATS, FRAME_TIMES, GROUPS, READ_RECEIPTS, USERS},
+    group::Group,
+    impl_candid_storable,
+    meeting::{self, Meeting},
+    runtime::Runtime,
+    user,
+};
+
+/// How far a member got in a group, members without one have read nothing yet.
+#[derive(Clone, Debug, Default, CandidType, Deserialize, PartialEq, Eq)]
+pub struct ReadReceipt {
+    /// Every chat up to and including this one was read
+    pub last_read_chat_id: u128,
+    /// Every video part created up to this time was seen, parts are spread across
+    /// meetings so their creation time is the only order they share
+    pub last_seen_frame_time_unix: u128,
+}
+
+impl_candid_storable!(ReadReceipt);
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub enum ReadMark {
+    Chat { chat_id: u128 },
+    VideoFrame { meeting_id: u128, frame_index: u128 },
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct MemberReadReceipt {
+    pub username: String,
+    pub receipt: ReadReceipt,
+}
+
+pub fn get_receipt(group_id: u128, principal: Principal) -> ReadReceipt {
+    READ_RECEIPTS
+        .with_borrow(|read_receipts| read_receipts.get(&(group_id, principal)))
+        .unwrap_or_default()
+}
+
+/// Receipts never move back, marking an older chat or video part changes nothing.
+fn advance(group_id: u128, principal: Principal, update: impl FnOnce(&mut ReadReceipt)) {
+    let mut receipt = get_receipt(group_id, principal);
+    update(&mut receipt);
+    READ_RECEIPTS
+        .with_borrow_mut(|read_receipts| read_receipts.insert((group_id, principal), receipt));
+}
+
+pub fn mark_chat_read(group_id: u128, principal: Principal, chat_id: u128) {
+    advance(group_id, principal, |receipt| {
+        receipt.last_read_chat_id = receipt.last_read_chat_id.max(chat_id)
+    });
+}
+
+pub fn mark_frame_seen(group_id: u128, principal: Principal, created_time_unix: u128) {
+    advance(group_id, principal, |receipt| {
+        receipt.last_seen_frame_time_unix = receipt.last_seen_frame_time_unix.max(created_time_unix)
+    });
+}
+
+pub fn remove_receipt(group_id: u128, principal: Principal) {
+    READ_RECEIPTS.with_borrow_mut(|read_receipts| read_receipts.remove(&(group_id, principal)));
+}
+
+pub fn unread_chat_count(group_id: u128, receipt: &ReadReceipt) -> u64 {
+    CHATS.with_borrow(|chats| {
+        chats
+            .keys_range(
+                (group_id, receipt.last_read_chat_id.saturating_add(1))..=(group_id, u128::MAX),
+            )
+            .count() as u64
+    })
+}
+
+pub fn frame_time_key(
+    group_id: u128,
+    meeting_id: u128,
+    frame_index: usize,
+    created_time_unix: u128,
+) -> (u128, u128, (u128, u128)) {
+    (
+        group_id,
+        created_time_unix,
+        (meeting_id, frame_index as u128),
+    )
+}
+
+/// Keeps track of a new video part, so it counts as unseen for the members.
+pub fn index_frame(group_id: u128, meeting_id: u128, frame_index: usize, created_time_unix: u128) {
+    let key = frame_time_key(group_id, meeting_id, frame_index, created_time_unix);
+    FRAME_TIMES.with_borrow_mut(|frame_times| frame_times.insert(key, ()));
+}
+
+pub fn unindex_frames(group_id: u128, meeting: &Meeting) {
+    FRAME_TIMES.with_borrow_mut(|frame_times| {
+        for (frame_index, frame) in meeting.frames.iter().enumerate() {
+            frame_times.remove(&frame_time_key(
+                group_id,
+                meeting.id,
+                frame_index,
+                frame.created_time_unix,
+            ));
+        }
+    });
+}
+
+pub fn unread_video_count(group_id: u128, receipt: &ReadReceipt) -> u64 {
+    let start = receipt.last_seen_frame_time_unix.saturating_add(1);
+    FRAME_TIMES.with_borrow(|frame_times| {
+        frame_times
+            .keys_range(
+                (group_id, start, (u128::MIN, u128::MIN))
+                    ..=(group_id, u128::MAX, (u128::MAX, u128::MAX)),
+            )
+            .count() as u64
+    })
+}
+
+fn get_member_group(rt: &impl Runtime, group_id: u128) -> Result<Group, ApiError> {
+    user::assert_user_logged_in(rt)?;
+
+    let selfname = user::get_selfname_force(rt)?;
+
+    let group = GROUPS
+        .with_borrow(|groups| groups.get(&group_id))
+        .ok_or(ApiError::not_found("group", group_id))?;
+    if !group.is_member(&selfname) {
+        return Err(ApiError::forbidden("This user is not in this group!"));
+    }
+
+    Ok(group)
+}
+
+pub fn mark_group_read(rt: &impl Runtime, group_id: u128, mark: ReadMark) -> Result<(), ApiError> {
+    get_member_group(rt, group_id)?;
+
+    match mark {
+        ReadMark::Chat { chat_id } => {
+            if !CHATS.with_borrow(|chats| chats.contains_key(&(group_id, chat_id))) {
+                return Err(ApiError::not_found("chat", chat_id));
+            }
+
+            mark_chat_read(group_id, rt.caller(), chat_id);
+        }
+        ReadMark::VideoFrame {
+            meeting_id,
+            frame_index,
+        } => {
+            let meeting = meeting::get_meeting(group_id, meeting_id)?;
+            let frame = meeting
+                .frames
+                .get(frame_index as usize)
+                .ok_or(ApiError::not_found("frame", frame_index))?;
+
+            mark_frame_seen(group_id, rt.caller(), frame.created_time_unix);
+        }
+    }
+
+    Ok(())
+}
+
+/// Receipts of every member, so clients can show who got how far.
+pub fn get_read_receipts(
+    rt: &impl Runtime,
+    group_id: u128,
+) -> Result<Vec<MemberReadReceipt>, ApiError> {
+    let group = get_member_group(rt, group_id)?;
+
+    Ok(group
+        .member_principals()
+        .into_iter()
+        .filter_map(|principal| {
+            let username = USERS.with_borrow(|users| users.get(&principal))?.username;
+
+            Some(MemberReadReceipt {
+                username,
+                receipt: get_receipt(group_id, principal),
+            })
+        })
+        .collect())
+}
+
+mod endpoints {
+    use super::*;
+    use crate::runtime::IcRuntime;
+
+    #[ic_cdk::update]
+    fn mark_group_read(group_id: u128, mark: ReadMark) -> Result<(), ApiError> {
+        super::mark_group_read(&IcRuntime, group_id, mark)
+    }
+
+    #[ic_cdk::query]
+    fn get_read_receipts(group_id: u128) -> Result<Vec<MemberReadReceipt>, ApiError> {
+        super::get_read_receipts(&IcRuntime, group_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        chat::Chat,
+        globals::MEETINGS,
+        group::{self, GroupMember, GroupMemberRole},
+        meeting::VideoFrame,
+        runtime::TestRuntime,
+        user::tests::sign_in,
+    };
+
+    fn add_chats(group_id: u128, ids: impl IntoIterator<Item = u128>) {
+        CHATS.with_borrow_mut(|chats| {
+            for id in ids {
+                let chat = Chat {
+                    id,
+                    uuid: id.to_string(),
+                    content: format!("chat {}", id),
+                    group_id,
+                    username: String::from("bob"),
+                    ..Default::default()
+                };
+                chats.insert((group_id, id), chat);
+            }
+        });
+    }
+
+    fn frame(created_time_unix: u128) -> VideoFrame {
+        VideoFrame {
+            created_time_unix,
+            ..Default::default()
+        }
+    }
+
+    fn unread_counts(rt: &TestRuntime) -> (u64, u64) {
+        let group = &group::get_all_groups(rt).unwrap()[0];
+        (group.unread_chat_count, group.unread_video_count)
+    }
+
+    #[test]
+    fn counts_what_each_member_did_not_read() {
+        let rt = TestRuntime::new();
+        let bob = sign_in(&rt, 2, "bob");
+        let alice = sign_in(&rt, 1, "alice");
+        let group_id = group::create_group(&rt, String::from("Team")).unwrap();
+        GROUPS.with_borrow_mut(|groups| {
+            let mut group = groups.get(&group_id).unwrap();
+            group
+                .members
+                .push(GroupMember::new("bob", GroupMemberRole::Member));
+            groups.insert(group_id, group);
+        });
+        add_chats(group_id, 1..=3);
+        let meeting = Meeting {
+            id: 1,
+            frames: vec![frame(10), frame(20)],
+            ..Default::default()
+        };
+        for (frame_index, frame) in meeting.frames.iter().enumerate() {
+            index_frame(group_id, 1, frame_index, frame.created_time_unix);
+        }
+        MEETINGS.with_borrow_mut(|meetings| meetings.insert((group_id, 1), meeting));
+
+        assert_eq!(unread_counts(&rt), (3, 2));
+
+        mark_group_read(&rt, group_id, ReadMark::Chat { chat_id: 2 }).unwrap();
+        // an older chat does not move the receipt back
+        mark_group_read(&rt, group_id, ReadMark::Chat { chat_id: 1 }).unwrap();
+        let frame_mark = ReadMark::VideoFrame {
+            meeting_id: 1,
+            frame_index: 0,
+        };
+        mark_group_read(&rt, group_id, frame_mark).unwrap();
+        assert_eq!(unread_counts(&rt), (1, 1));
+        assert_eq!(
+            get_receipt(group_id, alice),
+            ReadReceipt {
+                last_read_chat_id: 2,
+                last_seen_frame_time_unix: 10,
+            }
+        );
+
+        assert!(matches!(
+            mark_group_read(&rt, group_id, ReadMark::Chat { chat_id: 4 }),
+            Err(ApiError::NotFound { .. })
+        ));
+
+        rt.set_caller(bob);
+        assert_eq!(unread_counts(&rt), (3, 2));
+        let receipts = get_read_receipts(&rt, group_id)
+            .unwrap()
+            .into_iter()
+            .map(|member| (member.username, member.receipt.last_read_chat_id))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            receipts,
+            vec![(String::from("alice"), 2), (String::from("bob"), 0)]
+        );
+
+        let eve = sign_in(&rt, 3, "eve");
+        rt.set_caller(eve);
+        assert!(matches!(
+            mark_group_read(&rt, group_id, ReadMark::Chat { chat_id: 1 }),
+            Err(ApiError::Forbidden { .. })
+        ));
+    }
+}
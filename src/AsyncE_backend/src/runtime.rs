@@ -0,0 +1,273 @@
+use std::{future::Future, time::Duration};
+
+use candid::Principal;
+use ic_cdk::api::{
+    call::RejectionCode,
+    management_canister::http_request::{self, CanisterHttpRequestArgument, HttpResponse},
+};
+
+/// Everything the domain layer needs from the IC.
+///
+/// Endpoints pass [`IcRuntime`] while unit tests pass a `TestRuntime`, so the logic can run
+/// under `cargo test` where the ic0 system API does not exist.
+// canisters are single threaded, so the futures never have to be `Send`
+#[allow(async_fn_in_trait)]
+pub trait Runtime: Clone + 'static {
+    fn caller(&self) -> Principal;
+
+    /// Nanoseconds since the unix epoch.
+    fn time(&self) -> u128;
+
+    fn is_controller(&self, principal: &Principal) -> bool;
+
+    /// 32 bytes of randomness nobody can predict.
+    async fn random_bytes(&self) -> Result<Vec<u8>, String>;
+
+    /// Runs the future in the background, it is not awaited by the current call.
+    fn spawn(&self, future: impl Future<Output = ()> + 'static);
+
+    async fn http_request(
+        &self,
+        request: CanisterHttpRequestArgument,
+        cycles: u128,
+    ) -> Result<HttpResponse, (RejectionCode, String)>;
+
+    /// Queues a message for a client connected through the websocket gateway.
+    fn send_websocket(&self, client: Principal, message: Vec<u8>) -> Result<(), String>;
+
+    /// Runs `f` once after `delay`, in a message of its own.
+    fn set_timer(&self, delay: Duration, f: impl FnOnce() + 'static);
+
+    /// Sets the hash that certified query responses are checked against.
+    fn set_certified_data(&self, data: &[u8]);
+
+    /// The certificate over the certified data, only available in query calls.
+    fn data_certificate(&self) -> Option<Vec<u8>>;
+
+    fn canister_id(&self) -> Principal;
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct IcRuntime;
+
+impl Runtime for IcRuntime {
+    fn caller(&self) -> Principal {
+        ic_cdk::caller()
+    }
+
+    fn time(&self) -> u128 {
+        ic_cdk::api::time() as u128
+    }
+
+    fn is_controller(&self, principal: &Principal) -> bool {
+        ic_cdk::api::is_controller(principal)
+    }
+
+    async fn random_bytes(&self) -> Result<Vec<u8>, String> {
+        ic_cdk::api::management_canister::main::raw_rand()
+            .await
+            .map(|(bytes,)| bytes)
+            .map_err(|(code, err)| format!("{:?} {}", code, err))
+    }
+
+    fn spawn(&self, future: impl Future<Output = ()> + 'static) {
+        ic_cdk::spawn(future)
+    }
+
+    async fn http_request(
+        &self,
+        request: CanisterHttpRequestArgument,
+        cycles: u128,
+    ) -> Result<HttpResponse, (RejectionCode, String)> {
+        http_request::http_request(request, cycles)
+            .await
+            .map(|(response,)| response)
+    }
+
+    fn send_websocket(&self, client: Principal, message: Vec<u8>) -> Result<(), String> {
+        ic_websocket_cdk::send(client, message)
+    }
+
+    fn set_timer(&self, delay: Duration, f: impl FnOnce() + 'static) {
+        ic_cdk_timers::set_timer(delay, f);
+    }
+
+    fn set_certified_data(&self, data: &[u8]) {
+        ic_cdk::api::set_certified_data(data)
+    }
+
+    fn data_certificate(&self) -> Option<Vec<u8>> {
+        ic_cdk::api::data_certificate()
+    }
+
+    fn canister_id(&self) -> Principal {
+        ic_cdk::id()
+    }
+}
+
+#[cfg(test)]
+pub use test_runtime::{block_on, TestRuntime};
+
+#[cfg(test)]
+mod test_runtime {
+    use std::{
+        cell::{Cell, RefCell},
+        future::Future,
+        pin::pin,
+        rc::Rc,
+        task::{Context, Poll, Waker},
+        time::Duration,
+    };
+
+    use candid::Principal;
+    use ic_cdk::api::{
+        call::RejectionCode,
+        management_canister::http_request::{CanisterHttpRequestArgument, HttpResponse},
+    };
+
+    use super::Runtime;
+
+    type Timer = Box<dyn FnOnce()>;
+
+    struct State {
+        caller: Cell<Principal>,
+        time: Cell<u128>,
+        controllers: RefCell<Vec<Principal>>,
+        random_calls: Cell<u8>,
+        websocket_messages: RefCell<Vec<(Principal, Vec<u8>)>>,
+        timers: RefCell<Vec<(u128, Timer)>>,
+        certified_data: RefCell<Vec<u8>>,
+    }
+
+    /// A runtime whose caller and clock are set by the test.
+    #[derive(Clone)]
+    pub struct TestRuntime(Rc<State>);
+
+    impl TestRuntime {
+        /// 2024-01-01, the caller is anonymous until [`TestRuntime::set_caller`] is called.
+        pub fn new() -> Self {
+            Self(Rc::new(State {
+                caller: Cell::new(Principal::anonymous()),
+                time: Cell::new(Duration::from_secs(1_704_067_200).as_nanos()),
+                controllers: RefCell::default(),
+                random_calls: Cell::new(0),
+                websocket_messages: RefCell::default(),
+                timers: RefCell::default(),
+                certified_data: RefCell::default(),
+            }))
+        }
+
+        pub fn set_caller(&self, principal: Principal) {
+            self.0.caller.set(principal);
+        }
+
+        pub fn advance_time(&self, duration: Duration) {
+            self.0.time.set(self.0.time.get() + duration.as_nanos());
+        }
+
+        pub fn add_controller(&self, principal: Principal) {
+            self.0.controllers.borrow_mut().push(principal);
+        }
+
+        /// Drains the websocket messages sent so far, in the order they were sent.
+        pub fn take_websocket_messages(&self) -> Vec<(Principal, Vec<u8>)> {
+            self.0.websocket_messages.take()
+        }
+
+        /// Runs the timers that are due by now, including the ones they set themselves.
+        pub fn run_timers(&self) {
+            loop {
+                let now = self.time();
+                let timer = {
+                    let mut timers = self.0.timers.borrow_mut();
+                    let due = timers.iter().position(|(time, _)| *time <= now);
+                    due.map(|index| timers.remove(index).1)
+                };
+
+                match timer {
+                    Some(timer) => timer(),
+                    None => break,
+                }
+            }
+        }
+
+        pub fn certified_data(&self) -> Vec<u8> {
+            self.0.certified_data.borrow().clone()
+        }
+    }
+
+    impl Default for TestRuntime {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    /// Nothing in the test runtime waits on anything, so futures are ready on the first poll.
+    pub fn block_on<T>(future: impl Future<Output = T>) -> T {
+        match pin!(future).poll(&mut Context::from_waker(Waker::noop())) {
+            Poll::Ready(x) => x,
+            Poll::Pending => panic!("Futures must not be pending in unit tests!"),
+        }
+    }
+
+    impl Runtime for TestRuntime {
+        fn caller(&self) -> Principal {
+            self.0.caller.get()
+        }
+
+        fn time(&self) -> u128 {
+            self.0.time.get()
+        }
+
+        fn is_controller(&self, principal: &Principal) -> bool {
+            self.0.controllers.borrow().contains(principal)
+        }
+
+        async fn random_bytes(&self) -> Result<Vec<u8>, String> {
+            let calls = self.0.random_calls.get().wrapping_add(1);
+            self.0.random_calls.set(calls);
+            Ok(vec![calls; 32])
+        }
+
+        fn spawn(&self, future: impl Future<Output = ()> + 'static) {
+            block_on(future)
+        }
+
+        async fn http_request(
+            &self,
+            request: CanisterHttpRequestArgument,
+            _: u128,
+        ) -> Result<HttpResponse, (RejectionCode, String)> {
+            Err((
+                RejectionCode::SysFatal,
+                format!("No HTTP outcalls in unit tests: {}", request.url),
+            ))
+        }
+
+        fn send_websocket(&self, client: Principal, message: Vec<u8>) -> Result<(), String> {
+            self.0
+                .websocket_messages
+                .borrow_mut()
+                .push((client, message));
+            Ok(())
+        }
+
+        fn set_timer(&self, delay: Duration, f: impl FnOnce() + 'static) {
+            let time = self.time() + delay.as_nanos();
+            self.0.timers.borrow_mut().push((time, Box::new(f)));
+        }
+
+        fn set_certified_data(&self, data: &[u8]) {
+            self.0.certified_data.replace(data.to_vec());
+        }
+
+        /// Nothing signs the certified data in unit tests, so it stands in for the certificate.
+        fn data_certificate(&self) -> Option<Vec<u8>> {
+            Some(self.certified_data())
+        }
+
+        fn canister_id(&self) -> Principal {
+            Principal::from_slice(&[0xAB; 10])
+        }
+    }
+}
@@ -0,0 +1,505 @@
+use std::{
+    borrow::Cow,
+    collections::BTreeSet,
+    ops::{
+        Bound::{Excluded, Included},
+        RangeInclusive,
+    },
+};
+
+use candid::CandidType;
+use ic_stable_structures::{storable::Bound, Storable};
+use serde::Deserialize;
+
+use crate::{
+    chat::Chat,
+    error::ApiError,
+    globals::{SearchIndexStore, CHATS, GROUPS, MEETINGS, SEARCH_INDEX},
+    meeting::{MeetingHeader, VideoFrameHeader},
+    runtime::Runtime,
+    user,
+};
+
+/// Longer words are indexed by their first bytes, which still matches them by prefix.
+pub const MAX_TOKEN_BYTES: usize = 32;
+pub const MAX_QUERY_TOKENS: usize = 8;
+pub const MAX_SEARCH_PAGE_SIZE: u32 = 50;
+
+/// A case folded word, padded with zeroes so it can be part of a stable key.
+pub type SearchToken = [u8; MAX_TOKEN_BYTES];
+
+/// Something that can be found by searching a group.
+///
+/// Results are ordered by kind first (meetings, then video parts, then chats) and newest
+/// first within each kind, a page continues after the last document of the previous one.
+#[derive(Copy, Clone, Debug, CandidType, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SearchDoc {
+    Chat { chat_id: u128 },
+    VideoFrame { meeting_id: u128, frame_index: u128 },
+    Meeting { meeting_id: u128 },
+}
+
+impl SearchDoc {
+    const MIN: Self = Self::Chat { chat_id: u128::MIN };
+    const MAX: Self = Self::Meeting {
+        meeting_id: u128::MAX,
+    };
+}
+
+/// Encoded so the byte order matches the derived `Ord`.
+impl Storable for SearchDoc {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let (tag, id, frame_index) = match *self {
+            Self::Chat { chat_id } => (0u8, chat_id, 0),
+            Self::VideoFrame {
+                meeting_id,
+                frame_index,
+            } => (1, meeting_id, frame_index),
+            Self::Meeting { meeting_id } => (2, meeting_id, 0),
+        };
+
+        let mut bytes = Vec::with_capacity(33);
+        bytes.push(tag);
+        bytes.extend_from_slice(&id.to_be_bytes());
+        bytes.extend_from_slice(&frame_index.to_be_bytes());
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        let id = u128::from_be_bytes(bytes[1..17].try_into().unwrap());
+        let frame_index = u128::from_be_bytes(bytes[17..33].try_into().unwrap());
+
+        match bytes[0] {
+            0 => Self::Chat { chat_id: id },
+            1 => Self::VideoFrame {
+                meeting_id: id,
+                frame_index,
+            },
+            2 => Self::Meeting { meeting_id: id },
+            x => panic!("Unknown search document kind: {}", x),
+        }
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 33,
+        is_fixed_size: true,
+    };
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub enum SearchResult {
+    Chat(Chat),
+    Meeting(MeetingHeader),
+    VideoFrame {
+        meeting_id: u128,
+        frame_index: u128,
+        frame: VideoFrameHeader,
+    },
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct SearchPage {
+    pub results: Vec<SearchResult>,
+    /// Pass this back to get the next page, absent on the last one
+    pub next_cursor: Option<SearchDoc>,
+}
+
+/// Case folded words of the text, cut to [`MAX_TOKEN_BYTES`].
+fn words(text: &str) -> BTreeSet<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| {
+            let mut word = word.to_lowercase();
+            let mut end = word.len().min(MAX_TOKEN_BYTES);
+            while !word.is_char_boundary(end) {
+                end -= 1;
+            }
+            word.truncate(end);
+            word
+        })
+        .collect()
+}
+
+fn padded(word: &str, padding: u8) -> SearchToken {
+    let mut token = [padding; MAX_TOKEN_BYTES];
+    token[..word.len()].copy_from_slice(word.as_bytes());
+    token
+}
+
+/// Index keys of the document, the migration that builds the index uses them as well.
+pub fn index_keys(
+    group_id: u128,
+    doc: SearchDoc,
+    text: &str,
+) -> impl Iterator<Item = (u128, SearchToken, SearchDoc)> {
+    words(text)
+        .into_iter()
+        .map(move |word| (group_id, padded(&word, 0), doc))
+}
+
+pub fn index(group_id: u128, doc: SearchDoc, text: &str) {
+    SEARCH_INDEX.with_borrow_mut(|search_index| {
+        for key in index_keys(group_id, doc, text) {
+            search_index.insert(key, ());
+        }
+    });
+}
+
+/// `text` has to be the text the document was indexed with.
+pub fn unindex(group_id: u128, doc: SearchDoc, text: &str) {
+    SEARCH_INDEX.with_borrow_mut(|search_index| {
+        for key in index_keys(group_id, doc, text) {
+            search_index.remove(&key);
+        }
+    });
+}
+
+type IndexKey = (u128, SearchToken, SearchDoc);
+
+fn prefix_range(group_id: u128, word: &str) -> RangeInclusive<IndexKey> {
+    (group_id, padded(word, 0), SearchDoc::MIN)..=(group_id, padded(word, u8::MAX), SearchDoc::MAX)
+}
+
+/// Indexed words of the group that start with `word`, each one is read once by skipping past
+/// its documents.
+fn tokens_with_prefix(
+    search_index: &SearchIndexStore,
+    group_id: u128,
+    word: &str,
+) -> Vec<SearchToken> {
+    let range = prefix_range(group_id, word);
+    let mut lower = Included(*range.start());
+    let mut tokens = Vec::new();
+    while let Some((_, token, _)) = search_index
+        .keys_range((lower, Included(*range.end())))
+        .next()
+    {
+        tokens.push(token);
+        lower = Excluded((group_id, token, SearchDoc::MAX));
+    }
+
+    tokens
+}
+
+/// Position of the word with the fewest documents, the ranges are walked side by side so this
+/// reads no more than the smallest one for every word.
+fn smallest_range(search_index: &SearchIndexStore, group_id: u128, words: &[String]) -> usize {
+    let mut ranges: Vec<_> = words
+        .iter()
+        .map(|word| search_index.keys_range(prefix_range(group_id, word)))
+        .collect();
+
+    loop {
+        for (position, range) in ranges.iter_mut().enumerate() {
+            if range.next().is_none() {
+                return position;
+            }
+        }
+    }
+}
+
+/// Documents with one of the `tokens`, newest first and before the cursor.
+fn stream_docs<'a>(
+    search_index: &'a SearchIndexStore,
+    group_id: u128,
+    tokens: &[SearchToken],
+    cursor: Option<SearchDoc>,
+) -> impl Iterator<Item = SearchDoc> + 'a {
+    let mut postings: Vec<_> = tokens
+        .iter()
+        .map(|&token| {
+            let upper = match cursor {
+                Some(cursor) => Excluded((group_id, token, cursor)),
+                None => Included((group_id, token, SearchDoc::MAX)),
+            };
+
+            search_index
+                .keys_range((Included((group_id, token, SearchDoc::MIN)), upper))
+                .rev()
+                .map(|(_, _, doc)| doc)
+                .peekable()
+        })
+        .collect();
+
+    let mut last = None;
+    std::iter::from_fn(move || loop {
+        let newest = postings
+            .iter_mut()
+            .filter_map(|posting| posting.peek().copied())
+            .max()?;
+        for posting in postings.iter_mut() {
+            posting.next_if_eq(&newest);
+        }
+
+        // a document is listed once for every matching word it contains
+        if last != Some(newest) {
+            last = Some(newest);
+            return Some(newest);
+        }
+    })
+}
+
+fn load(group_id: u128, doc: SearchDoc) -> Option<SearchResult> {
+    match doc {
+        SearchDoc::Chat { chat_id } => CHATS
+            .with_borrow(|chats| chats.get(&(group_id, chat_id)))
+            .map(SearchResult::Chat),
+        SearchDoc::Meeting { meeting_id } => MEETINGS
+            .with_borrow(|meetings| meetings.get(&(group_id, meeting_id)))
+            .map(|meeting| SearchResult::Meeting(MeetingHeader::from(&meeting))),
+        SearchDoc::VideoFrame {
+            meeting_id,
+            frame_index,
+        } => {
+            let meeting = MEETINGS.with_borrow(|meetings| meetings.get(&(group_id, meeting_id)))?;
+            let frame = meeting.frames.get(frame_index as usize)?;
+
+            Some(SearchResult::VideoFrame {
+                meeting_id,
+                frame_index,
+                frame: VideoFrameHeader::from(frame),
+            })
+        }
+    }
+}
+
+/// Finds the chats, meetings and video parts of the group with a word starting with each
+/// word of the query.
+pub fn search_group(
+    rt: &impl Runtime,
+    group_id: u128,
+    query: String,
+    limit: u32,
+    cursor: Option<SearchDoc>,
+) -> Result<SearchPage, ApiError> {
+    user::assert_user_logged_in(rt)?;
+
+    let selfname = user::get_selfname_force(rt)?;
+    let group = GROUPS
+        .with_borrow(|groups| groups.get(&group_id))
+        .ok_or(ApiError::not_found("group", group_id))?;
+    if !group.is_member(&selfname) {
+        return Err(ApiError::forbidden("This user is not in this group!"));
+    }
+
+    let query_words = words(&query);
+    if query_words.is_empty() {
+        return Err(ApiError::invalid_input("query", "must contain a word"));
+    }
+    if query_words.len() > MAX_QUERY_TOKENS {
+        return Err(ApiError::invalid_input(
+            "query",
+            &format!("cannot contain more than {} words", MAX_QUERY_TOKENS),
+        ));
+    }
+    if limit == 0 {
+        return Err(ApiError::invalid_input("limit", "must be at least 1"));
+    }
+    let limit = limit.min(MAX_SEARCH_PAGE_SIZE) as usize;
+
+    let query_words: Vec<String> = query_words.into_iter().collect();
+    let (results, next_cursor) = SEARCH_INDEX.with_borrow(|search_index| {
+        let smallest = smallest_range(search_index, group_id, &query_words);
+        let mut tokens: Vec<Vec<SearchToken>> = query_words
+            .iter()
+            .map(|word| tokens_with_prefix(search_index, group_id, word))
+            .collect();
+        let streamed = tokens.swap_remove(smallest);
+
+        let mut docs = stream_docs(search_index, group_id, &streamed, cursor)
+            .filter(|&doc| {
+                tokens.iter().all(|tokens| {
+                    tokens
+                        .iter()
+                        .any(|&token| search_index.contains_key(&(group_id, token, doc)))
+                })
+            })
+            .peekable();
+
+        let mut results = Vec::new();
+        let mut next_cursor = None;
+        while results.len() < limit {
+            let Some(doc) = docs.next() else {
+                break;
+            };
+
+            if let Some(result) = load(group_id, doc) {
+                results.push(result);
+                next_cursor = Some(doc);
+            }
+        }
+        if docs.peek().is_none() {
+            next_cursor = None;
+        }
+
+        (results, next_cursor)
+    });
+
+    Ok(SearchPage {
+        results,
+        next_cursor,
+    })
+}
+
+mod endpoints {
+    use super::*;
+    use crate::runtime::IcRuntime;
+
+    #[ic_cdk::query]
+    fn search_group(
+        group_id: u128,
+        query: String,
+        limit: u32,
+        cursor: Option<SearchDoc>,
+    ) -> Result<SearchPage, ApiError> {
+        super::search_group(&IcRuntime, group_id, query, limit, cursor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{chat, group, meeting, runtime::TestRuntime, user::tests::sign_in};
+
+    fn add_chat(group_id: u128, chat_id: u128, content: &str) {
+        let chat = Chat {
+            id: chat_id,
+            uuid: chat_id.to_string(),
+            content: content.to_string(),
+            group_id,
+            username: String::from("alice"),
+            ..Default::default()
+        };
+        CHATS.with_borrow_mut(|chats| chats.insert((group_id, chat_id), chat));
+        index(group_id, SearchDoc::Chat { chat_id }, content);
+    }
+
+    fn search(rt: &TestRuntime, group_id: u128, query: &str) -> Vec<SearchDoc> {
+        let mut docs = Vec::new();
+        let mut cursor = None;
+        loop {
+            let page = search_group(rt, group_id, query.to_string(), 2, cursor).unwrap();
+            docs.extend(page.results.into_iter().map(|result| match result {
+                SearchResult::Chat(chat) => SearchDoc::Chat { chat_id: chat.id },
+                SearchResult::Meeting(meeting) => SearchDoc::Meeting {
+                    meeting_id: meeting.id,
+                },
+                SearchResult::VideoFrame {
+                    meeting_id,
+                    frame_index,
+                    ..
+                } => SearchDoc::VideoFrame {
+                    meeting_id,
+                    frame_index,
+                },
+            }));
+
+            cursor = page.next_cursor;
+            if cursor.is_none() {
+                return docs;
+            }
+        }
+    }
+
+    #[test]
+    fn finds_chats_and_meetings_by_word_prefix() {
+        let rt = TestRuntime::new();
+        sign_in(&rt, 1, "alice");
+        let group_id = group::create_group(&rt, String::from("Team")).unwrap();
+        let other_group_id = group::create_group(&rt, String::from("Other")).unwrap();
+
+        add_chat(group_id, 1, "Did you watch the Meeting?");
+        add_chat(group_id, 2, "meetings are long");
+        add_chat(group_id, 3, "Unrelated");
+        add_chat(group_id, 4, "the MEETING notes");
+        add_chat(other_group_id, 5, "meeting elsewhere");
+        let meeting_id =
+            meeting::create_meeting(&rt, group_id, String::from("Sprint meeting")).unwrap();
+
+        assert_eq!(
+            search(&rt, group_id, "meet"),
+            vec![
+                SearchDoc::Meeting { meeting_id },
+                SearchDoc::Chat { chat_id: 4 },
+                SearchDoc::Chat { chat_id: 2 },
+                SearchDoc::Chat { chat_id: 1 },
+            ]
+        );
+        assert_eq!(
+            search(&rt, group_id, "meeting, THE"),
+            vec![
+                SearchDoc::Chat { chat_id: 4 },
+                SearchDoc::Chat { chat_id: 1 }
+            ]
+        );
+        assert!(search(&rt, group_id, "sprint notes").is_empty());
+
+        rt.set_caller(sign_in(&rt, 2, "bob"));
+        assert!(matches!(
+            search_group(&rt, group_id, String::from("meet"), 10, None),
+            Err(ApiError::Forbidden { .. })
+        ));
+    }
+
+    #[test]
+    fn pages_through_documents_matching_several_words() {
+        let rt = TestRuntime::new();
+        sign_in(&rt, 1, "alice");
+        let group_id = group::create_group(&rt, String::from("Team")).unwrap();
+
+        for chat_id in 1..=6 {
+            add_chat(group_id, chat_id, "plan the planning");
+        }
+        add_chat(group_id, 7, "plans for the retro");
+        add_chat(group_id, 8, "retro planned");
+        add_chat(group_id, 9, "retro");
+
+        assert_eq!(
+            search(&rt, group_id, "plan"),
+            (1..=8)
+                .rev()
+                .map(|chat_id| SearchDoc::Chat { chat_id })
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            search(&rt, group_id, "plan retro"),
+            vec![
+                SearchDoc::Chat { chat_id: 8 },
+                SearchDoc::Chat { chat_id: 7 }
+            ]
+        );
+
+        let page = search_group(&rt, group_id, String::from("retro plan"), 1, None).unwrap();
+        assert_eq!(page.results.len(), 1);
+        assert_eq!(page.next_cursor, Some(SearchDoc::Chat { chat_id: 8 }));
+        assert!(matches!(
+            search_group(&rt, group_id, String::from("a b c d e f g h i"), 10, None),
+            Err(ApiError::InvalidInput { .. })
+        ));
+    }
+
+    #[test]
+    fn follows_edits_and_deletes() {
+        let rt = TestRuntime::new();
+        sign_in(&rt, 1, "alice");
+        user::buy_subscription(&rt).unwrap();
+        let group_id = group::create_group(&rt, String::from("Team")).unwrap();
+        add_chat(group_id, 1, "first draft");
+
+        chat::edit_chat(&rt, group_id, 1, String::from("final version")).unwrap();
+        assert!(search(&rt, group_id, "draft").is_empty());
+        assert_eq!(
+            search(&rt, group_id, "final"),
+            vec![SearchDoc::Chat { chat_id: 1 }]
+        );
+
+        chat::delete_chat(&rt, group_id, 1).unwrap();
+        assert!(search(&rt, group_id, "final").is_empty());
+        assert!(SEARCH_INDEX.with_borrow(|search_index| search_index.is_empty()));
+
+        assert!(matches!(
+            search_group(&rt, group_id, String::from("?!"), 10, None),
+            Err(ApiError::InvalidInput { .. })
+        ));
+    }
+}
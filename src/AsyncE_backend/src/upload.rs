@@ -0,0 +1,737 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    time::Duration,
+};
+
+use candid::{CandidType, Principal};
+use serde::Deserialize;
+use sha2::digest::generic_array::GenericArray;
+
+use crate::{
+    blob, chunk,
+    error::ApiError,
+    globals::{UPLOAD_CHUNKS, UPLOAD_SESSIONS},
+    impl_candid_storable,
+    meeting::{self, MeetingProcessType},
+    primary_key::{self, PrimaryKeyType},
+    runtime::{IcRuntime, Runtime},
+    user,
+};
+
+pub const MAX_VIDEO_UPLOAD_SIZE: u64 = 1024 * 1024 * 1024;
+
+/// Sessions without any activity for this long are dropped together with their chunks.
+pub const UPLOAD_SESSION_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+
+pub const MAX_UPLOAD_SESSIONS_PER_USER: usize = 3;
+
+/// Chunks fed into the running SHA-256 by a single call, so chunks that were sent far out of
+/// order cannot push one message over the instruction limit.
+const MAX_HASHED_CHUNKS_PER_CALL: u32 = 16;
+
+const SHA256_INITIAL_STATE: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+/// SHA-256 of the leading chunks of an upload, advanced as soon as the next chunk is there.
+#[derive(Clone, Debug, Default, CandidType, Deserialize)]
+pub struct UploadHashState {
+    pub hashed_chunks: u32,
+    /// Empty until the first block has been compressed
+    pub state: Vec<u32>,
+    /// Bytes that do not fill a whole 64 byte block yet
+    pub pending: Vec<u8>,
+}
+
+impl UploadHashState {
+    fn update(&mut self, mut data: &[u8]) {
+        let mut state = <[u32; 8]>::try_from(self.state.as_slice()).unwrap_or(SHA256_INITIAL_STATE);
+        let mut compress = |block: &[u8]| {
+            sha2::compress256(&mut state, &[*GenericArray::from_slice(block)]);
+        };
+
+        if !self.pending.is_empty() {
+            let filled = (64 - self.pending.len()).min(data.len());
+            self.pending.extend_from_slice(&data[..filled]);
+            data = &data[filled..];
+            if self.pending.len() == 64 {
+                compress(&self.pending);
+                self.pending.clear();
+            }
+        }
+
+        let mut blocks = data.chunks_exact(64);
+        for block in &mut blocks {
+            compress(block);
+        }
+        self.pending.extend_from_slice(blocks.remainder());
+        self.state = state.to_vec();
+    }
+
+    fn finalize(mut self, length: u64) -> [u8; 32] {
+        let mut padding = vec![0x80];
+        while (self.pending.len() + padding.len()) % 64 != 56 {
+            padding.push(0);
+        }
+        padding.extend_from_slice(&(length * 8).to_be_bytes());
+        self.update(&padding);
+
+        let mut hash = [0; 32];
+        for (bytes, word) in hash.chunks_exact_mut(4).zip(self.state) {
+            bytes.copy_from_slice(&word.to_be_bytes());
+        }
+        hash
+    }
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct VideoUploadSession {
+    pub id: u128,
+    pub owner: Principal,
+    pub group_id: u128,
+    pub meeting_id: u128,
+    pub title: String,
+    pub with_subtitles: bool,
+    pub total_size: u64,
+    pub chunk_count: u32,
+    pub sha256: String,
+    pub received_chunks: BTreeSet<u32>,
+    pub created_time_unix: u128,
+    pub last_activity_time_unix: u128,
+    /// Optional since sessions started before uploads were hashed incrementally do not have it
+    pub hash: Option<UploadHashState>,
+    /// Hex encoded SHA-256 of every received chunk
+    pub chunk_sha256: Option<BTreeMap<u32, String>>,
+}
+
+impl_candid_storable!(VideoUploadSession);
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct VideoUploadRequest {
+    pub title: String,
+    pub total_size: u64,
+    /// Hex encoded SHA-256 of the whole video, verified on commit
+    pub sha256: String,
+    pub with_subtitles: bool,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct VideoUploadStatus {
+    pub session_id: u128,
+    pub chunk_size: u64,
+    pub chunk_count: u32,
+    pub received_chunk_count: u32,
+    pub missing_chunks: Vec<u32>,
+    /// Hex encoded SHA-256 of every chunk, empty for the chunks that have not arrived yet.
+    /// Lets clients find the chunks to send again when the video does not match its hash.
+    pub chunk_sha256: Vec<String>,
+    pub expires_time_unix: u128,
+}
+
+impl From<&VideoUploadSession> for VideoUploadStatus {
+    fn from(value: &VideoUploadSession) -> Self {
+        Self {
+            session_id: value.id,
+            chunk_size: chunk::MB as u64,
+            chunk_count: value.chunk_count,
+            received_chunk_count: value.received_chunks.len() as u32,
+            missing_chunks: value.missing_chunks(),
+            chunk_sha256: (0..value.chunk_count)
+                .map(|index| {
+                    value
+                        .chunk_sha256
+                        .as_ref()
+                        .and_then(|chunk_sha256| chunk_sha256.get(&index).cloned())
+                        .unwrap_or_default()
+                })
+                .collect(),
+            expires_time_unix: value.last_activity_time_unix + UPLOAD_SESSION_TIMEOUT.as_nanos(),
+        }
+    }
+}
+
+impl VideoUploadSession {
+    pub fn missing_chunks(&self) -> Vec<u32> {
+        (0..self.chunk_count)
+            .filter(|x| !self.received_chunks.contains(x))
+            .collect()
+    }
+
+    pub fn expected_chunk_size(&self, chunk_index: u32) -> u64 {
+        chunk::expected_chunk_size(self.total_size, self.chunk_count, chunk_index)
+    }
+
+    fn is_expired(&self, now: u128) -> bool {
+        now.saturating_sub(self.last_activity_time_unix) > UPLOAD_SESSION_TIMEOUT.as_nanos()
+    }
+
+    /// Feeds the chunks that follow the hashed ones into the running SHA-256,
+    /// at most [`MAX_HASHED_CHUNKS_PER_CALL`] of them.
+    fn advance_hash(&mut self) {
+        let mut hash = self.hash.take().unwrap_or_default();
+        let last_chunk = (hash.hashed_chunks + MAX_HASHED_CHUNKS_PER_CALL).min(self.chunk_count);
+
+        while hash.hashed_chunks < last_chunk && self.received_chunks.contains(&hash.hashed_chunks)
+        {
+            let chunk = UPLOAD_CHUNKS
+                .with_borrow(|upload_chunks| upload_chunks.get(&(self.id, hash.hashed_chunks)))
+                .unwrap_or_default();
+            hash.update(&chunk);
+            hash.hashed_chunks += 1;
+        }
+
+        self.hash = Some(hash);
+    }
+}
+
+fn get_session(rt: &impl Runtime, session_id: u128) -> Result<VideoUploadSession, ApiError> {
+    let session = UPLOAD_SESSIONS
+        .with_borrow(|upload_sessions| upload_sessions.get(&session_id))
+        .ok_or(ApiError::not_found("upload session", session_id))?;
+
+    if session.owner != rt.caller() {
+        return Err(ApiError::forbidden(
+            "This upload session belongs to another user!",
+        ));
+    }
+
+    Ok(session)
+}
+
+fn remove_session(session: &VideoUploadSession) {
+    UPLOAD_CHUNKS.with_borrow_mut(|upload_chunks| {
+        for index in session.received_chunks.iter() {
+            upload_chunks.remove(&(session.id, *index));
+        }
+    });
+
+    UPLOAD_SESSIONS.with_borrow_mut(|upload_sessions| upload_sessions.remove(&session.id));
+}
+
+pub fn begin_video_upload(
+    rt: &impl Runtime,
+    group_id: u128,
+    meeting_id: u128,
+    request: VideoUploadRequest,
+) -> Result<u128, ApiError> {
+    user::assert_user_logged_in(rt)?;
+    meeting::assert_check_group(rt, group_id)?;
+
+    let selfuser = user::get_selfuser(rt)?.ok_or(ApiError::NoUsername)?;
+    if request.with_subtitles && selfuser.subscription.is_none() {
+        return Err(ApiError::SubscriptionRequired);
+    }
+
+    let meeting = meeting::get_meeting(group_id, meeting_id)?;
+    if meeting.process_type != MeetingProcessType::None {
+        return Err(ApiError::Busy);
+    }
+
+    let now = rt.time();
+    let open_sessions = UPLOAD_SESSIONS.with_borrow(|upload_sessions| {
+        upload_sessions
+            .iter()
+            .filter(|(_, session)| session.owner == rt.caller() && !session.is_expired(now))
+            .count()
+    });
+    if open_sessions >= MAX_UPLOAD_SESSIONS_PER_USER {
+        return Err(ApiError::conflict(&format!(
+            "You cannot have more than {} uploads at once, please finish or abort one first!",
+            MAX_UPLOAD_SESSIONS_PER_USER
+        )));
+    }
+
+    if request.total_size == 0 || request.total_size > MAX_VIDEO_UPLOAD_SIZE {
+        return Err(ApiError::invalid_input(
+            "total_size",
+            &format!("must be between 1 and {} bytes", MAX_VIDEO_UPLOAD_SIZE),
+        ));
+    }
+
+    if request.sha256.len() != 64 || hex::decode(&request.sha256).is_err() {
+        return Err(ApiError::invalid_input(
+            "sha256",
+            "must be 64 hexadecimal characters",
+        ));
+    }
+
+    let session = VideoUploadSession {
+        id: primary_key::get_primary_key(PrimaryKeyType::UploadSession),
+        owner: rt.caller(),
+        group_id,
+        meeting_id,
+        title: request.title,
+        with_subtitles: request.with_subtitles,
+        total_size: request.total_size,
+        chunk_count: request.total_size.div_ceil(chunk::MB as u64) as u32,
+        sha256: request.sha256.to_lowercase(),
+        received_chunks: BTreeSet::new(),
+        created_time_unix: now,
+        last_activity_time_unix: now,
+        hash: Some(UploadHashState::default()),
+        chunk_sha256: Some(BTreeMap::new()),
+    };
+    let session_id = session.id;
+
+    UPLOAD_SESSIONS.with_borrow_mut(|upload_sessions| upload_sessions.insert(session_id, session));
+
+    Ok(session_id)
+}
+
+pub fn put_video_upload_chunk(
+    rt: &impl Runtime,
+    session_id: u128,
+    chunk_index: u32,
+    data: Vec<u8>,
+) -> Result<(), ApiError> {
+    user::assert_user_logged_in(rt)?;
+
+    let mut session = get_session(rt, session_id)?;
+
+    if chunk_index >= session.chunk_count {
+        return Err(ApiError::invalid_input(
+            "chunk_index",
+            &format!("must be less than {}", session.chunk_count),
+        ));
+    }
+
+    if session.received_chunks.contains(&chunk_index) {
+        return Err(ApiError::conflict("This chunk has already been received!"));
+    }
+
+    let expected_size = session.expected_chunk_size(chunk_index);
+    if data.len() as u64 != expected_size {
+        return Err(ApiError::invalid_input(
+            "data",
+            &format!(
+                "chunk {} must be exactly {} bytes, got {}",
+                chunk_index,
+                expected_size,
+                data.len()
+            ),
+        ));
+    }
+
+    session
+        .chunk_sha256
+        .get_or_insert_with(BTreeMap::new)
+        .insert(chunk_index, hex::encode(blob::hash(&data)));
+    UPLOAD_CHUNKS
+        .with_borrow_mut(|upload_chunks| upload_chunks.insert((session_id, chunk_index), data));
+
+    session.received_chunks.insert(chunk_index);
+    session.last_activity_time_unix = rt.time();
+    session.advance_hash();
+    UPLOAD_SESSIONS.with_borrow_mut(|upload_sessions| upload_sessions.insert(session_id, session));
+
+    Ok(())
+}
+
+pub fn get_video_upload_status(
+    rt: &impl Runtime,
+    session_id: u128,
+) -> Result<VideoUploadStatus, ApiError> {
+    user::assert_user_logged_in(rt)?;
+
+    get_session(rt, session_id).map(|session| VideoUploadStatus::from(&session))
+}
+
+/// Drops chunks that were received, so they can be sent again,
+/// e.g. the ones whose SHA-256 differs from the client's after a failed commit.
+pub fn discard_video_upload_chunks(
+    rt: &impl Runtime,
+    session_id: u128,
+    chunk_indices: Vec<u32>,
+) -> Result<(), ApiError> {
+    user::assert_user_logged_in(rt)?;
+
+    let mut session = get_session(rt, session_id)?;
+
+    UPLOAD_CHUNKS.with_borrow_mut(|upload_chunks| {
+        for index in chunk_indices.iter() {
+            upload_chunks.remove(&(session_id, *index));
+        }
+    });
+    for index in chunk_indices.iter() {
+        session.received_chunks.remove(index);
+        if let Some(chunk_sha256) = session.chunk_sha256.as_mut() {
+            chunk_sha256.remove(index);
+        }
+    }
+
+    // the running hash cannot be rewound, it starts over once the chunks are back
+    let hashed_chunks = session.hash.as_ref().map(|hash| hash.hashed_chunks);
+    if chunk_indices
+        .iter()
+        .any(|index| Some(*index) < hashed_chunks)
+    {
+        session.hash = None;
+    }
+
+    session.last_activity_time_unix = rt.time();
+    UPLOAD_SESSIONS.with_borrow_mut(|upload_sessions| upload_sessions.insert(session_id, session));
+
+    Ok(())
+}
+
+/// Adds the uploaded video to the meeting.
+///
+/// Returns `UploadIncomplete` while chunks that were sent out of order are still being hashed,
+/// every call hashes some more of them. When the video does not match its SHA-256 the
+/// chunks are kept, so only the wrong ones have to be discarded and sent again.
+pub fn commit_video_upload(rt: &impl Runtime, session_id: u128) -> Result<(), ApiError> {
+    user::assert_user_logged_in(rt)?;
+
+    let mut session = get_session(rt, session_id)?;
+    meeting::assert_check_group(rt, session.group_id)?;
+
+    let missing_chunks = session.missing_chunks();
+    if !missing_chunks.is_empty() {
+        return Err(ApiError::conflict(&format!(
+            "Upload is missing {} chunk(s), first missing chunk is {}!",
+            missing_chunks.len(),
+            missing_chunks[0]
+        )));
+    }
+
+    let meeting = meeting::get_meeting(session.group_id, session.meeting_id)?;
+    if meeting.process_type != MeetingProcessType::None {
+        return Err(ApiError::Busy);
+    }
+
+    session.advance_hash();
+    session.last_activity_time_unix = rt.time();
+    let hash = session.hash.clone().unwrap_or_default();
+    if hash.hashed_chunks < session.chunk_count {
+        let chunk_count = session.chunk_count;
+        UPLOAD_SESSIONS
+            .with_borrow_mut(|upload_sessions| upload_sessions.insert(session_id, session));
+        return Err(ApiError::UploadIncomplete {
+            hashed_chunks: hash.hashed_chunks,
+            chunk_count,
+        });
+    }
+
+    let sha256 = hash.finalize(session.total_size);
+    if hex::encode(sha256) != session.sha256 {
+        session.hash = None;
+        UPLOAD_SESSIONS
+            .with_borrow_mut(|upload_sessions| upload_sessions.insert(session_id, session));
+        return Err(ApiError::conflict(
+            "Uploaded video does not match its SHA-256, please send the wrong chunks again!",
+        ));
+    }
+
+    let username = user::get_selfname_force(rt)?;
+    // the chunks move over to the blob store one by one
+    let video = blob::store_chunks(sha256, session.total_size, session.chunk_count, |index| {
+        UPLOAD_CHUNKS
+            .with_borrow_mut(|upload_chunks| upload_chunks.remove(&(session_id, index)))
+            .unwrap_or_default()
+    });
+    let result = meeting::add_video_part(
+        rt,
+        session.group_id,
+        session.meeting_id,
+        username,
+        session.title.clone(),
+        video,
+        session.with_subtitles,
+    );
+
+    remove_session(&session);
+
+    result
+}
+
+pub fn abort_video_upload(rt: &impl Runtime, session_id: u128) -> Result<(), ApiError> {
+    user::assert_user_logged_in(rt)?;
+
+    let session = get_session(rt, session_id)?;
+    remove_session(&session);
+
+    Ok(())
+}
+
+/// Drops the sessions that had no activity for [`UPLOAD_SESSION_TIMEOUT`].
+pub fn remove_expired_sessions(rt: &impl Runtime) {
+    let now = rt.time();
+    let expired_sessions = UPLOAD_SESSIONS.with_borrow(|upload_sessions| {
+        upload_sessions
+            .iter()
+            .filter(|(_, session)| session.is_expired(now))
+            .map(|(_, session)| session)
+            .collect::<Vec<_>>()
+    });
+
+    for session in expired_sessions {
+        ic_cdk::println!("Removing expired upload session {}", session.id);
+        remove_session(&session);
+    }
+}
+
+pub fn poll_expired_upload_sessions() {
+    ic_cdk::println!("Starting poll expired upload sessions");
+    ic_cdk_timers::set_timer_interval(Duration::from_secs(60), || {
+        remove_expired_sessions(&IcRuntime)
+    });
+}
+
+mod endpoints {
+    use super::*;
+    use crate::runtime::IcRuntime;
+
+    #[ic_cdk::update]
+    fn begin_video_upload(
+        group_id: u128,
+        meeting_id: u128,
+        request: VideoUploadRequest,
+    ) -> Result<u128, ApiError> {
+        super::begin_video_upload(&IcRuntime, group_id, meeting_id, request)
+    }
+
+    #[ic_cdk::update]
+    fn put_video_upload_chunk(
+        session_id: u128,
+        chunk_index: u32,
+        data: Vec<u8>,
+    ) -> Result<(), ApiError> {
+        super::put_video_upload_chunk(&IcRuntime, session_id, chunk_index, data)
+    }
+
+    #[ic_cdk::query]
+    fn get_video_upload_status(session_id: u128) -> Result<VideoUploadStatus, ApiError> {
+        super::get_video_upload_status(&IcRuntime, session_id)
+    }
+
+    #[ic_cdk::update]
+    fn discard_video_upload_chunks(
+        session_id: u128,
+        chunk_indices: Vec<u32>,
+    ) -> Result<(), ApiError> {
+        super::discard_video_upload_chunks(&IcRuntime, session_id, chunk_indices)
+    }
+
+    #[ic_cdk::update]
+    fn commit_video_upload(session_id: u128) -> Result<(), ApiError> {
+        super::commit_video_upload(&IcRuntime, session_id)
+    }
+
+    #[ic_cdk::update]
+    fn abort_video_upload(session_id: u128) -> Result<(), ApiError> {
+        super::abort_video_upload(&IcRuntime, session_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sha2::{Digest, Sha256};
+
+    use super::*;
+    use crate::{
+        group,
+        job::tests::{run_jobs, use_fake_processor},
+        runtime::TestRuntime,
+        user::tests::sign_in,
+    };
+
+    fn video() -> Vec<u8> {
+        (0..chunk::MB + 3).map(|x| x as u8).collect()
+    }
+
+    fn begin(rt: &TestRuntime, data: &[u8]) -> (u128, u128, u128) {
+        let group_id = group::create_group(rt, String::from("Team")).unwrap();
+        let meeting_id = meeting::create_meeting(rt, group_id, String::from("Demo")).unwrap();
+        let request = VideoUploadRequest {
+            title: String::from("Intro"),
+            total_size: data.len() as u64,
+            sha256: hex::encode(blob::hash(data)),
+            with_subtitles: false,
+        };
+        let session_id = begin_video_upload(rt, group_id, meeting_id, request).unwrap();
+
+        (group_id, meeting_id, session_id)
+    }
+
+    fn put(rt: &TestRuntime, session_id: u128, index: u32, data: &[u8]) -> Result<(), ApiError> {
+        let chunk = data
+            .chunks(chunk::MB)
+            .nth(index as usize)
+            .unwrap_or_default();
+        put_video_upload_chunk(rt, session_id, index, chunk.to_vec())
+    }
+
+    fn assert_no_uploads() {
+        assert!(UPLOAD_SESSIONS.with_borrow(|upload_sessions| upload_sessions.is_empty()));
+        assert!(UPLOAD_CHUNKS.with_borrow(|upload_chunks| upload_chunks.is_empty()));
+    }
+
+    #[test]
+    fn hashes_in_pieces() {
+        let data = video();
+        let mut hash = UploadHashState::default();
+        for piece in [&data[..1], &data[1..100], &data[100..]] {
+            hash.update(piece);
+        }
+
+        assert_eq!(
+            hash.finalize(data.len() as u64),
+            <[u8; 32]>::from(Sha256::digest(&data))
+        );
+        assert_eq!(
+            UploadHashState::default().finalize(0),
+            <[u8; 32]>::from(Sha256::digest([]))
+        );
+    }
+
+    #[test]
+    fn uploads_chunks_in_any_order() {
+        let rt = TestRuntime::new();
+        use_fake_processor();
+        sign_in(&rt, 1, "alice");
+        let data = video();
+        let (group_id, meeting_id, session_id) = begin(&rt, &data);
+
+        put(&rt, session_id, 1, &data).unwrap();
+        put(&rt, session_id, 0, &data).unwrap();
+        let status = get_video_upload_status(&rt, session_id).unwrap();
+        assert!(status.missing_chunks.is_empty());
+        assert_eq!(
+            status.chunk_sha256[1],
+            hex::encode(blob::hash(&data[chunk::MB..]))
+        );
+
+        commit_video_upload(&rt, session_id).unwrap();
+        assert_no_uploads();
+
+        run_jobs(&rt);
+        let meeting = meeting::get_meeting(group_id, meeting_id).unwrap();
+        assert_eq!(blob::read(&meeting.full_video.unwrap()), data);
+        assert_eq!(meeting.frames[0].title, "Intro");
+    }
+
+    #[test]
+    fn rejects_invalid_chunks() {
+        let rt = TestRuntime::new();
+        sign_in(&rt, 1, "alice");
+        let data = video();
+        let (_, _, session_id) = begin(&rt, &data);
+
+        assert!(matches!(
+            put_video_upload_chunk(&rt, session_id, 2, vec![0; 3]),
+            Err(ApiError::InvalidInput { .. })
+        ));
+        assert!(matches!(
+            put_video_upload_chunk(&rt, session_id, 1, vec![0; 4]),
+            Err(ApiError::InvalidInput { .. })
+        ));
+        assert!(matches!(
+            put_video_upload_chunk(&rt, session_id, 0, vec![0; 3]),
+            Err(ApiError::InvalidInput { .. })
+        ));
+
+        put(&rt, session_id, 0, &data).unwrap();
+        assert!(matches!(
+            put(&rt, session_id, 0, &data),
+            Err(ApiError::Conflict { .. })
+        ));
+        assert!(matches!(
+            commit_video_upload(&rt, session_id),
+            Err(ApiError::Conflict { .. })
+        ));
+        assert_eq!(
+            get_video_upload_status(&rt, session_id)
+                .unwrap()
+                .missing_chunks,
+            vec![1]
+        );
+
+        sign_in(&rt, 2, "bob");
+        assert!(matches!(
+            put(&rt, session_id, 1, &data),
+            Err(ApiError::Forbidden { .. })
+        ));
+    }
+
+    #[test]
+    fn keeps_the_chunks_when_the_hash_does_not_match() {
+        let rt = TestRuntime::new();
+        use_fake_processor();
+        sign_in(&rt, 1, "alice");
+        let data = video();
+        let (group_id, meeting_id, session_id) = begin(&rt, &data);
+
+        put(&rt, session_id, 0, &data).unwrap();
+        put_video_upload_chunk(&rt, session_id, 1, vec![0; 3]).unwrap();
+        assert!(matches!(
+            commit_video_upload(&rt, session_id),
+            Err(ApiError::Conflict { .. })
+        ));
+
+        let status = get_video_upload_status(&rt, session_id).unwrap();
+        assert!(status.missing_chunks.is_empty());
+        let wrong_chunks = data
+            .chunks(chunk::MB)
+            .zip(status.chunk_sha256)
+            .enumerate()
+            .filter(|(_, (chunk, sha256))| hex::encode(blob::hash(chunk)) != *sha256)
+            .map(|(index, _)| index as u32)
+            .collect::<Vec<_>>();
+        assert_eq!(wrong_chunks, vec![1]);
+
+        discard_video_upload_chunks(&rt, session_id, wrong_chunks).unwrap();
+        put(&rt, session_id, 1, &data).unwrap();
+        commit_video_upload(&rt, session_id).unwrap();
+        assert_no_uploads();
+
+        run_jobs(&rt);
+        let meeting = meeting::get_meeting(group_id, meeting_id).unwrap();
+        assert_eq!(blob::read(&meeting.full_video.unwrap()), data);
+    }
+
+    #[test]
+    fn limits_open_sessions() {
+        let rt = TestRuntime::new();
+        sign_in(&rt, 1, "alice");
+        let session_ids = (0..MAX_UPLOAD_SESSIONS_PER_USER)
+            .map(|_| begin(&rt, &[1]).2)
+            .collect::<Vec<_>>();
+
+        let group_id = group::create_group(&rt, String::from("Team")).unwrap();
+        let meeting_id = meeting::create_meeting(&rt, group_id, String::from("Demo")).unwrap();
+        let request = VideoUploadRequest {
+            title: String::from("Intro"),
+            total_size: 1,
+            sha256: hex::encode(blob::hash(&[1])),
+            with_subtitles: false,
+        };
+        assert!(matches!(
+            begin_video_upload(&rt, group_id, meeting_id, request.clone()),
+            Err(ApiError::Conflict { .. })
+        ));
+
+        abort_video_upload(&rt, session_ids[0]).unwrap();
+        assert!(begin_video_upload(&rt, group_id, meeting_id, request).is_ok());
+    }
+
+    #[test]
+    fn idle_sessions_expire() {
+        let rt = TestRuntime::new();
+        sign_in(&rt, 1, "alice");
+        let data = video();
+        let (_, _, session_id) = begin(&rt, &data);
+        put(&rt, session_id, 0, &data).unwrap();
+
+        rt.advance_time(UPLOAD_SESSION_TIMEOUT);
+        remove_expired_sessions(&rt);
+        assert!(get_video_upload_status(&rt, session_id).is_ok());
+
+        rt.advance_time(Duration::from_secs(1));
+        remove_expired_sessions(&rt);
+        assert!(matches!(
+            get_video_upload_status(&rt, session_id),
+            Err(ApiError::NotFound { .. })
+        ));
+        assert_no_uploads();
+    }
+}
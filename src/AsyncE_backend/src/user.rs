@@ -3,7 +3,17 @@ use std::time::Duration;
 use candid::{CandidType, Principal};
 use serde::Deserialize;
 
-use crate::{chunk, globals::USERS};
+use crate::{
+    blob::{self, BlobId},
+    chunk,
+    error::ApiError,
+    globals::{USERNAMES, USERS},
+    impl_candid_storable,
+    runtime::{IcRuntime, Runtime},
+};
+
+/// Upper bound for profile pictures of users and groups.
+pub const MAX_PROFILE_PICTURE_SIZE: u64 = 4 * 1024 * 1024;
 
 #[derive(Clone, Debug, Default, CandidType, Deserialize)]
 pub struct UserSubscription {
@@ -17,7 +27,7 @@ pub struct User {
     pub username: String,
     pub subscription: Option<UserSubscription>,
     pub created_time_unix: u128,
-    pub profile_picture_blob: Vec<u8>,
+    pub profile_picture: Option<BlobId>,
 }
 
 #[derive(Clone, Debug, Default, CandidType, Deserialize)]
@@ -28,6 +38,8 @@ pub struct UserCredentialsResponse {
     pub created_time_unix: u128,
 }
 
+impl_candid_storable!(User);
+
 impl From<&User> for UserCredentialsResponse {
     fn from(value: &User) -> Self {
         Self {
@@ -39,40 +51,45 @@ impl From<&User> for UserCredentialsResponse {
     }
 }
 
-pub fn assert_user_logged_in() -> Result<(), String> {
-    assert_user_logged_in_from(ic_cdk::caller())
+pub fn assert_user_logged_in(rt: &impl Runtime) -> Result<(), ApiError> {
+    assert_user_logged_in_from(rt.caller())
 }
 
-pub fn assert_user_logged_in_from(principal: Principal) -> Result<(), String> {
+pub fn assert_user_logged_in_from(principal: Principal) -> Result<(), ApiError> {
     if principal == Principal::anonymous() {
-        return Err(String::from("User needs to sign in to proceed!"));
+        return Err(ApiError::NotLoggedIn);
     }
 
     if USERS
-        .with_borrow(|users| users.get(&principal).map(|x| x.username.clone()))
+        .with_borrow(|users| users.get(&principal).map(|x| x.username))
         .is_none()
     {
-        return Err(String::from("User needs to have a username to proceed!"));
+        return Err(ApiError::NoUsername);
     }
 
     Ok(())
 }
 
-#[ic_cdk::query]
-pub fn get_user_credentials() -> Result<Option<UserCredentialsResponse>, String> {
-    let principal = ic_cdk::caller();
+pub fn get_user_credentials(
+    rt: &impl Runtime,
+) -> Result<Option<UserCredentialsResponse>, ApiError> {
+    let principal = rt.caller();
     if principal == Principal::anonymous() {
-        return Err(String::from("User needs to sign in to proceed!"));
+        return Err(ApiError::NotLoggedIn);
     }
 
-    let principal = ic_cdk::caller();
-    Ok(USERS.with_borrow(|users| users.get(&principal).map(UserCredentialsResponse::from)))
+    Ok(USERS.with_borrow(|users| {
+        users
+            .get(&principal)
+            .as_ref()
+            .map(UserCredentialsResponse::from)
+    }))
 }
 
-fn validate_user_register(name: &str, principal: Principal) -> Result<(), String> {
+fn validate_user_register(name: &str, principal: Principal) -> Result<(), ApiError> {
     USERS.with_borrow(|users| {
         if users.contains_key(&principal) {
-            return Err(String::from("User is already registered!"));
+            return Err(ApiError::conflict("User is already registered!"));
         }
 
         Ok(())
@@ -80,186 +97,310 @@ fn validate_user_register(name: &str, principal: Principal) -> Result<(), String
 
     let username = name.trim().to_string();
     if username.len() < 3 || username.len() > 20 {
-        return Err(String::from("Username must between 3 to 20 characters!"));
+        return Err(ApiError::invalid_input(
+            "username",
+            "must be between 3 and 20 characters",
+        ));
     }
 
     if username.chars().any(|x| !x.is_alphanumeric()) {
-        return Err(String::from("Username contains special characters!"));
+        return Err(ApiError::invalid_input(
+            "username",
+            "must not contain special characters",
+        ));
     }
 
-    USERS.with_borrow(|users| {
-        if users
-            .values()
-            .any(|x| x.username.eq_ignore_ascii_case(&username))
-        {
-            return Err(String::from("User is already registered!"));
-        }
-
-        Ok(())
-    })?;
+    if get_principal(&username).is_some() {
+        return Err(ApiError::conflict("Username is already taken!"));
+    }
 
     Ok(())
 }
 
-#[ic_cdk::update]
-pub fn register(name: String) -> Result<(), String> {
+pub fn register(rt: &impl Runtime, name: String) -> Result<(), ApiError> {
     // we don't use `assert_user_logged_in` since that function
     // also checks for `null username` which we definitely have
     // at this moment since it's "registering"
-    let principal = ic_cdk::caller();
+    let principal = rt.caller();
     if principal == Principal::anonymous() {
-        return Err(String::from("User needs to sign in to proceed!"));
+        return Err(ApiError::NotLoggedIn);
     }
 
     validate_user_register(&name, principal)?;
 
     let user = User {
         balance: 10,
-        username: name,
+        username: name.clone(),
         subscription: None,
-        created_time_unix: ic_cdk::api::time() as u128,
-        profile_picture_blob: Vec::new(),
+        created_time_unix: rt.time(),
+        profile_picture: None,
     };
     USERS.with_borrow_mut(|users| users.insert(principal, user));
+    USERNAMES.with_borrow_mut(|usernames| usernames.insert(name.to_ascii_lowercase(), principal));
 
     Ok(())
 }
 
-pub fn get_selfuser() -> Result<Option<User>, String> {
-    assert_user_logged_in()?;
+/// Usernames are unique regardless of ASCII case.
+pub fn get_principal(username: &str) -> Option<Principal> {
+    USERNAMES.with_borrow(|usernames| usernames.get(&username.to_ascii_lowercase()))
+}
 
-    let principal = ic_cdk::caller();
+pub fn get_selfuser(rt: &impl Runtime) -> Result<Option<User>, ApiError> {
+    assert_user_logged_in(rt)?;
 
-    Ok(USERS.with_borrow(|users| users.get(&principal).cloned()))
+    let principal = rt.caller();
+
+    Ok(USERS.with_borrow(|users| users.get(&principal)))
 }
 
-pub fn get_selfname() -> Result<Option<String>, String> {
-    assert_user_logged_in()?;
+pub fn get_selfname(rt: &impl Runtime) -> Result<Option<String>, ApiError> {
+    assert_user_logged_in(rt)?;
 
-    let principal = ic_cdk::caller();
+    let principal = rt.caller();
 
-    Ok(USERS.with_borrow(|users| users.get(&principal).map(|x| x.username.clone())))
+    Ok(USERS.with_borrow(|users| users.get(&principal).map(|x| x.username)))
 }
 
-pub fn get_selfname_force() -> Result<String, String> {
-    get_selfname()?.ok_or(String::from("This user does not have a username!"))
+pub fn get_selfname_force(rt: &impl Runtime) -> Result<String, ApiError> {
+    get_selfname(rt)?.ok_or(ApiError::NoUsername)
 }
 
-#[ic_cdk::query]
-pub fn validate_username(name: String) -> Result<bool, String> {
-    assert_user_logged_in()?;
+pub fn validate_username(rt: &impl Runtime, name: String) -> Result<bool, ApiError> {
+    assert_user_logged_in(rt)?;
 
-    Ok(USERS.with_borrow(|users| {
-        users
-            .values()
-            .any(|x| x.username.eq_ignore_ascii_case(&name))
-    }))
+    Ok(get_principal(&name).is_some())
 }
 
-#[ic_cdk::update]
 pub fn upload_profile_picture(
+    rt: &impl Runtime,
     chunk_data: Vec<u8>,
     chunk_index: u128,
     total_data_length: u128,
-) -> Result<(), String> {
-    assert_user_logged_in()?;
+) -> Result<(), ApiError> {
+    assert_user_logged_in(rt)?;
 
-    let principal = ic_cdk::caller();
+    let principal = rt.caller();
     USERS.with_borrow_mut(|users| {
-        let user = users
-            .get_mut(&principal)
-            .ok_or(String::from("Cannot find user with this principal!"))?;
-
-        if user.profile_picture_blob.capacity() != total_data_length as usize {
-            user.profile_picture_blob
-                .reserve_exact(total_data_length as usize);
+        let mut user = users
+            .get(&principal)
+            .ok_or(ApiError::not_found("user", principal))?;
+
+        let key = format!("profile_picture/{}", principal);
+        if let Some(data) = chunk::buffer_upload_chunk(
+            rt,
+            key,
+            chunk_data,
+            chunk_index,
+            total_data_length,
+            MAX_PROFILE_PICTURE_SIZE,
+        )? {
+            blob::replace(&mut user.profile_picture, blob::store(&data));
+            users.insert(principal, user);
         }
 
-        let offset = chunk_index as usize * chunk::MB;
-        user.profile_picture_blob.splice(offset..offset, chunk_data);
-
         Ok(())
     })
 }
 
-#[ic_cdk::query]
-pub fn get_profile_picture_size() -> Result<u128, String> {
-    assert_user_logged_in()?;
+pub fn get_profile_picture_size(rt: &impl Runtime) -> Result<u128, ApiError> {
+    assert_user_logged_in(rt)?;
 
-    let principal = ic_cdk::caller();
+    let principal = rt.caller();
     USERS.with_borrow(|users| {
         users
             .get(&principal)
-            .map(|x| x.profile_picture_blob.len() as u128)
-            .ok_or(String::from("Cannot find user with this principal!"))
+            .map(|x| {
+                x.profile_picture
+                    .as_ref()
+                    .map(blob::get_size)
+                    .unwrap_or_default()
+            })
+            .ok_or(ApiError::not_found("user", principal))
     })
 }
 
-#[ic_cdk::query]
-pub fn get_profile_picture_chunk_blob(index: u128) -> Result<Vec<u8>, String> {
-    assert_user_logged_in()?;
+pub fn get_profile_picture_chunk_blob(rt: &impl Runtime, index: u128) -> Result<Vec<u8>, ApiError> {
+    assert_user_logged_in(rt)?;
 
-    let principal = ic_cdk::caller();
+    let principal = rt.caller();
     USERS.with_borrow(|users| {
         users
             .get(&principal)
             .map(|x| {
-                x.profile_picture_blob
-                    .iter()
-                    .skip(index as usize * chunk::MB)
-                    .take(chunk::MB)
-                    .cloned()
-                    .collect()
+                x.profile_picture
+                    .map(|id| blob::get_chunk(&id, index))
+                    .unwrap_or_default()
             })
-            .ok_or(String::from("Cannot find user with this principal!"))
+            .ok_or(ApiError::not_found("user", principal))
     })
 }
 
-#[ic_cdk::update]
-pub fn buy_subscription() -> Result<(), String> {
-    assert_user_logged_in()?;
+pub fn buy_subscription(rt: &impl Runtime) -> Result<(), ApiError> {
+    assert_user_logged_in(rt)?;
 
-    let principal = ic_cdk::caller();
+    let principal = rt.caller();
     USERS.with_borrow_mut(|users| {
-        let user = users
-            .get_mut(&principal)
-            .ok_or(String::from("Cannot find current user!"))?;
+        let mut user = users
+            .get(&principal)
+            .ok_or(ApiError::not_found("user", principal))?;
         if user.balance < 5 {
-            return Err(String::from("Balance is not sufficient!"));
+            return Err(ApiError::InsufficientBalance);
         }
 
         if let Some(subscription) = user.subscription.as_mut() {
             subscription.duration_in_days += 30;
         } else {
             user.subscription = Some(UserSubscription {
-                time_started: ic_cdk::api::time() as u128,
+                time_started: rt.time(),
                 duration_in_days: 30,
             });
         }
         user.balance -= 5;
+        users.insert(principal, user);
 
         Ok(())
     })
 }
 
-pub fn poll_user_subscriptions() {
-    ic_cdk::println!("Starting poll user subscriptions");
-    ic_cdk_timers::set_timer_interval(Duration::from_secs(1), || {
-        USERS.with_borrow_mut(|users| {
-            users.values_mut().for_each(|user| {
-                if let Some(subscription) = user.subscription.as_mut() {
+/// Ends every subscription that ran out of days.
+pub fn expire_subscriptions(rt: &impl Runtime) {
+    let now = rt.time();
+    USERS.with_borrow_mut(|users| {
+        let expired_users = users
+            .iter()
+            .filter(|(_, user)| {
+                user.subscription.as_ref().is_some_and(|subscription| {
                     let duration =
                         Duration::from_secs(subscription.duration_in_days as u64 * 60 * 60 * 24);
 
-                    let time_passed = Duration::from_nanos(
-                        ic_cdk::api::time() - subscription.time_started as u64,
-                    );
+                    let time_passed =
+                        Duration::from_nanos(now.saturating_sub(subscription.time_started) as u64);
 
-                    if time_passed > duration {
-                        user.subscription = None;
-                    }
-                }
+                    time_passed > duration
+                })
             })
-        })
-    });
+            .collect::<Vec<_>>();
+
+        for (principal, mut user) in expired_users {
+            user.subscription = None;
+            users.insert(principal, user);
+        }
+    })
+}
+
+pub fn poll_user_subscriptions() {
+    ic_cdk::println!("Starting poll user subscriptions");
+    ic_cdk_timers::set_timer_interval(Duration::from_secs(1), || expire_subscriptions(&IcRuntime));
+}
+
+mod endpoints {
+    use super::{ApiError, UserCredentialsResponse};
+    use crate::runtime::IcRuntime;
+
+    #[ic_cdk::query]
+    fn get_user_credentials() -> Result<Option<UserCredentialsResponse>, ApiError> {
+        super::get_user_credentials(&IcRuntime)
+    }
+
+    #[ic_cdk::update]
+    fn register(name: String) -> Result<(), ApiError> {
+        super::register(&IcRuntime, name)
+    }
+
+    #[ic_cdk::query]
+    fn validate_username(name: String) -> Result<bool, ApiError> {
+        super::validate_username(&IcRuntime, name)
+    }
+
+    #[ic_cdk::update]
+    fn upload_profile_picture(
+        chunk_data: Vec<u8>,
+        chunk_index: u128,
+        total_data_length: u128,
+    ) -> Result<(), ApiError> {
+        super::upload_profile_picture(&IcRuntime, chunk_data, chunk_index, total_data_length)
+    }
+
+    #[ic_cdk::query]
+    fn get_profile_picture_size() -> Result<u128, ApiError> {
+        super::get_profile_picture_size(&IcRuntime)
+    }
+
+    #[ic_cdk::query]
+    fn get_profile_picture_chunk_blob(index: u128) -> Result<Vec<u8>, ApiError> {
+        super::get_profile_picture_chunk_blob(&IcRuntime, index)
+    }
+
+    #[ic_cdk::update]
+    fn buy_subscription() -> Result<(), ApiError> {
+        super::buy_subscription(&IcRuntime)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::runtime::TestRuntime;
+
+    /// Registers `name` under a principal derived from `id` and makes it the caller.
+    pub(crate) fn sign_in(rt: &TestRuntime, id: u8, name: &str) -> Principal {
+        let principal = Principal::from_slice(&[id; 29]);
+        rt.set_caller(principal);
+        register(rt, name.to_string()).unwrap();
+        principal
+    }
+
+    #[test]
+    fn validates_registration() {
+        let rt = TestRuntime::new();
+        assert_eq!(
+            register(&rt, String::from("alice")),
+            Err(ApiError::NotLoggedIn)
+        );
+
+        rt.set_caller(Principal::from_slice(&[1; 29]));
+        assert!(matches!(
+            register(&rt, String::from("al")),
+            Err(ApiError::InvalidInput { field, .. }) if field == "username"
+        ));
+        assert!(matches!(
+            register(&rt, String::from("al ice")),
+            Err(ApiError::InvalidInput { .. })
+        ));
+        register(&rt, String::from("alice")).unwrap();
+        assert!(matches!(
+            register(&rt, String::from("alice2")),
+            Err(ApiError::Conflict { .. })
+        ));
+
+        rt.set_caller(Principal::from_slice(&[2; 29]));
+        assert!(matches!(
+            register(&rt, String::from("ALICE")),
+            Err(ApiError::Conflict { .. })
+        ));
+        assert_eq!(get_selfuser(&rt).unwrap_err(), ApiError::NoUsername);
+    }
+
+    #[test]
+    fn subscriptions_cost_coins_and_expire() {
+        let rt = TestRuntime::new();
+        sign_in(&rt, 1, "alice");
+
+        buy_subscription(&rt).unwrap();
+        buy_subscription(&rt).unwrap();
+        assert_eq!(buy_subscription(&rt), Err(ApiError::InsufficientBalance));
+
+        let user = get_selfuser(&rt).unwrap().unwrap();
+        assert_eq!(user.balance, 0);
+        assert_eq!(user.subscription.unwrap().duration_in_days, 60);
+
+        rt.advance_time(Duration::from_secs(59 * 24 * 60 * 60));
+        expire_subscriptions(&rt);
+        assert!(get_selfuser(&rt).unwrap().unwrap().subscription.is_some());
+
+        rt.advance_time(Duration::from_secs(2 * 24 * 60 * 60));
+        expire_subscriptions(&rt);
+        assert!(get_selfuser(&rt).unwrap().unwrap().subscription.is_none());
+    }
 }
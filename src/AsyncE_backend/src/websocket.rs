@@ -8,11 +8,21 @@ use ic_websocket_cdk::{
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    chat::Chat,
+    attachment,
+    chat::{self, Chat},
+    error::ApiError,
+    event,
     globals::{CHATS, GROUPS, USERS, WEBSOCKET_CLIENTS},
     group::Group,
     invite::GroupInviteResponse,
+    job::Job,
+    notification::Notification,
+    presence,
     primary_key::{self, PrimaryKeyType},
+    reaction::{Reaction, ReactionTarget},
+    receipt,
+    runtime::{IcRuntime, Runtime},
+    search::{self, SearchDoc},
     user,
 };
 
@@ -21,6 +31,18 @@ pub enum WebsocketEventMessage {
     Ping,
     GroupInvited(GroupInviteResponse),
     AddChat(Chat),
+    ReactionChanged {
+        group_id: u128,
+        target: ReactionTarget,
+        reactions: Vec<Reaction>,
+    },
+    /// Sent next to the `AddChat` of every reply, so clients can update the parent
+    ThreadReply {
+        group_id: u128,
+        parent_id: u128,
+        chat_id: u128,
+        reply_count: u64,
+    },
     NewVideoPart {
         group_id: u128,
         meeting_id: u128,
@@ -31,15 +53,58 @@ pub enum WebsocketEventMessage {
         group_id: u128,
         new_content: String,
     },
+    /// The chat is a tombstone now
     DeleteChat {
         chat_id: u128,
         group_id: u128,
     },
+    /// The chat is gone for good
+    PurgeChat {
+        chat_id: u128,
+        group_id: u128,
+    },
     Thumbnail {
         group_id: u128,
         meeting_id: u128,
         frame_index: u128,
     },
+    ProcessingFailed {
+        job_id: u128,
+        group_id: u128,
+        meeting_id: u128,
+        error: String,
+    },
+    /// Sent by clients that want an `Ack` or an `Error` back for the wrapped message
+    Request {
+        request_id: String,
+        message: Box<WebsocketEventMessage>,
+    },
+    Ack {
+        request_id: String,
+    },
+    /// Sent back to a client whose message was rejected
+    Error {
+        request_id: Option<String>,
+        code: ApiError,
+        message: String,
+    },
+    /// A logged event, clients acknowledge it with `AckEvents`
+    Event {
+        seq: u64,
+        message: Box<WebsocketEventMessage>,
+    },
+    /// Sent by clients once every event up to and including `seq` was handled
+    AckEvents {
+        seq: u64,
+    },
+    /// A new item in the notification inbox of the user
+    Notification(Notification),
+    /// Sent by clients while their user types in the group, forwarded to the other
+    /// connected members and never logged
+    Typing {
+        group_id: u128,
+        username: String,
+    },
 }
 
 impl WebsocketEventMessage {
@@ -72,93 +137,213 @@ fn ws_get_messages(args: CanisterWsGetMessagesArguments) -> CanisterWsGetMessage
 }
 
 pub fn on_open(args: OnOpenCallbackArgs) {
-    send_websocket_message(args.client_principal, WebsocketEventMessage::Ping);
+    handle_open(&IcRuntime, args.client_principal)
+}
+
+pub(crate) fn handle_open(rt: &impl Runtime, client_principal: ClientPrincipal) {
+    send_websocket_message(rt, client_principal, WebsocketEventMessage::Ping);
 
     WEBSOCKET_CLIENTS
-        .with_borrow_mut(|websocket_clients| websocket_clients.insert(args.client_principal));
+        .with_borrow_mut(|websocket_clients| websocket_clients.insert(client_principal));
+    presence::touch(rt, client_principal);
+
+    // whatever the client missed while it was offline, the rest follows once it acknowledges
+    for event in event::start_replay(client_principal) {
+        send_websocket_message(rt, client_principal, event.into());
+    }
+}
+
+fn ack_events(
+    rt: &impl Runtime,
+    client_principal: ClientPrincipal,
+    seq: u64,
+) -> Result<(), ApiError> {
+    event::ack(client_principal, seq)?;
+
+    for event in event::continue_replay(client_principal, seq) {
+        send_websocket_message(rt, client_principal, event.into());
+    }
+
+    Ok(())
 }
 
 pub fn on_message(args: OnMessageCallbackArgs) {
+    handle_message(&IcRuntime, args)
+}
+
+fn handle_message(rt: &impl Runtime, args: OnMessageCallbackArgs) {
     ic_cdk::println!(
         "Received candid message: {} {:?}",
         args.client_principal,
         args.message
     );
 
-    let app_msg: WebsocketEventMessage = candid::decode_one(&args.message)
-        .expect("Cannot decode message data to candid predefined type!");
+    let (request_id, result) = match candid::decode_one(&args.message) {
+        Ok(WebsocketEventMessage::Request {
+            request_id,
+            message,
+        }) => (
+            Some(request_id),
+            handle_event(rt, args.client_principal, *message),
+        ),
+        Ok(app_msg) => (None, handle_event(rt, args.client_principal, app_msg)),
+        Err(e) => (
+            None,
+            Err(ApiError::invalid_input("message", &e.to_string())),
+        ),
+    };
+
+    let reply = match result {
+        Ok(()) => match request_id {
+            Some(request_id) => WebsocketEventMessage::Ack { request_id },
+            None => return,
+        },
+        Err(err) => {
+            ic_cdk::println!("Rejected message from {}: {}", args.client_principal, err);
 
+            WebsocketEventMessage::Error {
+                request_id,
+                message: err.to_string(),
+                code: err,
+            }
+        }
+    };
+
+    send_websocket_message(rt, args.client_principal, reply);
+}
+
+fn handle_event(
+    rt: &impl Runtime,
+    client_principal: ClientPrincipal,
+    app_msg: WebsocketEventMessage,
+) -> Result<(), ApiError> {
     ic_cdk::println!("Received message: {:?}", app_msg);
 
-    user::assert_user_logged_in_from(args.client_principal)
-        .expect("Current user is not logged in yet!");
+    user::assert_user_logged_in_from(client_principal)?;
+    presence::touch(rt, client_principal);
 
     match app_msg {
-        WebsocketEventMessage::Ping => {}
-
-        WebsocketEventMessage::GroupInvited { .. }
-        | WebsocketEventMessage::NewVideoPart { .. }
-        | WebsocketEventMessage::DeleteChat { .. }
-        | WebsocketEventMessage::EditChat { .. }
-        | WebsocketEventMessage::Thumbnail { .. } => {}
-
-        WebsocketEventMessage::AddChat(mut chat) => {
-            let name = USERS
-                .with_borrow(|users| {
-                    users
-                        .get(&args.client_principal)
-                        .map(|x| x.username.clone())
-                })
-                .expect("Cannot find current username!");
-
-            GROUPS.with_borrow(|groups| {
-                let group = groups
-                    .get(&chat.group_id)
-                    .expect("Cannot find group with this ID!");
-                if !group.is_member(&name) {
-                    panic!("This user is not in this group!")
-                }
+        WebsocketEventMessage::Ping => Ok(()),
+        WebsocketEventMessage::AddChat(chat) => add_chat(rt, client_principal, chat),
+        WebsocketEventMessage::AckEvents { seq } => ack_events(rt, client_principal, seq),
+        WebsocketEventMessage::Typing { group_id, .. } => {
+            broadcast_typing(rt, client_principal, group_id)
+        }
+        _ => Err(ApiError::invalid_input(
+            "message",
+            "only Ping, AddChat, AckEvents and Typing can be sent by clients",
+        )),
+    }
+}
 
-                chat.id = primary_key::get_primary_key(PrimaryKeyType::Chat);
-                chat.username = name;
-                chat.created_time_unix = ic_cdk::api::time() as u128;
+/// Returns the username of the client and the group, as long as the client is a member.
+fn get_member_group(
+    client_principal: ClientPrincipal,
+    group_id: u128,
+) -> Result<(String, Group), ApiError> {
+    let name = USERS
+        .with_borrow(|users| users.get(&client_principal).map(|x| x.username))
+        .ok_or(ApiError::NoUsername)?;
 
-                CHATS.with_borrow_mut(|chats| {
-                    chats
-                        .entry(chat.group_id)
-                        .or_default()
-                        .insert(chat.id, chat.clone());
+    let group = GROUPS
+        .with_borrow(|groups| groups.get(&group_id))
+        .ok_or(ApiError::not_found("group", group_id))?;
+    if !group.is_member(&name) {
+        return Err(ApiError::forbidden("This user is not in this group!"));
+    }
+
+    Ok((name, group))
+}
+
+fn add_chat(
+    rt: &impl Runtime,
+    client_principal: ClientPrincipal,
+    mut chat: Chat,
+) -> Result<(), ApiError> {
+    let (name, group) = get_member_group(client_principal, chat.group_id)?;
+    if let Some(parent_id) = chat.reply_to {
+        chat::validate_reply(chat.group_id, parent_id)?;
+    }
+
+    chat.id = primary_key::get_primary_key(PrimaryKeyType::Chat);
+    chat.created_time_unix = rt.time();
+    chat.reply_count = None;
+    chat.reactions = None;
+    chat.mentions = None;
+    chat.edited_time_unix = None;
+    chat.deleted_time_unix = None;
+    let attachments = attachment::take_staged(client_principal, &chat)?;
+    chat.attachments = (!attachments.is_empty()).then_some(attachments);
+    chat::update_mentions(rt, &group, &mut chat, &name);
+    chat.username = name;
+
+    CHATS.with_borrow_mut(|chats| chats.insert((chat.group_id, chat.id), chat.clone()));
+    // whatever the sender missed, they are caught up by now
+    receipt::mark_chat_read(chat.group_id, client_principal, chat.id);
+    search::index(
+        chat.group_id,
+        SearchDoc::Chat { chat_id: chat.id },
+        &chat.content,
+    );
+    let thread_reply = chat
+        .reply_to
+        .map(|parent_id| WebsocketEventMessage::ThreadReply {
+            group_id: chat.group_id,
+            parent_id,
+            chat_id: chat.id,
+            reply_count: chat::link_reply(&chat, parent_id),
+        });
 
-                    broadcast_chat(group, chat);
-                })
-            });
+    broadcast_chat(rt, &group, chat);
+    if let Some(thread_reply) = thread_reply {
+        broadcast_group_message(rt, &group, thread_reply);
+    }
+
+    Ok(())
+}
+
+/// Typing indicators are only useful right now, so they skip the event log.
+fn broadcast_typing(
+    rt: &impl Runtime,
+    client_principal: ClientPrincipal,
+    group_id: u128,
+) -> Result<(), ApiError> {
+    let (username, group) = get_member_group(client_principal, group_id)?;
+
+    let msg = WebsocketEventMessage::Typing { group_id, username };
+    for principal in group.member_principals() {
+        if principal != client_principal {
+            send_websocket_message(rt, principal, msg.clone());
         }
     }
+
+    Ok(())
+}
+
+/// Sends the message to every member of the group, and to nobody else.
+pub fn broadcast_group_message(rt: &impl Runtime, group: &Group, msg: WebsocketEventMessage) {
+    for principal in group.member_principals() {
+        send_user_event(rt, principal, msg.clone());
+    }
 }
 
-pub fn broadcast_chat(group: &Group, chat: Chat) {
-    for group_member in group.members.iter() {
-        USERS.with_borrow(|users| {
-            if let Some(principal) = users
-                .iter()
-                .find(|x| x.1.username.eq_ignore_ascii_case(&group_member.username))
-                .map(|x| x.0)
-            {
-                send_websocket_message(*principal, WebsocketEventMessage::AddChat(chat.clone()));
-            }
-        })
+pub fn send_user_message(rt: &impl Runtime, username: &str, msg: WebsocketEventMessage) {
+    if let Some(principal) = user::get_principal(username) {
+        send_user_event(rt, principal, msg);
     }
 }
 
-pub fn broadcast_websocket_message(msg: WebsocketEventMessage) {
-    WEBSOCKET_CLIENTS.with_borrow(|websocket_clients| {
-        for &client_principal in websocket_clients.iter() {
-            send_websocket_message(client_principal, msg.clone());
-        }
-    })
+/// Logs the message for the user and sends it right away when the user is connected.
+pub fn send_user_event(rt: &impl Runtime, principal: Principal, msg: WebsocketEventMessage) {
+    let event = event::record(rt, principal, msg);
+    send_websocket_message(rt, principal, event.into());
 }
 
-pub fn send_websocket_message(client_principal: ClientPrincipal, msg: WebsocketEventMessage) {
+pub fn send_websocket_message(
+    rt: &impl Runtime,
+    client_principal: ClientPrincipal,
+    msg: WebsocketEventMessage,
+) {
     if !WEBSOCKET_CLIENTS
         .with_borrow(|websocket_clients| websocket_clients.contains(&client_principal))
     {
@@ -167,7 +352,7 @@ pub fn send_websocket_message(client_principal: ClientPrincipal, msg: WebsocketE
 
     ic_cdk::println!("Sending message to {}: {:?}", client_principal, msg);
 
-    if let Err(e) = ic_websocket_cdk::send(client_principal, msg.candid_serialize()) {
+    if let Err(e) = rt.send_websocket(client_principal, msg.candid_serialize()) {
         ic_cdk::println!(
             "Could not send message to {} with payload: {:?}: {}",
             client_principal,
@@ -178,14 +363,30 @@ pub fn send_websocket_message(client_principal: ClientPrincipal, msg: WebsocketE
 }
 
 pub fn on_close(args: OnCloseCallbackArgs) {
-    ic_cdk::println!("Client {} disconnected", args.client_principal);
+    handle_close(&IcRuntime, args.client_principal)
+}
+
+pub(crate) fn handle_close(rt: &impl Runtime, client_principal: ClientPrincipal) {
+    ic_cdk::println!("Client {} disconnected", client_principal);
 
     WEBSOCKET_CLIENTS
-        .with_borrow_mut(|websocket_clients| websocket_clients.remove(&args.client_principal));
+        .with_borrow_mut(|websocket_clients| websocket_clients.remove(&client_principal));
+    event::stop_replay(client_principal);
+    presence::touch(rt, client_principal);
+}
+
+pub fn broadcast_chat(rt: &impl Runtime, group: &Group, chat: Chat) {
+    broadcast_group_message(rt, group, WebsocketEventMessage::AddChat(chat));
 }
 
-pub fn send_group_invited_notif(principal: Principal, group_id: u128, group_name: &str) {
-    send_websocket_message(
+pub fn send_group_invited_notif(
+    rt: &impl Runtime,
+    principal: Principal,
+    group_id: u128,
+    group_name: &str,
+) {
+    send_user_event(
+        rt,
         principal,
         WebsocketEventMessage::GroupInvited(GroupInviteResponse {
             group_id,
@@ -194,43 +395,659 @@ pub fn send_group_invited_notif(principal: Principal, group_id: u128, group_name
     );
 }
 
-pub fn broadcast_new_video_part(group_id: u128, meeting_id: u128, created_by: String) {
-    broadcast_websocket_message(WebsocketEventMessage::NewVideoPart {
-        group_id,
-        meeting_id,
-        created_by,
-    })
-}
-
-pub fn broadcast_edit_chat(group_id: u128, chat_id: u128, new_content: String) {
-    broadcast_websocket_message(WebsocketEventMessage::EditChat {
-        chat_id,
-        group_id,
-        new_content,
-    });
-}
-
-pub fn broadcast_delete_chat(group_id: u128, chat_id: u128) {
-    broadcast_websocket_message(WebsocketEventMessage::DeleteChat { chat_id, group_id });
-}
-
-pub fn broadcast_thumbnail(group: &Group, meeting_id: u128, frame_index: usize) {
-    for group_member in group.members.iter() {
-        USERS.with_borrow(|users| {
-            if let Some(principal) = users
-                .iter()
-                .find(|x| x.1.username.eq_ignore_ascii_case(&group_member.username))
-                .map(|x| x.0)
-            {
-                send_websocket_message(
-                    *principal,
-                    WebsocketEventMessage::Thumbnail {
-                        group_id: group.id,
-                        meeting_id,
-                        frame_index: frame_index as u128,
-                    },
-                );
-            }
+pub fn broadcast_new_video_part(
+    rt: &impl Runtime,
+    group: &Group,
+    meeting_id: u128,
+    created_by: String,
+) {
+    broadcast_group_message(
+        rt,
+        group,
+        WebsocketEventMessage::NewVideoPart {
+            group_id: group.id,
+            meeting_id,
+            created_by,
+        },
+    )
+}
+
+pub fn broadcast_edit_chat(rt: &impl Runtime, group: &Group, chat_id: u128, new_content: String) {
+    broadcast_group_message(
+        rt,
+        group,
+        WebsocketEventMessage::EditChat {
+            chat_id,
+            group_id: group.id,
+            new_content,
+        },
+    );
+}
+
+pub fn broadcast_delete_chat(rt: &impl Runtime, group: &Group, chat_id: u128) {
+    broadcast_group_message(
+        rt,
+        group,
+        WebsocketEventMessage::DeleteChat {
+            chat_id,
+            group_id: group.id,
+        },
+    );
+}
+
+pub fn broadcast_purge_chat(rt: &impl Runtime, group: &Group, chat_id: u128) {
+    broadcast_group_message(
+        rt,
+        group,
+        WebsocketEventMessage::PurgeChat {
+            chat_id,
+            group_id: group.id,
+        },
+    );
+}
+
+pub fn broadcast_thumbnail(rt: &impl Runtime, group: &Group, meeting_id: u128, frame_index: usize) {
+    broadcast_group_message(
+        rt,
+        group,
+        WebsocketEventMessage::Thumbnail {
+            group_id: group.id,
+            meeting_id,
+            frame_index: frame_index as u128,
+        },
+    );
+}
+
+pub fn broadcast_reaction_changed(
+    rt: &impl Runtime,
+    group: &Group,
+    target: ReactionTarget,
+    reactions: Vec<Reaction>,
+) {
+    broadcast_group_message(
+        rt,
+        group,
+        WebsocketEventMessage::ReactionChanged {
+            group_id: group.id,
+            target,
+            reactions,
+        },
+    );
+}
+
+pub fn send_processing_failed_notif(rt: &impl Runtime, job: &Job) {
+    send_user_message(
+        rt,
+        &job.requested_by,
+        WebsocketEventMessage::ProcessingFailed {
+            job_id: job.id,
+            group_id: job.group_id,
+            meeting_id: job.meeting_id,
+            error: job.last_error.clone().unwrap_or_default(),
+        },
+    );
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::{
+        attachment::{AttachedFile, AttachmentUpload, ChatAttachment},
+        chat,
+        globals::STAGED_ATTACHMENTS,
+        group::{self, GroupMember, GroupMemberRole},
+        notification::{self, NotificationKind},
+        runtime::TestRuntime,
+        user::{self, tests::sign_in},
+    };
+
+    struct Members {
+        alice: Principal,
+        bob: Principal,
+        eve: Principal,
+        group: Group,
+    }
+
+    /// Alice and Bob share a group, Eve is connected as well but is not a member.
+    fn setup(rt: &TestRuntime) -> Members {
+        let bob = sign_in(rt, 2, "bob");
+        let eve = sign_in(rt, 3, "eve");
+        let alice = sign_in(rt, 1, "alice");
+        let group_id = group::create_group(rt, String::from("Team")).unwrap();
+        let group = GROUPS.with_borrow_mut(|groups| {
+            let mut group = groups.get(&group_id).unwrap();
+            group
+                .members
+                .push(GroupMember::new("bob", GroupMemberRole::Member));
+            groups.insert(group_id, group.clone());
+            group
+        });
+
+        for principal in [alice, bob, eve] {
+            handle_open(rt, principal);
+        }
+        rt.take_websocket_messages();
+
+        Members {
+            alice,
+            bob,
+            eve,
+            group,
+        }
+    }
+
+    fn new_chat(group_id: u128) -> Chat {
+        Chat {
+            id: 0,
+            uuid: String::from("uuid"),
+            content: String::from("Hello!"),
+            group_id,
+            ..Default::default()
+        }
+    }
+
+    fn add_chat_message(group_id: u128) -> WebsocketEventMessage {
+        WebsocketEventMessage::AddChat(new_chat(group_id))
+    }
+
+    fn reply_message(group_id: u128, parent_id: u128) -> WebsocketEventMessage {
+        WebsocketEventMessage::AddChat(Chat {
+            reply_to: Some(parent_id),
+            ..new_chat(group_id)
         })
     }
+
+    pub fn send_from(rt: &TestRuntime, client_principal: Principal, msg: WebsocketEventMessage) {
+        handle_message(
+            rt,
+            OnMessageCallbackArgs {
+                client_principal,
+                message: candid::encode_one(msg).unwrap(),
+            },
+        );
+    }
+
+    fn request(request_id: &str, msg: WebsocketEventMessage) -> WebsocketEventMessage {
+        WebsocketEventMessage::Request {
+            request_id: request_id.to_string(),
+            message: Box::new(msg),
+        }
+    }
+
+    fn sent_messages(rt: &TestRuntime) -> Vec<(Principal, WebsocketEventMessage)> {
+        rt.take_websocket_messages()
+            .into_iter()
+            .map(|(principal, bytes)| {
+                let msg = match candid::decode_one(&bytes).unwrap() {
+                    WebsocketEventMessage::Event { message, .. } => *message,
+                    msg => msg,
+                };
+                (principal, msg)
+            })
+            .collect()
+    }
+
+    fn recipients(rt: &TestRuntime) -> Vec<Principal> {
+        rt.take_websocket_messages()
+            .into_iter()
+            .map(|(principal, _)| principal)
+            .collect()
+    }
+
+    #[test]
+    fn group_events_only_reach_members() {
+        let rt = TestRuntime::new();
+        let members = setup(&rt);
+        let expected = vec![members.alice, members.bob];
+
+        send_from(&rt, members.bob, add_chat_message(members.group.id));
+        assert_eq!(recipients(&rt), expected);
+
+        rt.set_caller(members.alice);
+        user::buy_subscription(&rt).unwrap();
+        let chat_id = chat::get_chats(&rt, members.group.id).unwrap()[0].id;
+        chat::edit_chat(&rt, members.group.id, chat_id, String::from("Hi!")).unwrap();
+        assert_eq!(recipients(&rt), expected);
+        chat::delete_chat(&rt, members.group.id, chat_id).unwrap();
+        assert_eq!(recipients(&rt), expected);
+
+        broadcast_new_video_part(&rt, &members.group, 1, String::from("alice"));
+        assert_eq!(recipients(&rt), expected);
+        broadcast_thumbnail(&rt, &members.group, 1, 0);
+        assert_eq!(recipients(&rt), expected);
+
+        // Eve is connected, just not a member
+        send_user_message(&rt, "eve", WebsocketEventMessage::Ping);
+        assert_eq!(recipients(&rt), vec![members.eve]);
+    }
+
+    fn mentions_of(rt: &TestRuntime, principal: Principal) -> Vec<NotificationKind> {
+        rt.set_caller(principal);
+        notification::get_notifications(rt, None, 10)
+            .unwrap()
+            .notifications
+            .into_iter()
+            .map(|notification| notification.kind)
+            .collect()
+    }
+
+    #[test]
+    fn mentions_notify_the_addressed_members() {
+        let rt = TestRuntime::new();
+        let members = setup(&rt);
+        let group_id = members.group.id;
+
+        // Eve is not a member and the email address is no mention
+        let chat = Chat {
+            content: String::from("Hi @Alice and @eve, write to bob@alice.com @alice"),
+            ..new_chat(group_id)
+        };
+        send_from(&rt, members.bob, WebsocketEventMessage::AddChat(chat));
+        let chat = CHATS.with_borrow(|chats| chats.iter().next().unwrap().1);
+        assert_eq!(chat.mentions, Some(vec![String::from("alice")]));
+        let mention = NotificationKind::Mention {
+            group_id,
+            chat_id: chat.id,
+            mentioned_by: String::from("bob"),
+        };
+        assert_eq!(mentions_of(&rt, members.alice), vec![mention]);
+        assert!(mentions_of(&rt, members.eve).is_empty());
+
+        // only the newly mentioned hear about an edit, never the editor
+        rt.set_caller(members.alice);
+        user::buy_subscription(&rt).unwrap();
+        let content = String::from("@alice @bob");
+        chat::edit_chat(&rt, group_id, chat.id, content).unwrap();
+        let mention = NotificationKind::Mention {
+            group_id,
+            chat_id: chat.id,
+            mentioned_by: String::from("alice"),
+        };
+        assert_eq!(mentions_of(&rt, members.bob), vec![mention]);
+        assert_eq!(mentions_of(&rt, members.alice).len(), 1);
+
+        // mentions go away with the chat, whether it is deleted or purged right away
+        rt.set_caller(members.alice);
+        chat::delete_chat(&rt, group_id, chat.id).unwrap();
+        assert!(mentions_of(&rt, members.bob).is_empty());
+        assert_eq!(notification::get_unread_notification_count(&rt).unwrap(), 0);
+
+        let chat = Chat {
+            content: String::from("@bob"),
+            ..new_chat(group_id)
+        };
+        send_from(&rt, members.alice, WebsocketEventMessage::AddChat(chat));
+        assert_eq!(mentions_of(&rt, members.bob).len(), 1);
+        rt.set_caller(members.alice);
+        let chat_id = chat::get_chats(&rt, group_id).unwrap().last().unwrap().id;
+        chat::purge_chat(&rt, group_id, chat_id).unwrap();
+        assert!(mentions_of(&rt, members.bob).is_empty());
+        assert_eq!(notification::get_unread_notification_count(&rt).unwrap(), 0);
+    }
+
+    #[test]
+    fn attachments_come_from_staged_uploads() {
+        let rt = TestRuntime::new();
+        let members = setup(&rt);
+        let group_id = members.group.id;
+        let image = [&[0x89, b'P', b'N', b'G'][..], &[0; 60]].concat();
+        let upload = |name: &str, mime_type: &str, data: &[u8]| {
+            let upload = AttachmentUpload {
+                name: name.to_string(),
+                mime_type: mime_type.to_string(),
+            };
+            attachment::upload_chat_attachment(
+                &rt,
+                group_id,
+                String::from("uuid"),
+                upload,
+                data.to_vec(),
+                0,
+                data.len() as u128,
+            )
+        };
+
+        rt.set_caller(members.bob);
+        upload("cat.png", "image/png", &image).unwrap();
+        assert!(matches!(
+            upload("notes.pdf", "application/pdf", b"%PDF"),
+            Err(ApiError::SubscriptionRequired)
+        ));
+        assert!(matches!(
+            upload("dog.jpg", "image/jpeg", &image),
+            Err(ApiError::InvalidInput { .. })
+        ));
+
+        // files the client puts on the chat itself are never trusted
+        let forged = ChatAttachment::File(AttachedFile {
+            name: String::from("secret"),
+            mime_type: String::from("text/plain"),
+            size: 1,
+            blob: String::from("00"),
+        });
+        let chat = Chat {
+            attachments: Some(vec![forged]),
+            ..new_chat(group_id)
+        };
+        send_from(&rt, members.bob, WebsocketEventMessage::AddChat(chat));
+        let chat = CHATS.with_borrow(|chats| chats.iter().next().unwrap().1);
+        let attachments = chat.attachments.clone().unwrap();
+        assert!(matches!(
+            attachments.as_slice(),
+            [ChatAttachment::Image(file)] if file.name == "cat.png" && file.size == 64
+        ));
+        assert!(STAGED_ATTACHMENTS.with_borrow(|staged_attachments| staged_attachments.is_empty()));
+
+        rt.set_caller(members.alice);
+        let chunk = attachment::get_chat_attachment_chunk(&rt, group_id, chat.id, 0, 0).unwrap();
+        assert_eq!(chunk, image);
+
+        rt.set_caller(members.eve);
+        assert!(matches!(
+            attachment::get_chat_attachment_chunk(&rt, group_id, chat.id, 0, 0),
+            Err(ApiError::Forbidden { .. })
+        ));
+    }
+
+    #[test]
+    fn clients_are_indexed_by_username() {
+        let rt = TestRuntime::new();
+        let principal = Principal::from_slice(&[1; 29]);
+
+        // the client connects right after signing in, before picking a username
+        handle_open(&rt, principal);
+        rt.set_caller(principal);
+        user::register(&rt, String::from("Alice")).unwrap();
+
+        send_user_message(&rt, "alice", WebsocketEventMessage::Ping);
+        assert_eq!(recipients(&rt), vec![principal]);
+
+        handle_close(&rt, principal);
+        send_user_message(&rt, "alice", WebsocketEventMessage::Ping);
+        assert!(recipients(&rt).is_empty());
+
+        // the message was kept and reaches the client once it is back
+        handle_open(&rt, principal);
+        assert_eq!(recipients(&rt), vec![principal, principal]);
+    }
+
+    fn sent_seqs(rt: &TestRuntime) -> Vec<u64> {
+        rt.take_websocket_messages()
+            .into_iter()
+            .filter_map(|(_, bytes)| match candid::decode_one(&bytes).unwrap() {
+                WebsocketEventMessage::Event { seq, .. } => Some(seq),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn unacknowledged_events_are_replayed() {
+        let rt = TestRuntime::new();
+        let members = setup(&rt);
+
+        send_from(&rt, members.alice, add_chat_message(members.group.id));
+        send_from(&rt, members.alice, add_chat_message(members.group.id));
+        rt.take_websocket_messages();
+
+        handle_close(&rt, members.bob);
+        broadcast_thumbnail(&rt, &members.group, 1, 0);
+        rt.take_websocket_messages();
+
+        // the ping is not logged, the replayed events keep their numbers
+        handle_open(&rt, members.bob);
+        assert_eq!(sent_seqs(&rt), vec![1, 2, 3]);
+
+        send_from(
+            &rt,
+            members.bob,
+            WebsocketEventMessage::AckEvents { seq: 2 },
+        );
+        assert!(rt.take_websocket_messages().is_empty());
+        handle_open(&rt, members.bob);
+        assert_eq!(sent_seqs(&rt), vec![3]);
+
+        // acknowledging an older event does not bring the replayed ones back
+        send_from(
+            &rt,
+            members.bob,
+            WebsocketEventMessage::AckEvents { seq: 1 },
+        );
+        handle_open(&rt, members.bob);
+        assert_eq!(sent_seqs(&rt), vec![3]);
+
+        send_from(
+            &rt,
+            members.bob,
+            request("1", WebsocketEventMessage::AckEvents { seq: 4 }),
+        );
+        assert!(matches!(
+            sent_messages(&rt).as_slice(),
+            [(
+                _,
+                WebsocketEventMessage::Error {
+                    code: ApiError::InvalidInput { .. },
+                    ..
+                }
+            )]
+        ));
+    }
+
+    #[test]
+    fn replies_are_threaded() {
+        let rt = TestRuntime::new();
+        let members = setup(&rt);
+        let group_id = members.group.id;
+
+        send_from(&rt, members.alice, add_chat_message(group_id));
+        rt.set_caller(members.alice);
+        let parent_id = chat::get_chats(&rt, group_id).unwrap()[0].id;
+        rt.take_websocket_messages();
+
+        send_from(&rt, members.bob, reply_message(group_id, parent_id));
+        send_from(&rt, members.alice, reply_message(group_id, parent_id));
+        let thread_replies = sent_messages(&rt)
+            .into_iter()
+            .filter_map(|(principal, msg)| match msg {
+                WebsocketEventMessage::ThreadReply { reply_count, .. } => {
+                    Some((principal, reply_count))
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            thread_replies,
+            vec![
+                (members.alice, 1),
+                (members.bob, 1),
+                (members.alice, 2),
+                (members.bob, 2)
+            ]
+        );
+
+        let thread = chat::get_thread(&rt, group_id, parent_id).unwrap();
+        assert_eq!(thread.parent.reply_count, Some(2));
+        assert_eq!(thread.replies.len(), 2);
+        assert!(thread
+            .replies
+            .iter()
+            .all(|reply| reply.reply_to == Some(parent_id)));
+
+        // threads are one level deep
+        let reply_id = thread.replies[0].id;
+        send_from(&rt, members.bob, reply_message(group_id, reply_id));
+        assert!(matches!(
+            sent_messages(&rt).as_slice(),
+            [(
+                _,
+                WebsocketEventMessage::Error {
+                    code: ApiError::InvalidInput { .. },
+                    ..
+                }
+            )]
+        ));
+
+        // deleted replies stay in the thread as tombstones until they are purged
+        user::buy_subscription(&rt).unwrap();
+        chat::delete_chat(&rt, group_id, reply_id).unwrap();
+        let thread = chat::get_thread(&rt, group_id, parent_id).unwrap();
+        assert_eq!(thread.parent.reply_count, Some(2));
+        assert!(thread.replies[0].deleted_time_unix.is_some());
+        chat::purge_chat(&rt, group_id, reply_id).unwrap();
+        let thread = chat::get_thread(&rt, group_id, parent_id).unwrap();
+        assert_eq!(thread.parent.reply_count, Some(1));
+        assert_eq!(thread.replies.len(), 1);
+    }
+
+    #[test]
+    fn typing_reaches_the_other_members() {
+        let rt = TestRuntime::new();
+        let members = setup(&rt);
+        let typing = |group_id| WebsocketEventMessage::Typing {
+            group_id,
+            username: String::from("mallory"),
+        };
+
+        send_from(&rt, members.alice, typing(members.group.id));
+        let sent = sent_messages(&rt);
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].0, members.bob);
+        assert!(matches!(
+            &sent[0].1,
+            WebsocketEventMessage::Typing { username, .. } if username == "alice"
+        ));
+
+        // nothing is kept for members that were not connected
+        handle_close(&rt, members.bob);
+        send_from(&rt, members.alice, typing(members.group.id));
+        handle_open(&rt, members.bob);
+        assert!(sent_messages(&rt)
+            .iter()
+            .all(|(_, msg)| matches!(msg, WebsocketEventMessage::Ping)));
+
+        send_from(&rt, members.eve, typing(members.group.id));
+        assert!(matches!(
+            sent_messages(&rt).as_slice(),
+            [(
+                _,
+                WebsocketEventMessage::Error {
+                    code: ApiError::Forbidden { .. },
+                    ..
+                }
+            )]
+        ));
+    }
+
+    #[test]
+    fn rejected_messages_get_error_replies() {
+        let rt = TestRuntime::new();
+        let members = setup(&rt);
+
+        send_from(
+            &rt,
+            members.eve,
+            request("1", add_chat_message(members.group.id)),
+        );
+        let replies = sent_messages(&rt);
+        assert_eq!(replies.len(), 1);
+        assert_eq!(replies[0].0, members.eve);
+        assert!(matches!(
+            &replies[0].1,
+            WebsocketEventMessage::Error {
+                request_id: Some(request_id),
+                code: ApiError::Forbidden { .. },
+                ..
+            } if request_id == "1"
+        ));
+
+        send_from(&rt, members.eve, add_chat_message(404));
+        assert!(matches!(
+            sent_messages(&rt).as_slice(),
+            [(
+                _,
+                WebsocketEventMessage::Error {
+                    request_id: None,
+                    code: ApiError::NotFound { .. },
+                    ..
+                }
+            )]
+        ));
+
+        handle_message(
+            &rt,
+            OnMessageCallbackArgs {
+                client_principal: members.eve,
+                message: vec![1, 2, 3],
+            },
+        );
+        assert!(matches!(
+            sent_messages(&rt).as_slice(),
+            [(
+                _,
+                WebsocketEventMessage::Error {
+                    code: ApiError::InvalidInput { .. },
+                    ..
+                }
+            )]
+        ));
+
+        let stranger = Principal::from_slice(&[9; 29]);
+        handle_open(&rt, stranger);
+        send_from(&rt, stranger, request("2", WebsocketEventMessage::Ping));
+        assert!(matches!(
+            sent_messages(&rt).as_slice(),
+            [(
+                _,
+                WebsocketEventMessage::Error {
+                    code: ApiError::NoUsername,
+                    ..
+                }
+            )]
+        ));
+    }
+
+    #[test]
+    fn accepted_requests_are_acknowledged() {
+        let rt = TestRuntime::new();
+        let members = setup(&rt);
+
+        send_from(
+            &rt,
+            members.bob,
+            request("42", add_chat_message(members.group.id)),
+        );
+        let replies = sent_messages(&rt);
+        assert_eq!(replies.len(), 3);
+        assert!(replies[..2]
+            .iter()
+            .all(|(_, msg)| matches!(msg, WebsocketEventMessage::AddChat(_))));
+        assert_eq!(replies[2].0, members.bob);
+        assert!(matches!(
+            &replies[2].1,
+            WebsocketEventMessage::Ack { request_id } if request_id == "42"
+        ));
+
+        // clients cannot fake events that only the canister sends
+        send_from(
+            &rt,
+            members.bob,
+            request(
+                "43",
+                WebsocketEventMessage::DeleteChat {
+                    chat_id: 0,
+                    group_id: members.group.id,
+                },
+            ),
+        );
+        assert!(matches!(
+            sent_messages(&rt).as_slice(),
+            [(
+                _,
+                WebsocketEventMessage::Error {
+                    code: ApiError::InvalidInput { .. },
+                    ..
+                }
+            )]
+        ));
+    }
 }
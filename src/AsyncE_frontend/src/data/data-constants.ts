@@ -21,4 +21,16 @@ const CHAT_OPTIONS = [
 
 const MAX_MEMBERS_FOR_BASIC_PLAN = 10;
 
-export { USER_DROPDOWN_OPTIONS, CHAT_OPTIONS, MB, MAX_MEMBERS_FOR_BASIC_PLAN };
+const CHAT_PAGE_SIZE = 50;
+const SEARCH_PAGE_SIZE = 20;
+const NOTIFICATION_PAGE_SIZE = 20;
+
+export {
+    USER_DROPDOWN_OPTIONS,
+    CHAT_OPTIONS,
+    MB,
+    MAX_MEMBERS_FOR_BASIC_PLAN,
+    CHAT_PAGE_SIZE,
+    SEARCH_PAGE_SIZE,
+    NOTIFICATION_PAGE_SIZE,
+};
@@ -1,11 +1,14 @@
 import { ref } from "vue";
 import { defineStore } from "pinia";
 import { storeToRefs } from "pinia";
-import { MB } from "@data/data-constants";
+import { CHAT_PAGE_SIZE, MB, SEARCH_PAGE_SIZE } from "@data/data-constants";
 import {
     GroupMemberRole,
     GroupQueryResponse,
     MeetingHeader,
+    ReadMark,
+    SearchDoc,
+    UserPresence,
 } from "@declarations/AsyncE_backend/AsyncE_backend.did";
 import { useUserStore } from "@stores/user-store";
 import { Group, MeetingList, VideoFrameHeader } from "@/types/api/model";
@@ -17,6 +20,7 @@ export const useGroupStore = defineStore("group", () => {
     const groupPicture = ref<string>("");
     const groupList = ref<Group[]>([]);
     const currentGroup = ref<GroupQueryResponse>();
+    const onlineMembers = ref<UserPresence[]>([]);
     const uploadVideoProgress = ref<number>(0);
 
     const meetingList = ref<MeetingList[]>([]);
@@ -32,6 +36,8 @@ export const useGroupStore = defineStore("group", () => {
             owner: groupResponse.owner,
             members: groupResponse.members,
             created_time_unix: groupResponse.created_time_unix,
+            unread_chat_count: groupResponse.unread_chat_count,
+            unread_video_count: groupResponse.unread_video_count,
             profile_picture_blob: new Uint8Array(),
         };
     }
@@ -100,6 +106,12 @@ export const useGroupStore = defineStore("group", () => {
         }
     }
 
+    async function getOnlineMembers(groupId: bigint) {
+        const response = await actor.value?.get_online_members(groupId);
+
+        onlineMembers.value = validateResponse(response);
+    }
+
     async function getGroup(id: string) {
         const response = await actor.value?.get_group(BigInt(id));
 
@@ -153,40 +165,50 @@ export const useGroupStore = defineStore("group", () => {
         title: string,
         subtitle: boolean,
     ) {
-        const totalChunks = Math.ceil(data.length / MB);
-        const uuid = crypto.randomUUID();
-        let totalBytesUploaded = 0;
+        const digest = await crypto.subtle.digest("SHA-256", data);
+        const sha256 = Array.from(new Uint8Array(digest))
+            .map((byte) => byte.toString(16).padStart(2, "0"))
+            .join("");
 
-        const uploadPromises = [];
+        const sessionId = validateResponse(
+            await actor.value?.begin_video_upload(
+                BigInt(groupId),
+                BigInt(meetingId),
+                {
+                    title,
+                    total_size: BigInt(data.length),
+                    sha256,
+                    with_subtitles: subtitle,
+                },
+            ),
+        );
 
+        // chunks are sent in order, so the canister can hash them as they arrive
+        const totalChunks = Math.ceil(data.length / MB);
         for (let i = 0; i < totalChunks; ++i) {
             const start = i * MB;
             const end = Math.min(start + MB, data.length);
-            const chunk = data.slice(start, end);
 
-            const uploadPromise = actor.value
-                ?.upload_video(
-                    BigInt(groupId),
-                    BigInt(meetingId),
-                    chunk,
-                    i === totalChunks - 1,
-                    title,
-                    uuid,
-                    BigInt(i),
-                    BigInt(data.length),
-                    subtitle,
-                )
-                .then((response) => {
-                    validateResponse(response);
-                    totalBytesUploaded += chunk.length;
-                    uploadVideoProgress.value =
-                        (totalBytesUploaded / data.length) * 100;
-                });
-
-            uploadPromises.push(uploadPromise);
+            validateResponse(
+                await actor.value?.put_video_upload_chunk(
+                    sessionId,
+                    i,
+                    data.slice(start, end),
+                ),
+            );
+            uploadVideoProgress.value = (end / data.length) * 100;
         }
 
-        await Promise.all(uploadPromises);
+        // `UploadIncomplete` means the canister is still hashing, every call does some more of it
+        for (;;) {
+            const response = await actor.value?.commit_video_upload(sessionId);
+            if (response && "Err" in response && "UploadIncomplete" in response.Err) {
+                continue;
+            }
+
+            validateResponse(response);
+            break;
+        }
 
         console.log("All chunks uploaded successfully!");
     }
@@ -462,13 +484,130 @@ export const useGroupStore = defineStore("group", () => {
     }
 
     async function getChats(groupId: string) {
-        const response = await actor.value?.get_chats(BigInt(groupId));
+        const response = await actor.value?.get_latest_chats(
+            BigInt(groupId),
+            CHAT_PAGE_SIZE,
+        );
 
         const okResponse = validateResponse(response);
 
         return okResponse;
     }
 
+    async function getOlderChats(groupId: string, beforeChatId: bigint) {
+        const response = await actor.value?.get_chat_page(
+            BigInt(groupId),
+            { Before: beforeChatId },
+            CHAT_PAGE_SIZE,
+        );
+
+        const okResponse = validateResponse(response);
+
+        return okResponse;
+    }
+
+    async function searchGroup(
+        groupId: string,
+        query: string,
+        cursor?: SearchDoc,
+    ) {
+        const response = await actor.value?.search_group(
+            BigInt(groupId),
+            query,
+            SEARCH_PAGE_SIZE,
+            cursor ? [cursor] : [],
+        );
+
+        const okResponse = validateResponse(response);
+
+        return okResponse;
+    }
+
+    async function markGroupRead(groupId: string, mark: ReadMark) {
+        const response = await actor.value?.mark_group_read(
+            BigInt(groupId),
+            mark,
+        );
+
+        validateResponse(response);
+    }
+
+    async function getReadReceipts(groupId: string) {
+        const response = await actor.value?.get_read_receipts(BigInt(groupId));
+
+        const okResponse = validateResponse(response);
+
+        return okResponse;
+    }
+
+    async function getThread(groupId: string, chatId: bigint) {
+        const response = await actor.value?.get_thread(BigInt(groupId), chatId);
+
+        const okResponse = validateResponse(response);
+
+        return okResponse;
+    }
+
+    async function addChatReaction(
+        groupId: string,
+        chatId: bigint,
+        emoji: string,
+    ) {
+        const response = await actor.value?.add_chat_reaction(
+            BigInt(groupId),
+            chatId,
+            emoji,
+        );
+
+        validateResponse(response);
+    }
+
+    async function removeChatReaction(
+        groupId: string,
+        chatId: bigint,
+        emoji: string,
+    ) {
+        const response = await actor.value?.remove_chat_reaction(
+            BigInt(groupId),
+            chatId,
+            emoji,
+        );
+
+        validateResponse(response);
+    }
+
+    async function addVideoFrameReaction(
+        groupId: string,
+        meetingId: string,
+        frameIndex: number,
+        emoji: string,
+    ) {
+        const response = await actor.value?.add_video_frame_reaction(
+            BigInt(groupId),
+            BigInt(meetingId),
+            BigInt(frameIndex),
+            emoji,
+        );
+
+        validateResponse(response);
+    }
+
+    async function removeVideoFrameReaction(
+        groupId: string,
+        meetingId: string,
+        frameIndex: number,
+        emoji: string,
+    ) {
+        const response = await actor.value?.remove_video_frame_reaction(
+            BigInt(groupId),
+            BigInt(meetingId),
+            BigInt(frameIndex),
+            emoji,
+        );
+
+        validateResponse(response);
+    }
+
     async function editChat(groupId: string, chatId: bigint, message: string) {
         const response = await actor.value?.edit_chat(
             BigInt(groupId),
@@ -487,6 +626,74 @@ export const useGroupStore = defineStore("group", () => {
         validateResponse(response);
     }
 
+    async function purgeChat(groupId: string, chatId: bigint) {
+        const response = await actor.value?.purge_chat(BigInt(groupId), chatId);
+
+        validateResponse(response);
+    }
+
+    async function getChatHistory(groupId: string, chatId: bigint) {
+        const response = await actor.value?.get_chat_history(
+            BigInt(groupId),
+            chatId,
+        );
+
+        const okResponse = validateResponse(response);
+
+        return okResponse;
+    }
+
+    async function uploadChatAttachment(
+        groupId: string,
+        chatUuid: string,
+        file: File,
+    ) {
+        const data = new Uint8Array(await file.arrayBuffer());
+        const upload = { name: file.name, mime_type: file.type };
+
+        for (let i = 0; i < Math.ceil(data.length / MB); ++i) {
+            const start = i * MB;
+            const end = Math.min(start + MB, data.length);
+            const response = await actor.value?.upload_chat_attachment(
+                BigInt(groupId),
+                chatUuid,
+                upload,
+                data.slice(start, end),
+                BigInt(i),
+                BigInt(data.length),
+            );
+            validateResponse(response);
+        }
+    }
+
+    async function getChatAttachment(
+        groupId: string,
+        chatId: bigint,
+        attachmentIndex: number,
+        size: number,
+    ) {
+        const data = new Uint8Array(size);
+
+        const chunkPromises = Array.from(
+            { length: Math.ceil(size / MB) },
+            (_, i) =>
+                actor.value
+                    ?.get_chat_attachment_chunk(
+                        BigInt(groupId),
+                        chatId,
+                        attachmentIndex,
+                        BigInt(i),
+                    )
+                    .then((chunk) => {
+                        const okChunk = validateResponse(chunk);
+                        data.set(okChunk, i * MB);
+                    }),
+        );
+
+        await Promise.all(chunkPromises);
+        return data;
+    }
+
     async function editRole(
         groupId: string,
         username: string,
@@ -510,6 +717,7 @@ export const useGroupStore = defineStore("group", () => {
 
     return {
         currentGroup,
+        onlineMembers,
         groupList,
         groupPicture,
         uploadVideoProgress,
@@ -524,6 +732,10 @@ export const useGroupStore = defineStore("group", () => {
         editChat,
         getAllGroups,
         deleteChat,
+        purgeChat,
+        getChatHistory,
+        uploadChatAttachment,
+        getChatAttachment,
         getAllMeetings,
         getVideo,
         createMeeting,
@@ -531,8 +743,18 @@ export const useGroupStore = defineStore("group", () => {
         getAllThumbnails,
         getSpecificThumbnail,
         getChats,
+        getOlderChats,
+        getThread,
+        searchGroup,
+        markGroupRead,
+        getReadReceipts,
+        addChatReaction,
+        removeChatReaction,
+        addVideoFrameReaction,
+        removeVideoFrameReaction,
         getMeetingVideo,
         getGroup,
+        getOnlineMembers,
         getInvites,
         handleInvitation,
         createGroup,
@@ -1,8 +1,9 @@
 import { ref, toRaw } from "vue";
 import { defineStore } from "pinia";
-import { MB } from "@data/data-constants";
+import { MB, NOTIFICATION_PAGE_SIZE } from "@data/data-constants";
 import {
     _SERVICE,
+    Notification,
     UserCredentialsResponse,
 } from "@declarations/AsyncE_backend/AsyncE_backend.did";
 import { createActor } from "@declarations/AsyncE_backend/index";
@@ -18,6 +19,9 @@ const actor = ref<ActorSubclass<_SERVICE> | null>();
 const userCredentials = ref<UserCredentialsResponse>();
 const profilePicture = ref<string>("");
 
+const notifications = ref<Notification[]>([]);
+const unreadNotificationCount = ref<bigint>(BigInt(0));
+
 export const getIdentityProvider = () => {
     let idpProvider;
 
@@ -168,14 +172,69 @@ export const useUserStore = defineStore("user", () => {
         return okResponse;
     }
 
+    async function getNotifications(cursor?: bigint) {
+        const response = await actor.value?.get_notifications(
+            cursor !== undefined ? [cursor] : [],
+            NOTIFICATION_PAGE_SIZE,
+        );
+
+        const okResponse = validateResponse(response);
+
+        notifications.value = cursor
+            ? [...notifications.value, ...okResponse.notifications]
+            : okResponse.notifications;
+
+        return okResponse;
+    }
+
+    async function getUnreadNotificationCount() {
+        const response = await actor.value?.get_unread_notification_count();
+
+        unreadNotificationCount.value = validateResponse(response);
+    }
+
+    async function markNotificationsRead(ids: bigint[]) {
+        const response = await actor.value?.mark_notifications_read(ids);
+
+        validateResponse(response);
+
+        for (const notification of notifications.value) {
+            if (ids.includes(notification.id)) notification.read = true;
+        }
+        await getUnreadNotificationCount();
+    }
+
+    async function markAllNotificationsRead() {
+        const response = await actor.value?.mark_all_notifications_read();
+
+        validateResponse(response);
+
+        for (const notification of notifications.value) {
+            notification.read = true;
+        }
+        unreadNotificationCount.value = BigInt(0);
+    }
+
+    function receiveNotification(notification: Notification) {
+        notifications.value = [notification, ...notifications.value];
+        unreadNotificationCount.value += BigInt(1);
+    }
+
     return {
         isAuthenticated,
         identity,
         actor,
         userCredentials,
         profilePicture,
+        notifications,
+        unreadNotificationCount,
 
         buySubscription,
+        getNotifications,
+        getUnreadNotificationCount,
+        markNotificationsRead,
+        markAllNotificationsRead,
+        receiveNotification,
         init,
         login,
         logout,
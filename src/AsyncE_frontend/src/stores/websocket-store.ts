@@ -4,13 +4,25 @@ import {
     _SERVICE,
     Chat,
     GroupInviteResponse,
+    Notification,
+    Reaction,
+    ReactionTarget,
     WebsocketEventMessage,
 } from "@declarations/AsyncE_backend/AsyncE_backend.did";
 import { canisterId as backendCanisterId } from "@declarations/AsyncE_backend/index";
 import { SignIdentity } from "@dfinity/agent";
 import IcWebSocket, { createWsConfig } from "ic-websocket-js";
 import { useUserStore } from "@/stores/user-store";
-import { DeleteChat, EditChat, Thumbnail } from "@/types/api/model";
+import {
+    DeleteChat,
+    EditChat,
+    ThreadReply,
+    Thumbnail,
+    Typing,
+} from "@/types/api/model";
+
+// keeps the user from showing up as away while the tab is open
+const HEARTBEAT_INTERVAL_MS = 60_000;
 
 export const useWebsocketStore = defineStore("websocket", () => {
     const { actor, identity } = storeToRefs(useUserStore());
@@ -21,7 +33,13 @@ export const useWebsocketStore = defineStore("websocket", () => {
     let onChatReceive = (chat: Chat) => {};
     let onChatEdit = (chat: EditChat) => {};
     let onChatDelete = (chat: DeleteChat) => {};
+    let onChatPurge = (chat: DeleteChat) => {};
     let onThumbnailAvailable = (thumbnail: Thumbnail) => {};
+    let onTyping = (typing: Typing) => {};
+    let onNotification = (notification: Notification) => {};
+    let onThreadReply = (threadReply: ThreadReply) => {};
+    let onReactionChanged = (target: ReactionTarget, reactions: Reaction[]) => {};
+    let heartbeat: ReturnType<typeof setInterval> | undefined;
 
     function sendMessage(chat: Chat) {
         if (ws.value) {
@@ -32,6 +50,12 @@ export const useWebsocketStore = defineStore("websocket", () => {
         }
     }
 
+    function sendTyping(groupId: bigint) {
+        ws.value?.send({
+            Typing: { group_id: groupId, username: "" },
+        });
+    }
+
     async function setWebsockets() {
         if (!actor.value) return;
 
@@ -49,7 +73,13 @@ export const useWebsocketStore = defineStore("websocket", () => {
         ws.value.onmessage = async (event) => {
             console.log("Received message:", event.data);
 
-            const message = event.data;
+            let message = event.data;
+            let seq: bigint | undefined;
+            if ("Event" in message) {
+                seq = message.Event.seq;
+                message = message.Event.message;
+            }
+
             switch (true) {
                 case "AddChat" in message:
                     onChatReceive(message.AddChat);
@@ -66,6 +96,10 @@ export const useWebsocketStore = defineStore("websocket", () => {
                     console.log(message.DeleteChat);
                     break;
 
+                case "PurgeChat" in message:
+                    onChatPurge(message.PurgeChat);
+                    break;
+
                 case "Ping" in message:
                     console.log("Received a Ping");
                     break;
@@ -83,14 +117,55 @@ export const useWebsocketStore = defineStore("websocket", () => {
                     console.log(message.Thumbnail);
                     break;
 
+                case "ReactionChanged" in message:
+                    onReactionChanged(
+                        message.ReactionChanged.target,
+                        message.ReactionChanged.reactions,
+                    );
+                    break;
+
+                case "ThreadReply" in message:
+                    onThreadReply(message.ThreadReply);
+                    break;
+
+                case "Notification" in message:
+                    onNotification(message.Notification);
+                    break;
+
+                case "Typing" in message:
+                    onTyping(message.Typing);
+                    break;
+
+                case "Ack" in message:
+                    console.log("Request acknowledged:", message.Ack.request_id);
+                    break;
+
+                case "Error" in message:
+                    console.error(
+                        "Message was rejected:",
+                        message.Error.request_id,
+                        message.Error.message,
+                    );
+                    break;
+
                 default:
                     console.log("Unknown variant");
             }
+
+            // missed events are replayed on reconnect until they are acknowledged
+            if (seq !== undefined) {
+                ws.value?.send({ AckEvents: { seq } });
+            }
         };
 
         await new Promise((resolve, reject) => {
             ws.value!.onopen = () => {
                 console.log("Websocket is opened");
+                heartbeat = setInterval(() => {
+                    if (document.visibilityState === "visible") {
+                        ws.value?.send({ Ping: null });
+                    }
+                }, HEARTBEAT_INTERVAL_MS);
                 resolve(null);
             };
 
@@ -101,6 +176,7 @@ export const useWebsocketStore = defineStore("websocket", () => {
 
             ws.value!.onclose = () => {
                 console.log("Disconnected from the canister");
+                clearInterval(heartbeat);
                 reject(null);
             };
         });
@@ -109,6 +185,7 @@ export const useWebsocketStore = defineStore("websocket", () => {
     return {
         ws,
         sendMessage,
+        sendTyping,
         setWebsockets,
 
         setOnThumbnailAvailable: (callback: (thumbnail: Thumbnail) => void) =>
@@ -119,6 +196,17 @@ export const useWebsocketStore = defineStore("websocket", () => {
             (onChatEdit = callback),
         setOnChatDelete: (callback: (chat: DeleteChat) => void) =>
             (onChatDelete = callback),
+        setOnChatPurge: (callback: (chat: DeleteChat) => void) =>
+            (onChatPurge = callback),
+        setOnReactionChanged: (
+            callback: (target: ReactionTarget, reactions: Reaction[]) => void,
+        ) => (onReactionChanged = callback),
+        setOnThreadReply: (callback: (threadReply: ThreadReply) => void) =>
+            (onThreadReply = callback),
+        setOnTyping: (callback: (typing: Typing) => void) =>
+            (onTyping = callback),
+        setOnNotification: (callback: (notification: Notification) => void) =>
+            (onNotification = callback),
         setOnGroupInvited: (callback: (group: GroupInviteResponse) => void) =>
             (onGroupInvited = callback),
     };
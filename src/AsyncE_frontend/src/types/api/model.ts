@@ -30,6 +30,8 @@ interface Group {
     owner: string;
     name: string;
     members: GroupMember[];
+    unread_chat_count: bigint;
+    unread_video_count: bigint;
 }
 
 interface Video {
@@ -150,6 +152,18 @@ interface DeleteChat {
     group_id: bigint;
 }
 
+interface ThreadReply {
+    group_id: bigint;
+    parent_id: bigint;
+    chat_id: bigint;
+    reply_count: bigint;
+}
+
+interface Typing {
+    group_id: bigint;
+    username: string;
+}
+
 type RoleKeys = "Admin" | "Member";
 
 export type {
@@ -176,4 +190,6 @@ export type {
     VideoRefs,
     Thumbnail,
     GroupInvite,
+    Typing,
+    ThreadReply,
 };
@@ -20,15 +20,33 @@ function generateUUID() {
     return crypto.randomUUID();
 }
 
+// Mirrors the Candid `ApiError` variant
+type ApiError = { [variant: string]: null | Record<string, string> };
+
+function describeError(error: ApiError): string {
+    const [variant, fields] = Object.entries(error)[0];
+    if (!fields) {
+        return variant;
+    }
+    if ("reason" in fields) {
+        return fields.reason;
+    }
+    if ("entity" in fields) {
+        return `Cannot find ${fields.entity} with ID ${fields.id}!`;
+    }
+
+    return `${variant}: ${JSON.stringify(fields)}`;
+}
+
 function validateResponse<T>(
-    response: { Ok: T } | { Err: string } | undefined | null,
+    response: { Ok: T } | { Err: ApiError } | undefined | null,
 ): T {
     if (!response) {
         throw new Error("Response is null or undefined");
     }
 
     if (!("Ok" in response)) {
-        throw Error(response.Err);
+        throw Error(describeError(response.Err));
     }
 
     return response.Ok;
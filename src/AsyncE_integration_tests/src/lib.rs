@@ -0,0 +1,271 @@
+#![allow(non_snake_case)]
+
+//! Drives the `AsyncE_backend` wasm inside PocketIC.
+//!
+//! Build the canister first with
+//! `cargo build --target wasm32-unknown-unknown --release -p AsyncE_backend`, or point
+//! `ASYNCE_BACKEND_WASM` at a wasm built elsewhere, and point `POCKET_IC_BIN` at the
+//! PocketIC server binary. The tests are `#[ignore]`d so `cargo test --workspace` still works
+//! without a wasm toolchain, run them with
+//! `cargo test -p AsyncE_integration_tests -- --ignored`.
+
+use std::{path::PathBuf, time::Duration};
+
+use candid::{utils::ArgumentEncoder, CandidType, Deserialize, Principal};
+use ic_websocket_cdk::{
+    types::{ClientKey, WebsocketMessage},
+    CanisterWsMessageArguments, CanisterWsMessageResult, CanisterWsOpenArguments,
+    CanisterWsOpenResult,
+};
+use pocket_ic::{PocketIc, PocketIcBuilder};
+use AsyncE_backend::{
+    blob, chunk,
+    config::{ConfigUpdate, InitArgs},
+    error::ApiError,
+    job::JobResponse,
+    processor::ProcessorKind,
+    upload::VideoUploadRequest,
+    websocket::WebsocketEventMessage,
+};
+
+const WASM_ENV: &str = "ASYNCE_BACKEND_WASM";
+const SERVER_ENV: &str = "POCKET_IC_BIN";
+const INITIAL_CYCLES: u128 = 100_000_000_000_000;
+
+/// How often [`TestEnv::wait_for_jobs`] moves the clock before giving up.
+const MAX_JOB_ROUNDS: usize = 40;
+
+fn wasm_path() -> PathBuf {
+    std::env::var_os(WASM_ENV)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| {
+            PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+                .join("../../target/wasm32-unknown-unknown/release/AsyncE_backend.wasm")
+        })
+}
+
+pub fn load_wasm() -> Vec<u8> {
+    let path = wasm_path();
+    std::fs::read(&path).unwrap_or_else(|e| {
+        panic!(
+            "Cannot read {} ({}), build the canister or set {}!",
+            path.display(),
+            e,
+            WASM_ENV
+        )
+    })
+}
+
+pub fn server_path() -> PathBuf {
+    match std::env::var_os(SERVER_ENV).map(PathBuf::from) {
+        Some(path) if path.is_file() => path,
+        _ => panic!("Set {} to the PocketIC server binary!", SERVER_ENV),
+    }
+}
+
+/// Principals are derived from a single byte so every test uses the same identities.
+pub fn principal(id: u8) -> Principal {
+    Principal::from_slice(&[id; 29])
+}
+
+/// The fake processor keeps the tests independent from the video editor service.
+pub fn init_args() -> Option<InitArgs> {
+    Some(InitArgs {
+        config: Some(ConfigUpdate {
+            processor: Some(ProcessorKind::Fake),
+            ..Default::default()
+        }),
+    })
+}
+
+pub struct TestEnv {
+    pub pic: PocketIc,
+    pub canister_id: Principal,
+    pub controller: Principal,
+    wasm: Vec<u8>,
+}
+
+impl Default for TestEnv {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A websocket connection opened by a client, messages are sent as if they were relayed
+/// by a gateway.
+pub struct WsClient {
+    key: ClientKey,
+    next_sequence_num: u64,
+}
+
+impl TestEnv {
+    /// Panics with what is missing when the canister or the PocketIC server are not there.
+    pub fn new() -> Self {
+        let server = server_path();
+        let wasm = load_wasm();
+        let pic = PocketIcBuilder::new()
+            .with_server_binary(server)
+            .with_application_subnet()
+            .build();
+        let controller = principal(0);
+
+        let canister_id = pic.create_canister_with_settings(Some(controller), None);
+        pic.add_cycles(canister_id, INITIAL_CYCLES);
+        pic.install_canister(
+            canister_id,
+            wasm.clone(),
+            candid::encode_one(init_args()).unwrap(),
+            Some(controller),
+        );
+
+        Self {
+            pic,
+            canister_id,
+            controller,
+            wasm,
+        }
+    }
+
+    pub fn update<R>(&self, sender: Principal, method: &str, args: impl ArgumentEncoder) -> R
+    where
+        R: CandidType + for<'de> Deserialize<'de>,
+    {
+        let reply = self
+            .pic
+            .update_call(
+                self.canister_id,
+                sender,
+                method,
+                candid::encode_args(args).unwrap(),
+            )
+            .unwrap_or_else(|e| panic!("Update call {} was rejected: {:?}", method, e));
+
+        candid::decode_one(&reply).unwrap()
+    }
+
+    pub fn query<R>(&self, sender: Principal, method: &str, args: impl ArgumentEncoder) -> R
+    where
+        R: CandidType + for<'de> Deserialize<'de>,
+    {
+        let reply = self
+            .pic
+            .query_call(
+                self.canister_id,
+                sender,
+                method,
+                candid::encode_args(args).unwrap(),
+            )
+            .unwrap_or_else(|e| panic!("Query call {} was rejected: {:?}", method, e));
+
+        candid::decode_one(&reply).unwrap()
+    }
+
+    /// Reinstalls the same wasm through `post_upgrade` without init arguments.
+    pub fn upgrade(&self) {
+        self.pic
+            .upgrade_canister(
+                self.canister_id,
+                self.wasm.clone(),
+                candid::encode_one(None::<InitArgs>).unwrap(),
+                Some(self.controller),
+            )
+            .expect("Cannot upgrade canister!");
+    }
+
+    pub fn register(&self, sender: Principal, username: &str) {
+        let result: Result<(), ApiError> = self.update(sender, "register", (username,));
+        result.unwrap();
+    }
+
+    /// Uploads `data` as a new part of the meeting through an upload session.
+    pub fn upload_video(
+        &self,
+        sender: Principal,
+        group_id: u128,
+        meeting_id: u128,
+        title: &str,
+        data: &[u8],
+    ) {
+        let request = VideoUploadRequest {
+            title: title.to_string(),
+            total_size: data.len() as u64,
+            sha256: hex::encode(blob::hash(data)),
+            with_subtitles: false,
+        };
+        let session_id: Result<u128, ApiError> = self.update(
+            sender,
+            "begin_video_upload",
+            (group_id, meeting_id, request),
+        );
+        let session_id = session_id.unwrap();
+
+        for (index, chunk) in data.chunks(chunk::MB).enumerate() {
+            let result: Result<(), ApiError> = self.update(
+                sender,
+                "put_video_upload_chunk",
+                (session_id, index as u32, chunk.to_vec()),
+            );
+            result.unwrap();
+        }
+
+        let result: Result<(), ApiError> =
+            self.update(sender, "commit_video_upload", (session_id,));
+        result.unwrap();
+    }
+
+    /// Moves the clock until every job of the meeting is finished and returns them.
+    pub fn wait_for_jobs(
+        &self,
+        sender: Principal,
+        group_id: u128,
+        meeting_id: u128,
+    ) -> Vec<JobResponse> {
+        for _ in 0..MAX_JOB_ROUNDS {
+            self.pic.advance_time(Duration::from_secs(30));
+            for _ in 0..5 {
+                self.pic.tick();
+            }
+
+            let jobs: Result<Vec<JobResponse>, ApiError> =
+                self.query(sender, "get_meeting_jobs", (group_id, meeting_id));
+            let jobs = jobs.unwrap();
+            if jobs.iter().all(|job| job.state.is_finished()) {
+                return jobs;
+            }
+        }
+
+        panic!("Jobs of meeting {} did not finish in time!", meeting_id)
+    }
+
+    pub fn ws_open(&self, sender: Principal, client_nonce: u64) -> WsClient {
+        let args = CanisterWsOpenArguments::new(client_nonce, principal(255));
+        let result: CanisterWsOpenResult = self.update(sender, "ws_open", (args,));
+        result.unwrap();
+
+        WsClient {
+            key: ClientKey::new(sender, client_nonce),
+            next_sequence_num: 1,
+        }
+    }
+
+    pub fn ws_send(&self, client: &mut WsClient, message: WebsocketEventMessage) {
+        let msg = WebsocketMessage::new(
+            client.key.clone(),
+            client.next_sequence_num,
+            self.pic.get_time().as_nanos_since_unix_epoch(),
+            false,
+            candid::encode_one(message).unwrap(),
+        );
+        client.next_sequence_num += 1;
+
+        let result: CanisterWsMessageResult = self.update(
+            client.key.client_principal,
+            "ws_message",
+            (
+                CanisterWsMessageArguments::new(msg),
+                None::<WebsocketEventMessage>,
+            ),
+        );
+        result.unwrap();
+    }
+}
@@ -0,0 +1,244 @@
+use candid::Principal;
+use AsyncE_backend::{
+    chat::Chat,
+    chunk,
+    error::ApiError,
+    group::GroupQueryResponse,
+    invite::GroupInviteResponse,
+    job::{JobKind, JobState},
+    meeting::{MeetingHeader, MeetingProcessType},
+    websocket::WebsocketEventMessage,
+};
+use AsyncE_integration_tests::{principal, TestEnv, WsClient};
+
+struct Members {
+    alice: Principal,
+    bob: Principal,
+    group_id: u128,
+}
+
+/// Alice creates a group and invites Bob, who accepts.
+fn setup_group(env: &TestEnv) -> Members {
+    let alice = principal(1);
+    let bob = principal(2);
+    env.register(alice, "alice");
+    env.register(bob, "bob");
+
+    let group_id: Result<u128, ApiError> = env.update(alice, "create_group", ("Team",));
+    let group_id = group_id.unwrap();
+
+    let result: Result<(), ApiError> = env.update(alice, "invite_user", (group_id, "bob"));
+    result.unwrap();
+
+    let invites: Result<Vec<GroupInviteResponse>, ApiError> =
+        env.query(bob, "get_self_group_invites", ());
+    let invites = invites.unwrap();
+    assert_eq!(invites.len(), 1);
+    assert_eq!(invites[0].group_id, group_id);
+
+    let result: Result<(), ApiError> = env.update(bob, "update_group_invite", (group_id, true));
+    result.unwrap();
+
+    Members {
+        alice,
+        bob,
+        group_id,
+    }
+}
+
+fn create_meeting(env: &TestEnv, members: &Members) -> u128 {
+    let meeting_id: Result<u128, ApiError> = env.update(
+        members.alice,
+        "create_meeting",
+        (members.group_id, "Standup"),
+    );
+    meeting_id.unwrap()
+}
+
+fn meeting_detail(
+    env: &TestEnv,
+    sender: Principal,
+    group_id: u128,
+    meeting_id: u128,
+) -> MeetingHeader {
+    let meeting: Result<MeetingHeader, ApiError> =
+        env.update(sender, "get_meeting_detail", (group_id, meeting_id));
+    meeting.unwrap()
+}
+
+fn send_chat(env: &TestEnv, client: &mut WsClient, group_id: u128, content: &str) {
+    let chat = Chat {
+        id: 0,
+        uuid: content.to_string(),
+        content: content.to_string(),
+        group_id,
+        username: String::new(),
+        created_time_unix: 0,
+        reply_to: None,
+        reply_count: None,
+        reactions: None,
+        mentions: None,
+        edited_time_unix: None,
+        deleted_time_unix: None,
+        attachments: None,
+    };
+    env.ws_send(client, WebsocketEventMessage::AddChat(chat));
+}
+
+fn chat_contents(env: &TestEnv, sender: Principal, group_id: u128) -> Vec<(String, String)> {
+    let chats: Result<Vec<Chat>, ApiError> = env.update(sender, "get_chats", (group_id,));
+    chats
+        .unwrap()
+        .into_iter()
+        .map(|chat| (chat.username, chat.content))
+        .collect()
+}
+
+#[test]
+#[ignore = "needs the canister wasm and a PocketIC server, see the crate docs"]
+fn members_share_meetings_and_chats() {
+    let env = TestEnv::new();
+    let members = setup_group(&env);
+
+    let group: Result<Option<GroupQueryResponse>, ApiError> =
+        env.query(members.bob, "get_group", (members.group_id,));
+    let usernames = group
+        .unwrap()
+        .unwrap()
+        .members
+        .into_iter()
+        .map(|member| member.username)
+        .collect::<Vec<_>>();
+    assert_eq!(usernames, ["alice", "bob"]);
+
+    let meeting_id = create_meeting(&env, &members);
+    let first_part = vec![1; chunk::MB + 10];
+    let second_part = vec![2; 100];
+
+    env.upload_video(
+        members.alice,
+        members.group_id,
+        meeting_id,
+        "First",
+        &first_part,
+    );
+    env.wait_for_jobs(members.alice, members.group_id, meeting_id);
+    env.upload_video(
+        members.bob,
+        members.group_id,
+        meeting_id,
+        "Second",
+        &second_part,
+    );
+    let jobs = env.wait_for_jobs(members.bob, members.group_id, meeting_id);
+
+    let kinds = jobs.iter().map(|job| job.kind).collect::<Vec<_>>();
+    assert_eq!(
+        kinds,
+        [JobKind::Thumbnail, JobKind::Concat, JobKind::Thumbnail]
+    );
+    assert!(jobs.iter().all(|job| job.state == JobState::Done));
+
+    let meeting = meeting_detail(&env, members.bob, members.group_id, meeting_id);
+    assert_eq!(meeting.frames_count, 2);
+    assert_eq!(meeting.process_type, MeetingProcessType::None);
+
+    let video_size: Result<u128, ApiError> = env.update(
+        members.bob,
+        "get_video_meeting_size",
+        (members.group_id, meeting_id),
+    );
+    assert_eq!(
+        video_size.unwrap(),
+        (first_part.len() + second_part.len()) as u128
+    );
+
+    // the fake processor answers thumbnails with a SHA-256
+    let thumbnail_size: Result<u128, ApiError> = env.update(
+        members.bob,
+        "get_meeting_thumbnail_size",
+        (members.group_id, meeting_id),
+    );
+    assert_eq!(thumbnail_size.unwrap(), 32);
+
+    let mut alice_client = env.ws_open(members.alice, 1);
+    env.ws_open(members.bob, 1);
+    send_chat(&env, &mut alice_client, members.group_id, "Hello Bob!");
+    send_chat(&env, &mut alice_client, members.group_id, "Meeting is up.");
+
+    assert_eq!(
+        chat_contents(&env, members.bob, members.group_id),
+        [
+            (String::from("alice"), String::from("Hello Bob!")),
+            (String::from("alice"), String::from("Meeting is up.")),
+        ]
+    );
+}
+
+#[test]
+#[ignore = "needs the canister wasm and a PocketIC server, see the crate docs"]
+fn upgrade_in_the_middle_keeps_state_and_finishes_jobs() {
+    let env = TestEnv::new();
+    let members = setup_group(&env);
+    let meeting_id = create_meeting(&env, &members);
+
+    let first_part = vec![1; 100];
+    let second_part = vec![2; 50];
+    env.upload_video(
+        members.alice,
+        members.group_id,
+        meeting_id,
+        "First",
+        &first_part,
+    );
+    env.wait_for_jobs(members.alice, members.group_id, meeting_id);
+
+    let mut alice_client = env.ws_open(members.alice, 1);
+    send_chat(&env, &mut alice_client, members.group_id, "Before upgrade");
+
+    // the concat job is queued but has not run yet
+    env.upload_video(
+        members.bob,
+        members.group_id,
+        meeting_id,
+        "Second",
+        &second_part,
+    );
+    let meeting = meeting_detail(&env, members.bob, members.group_id, meeting_id);
+    assert_eq!(meeting.process_type, MeetingProcessType::Concat);
+
+    env.upgrade();
+
+    let meeting = meeting_detail(&env, members.bob, members.group_id, meeting_id);
+    assert_eq!(meeting.title, "Standup");
+    assert_eq!(meeting.frames_count, 2);
+    assert_eq!(meeting.process_type, MeetingProcessType::Concat);
+
+    let jobs = env.wait_for_jobs(members.bob, members.group_id, meeting_id);
+    assert!(jobs.iter().all(|job| job.state == JobState::Done));
+
+    let meeting = meeting_detail(&env, members.bob, members.group_id, meeting_id);
+    assert_eq!(meeting.process_type, MeetingProcessType::None);
+
+    let video_size: Result<u128, ApiError> = env.update(
+        members.alice,
+        "get_video_meeting_size",
+        (members.group_id, meeting_id),
+    );
+    assert_eq!(
+        video_size.unwrap(),
+        (first_part.len() + second_part.len()) as u128
+    );
+
+    // connections do not survive the upgrade, the client reconnects with a new nonce
+    let mut bob_client = env.ws_open(members.bob, 2);
+    send_chat(&env, &mut bob_client, members.group_id, "After upgrade");
+
+    assert_eq!(
+        chat_contents(&env, members.alice, members.group_id),
+        [
+            (String::from("alice"), String::from("Before upgrade")),
+            (String::from("bob"), String::from("After upgrade")),
+        ]
+    );
+}